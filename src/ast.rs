@@ -0,0 +1,818 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Parsing C expressions from tokens without evaluating them.
+//!
+//! [`parse`] accepts the same expressions as the evaluator in [`expr`], and
+//! returns their structure as an [`Expr`]. [`visit`] parses the same
+//! expressions, but instead reports each part of the expression to a
+//! [`Visitor`] as it is parsed, without building a tree. Nothing is evaluated,
+//! so identifiers don't need to be known and operators are kept even if they
+//! can't be applied to their operands. Only literals are parsed to their
+//! values. Parentheses only group subexpressions, and are not part of the tree.
+//!
+//! The identifiers in an [`Expr`] are [`Symbol`]s, whose spellings are kept
+//! in a [`Symbols`] table. The expressions of a whole header can share one
+//! table, so that the spelling of each identifier is allocated once, and
+//! identifiers can be compared without comparing their spellings.
+//!
+//! [`IdentifierParser::partial_expr`] parses an expression the same way, but
+//! evaluates the parts of it that can be evaluated.
+//!
+//! As in [`expr`], casts are recognized only to the types the evaluator
+//! supports, because `(T)(x)` can't be told apart from a parenthesized
+//! identifier applied to `(x)` without knowing whether `T` is a type. The
+//! types of `sizeof`, `_Alignof` and `__builtin_offsetof` can be any type.
+//!
+//! [`expr`]: crate::expr
+//! [`IdentifierParser::partial_expr`]: crate::expr::IdentifierParser::partial_expr
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::sync::Arc;
+
+use nom::branch::alt;
+use nom::combinator::{complete, map};
+use nom::sequence::{delimited, pair, preceded};
+use nom::Err;
+
+use crate::expr::{
+    cast_type, identifier, identifier_token, keyword, keyword_token, one_of_punctuation, p,
+    qualified_name, skip_whitespace, type_name, CResult, EvalResult, MAX_DEPTH,
+};
+use crate::literal;
+use crate::token::{Kind as TokenKind, Token};
+
+/// An identifier in a [`Symbols`] table.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// The index of this symbol in its table: the symbols of a table are
+    /// numbered from `0` in the order they were added, so that they can index
+    /// a side table with data about each identifier.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The spellings of the identifiers that [`Symbol`]s refer to.
+///
+/// # Example
+/// ```
+/// use cexpr::assert_full_parse;
+/// use cexpr::ast::{parse, Expr, Symbols};
+/// use cexpr::token::tokenize;
+///
+/// let mut symbols = Symbols::new();
+/// let (_, expr) = assert_full_parse(parse(&tokenize(b"A"), &mut symbols)).unwrap();
+/// let a = symbols.get(b"A").unwrap();
+/// assert_eq!(expr, Expr::Identifier(a));
+/// assert_eq!(symbols.name(a), b"A");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    names: Vec<Arc<[u8]>>,
+    symbols: HashMap<Arc<[u8]>, Symbol>,
+}
+
+impl Symbols {
+    /// Create an empty table.
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// The symbol of the identifier `name`, which is added to the table if
+    /// it isn't in it yet.
+    ///
+    /// # Panics
+    /// If the table already has 2<sup>32</sup> symbols.
+    pub fn intern(&mut self, name: &[u8]) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(u32::try_from(self.names.len()).expect("too many symbols"));
+        let name: Arc<[u8]> = name.into();
+        self.names.push(name.clone());
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    /// The symbol of the identifier `name`, if it is in the table.
+    pub fn get(&self, name: &[u8]) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// The spelling of the identifier `symbol` refers to.
+    ///
+    /// # Panics
+    /// If `symbol` is not from this table.
+    pub fn name(&self, symbol: Symbol) -> &[u8] {
+        &self.names[symbol.index()]
+    }
+
+    /// The number of symbols in the table.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Whether the table has no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// A parsed C expression.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum Expr {
+    /// A literal, with its value.
+    Literal(EvalResult),
+    /// An identifier, or a C++ qualified name such as `ns::NAME`, spelled
+    /// with its `::`.
+    Identifier(Symbol),
+    /// Adjacent strings that are concatenated, as in `"a" NAME "b"`. At least
+    /// one of them is a string literal or stringified.
+    Concat(Vec<Expr>),
+    /// The stringification operator applied to an identifier, as in `#x`.
+    Stringify(Symbol),
+    /// The preprocessor operator `defined`, as in `defined(FOO)`.
+    Defined(Symbol),
+    /// `sizeof(type)`.
+    Sizeof(String),
+    /// `_Alignof(type)` or `alignof(type)`.
+    Alignof(String),
+    /// `__builtin_offsetof(type, member)`.
+    Offsetof { ty: String, member: String },
+    /// `__builtin_choose_expr(cond, then, otherwise)`.
+    ChooseExpr {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// A unary operator, `+`, `-`, `~` or `!`.
+    Unary { op: String, operand: Box<Expr> },
+    /// A binary operator, including the comma operator.
+    Binary {
+        op: String,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// The conditional operator `?:`.
+    Conditional {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// A C cast, as in `(unsigned int)x`.
+    Cast { ty: String, operand: Box<Expr> },
+    /// A C++ cast such as `static_cast<int>(x)`, where `cast` is the name of
+    /// the cast.
+    NamedCast {
+        cast: String,
+        ty: String,
+        operand: Box<Expr>,
+    },
+    /// A subscript, as in `"abc"[1]`.
+    Subscript { base: Box<Expr>, index: Box<Expr> },
+}
+
+/// Receives the parts of an expression from [`visit`].
+///
+/// The parts are reported in postfix order: the operands of an operator are
+/// reported before the operator, from left to right, so `1 + A * 2` is
+/// reported as `1`, `A`, `2`, `*`, `+`. Parentheses are not reported. Every
+/// method does nothing by default.
+///
+/// If parsing fails, the parts reported so far may not form a complete
+/// expression.
+#[allow(unused_variables)]
+pub trait Visitor {
+    /// A literal, with its value.
+    fn on_literal(&mut self, value: EvalResult) {}
+    /// An identifier, or a C++ qualified name such as `ns::NAME`, spelled
+    /// with its `::`.
+    fn on_identifier(&mut self, name: &[u8]) {}
+    /// The stringification operator applied to an identifier, as in `#x`.
+    fn on_stringify(&mut self, name: &[u8]) {}
+    /// The `count` strings reported last are concatenated, as in
+    /// `"a" NAME "b"`.
+    fn on_concat(&mut self, count: usize) {}
+    /// The preprocessor operator `defined`, as in `defined(FOO)`.
+    fn on_defined(&mut self, name: &[u8]) {}
+    /// `sizeof(type)`, where `ty` are the tokens of the type.
+    fn on_sizeof(&mut self, ty: &[Token]) {}
+    /// `_Alignof(type)` or `alignof(type)`, where `ty` are the tokens of the
+    /// type.
+    fn on_alignof(&mut self, ty: &[Token]) {}
+    /// `__builtin_offsetof(type, member)`, where `ty` and `member` are the
+    /// tokens of the type and of the member designator.
+    fn on_offsetof(&mut self, ty: &[Token], member: &[Token]) {}
+    /// `__builtin_choose_expr` applied to the three expressions reported
+    /// last.
+    fn on_choose_expr(&mut self) {}
+    /// A unary operator, `+`, `-`, `~` or `!`, applied to the expression
+    /// reported last.
+    fn on_unary_op(&mut self, op: &[u8]) {}
+    /// A binary operator, including the comma operator, applied to the two
+    /// expressions reported last.
+    fn on_binary_op(&mut self, op: &[u8]) {}
+    /// The conditional operator `?:` applied to the three expressions
+    /// reported last.
+    fn on_conditional(&mut self) {}
+    /// A C cast of the expression reported last to the type with the tokens
+    /// `ty`.
+    fn on_cast(&mut self, ty: &[Token]) {}
+    /// A C++ cast such as `static_cast` of the expression reported last to
+    /// the type with the tokens `ty`.
+    fn on_named_cast(&mut self, cast: &[u8], ty: &[Token]) {}
+    /// The expression reported second to last subscripted with the
+    /// expression reported last.
+    fn on_subscript(&mut self) {}
+}
+
+/// The binary operators of each precedence level, from lowest to highest.
+const LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+fn spelling(raw: &[u8]) -> String {
+    String::from_utf8_lossy(raw).into_owned()
+}
+
+/// The spelling of `tokens`, separated by `sep`, without whitespace tokens.
+fn spell_tokens(tokens: &[Token], sep: &str) -> String {
+    tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Whitespace)
+        .map(|token| String::from_utf8_lossy(&token.raw))
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+/// Check the result `res` of parsing something that must follow what was
+/// parsed before, such as the right operand of a binary operator.
+///
+/// As in the evaluator, if that fails, the expression fails there. This also
+/// means that nothing is reported to the visitor for an alternative that is
+/// abandoned later.
+fn required<T>(res: CResult<'_, T>) -> CResult<'_, T> {
+    match res {
+        Err(Err::Error(e)) => Err(Err::Failure(e)),
+        res => res,
+    }
+}
+
+/// The tokens of a type that casts are supported to, see [`cast_type`].
+fn cast_type_tokens(input: &[Token]) -> CResult<'_, &[Token]> {
+    let input = skip_whitespace(input);
+    let (rest, _) = cast_type(input)?;
+    Ok((rest, &input[..input.len() - rest.len()]))
+}
+
+fn literal(input: &[Token]) -> CResult<'_, EvalResult> {
+    let input = skip_whitespace(input);
+    match input.split_first() {
+        None => Err(Err::Incomplete(nom::Needed::new(1))),
+        Some((token, rest)) if token.kind == TokenKind::Literal => match literal::parse(&token.raw)
+        {
+            Ok((_, value)) => Ok((rest, value)),
+            Err(Err::Error(e)) if e.error == crate::ErrorKind::LiteralOverflow => {
+                Err(Err::Error((input, e.error).into()))
+            }
+            _ => Err(Err::Error((input, crate::ErrorKind::InvalidLiteral).into())),
+        },
+        Some(_) => Err(Err::Error(
+            (input, crate::ErrorKind::TypedToken(TokenKind::Literal)).into(),
+        )),
+    }
+}
+
+fn defined_name(input: &[Token]) -> CResult<'_, Cow<'_, [u8]>> {
+    alt((qualified_name, map(keyword_token, Cow::Borrowed)))(input)
+}
+
+/// An operand that may be concatenated with the adjacent operands if it is a
+/// string.
+enum Item<'t> {
+    Literal(EvalResult),
+    Identifier(Cow<'t, [u8]>),
+    Stringify(&'t [u8]),
+}
+
+impl Item<'_> {
+    fn is_string(&self) -> bool {
+        matches!(self, Item::Literal(EvalResult::Str(_)) | Item::Stringify(_))
+    }
+
+    fn can_concat(&self) -> bool {
+        self.is_string() || matches!(self, Item::Identifier(_))
+    }
+}
+
+fn item(input: &[Token]) -> CResult<'_, Item<'_>> {
+    alt((
+        map(literal, Item::Literal),
+        map(qualified_name, Item::Identifier),
+        map(preceded(p("#"), identifier_token), Item::Stringify),
+    ))(input)
+}
+
+struct Parser<'v, V: ?Sized> {
+    visitor: &'v mut V,
+    /// How deeply the current subexpression is nested.
+    depth: usize,
+    /// The number of tokens of the whole expression.
+    len: usize,
+    /// If set, the indices of the tokens of each part of the expression, in
+    /// the order the parts are reported.
+    spans: Option<&'v mut Vec<Range<usize>>>,
+}
+
+impl<V: Visitor + ?Sized> Parser<'_, V> {
+    /// Parse a subexpression of `input` with `f`, failing if it is nested too
+    /// deeply.
+    fn nested<'t>(
+        &mut self,
+        input: &'t [Token],
+        f: fn(&mut Self, &'t [Token]) -> CResult<'t, ()>,
+    ) -> CResult<'t, ()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Err::Failure((input, crate::ErrorKind::TooDeep).into()));
+        }
+        self.depth += 1;
+        let res = f(self, input);
+        self.depth -= 1;
+        res
+    }
+
+    /// Report a part of the expression with `event`, where the part starts
+    /// at `start` and ends before `end`.
+    fn report(&mut self, start: &[Token], end: &[Token], event: impl FnOnce(&mut V)) {
+        event(self.visitor);
+        if let Some(spans) = &mut self.spans {
+            spans.push(self.len - start.len()..self.len - end.len());
+        }
+    }
+
+    fn emit(&mut self, start: &[Token], end: &[Token], item: Item<'_>) {
+        self.report(start, end, |visitor| match item {
+            Item::Literal(value) => visitor.on_literal(value),
+            Item::Identifier(name) => visitor.on_identifier(&name),
+            Item::Stringify(name) => visitor.on_stringify(name),
+        })
+    }
+
+    /// A literal, an identifier or `#x`, optionally followed by more strings
+    /// that are concatenated to it.
+    fn strings<'t>(&mut self, start: &'t [Token]) -> CResult<'t, ()> {
+        let (mut input, first) = item(start)?;
+        let mut string = first.is_string();
+        let can_concat = first.can_concat();
+        self.emit(start, input, first);
+        if !can_concat {
+            return Ok((input, ()));
+        }
+        let mut count = 1;
+        while let Ok((rest, next)) = complete(item)(input) {
+            if !next.can_concat() || !(string || next.is_string()) {
+                break;
+            }
+            string |= next.is_string();
+            self.emit(input, rest, next);
+            count += 1;
+            input = rest;
+        }
+        if count > 1 {
+            self.report(start, input, |visitor| visitor.on_concat(count));
+        }
+        Ok((input, ()))
+    }
+
+    /// A primary expression, optionally followed by subscripts.
+    fn postfix<'t>(&mut self, start: &'t [Token]) -> CResult<'t, ()> {
+        let (mut input, ()) = match p("(")(start) {
+            Ok((rest, _)) => {
+                let (rest, ()) = required(self.nested(rest, Self::comma))?;
+                let (rest, _) = required(p(")")(rest))?;
+                (rest, ())
+            }
+            Err(_) => self.strings(start)?,
+        };
+        while let Ok((rest, _)) = complete(p("["))(input) {
+            let (rest, ()) = required(self.nested(rest, Self::comma))?;
+            let (rest, _) = required(p("]")(rest))?;
+            self.report(start, rest, |visitor| visitor.on_subscript());
+            input = rest;
+        }
+        Ok((input, ()))
+    }
+
+    fn unary<'t>(&mut self, input: &'t [Token]) -> CResult<'t, ()> {
+        if let Ok((rest, ty)) = delimited(p("("), cast_type_tokens, p(")"))(input) {
+            let (rest, ()) = required(self.nested(rest, Self::unary))?;
+            self.report(input, rest, |visitor| visitor.on_cast(ty));
+            return Ok((rest, ()));
+        }
+        let mut named_cast = alt((
+            identifier("static_cast"),
+            identifier("const_cast"),
+            identifier("reinterpret_cast"),
+        ));
+        if let Ok((rest, cast)) = named_cast(input) {
+            let (rest, ty) = required(delimited(p("<"), cast_type_tokens, p(">"))(rest))?;
+            let (rest, _) = required(p("(")(rest))?;
+            let (rest, ()) = required(self.nested(rest, Self::comma))?;
+            let (rest, _) = required(p(")")(rest))?;
+            self.report(input, rest, |visitor| visitor.on_named_cast(cast, ty));
+            return Ok((rest, ()));
+        }
+        if let Ok((rest, _)) = identifier("__builtin_offsetof")(input) {
+            let (rest, (ty, member)) = required(delimited(
+                p("("),
+                pair(type_name, preceded(p(","), type_name)),
+                p(")"),
+            )(rest))?;
+            self.report(input, rest, |visitor| visitor.on_offsetof(ty, member));
+            return Ok((rest, ()));
+        }
+        let mut layout = alt((
+            keyword("sizeof"),
+            keyword("_Alignof"),
+            identifier("alignof"),
+        ));
+        if let Ok((rest, op)) = layout(input) {
+            let (rest, ty) = required(delimited(p("("), type_name, p(")"))(rest))?;
+            self.report(input, rest, |visitor| match op {
+                b"sizeof" => visitor.on_sizeof(ty),
+                _ => visitor.on_alignof(ty),
+            });
+            return Ok((rest, ()));
+        }
+        if let Ok((rest, _)) = identifier("__builtin_choose_expr")(input) {
+            let (mut rest, _) = required(p("(")(rest))?;
+            for sep in [",", ",", ")"] {
+                let (r, ()) = required(self.nested(rest, Self::ternary))?;
+                let (r, _) = required(p(sep)(r))?;
+                rest = r;
+            }
+            self.report(input, rest, |visitor| visitor.on_choose_expr());
+            return Ok((rest, ()));
+        }
+        if let Ok((rest, _)) = identifier("defined")(input) {
+            let (rest, name) =
+                required(alt((delimited(p("("), defined_name, p(")")), defined_name))(rest))?;
+            self.report(input, rest, |visitor| visitor.on_defined(&name));
+            return Ok((rest, ()));
+        }
+        if let Ok((rest, op)) = one_of_punctuation(&["+", "-", "~", "!"][..])(input) {
+            let (rest, ()) = required(self.nested(rest, Self::unary))?;
+            self.report(input, rest, |visitor| visitor.on_unary_op(op));
+            return Ok((rest, ()));
+        }
+        self.postfix(input)
+    }
+
+    /// The binary operators of `LEVELS[level]` and higher.
+    fn binary<'t>(&mut self, level: usize, start: &'t [Token]) -> CResult<'t, ()> {
+        let (mut input, ()) = self.operand(level, start)?;
+        while let Ok((rest, op)) = complete(one_of_punctuation(LEVELS[level]))(input) {
+            let (rest, ()) = required(self.operand(level, rest))?;
+            self.report(start, rest, |visitor| visitor.on_binary_op(op));
+            input = rest;
+        }
+        Ok((input, ()))
+    }
+
+    /// An operand of the binary operators of `LEVELS[level]`.
+    fn operand<'t>(&mut self, level: usize, input: &'t [Token]) -> CResult<'t, ()> {
+        if level + 1 < LEVELS.len() {
+            self.binary(level + 1, input)
+        } else {
+            self.unary(input)
+        }
+    }
+
+    fn ternary<'t>(&mut self, start: &'t [Token]) -> CResult<'t, ()> {
+        let (input, ()) = self.binary(0, start)?;
+        let rest = match complete(p("?"))(input) {
+            Ok((rest, _)) => rest,
+            Err(_) => return Ok((input, ())),
+        };
+        let (rest, ()) = required(self.nested(rest, Self::comma))?;
+        let (rest, _) = required(p(":")(rest))?;
+        let (rest, ()) = required(self.nested(rest, Self::ternary))?;
+        self.report(start, rest, |visitor| visitor.on_conditional());
+        Ok((rest, ()))
+    }
+
+    fn comma<'t>(&mut self, start: &'t [Token]) -> CResult<'t, ()> {
+        let (mut input, ()) = self.ternary(start)?;
+        while let Ok((rest, op)) = complete(p(","))(input) {
+            let (rest, ()) = required(self.ternary(rest))?;
+            self.report(start, rest, |visitor| visitor.on_binary_op(op));
+            input = rest;
+        }
+        Ok((input, ()))
+    }
+}
+
+/// Builds an [`Expr`] from the parts reported by [`visit`].
+struct Builder<'s> {
+    stack: Vec<Expr>,
+    symbols: &'s mut Symbols,
+}
+
+impl Builder<'_> {
+    fn new(symbols: &mut Symbols) -> Builder<'_> {
+        Builder {
+            stack: vec![],
+            symbols,
+        }
+    }
+
+    fn pop(&mut self) -> Box<Expr> {
+        Box::new(self.stack.pop().expect("an operand"))
+    }
+
+    fn push(&mut self, expr: Expr) {
+        self.stack.push(expr)
+    }
+}
+
+impl Visitor for Builder<'_> {
+    fn on_literal(&mut self, value: EvalResult) {
+        self.push(Expr::Literal(value))
+    }
+
+    fn on_identifier(&mut self, name: &[u8]) {
+        let symbol = self.symbols.intern(name);
+        self.push(Expr::Identifier(symbol))
+    }
+
+    fn on_stringify(&mut self, name: &[u8]) {
+        let symbol = self.symbols.intern(name);
+        self.push(Expr::Stringify(symbol))
+    }
+
+    fn on_concat(&mut self, count: usize) {
+        let items = self.stack.split_off(self.stack.len() - count);
+        self.push(Expr::Concat(items))
+    }
+
+    fn on_defined(&mut self, name: &[u8]) {
+        let symbol = self.symbols.intern(name);
+        self.push(Expr::Defined(symbol))
+    }
+
+    fn on_sizeof(&mut self, ty: &[Token]) {
+        self.push(Expr::Sizeof(spell_tokens(ty, " ")))
+    }
+
+    fn on_alignof(&mut self, ty: &[Token]) {
+        self.push(Expr::Alignof(spell_tokens(ty, " ")))
+    }
+
+    fn on_offsetof(&mut self, ty: &[Token], member: &[Token]) {
+        self.push(Expr::Offsetof {
+            ty: spell_tokens(ty, " "),
+            member: spell_tokens(member, ""),
+        })
+    }
+
+    fn on_choose_expr(&mut self) {
+        let (otherwise, then, cond) = (self.pop(), self.pop(), self.pop());
+        self.push(Expr::ChooseExpr {
+            cond,
+            then,
+            otherwise,
+        })
+    }
+
+    fn on_unary_op(&mut self, op: &[u8]) {
+        let operand = self.pop();
+        self.push(Expr::Unary {
+            op: spelling(op),
+            operand,
+        })
+    }
+
+    fn on_binary_op(&mut self, op: &[u8]) {
+        let (rhs, lhs) = (self.pop(), self.pop());
+        self.push(Expr::Binary {
+            op: spelling(op),
+            lhs,
+            rhs,
+        })
+    }
+
+    fn on_conditional(&mut self) {
+        let (otherwise, then, cond) = (self.pop(), self.pop(), self.pop());
+        self.push(Expr::Conditional {
+            cond,
+            then,
+            otherwise,
+        })
+    }
+
+    fn on_cast(&mut self, ty: &[Token]) {
+        let operand = self.pop();
+        self.push(Expr::Cast {
+            ty: spell_tokens(ty, " "),
+            operand,
+        })
+    }
+
+    fn on_named_cast(&mut self, cast: &[u8], ty: &[Token]) {
+        let operand = self.pop();
+        self.push(Expr::NamedCast {
+            cast: spelling(cast),
+            ty: spell_tokens(ty, " "),
+            operand,
+        })
+    }
+
+    fn on_subscript(&mut self) {
+        let (index, base) = (self.pop(), self.pop());
+        self.push(Expr::Subscript { base, index })
+    }
+}
+
+/// Parse a C expression from a list of tokens, without evaluating it. The
+/// identifiers in it are added to `symbols`.
+///
+/// # Example
+/// ```
+/// use cexpr::ast::{parse, Expr, Symbols};
+/// use cexpr::assert_full_parse;
+/// use cexpr::expr::EvalResult;
+/// use cexpr::token::tokenize;
+///
+/// let mut symbols = Symbols::new();
+/// let tokens = tokenize(b"(unsigned)A << 2");
+/// let (_, expr) = assert_full_parse(parse(&tokens, &mut symbols)).unwrap();
+/// assert_eq!(expr, Expr::Binary {
+///     op: "<<".into(),
+///     lhs: Box::new(Expr::Cast {
+///         ty: "unsigned".into(),
+///         operand: Box::new(Expr::Identifier(symbols.intern(b"A"))),
+///     }),
+///     rhs: Box::new(Expr::Literal(EvalResult::Int(std::num::Wrapping(2)))),
+/// });
+/// ```
+pub fn parse<'t>(input: &'t [Token], symbols: &mut Symbols) -> CResult<'t, Expr> {
+    let mut builder = Builder::new(symbols);
+    let (rest, ()) = visit(input, &mut builder)?;
+    Ok((rest, *builder.pop()))
+}
+
+/// Parse a C expression from a list of tokens, reporting its parts to
+/// `visitor` instead of building an [`Expr`].
+///
+/// # Example
+/// ```
+/// use cexpr::ast::{visit, Visitor};
+/// use cexpr::token::tokenize;
+///
+/// #[derive(Default)]
+/// struct CountOperators(usize);
+///
+/// impl Visitor for CountOperators {
+///     fn on_unary_op(&mut self, _: &[u8]) {
+///         self.0 += 1;
+///     }
+///
+///     fn on_binary_op(&mut self, _: &[u8]) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// let mut count = CountOperators::default();
+/// visit(&tokenize(b"-A + (B << 2)"), &mut count).unwrap();
+/// assert_eq!(count.0, 3);
+/// ```
+pub fn visit<'t, V: Visitor + ?Sized>(input: &'t [Token], visitor: &mut V) -> CResult<'t, ()> {
+    crate::token::check(input)?;
+    Parser {
+        visitor,
+        depth: 0,
+        len: input.len(),
+        spans: None,
+    }
+    .comma(input)
+}
+
+/// Parse a C expression from a list of tokens like [`parse`], and replace
+/// each part of it that `evaluate` can evaluate with its value.
+///
+/// `evaluate` is called with the tokens of each part of the expression, the
+/// operands of an operator before the operator.
+pub(crate) fn partial<'t>(
+    input: &'t [Token],
+    symbols: &mut Symbols,
+    mut evaluate: impl FnMut(&[Token]) -> Option<EvalResult>,
+) -> CResult<'t, Expr> {
+    let mut builder = Builder::new(symbols);
+    let mut spans = vec![];
+    let (rest, ()) = Parser {
+        visitor: &mut builder,
+        depth: 0,
+        len: input.len(),
+        spans: Some(&mut spans),
+    }
+    .comma(input)?;
+    let mut spans = spans.into_iter();
+    let mut evaluate = |span: Range<usize>| evaluate(&input[span]);
+    let expr = fold(*builder.pop(), &mut spans, &mut evaluate);
+    Ok((rest, expr))
+}
+
+fn fold_box(
+    mut expr: Box<Expr>,
+    spans: &mut impl Iterator<Item = Range<usize>>,
+    evaluate: &mut impl FnMut(Range<usize>) -> Option<EvalResult>,
+) -> Box<Expr> {
+    *expr = fold(*expr, spans, evaluate);
+    expr
+}
+
+/// Replace the parts of `expr` that can be evaluated with their values,
+/// where `spans` are the spans of the parts of `expr` in postfix order.
+fn fold(
+    expr: Expr,
+    spans: &mut impl Iterator<Item = Range<usize>>,
+    evaluate: &mut impl FnMut(Range<usize>) -> Option<EvalResult>,
+) -> Expr {
+    let expr = match expr {
+        Expr::Concat(items) => Expr::Concat(
+            items
+                .into_iter()
+                .map(|item| fold(item, spans, evaluate))
+                .collect(),
+        ),
+        Expr::ChooseExpr {
+            cond,
+            then,
+            otherwise,
+        } => Expr::ChooseExpr {
+            cond: fold_box(cond, spans, evaluate),
+            then: fold_box(then, spans, evaluate),
+            otherwise: fold_box(otherwise, spans, evaluate),
+        },
+        Expr::Unary { op, operand } => Expr::Unary {
+            op,
+            operand: fold_box(operand, spans, evaluate),
+        },
+        Expr::Binary { op, lhs, rhs } => Expr::Binary {
+            op,
+            lhs: fold_box(lhs, spans, evaluate),
+            rhs: fold_box(rhs, spans, evaluate),
+        },
+        Expr::Conditional {
+            cond,
+            then,
+            otherwise,
+        } => Expr::Conditional {
+            cond: fold_box(cond, spans, evaluate),
+            then: fold_box(then, spans, evaluate),
+            otherwise: fold_box(otherwise, spans, evaluate),
+        },
+        Expr::Cast { ty, operand } => Expr::Cast {
+            ty,
+            operand: fold_box(operand, spans, evaluate),
+        },
+        Expr::NamedCast { cast, ty, operand } => Expr::NamedCast {
+            cast,
+            ty,
+            operand: fold_box(operand, spans, evaluate),
+        },
+        Expr::Subscript { base, index } => Expr::Subscript {
+            base: fold_box(base, spans, evaluate),
+            index: fold_box(index, spans, evaluate),
+        },
+        expr => expr,
+    };
+    let span = spans.next().expect("a span for each part");
+    match expr {
+        Expr::Literal(_) => expr,
+        // Even if an operand can't be evaluated, the operator may not need
+        // it, as in `1 ? 2 : UNKNOWN`.
+        expr => evaluate(span).map_or(expr, Expr::Literal),
+    }
+}
@@ -0,0 +1,378 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Keeping the evaluated macros of headers on disk.
+//!
+//! Evaluating all macros of a large header, such as those of an SDK, takes a
+//! while, but the result only depends on the content of the header and the
+//! target. A [`Cache`] stores the [`Resolution`] of a header in a file named
+//! after a hash of those, so that evaluating an unchanged header again only
+//! reads the file.
+//!
+//! This module is only available with the `cache` feature.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fs, io, process};
+
+use crate::expr::EvalResult;
+use crate::literal::CChar;
+use crate::macros::{MacroSet, Outcome, Resolution};
+use crate::target::{IntType, TargetInfo, Type};
+use crate::token::{self, DecodeError, Reader};
+use crate::ErrorKind;
+
+/// The start of a cache file.
+const MAGIC: &[u8; 4] = b"CXMC";
+
+/// The version of the format of cache files. It is incremented whenever the
+/// format or the way macros are evaluated changes, so that files written by
+/// another version of cexpr are not used.
+const FORMAT_VERSION: u8 = 11;
+
+/// A directory of evaluated headers.
+///
+/// # Example
+/// ```
+/// use cexpr::cache::Cache;
+///
+/// let dir = std::env::temp_dir().join(format!("cexpr-doc-{}", std::process::id()));
+/// let cache = Cache::new(&dir);
+/// let header = b"#define FLAGS (A | B)\n#define A 1\n#define B 2\n";
+/// let resolution = cache.resolve(header, Default::default()).unwrap();
+/// // The second time, the resolution is read from the cache.
+/// assert_eq!(cache.resolve(header, Default::default()).unwrap(), resolution);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    /// Use the directory `dir`, which is created when the first header is
+    /// stored.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache { dir: dir.into() }
+    }
+
+    /// Evaluate the object-like macros of `header` for `target`, see
+    /// [`MacroSet::from_header`].
+    ///
+    /// If the cache has the resolution of the same header for the same
+    /// target, that is returned. Otherwise, the macros are evaluated and the
+    /// resolution is stored. A file in the cache that can't be read or is
+    /// corrupt is replaced.
+    ///
+    /// Returns an error if the resolution could not be stored.
+    pub fn resolve(&self, header: &[u8], target: TargetInfo) -> io::Result<Resolution> {
+        let macros = MacroSet::from_header(header);
+        let path = self.dir.join(format!("{:016x}", key(header, target)));
+        if let Ok(data) = fs::read(&path) {
+            if let Ok(resolution) = decode(&data, header, target, &macros) {
+                return Ok(resolution);
+            }
+        }
+        let resolution = macros.resolve_for(target);
+        fs::create_dir_all(&self.dir)?;
+        // Write to a temporary file first, so that other processes using the
+        // cache never read a partially written file.
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp = self.dir.join(format!(
+            ".tmp-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::write(&temp, encode(&resolution, header, target))?;
+        fs::rename(&temp, &path)?;
+        Ok(resolution)
+    }
+}
+
+/// The hash of everything the resolution of `header` depends on.
+fn key(header: &[u8], target: TargetInfo) -> u64 {
+    let mut data = vec![FORMAT_VERSION];
+    token::write_varint(&mut data, target.pointer_width.into());
+    token::write_varint(&mut data, target.long_width().into());
+    token::write_varint(&mut data, target.wchar_width.into());
+    token::write_varint(&mut data, target.char_signed.into());
+    data.extend_from_slice(header);
+    token::fnv1a(&data)
+}
+
+/// The tags of the outcomes in a cache file.
+const EVALUATED_INT: u8 = 0;
+const EVALUATED_FLOAT: u8 = 1;
+const EVALUATED_CHAR: u8 = 2;
+const EVALUATED_RAW_CHAR: u8 = 3;
+const EVALUATED_STR: u8 = 4;
+const EVALUATED_INVALID: u8 = 5;
+const INVALID: u8 = 6;
+const CYCLIC: u8 = 7;
+/// An outcome that can't be stored, such as one with an error that lists the
+/// tokens that were expected. The macro is evaluated again when the file is
+/// read.
+const UNKNOWN: u8 = 8;
+
+/// The types of evaluated macros, which are stored by their index.
+const TYPES: &[Type] = &[
+    Type::Int(IntType::Bool),
+    Type::Int(IntType::Char),
+    Type::Int(IntType::SignedChar),
+    Type::Int(IntType::UnsignedChar),
+    Type::Int(IntType::Short),
+    Type::Int(IntType::UnsignedShort),
+    Type::Int(IntType::Int),
+    Type::Int(IntType::UnsignedInt),
+    Type::Int(IntType::Long),
+    Type::Int(IntType::UnsignedLong),
+    Type::Int(IntType::LongLong),
+    Type::Int(IntType::UnsignedLongLong),
+    Type::Int(IntType::SizeT),
+    Type::Int(IntType::SSizeT),
+    Type::Int(IntType::UIntPtrT),
+    Type::Int(IntType::IntPtrT),
+    Type::Int(IntType::PtrDiffT),
+    Type::Float,
+    Type::Double,
+    Type::LongDouble,
+    Type::Str,
+];
+
+fn encode(resolution: &Resolution, header: &[u8], target: TargetInfo) -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(FORMAT_VERSION);
+    // The key is only a hash, so the file repeats what it depends on, except
+    // for the header itself.
+    token::write_varint(&mut data, header.len() as u64);
+    token::write_varint(&mut data, target.pointer_width.into());
+    token::write_varint(&mut data, target.long_width().into());
+    token::write_varint(&mut data, target.wchar_width.into());
+    token::write_varint(&mut data, target.char_signed.into());
+    let mut names: Vec<&Vec<u8>> = resolution.outcomes.keys().collect();
+    names.sort_unstable();
+    token::write_varint(&mut data, names.len() as u64);
+    for name in names {
+        write_bytes(&mut data, name);
+        match &resolution.outcomes[name] {
+            Outcome::Evaluated(value) => {
+                let ty = resolution.types.get(name);
+                match ty.and_then(|ty| TYPES.iter().position(|t| t == ty)) {
+                    Some(code) => {
+                        encode_value(&mut data, value);
+                        data.push(code as u8);
+                    }
+                    None => data.push(UNKNOWN),
+                }
+            }
+            Outcome::Invalid(error) => match error_code(error) {
+                Some(code) => {
+                    data.push(INVALID);
+                    data.extend(code);
+                }
+                None => data.push(UNKNOWN),
+            },
+            Outcome::Cyclic => data.push(CYCLIC),
+        }
+    }
+    let checksum = token::fnv1a(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+fn encode_value(data: &mut Vec<u8>, value: &EvalResult) {
+    match value {
+        EvalResult::Int(i) => {
+            data.push(EVALUATED_INT);
+            token::write_varint(data, i.0 as u64);
+        }
+        EvalResult::Float(f) => {
+            data.push(EVALUATED_FLOAT);
+            data.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        EvalResult::Char(CChar::Char(c)) => {
+            data.push(EVALUATED_CHAR);
+            token::write_varint(data, u64::from(*c));
+        }
+        EvalResult::Char(CChar::Raw(c)) => {
+            data.push(EVALUATED_RAW_CHAR);
+            token::write_varint(data, *c);
+        }
+        EvalResult::Str(s) => {
+            data.push(EVALUATED_STR);
+            write_bytes(data, s);
+        }
+        EvalResult::Invalid => data.push(EVALUATED_INVALID),
+    }
+}
+
+fn write_bytes(data: &mut Vec<u8>, bytes: &[u8]) {
+    token::write_varint(data, bytes.len() as u64);
+    data.extend_from_slice(bytes);
+}
+
+/// The bytes that stand for `error` in a cache file, if it can be stored.
+fn error_code(error: &ErrorKind) -> Option<Vec<u8>> {
+    let code = match error {
+        ErrorKind::ExactToken(kind, raw) => {
+            token::known_spelling(raw)?;
+            let mut code = vec![0, token::kind_code(*kind)];
+            write_bytes(&mut code, raw);
+            return Some(code);
+        }
+        ErrorKind::TypedToken(kind) => return Some(vec![1, token::kind_code(*kind)]),
+        ErrorKind::UnexpectedToken => 2,
+        ErrorKind::UnknownIdentifier => 3,
+        ErrorKind::InvalidLiteral => 4,
+        ErrorKind::LiteralOverflow => 5,
+        ErrorKind::Partial => 6,
+        ErrorKind::UnknownLayout => 7,
+        ErrorKind::StringArithmetic => 8,
+        ErrorKind::NotInteger => 9,
+        ErrorKind::NotConstant => 10,
+        ErrorKind::TooDeep => 11,
+        ErrorKind::TooLarge => 12,
+        ErrorKind::ArgumentCount => 13,
+        ErrorKind::InvalidPaste => 14,
+        ErrorKind::InvalidToken => 15,
+        ErrorKind::DivisionByZero => 16,
+        ErrorKind::Unsupported => 17,
+        ErrorKind::Overflow => 18,
+        ErrorKind::InvalidShift => 19,
+        ErrorKind::ExactTokens(..) => return None,
+    };
+    Some(vec![code])
+}
+
+fn decode_error(reader: &mut Reader<'_>) -> Result<ErrorKind, DecodeError> {
+    let kind = |reader: &mut Reader<'_>| {
+        token::kind_from_code(reader.bytes(1)?[0]).ok_or(DecodeError::Corrupt)
+    };
+    Ok(match reader.bytes(1)?[0] {
+        0 => {
+            let kind = kind(reader)?;
+            let len = reader.len()?;
+            let raw = token::known_spelling(reader.bytes(len)?).ok_or(DecodeError::Corrupt)?;
+            ErrorKind::ExactToken(kind, raw)
+        }
+        1 => ErrorKind::TypedToken(kind(reader)?),
+        2 => ErrorKind::UnexpectedToken,
+        3 => ErrorKind::UnknownIdentifier,
+        4 => ErrorKind::InvalidLiteral,
+        5 => ErrorKind::LiteralOverflow,
+        6 => ErrorKind::Partial,
+        7 => ErrorKind::UnknownLayout,
+        8 => ErrorKind::StringArithmetic,
+        9 => ErrorKind::NotInteger,
+        10 => ErrorKind::NotConstant,
+        11 => ErrorKind::TooDeep,
+        12 => ErrorKind::TooLarge,
+        13 => ErrorKind::ArgumentCount,
+        14 => ErrorKind::InvalidPaste,
+        15 => ErrorKind::InvalidToken,
+        16 => ErrorKind::DivisionByZero,
+        17 => ErrorKind::Unsupported,
+        18 => ErrorKind::Overflow,
+        19 => ErrorKind::InvalidShift,
+        _ => return Err(DecodeError::Corrupt),
+    })
+}
+
+/// Read the resolution of the `macros` of `header` from a cache file.
+fn decode(
+    data: &[u8],
+    header: &[u8],
+    target: TargetInfo,
+    macros: &MacroSet,
+) -> Result<Resolution, DecodeError> {
+    if data.len() < MAGIC.len() + 1 + 8 || data[..MAGIC.len()] != MAGIC[..] {
+        return Err(DecodeError::NotTokens);
+    }
+    if data[MAGIC.len()] != FORMAT_VERSION {
+        return Err(DecodeError::Version(data[MAGIC.len()]));
+    }
+    let (data, checksum) = data.split_at(data.len() - 8);
+    if token::fnv1a(data).to_le_bytes() != checksum {
+        return Err(DecodeError::Corrupt);
+    }
+
+    let mut reader = Reader(&data[MAGIC.len() + 1..]);
+    let depends_on = [
+        header.len(),
+        target.pointer_width as usize,
+        target.long_width() as usize,
+        target.wchar_width as usize,
+        target.char_signed as usize,
+    ];
+    for &expected in &depends_on {
+        if reader.number()? != expected {
+            return Err(DecodeError::Corrupt);
+        }
+    }
+    let mut resolution = Resolution::default();
+    let mut unknown = vec![];
+    for _ in 0..reader.len()? {
+        let len = reader.len()?;
+        let name = reader.bytes(len)?.to_vec();
+        let outcome = match reader.bytes(1)?[0] {
+            EVALUATED_INT => EvalResult::Int(std::num::Wrapping(reader.number64()? as i64)),
+            EVALUATED_FLOAT => {
+                let mut bits = [0; 8];
+                bits.copy_from_slice(reader.bytes(8)?);
+                EvalResult::Float(f64::from_bits(u64::from_le_bytes(bits)))
+            }
+            EVALUATED_CHAR => {
+                let c = std::char::from_u32(reader.number64()? as u32);
+                EvalResult::Char(CChar::Char(c.ok_or(DecodeError::Corrupt)?))
+            }
+            EVALUATED_RAW_CHAR => EvalResult::Char(CChar::Raw(reader.number64()?)),
+            EVALUATED_STR => {
+                let len = reader.len()?;
+                EvalResult::Str(reader.bytes(len)?.to_vec())
+            }
+            EVALUATED_INVALID => EvalResult::Invalid,
+            INVALID => {
+                resolution
+                    .outcomes
+                    .insert(name, Outcome::Invalid(decode_error(&mut reader)?));
+                continue;
+            }
+            CYCLIC => {
+                resolution.outcomes.insert(name, Outcome::Cyclic);
+                continue;
+            }
+            UNKNOWN => {
+                unknown.push(name);
+                continue;
+            }
+            _ => return Err(DecodeError::Corrupt),
+        };
+        let ty = *TYPES
+            .get(usize::from(reader.bytes(1)?[0]))
+            .ok_or(DecodeError::Corrupt)?;
+        resolution.types.insert(name.clone(), ty);
+        resolution
+            .outcomes
+            .insert(name, Outcome::Evaluated(outcome));
+    }
+    if !reader.is_empty() || resolution.outcomes.len() + unknown.len() != macros.len() {
+        return Err(DecodeError::Corrupt);
+    }
+
+    // A macro is evaluated with the values of the macros it uses, which are
+    // the same as when the resolution was stored.
+    let values = resolution.typed_values();
+    for name in unknown {
+        let tokens = macros.get(&name).ok_or(DecodeError::Corrupt)?;
+        let (outcome, ty) = macros.evaluate(target, &values, tokens);
+        if let Some(ty) = ty {
+            resolution.types.insert(name.clone(), ty);
+        }
+        resolution.outcomes.insert(name, outcome);
+    }
+    Ok(resolution)
+}
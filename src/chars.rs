@@ -0,0 +1,71 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Classification of bytes in C source.
+//!
+//! The classes are looked up in a table over all byte values, which doesn't
+//! depend on the locale or on any other environment setting. Only ASCII
+//! bytes are members of any class.
+
+/// A set of bytes with a common meaning in C source, such as octal digits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Class(u8);
+
+impl Class {
+    /// `0` and `1`.
+    pub const BINARY: Class = Class(1 << 0);
+    /// `0` to `7`.
+    pub const OCTAL: Class = Class(1 << 1);
+    /// `0` to `9`.
+    pub const DECIMAL: Class = Class(1 << 2);
+    /// `0` to `9`, `a` to `f` and `A` to `F`.
+    pub const HEXADECIMAL: Class = Class(1 << 3);
+    /// Letters and `_`, which may start an identifier.
+    pub const IDENTIFIER_START: Class = Class(1 << 4);
+    /// Letters, digits and `_`, which may continue an identifier.
+    pub const IDENTIFIER: Class = Class(1 << 5);
+    /// Space, horizontal and vertical tab, form feed, carriage return and
+    /// newline.
+    pub const WHITESPACE: Class = Class(1 << 6);
+
+    /// Whether `byte` is a member of this class.
+    pub fn contains(self, byte: u8) -> bool {
+        TABLE[byte as usize] & self.0 != 0
+    }
+}
+
+static TABLE: [u8; 256] = table();
+
+const fn table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let c = i as u8;
+        let mut class = 0;
+        if c == b'0' || c == b'1' {
+            class |= Class::BINARY.0;
+        }
+        if c >= b'0' && c <= b'7' {
+            class |= Class::OCTAL.0;
+        }
+        if c.is_ascii_digit() {
+            class |= Class::DECIMAL.0 | Class::HEXADECIMAL.0 | Class::IDENTIFIER.0;
+        }
+        if (c >= b'a' && c <= b'f') || (c >= b'A' && c <= b'F') {
+            class |= Class::HEXADECIMAL.0;
+        }
+        if c.is_ascii_alphabetic() || c == b'_' {
+            class |= Class::IDENTIFIER_START.0 | Class::IDENTIFIER.0;
+        }
+        if matches!(c, b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r' | b'\n') {
+            class |= Class::WHITESPACE.0;
+        }
+        table[i] = class;
+        i += 1;
+    }
+    table
+}
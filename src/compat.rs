@@ -0,0 +1,79 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! The results of evaluating macros in the shape of
+//! [`IdentifierParser::macro_definition`].
+//!
+//! A [`MacroSet`] describes what happened to each macro with an [`Outcome`],
+//! which tells cyclic macros apart from macros that could not be evaluated
+//! for other reasons. Code written against `macro_definition`, which gives
+//! an [`EvalResult`] or an error for each macro it is called on, can use the
+//! functions of this module to get results of that shape from a
+//! [`Resolution`], and move to the outcomes one use at a time.
+//!
+//! # Example
+//! ```
+//! use std::num::Wrapping;
+//! use cexpr::compat;
+//! use cexpr::expr::EvalResult;
+//! use cexpr::macros::MacroSet;
+//! use cexpr::token::tokenize;
+//! use cexpr::ErrorKind;
+//!
+//! let mut macros = MacroSet::new();
+//! macros.insert(b"A".to_vec(), tokenize(b"(B + 1)"));
+//! macros.insert(b"B".to_vec(), tokenize(b"1"));
+//! macros.insert(b"C".to_vec(), tokenize(b"C"));
+//! let resolution = macros.resolve();
+//! assert_eq!(
+//!     compat::evaluated(&resolution),
+//!     [
+//!         (b"A".to_vec(), EvalResult::Int(Wrapping(2))),
+//!         (b"B".to_vec(), EvalResult::Int(Wrapping(1))),
+//!     ]
+//! );
+//! let c = &resolution.outcomes[&b"C"[..]];
+//! assert_eq!(compat::result(c), Err(ErrorKind::UnknownIdentifier));
+//! assert_eq!(compat::eval_result(c), EvalResult::Invalid);
+//! ```
+//!
+//! [`IdentifierParser::macro_definition`]: crate::expr::IdentifierParser::macro_definition
+//! [`MacroSet`]: crate::macros::MacroSet
+
+use crate::expr::EvalResult;
+use crate::macros::{Outcome, Resolution};
+use crate::ErrorKind;
+
+/// The value of a macro with the outcome `outcome`, or the error that
+/// evaluating it failed with. A cyclic macro fails with
+/// [`ErrorKind::UnknownIdentifier`], as it does when it is evaluated with
+/// `macro_definition` before the macros it uses are known.
+pub fn result(outcome: &Outcome) -> Result<EvalResult, ErrorKind> {
+    match outcome {
+        Outcome::Evaluated(value) => Ok(value.clone()),
+        Outcome::Invalid(error) => Err(error.clone()),
+        Outcome::Cyclic => Err(ErrorKind::UnknownIdentifier),
+    }
+}
+
+/// The value of a macro with the outcome `outcome`, which is
+/// [`EvalResult::Invalid`] if it could not be evaluated.
+pub fn eval_result(outcome: &Outcome) -> EvalResult {
+    result(outcome).unwrap_or(EvalResult::Invalid)
+}
+
+/// The name and value of each macro that was evaluated, in order of their
+/// names.
+pub fn evaluated(resolution: &Resolution) -> Vec<(Vec<u8>, EvalResult)> {
+    let mut evaluated: Vec<(Vec<u8>, EvalResult)> = resolution
+        .outcomes
+        .iter()
+        .filter_map(|(name, outcome)| Some((name.clone(), result(outcome).ok()?)))
+        .collect();
+    evaluated.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    evaluated
+}
@@ -0,0 +1,282 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Checking evaluation results against a C compiler.
+//!
+//! This module is only available with the `compiler` feature.
+//!
+//! A [`Compiler`] generates a small C program that uses a macro and asks the
+//! configured C compiler whether it agrees with the value cexpr computed.
+//! Integer, character and floating point values are checked with
+//! `_Static_assert`, so only compilation is needed and cross compilers can be
+//! used. Strings can't be compared in a constant expression, so for those the
+//! program is compiled and run, which requires a compiler for the host.
+//!
+//! Integers are compared as `long long`, so `-1` is the value of a macro that
+//! evaluates to `0xffffffffffffffffull`. Check the type of the macro with
+//! [`check_type`](Compiler::check_type) as well to be sure that the compiler
+//! computes the same value.
+//!
+//! ```no_run
+//! use cexpr::compiler::Compiler;
+//! use cexpr::expr::EvalResult;
+//! use std::num::Wrapping;
+//!
+//! let cc = Compiler::new("cc").arg("-std=c11").include("config.h");
+//! assert!(cc.check(b"BUFFER_SIZE", &EvalResult::Int(Wrapping(4096))).unwrap());
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
+use std::num::Wrapping;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, io, process};
+
+use crate::expr::EvalResult;
+use crate::literal::CChar;
+use crate::target::{IntType, Type};
+use crate::token::{Kind, Token};
+
+/// Errors that prevent a value from being checked.
+#[derive(Debug)]
+pub enum Error {
+    /// The compiler or the compiled program could not be run, or the
+    /// temporary files could not be written.
+    Io(io::Error),
+    /// The compiler rejected the program for a reason other than the value
+    /// being different. Contains the compiler's error output.
+    Compile(String),
+    /// The compiled program did not run successfully.
+    Run(String),
+    /// The value can't be checked, e.g. because it is `EvalResult::Invalid`.
+    Unsupported,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A C compiler invocation used to check evaluation results.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    command: OsString,
+    args: Vec<OsString>,
+    prelude: String,
+}
+
+impl Compiler {
+    /// Use the specified compiler command, e.g. `cc` or `clang`.
+    ///
+    /// The compiler needs to accept GCC-style `-c` and `-o` options.
+    pub fn new<S: AsRef<OsStr>>(command: S) -> Compiler {
+        Compiler {
+            command: command.as_ref().to_owned(),
+            args: vec![],
+            prelude: String::new(),
+        }
+    }
+
+    /// Pass an additional argument to the compiler, such as a target or
+    /// language standard selection.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> Compiler {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Include the specified header in the generated program.
+    pub fn include<P: AsRef<Path>>(mut self, header: P) -> Compiler {
+        writeln!(self.prelude, "#include \"{}\"", header.as_ref().display()).unwrap();
+        self
+    }
+
+    /// Define a macro in the generated program, after any included headers.
+    ///
+    /// The tokens are the replacement list of the macro.
+    pub fn define(mut self, name: &[u8], tokens: &[Token]) -> Compiler {
+        let mut line = b"#define ".to_vec();
+        line.extend_from_slice(name);
+        for token in tokens.iter().filter(|t| t.kind != Kind::Whitespace) {
+            line.push(b' ');
+            line.extend_from_slice(&token.raw);
+        }
+        self.prelude.push_str(&String::from_utf8_lossy(&line));
+        self.prelude.push('\n');
+        self
+    }
+
+    /// Check whether the compiler agrees that the macro `name` evaluates to
+    /// `value`.
+    ///
+    /// Returns `Ok(false)` if the compiler computes a different value, and an
+    /// error if the check couldn't be performed.
+    pub fn check(&self, name: &[u8], value: &EvalResult) -> Result<bool, Error> {
+        let name = String::from_utf8_lossy(name);
+        let expected = match *value {
+            EvalResult::Int(Wrapping(i)) => int_constant(i),
+            EvalResult::Char(CChar::Char(c)) => int_constant(c as i64),
+            EvalResult::Char(CChar::Raw(i)) => int_constant(i as i64),
+            EvalResult::Float(f) if f.is_nan() => {
+                return self.static_assert("", &format!("({0}) != ({0})", name));
+            }
+            EvalResult::Float(f) if f.is_infinite() => {
+                let sign = if f < 0.0 { "-" } else { "" };
+                format!("{}__builtin_inf()", sign)
+            }
+            EvalResult::Float(f) => format!("{:e}", f),
+            EvalResult::Str(ref s) => return self.check_str(&name, s),
+            EvalResult::Invalid => return Err(Error::Unsupported),
+        };
+        self.static_assert("", &format!("({}) == ({})", name, expected))
+    }
+
+    /// Check whether the compiler agrees that the macro `name` has the type
+    /// `ty`, such as one computed by
+    /// [`expr_with_type`](crate::expr::IdentifierParser::expr_with_type).
+    ///
+    /// This uses `_Generic`, so the compiler needs to support C11. Types
+    /// such as `size_t` are compared as the types they are defined as for
+    /// the compiler's target, and `ssize_t` requires a POSIX system header.
+    ///
+    /// Returns `Ok(false)` if the compiler gives the macro a different type,
+    /// and an error if the check couldn't be performed.
+    pub fn check_type(&self, name: &[u8], ty: Type) -> Result<bool, Error> {
+        let name = String::from_utf8_lossy(name);
+        let header = match ty {
+            Type::Int(IntType::SizeT) | Type::Int(IntType::PtrDiffT) => "#include <stddef.h>\n",
+            Type::Int(IntType::SSizeT) => "#include <sys/types.h>\n",
+            Type::Int(IntType::UIntPtrT) | Type::Int(IntType::IntPtrT) => "#include <stdint.h>\n",
+            _ => "",
+        };
+        let spelling = match ty {
+            Type::Int(ty) => ty.spelling(),
+            Type::Float => "float",
+            Type::Double => "double",
+            Type::LongDouble => "long double",
+            Type::Str => {
+                // A string literal is an array of `char`, which decays to a
+                // pointer in `_Generic`.
+                return self
+                    .static_assert("", &format!("_Generic(({}), char *: 1, default: 0)", name));
+            }
+        };
+        self.static_assert(
+            header,
+            &format!("_Generic(({}), {}: 1, default: 0)", name, spelling),
+        )
+    }
+
+    fn static_assert(&self, header: &str, condition: &str) -> Result<bool, Error> {
+        // Make sure the condition is valid first, so that other errors are
+        // not mistaken for a failed assertion.
+        match self.compile(header, &format!("({}) || 1", condition))? {
+            Ok(()) => Ok(self.compile(header, condition)?.is_ok()),
+            Err(stderr) => Err(Error::Compile(stderr)),
+        }
+    }
+
+    /// Compile a static assertion after `header`, returning the compiler's
+    /// error output if it fails.
+    fn compile(&self, header: &str, condition: &str) -> io::Result<Result<(), String>> {
+        let source = format!(
+            "{}{}_Static_assert({}, \"\");\n",
+            header, self.prelude, condition
+        );
+        let files = TempFiles::new();
+        fs::write(&files.source, source)?;
+        let output = self
+            .command()
+            .arg("-c")
+            .arg(&files.source)
+            .arg("-o")
+            .arg(&files.output)
+            .output()?;
+        if output.status.success() {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(String::from_utf8_lossy(&output.stderr).into_owned()))
+        }
+    }
+
+    fn check_str(&self, name: &str, value: &[u8]) -> Result<bool, Error> {
+        let source = format!(
+            "{}\n#include <stdio.h>\n\
+             int main(void) {{\n\
+             \tstatic const char value[] = {};\n\
+             \tfwrite(value, 1, sizeof(value) - 1, stdout);\n\
+             \treturn 0;\n\
+             }}\n",
+            self.prelude, name
+        );
+        let files = TempFiles::new();
+        fs::write(&files.source, source)?;
+        let output = self
+            .command()
+            .arg(&files.source)
+            .arg("-o")
+            .arg(&files.output)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Compile(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        let output = Command::new(&files.output).output()?;
+        if !output.status.success() {
+            return Err(Error::Run(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        Ok(output.stdout == value)
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.command);
+        command.args(&self.args);
+        command
+    }
+}
+
+fn int_constant(i: i64) -> String {
+    if i == i64::MIN {
+        format!("-{}LL - 1", i64::MAX)
+    } else {
+        format!("{}LL", i)
+    }
+}
+
+/// Uniquely named temporary files that are removed when dropped.
+struct TempFiles {
+    source: PathBuf,
+    output: PathBuf,
+}
+
+impl TempFiles {
+    fn new() -> TempFiles {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir();
+        let stem = format!(
+            "cexpr-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        TempFiles {
+            source: dir.join(format!("{}.c", stem)),
+            output: dir.join(format!("{}.out", stem)),
+        }
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.source);
+        let _ = fs::remove_file(&self.output);
+    }
+}
@@ -7,46 +7,760 @@
 // except according to those terms.
 //! Evaluating C expressions from tokens.
 //!
-//! Numerical operators are supported. All numerical values are treated as
-//! `i64` or `f64`. Type casting is not supported. `i64` are converted to
-//! `f64` when used in conjunction with a `f64`. Right shifts are always
-//! arithmetic shifts.
+//! Numerical operators are supported. All numerical values are represented
+//! as `i64` or `f64`. `i64` are converted to `f64` when used in conjunction
+//! with a `f64`. Comparisons evaluate to `0` or `1`, and are
+//! left-associative: `3 > 2 > 1` compares `1` to `1`. A character constant
+//! used as an operand is the integer of its type with its code point or raw
+//! value, so `'a' + 1` evaluates to `98`, while `'a'` by itself evaluates to
+//! the character.
 //!
-//! The `sizeof` operator is not supported.
+//! Integer operands have the type C gives them. It follows from the suffixes
+//! and values of literals, casts, the integer promotions and the usual
+//! arithmetic conversions. An identifier has the type its resolver gives it,
+//! see [`Resolve::ty`], or else that of a decimal literal with its value. If
+//! the operands of an operator are converted to an unsigned type, they are
+//! operated on as values of that type, and the result wraps around:
+//! `~0u >> 4` evaluates to `0x0fffffff` and `-1 < 0u` to `0`. Values of
+//! 64-bit unsigned types that don't fit in an `i64` are represented by the
+//! `i64` with the same bits, and converted to floating point as unsigned
+//! values. Results of signed types that overflow wrap around to the width of
+//! the type as well, which is what clang computes for them: `2147483647 + 1`
+//! evaluates to `-2147483648`, unless evaluating with
+//! [`with_checked_arithmetic`](IdentifierParser::with_checked_arithmetic),
+//! which reports such overflows as errors. Right shifts of negative values are
+//! arithmetic shifts. Shifts by a negative count, or by the width of the type
+//! of the shifted value or more, are undefined, and cexpr clamps the count to
+//! that range: `1 << 40` evaluates to `0`, and `-8 >> 40` to `-1`. With checked
+//! arithmetic they are errors too. The type of an expression can be computed
+//! along with its value with
+//! [`expr_with_type`](IdentifierParser::expr_with_type).
 //!
-//! String concatenation is supported, but width prefixes are ignored; all
-//! strings are treated as narrow strings.
+//! Casts to the pointer-sized integer types `size_t`, `ssize_t`, `uintptr_t`,
+//! `intptr_t` and `ptrdiff_t` are supported, using the pointer width of the
+//! [`TargetInfo`] the parser was created with. Casts to the basic integer
+//! types spelled with `signed`, `unsigned`, `char`, `short`, `int` and
+//! `long` are supported too, with the widths given by [`IntType::width`],
+//! and so are casts to the exact-width types such as `uint32_t`, which stand
+//! for the basic type of the same width. A cast to `_Bool` or `bool` converts
+//! any non-zero value to `1`.
+//! The qualifiers `const`, `volatile` and `restrict` are allowed in the type
+//! and don't affect the result; they are only part of the type's name in a
+//! [`Trace`]. The result
+//! of a cast is an ordinary integer, and can be used as an operand of any
+//! operator: `(unsigned)-1 > 0` evaluates to `1`.
+//! Other type casting is not supported.
+//!
+//! The C++ casts `static_cast<type>(expr)` and `const_cast<type>(expr)` are
+//! evaluated like C casts to the same types. `reinterpret_cast` never yields
+//! a constant expression, so it fails with
+//! [`ErrorKind::NotConstant`](crate::ErrorKind::NotConstant).
+//!
+//! `sizeof(type)` is supported for the basic integer types, `_Bool`, `float`,
+//! `double`, pointers, the pointer-sized types above and the exact-width
+//! types such as `uint32_t`, with the sizes given by the [`TargetInfo`]. The
+//! sizes of other types, or different sizes for the same types, can be
+//! provided with [`with_sizeof`](IdentifierParser::with_sizeof). `sizeof`
+//! applied to an expression is not supported. `_Alignof(type)` is supported
+//! for the same types, which are aligned to their size, and for other types
+//! with [`with_alignof`](IdentifierParser::with_alignof). With
+//! [`with_c23`](IdentifierParser::with_c23), it can also be spelled
+//! `alignof(type)`, and the type of a cast, `sizeof` or `alignof` can be
+//! given with `typeof`, as in `(typeof(X))-1`. `__builtin_offsetof` is
+//! supported if the layout of types is provided with
+//! [`with_offsetof`](IdentifierParser::with_offsetof).
+//!
+//! The GNU builtin `__builtin_choose_expr(cond, a, b)` evaluates to `a` if
+//! the integer `cond` is non-zero and to `b` otherwise. As with the
+//! conditional operator, only the selected expression is evaluated, but the
+//! result has the type of the selected expression only: `a` and `b` are not
+//! converted to a common type.
+//!
+//! The conditional operator `?:` is supported. As in C, only the selected
+//! branch is evaluated, so `A ? 1 : (1/0)` evaluates to `1` if `A` is
+//! non-zero. The other branch is still parsed, and contributes to the type of
+//! the result: `1 ? 1 : 2.0` evaluates to `1.0`. Unknown identifiers and type
+//! errors in the branch that is not selected are ignored, unless the parser
+//! is put in strict mode with
+//! [`with_strict_ternary`](IdentifierParser::with_strict_ternary).
+//!
+//! The GNU form `a ?: b` of the conditional operator is supported with
+//! [`with_gnu_extensions`](IdentifierParser::with_gnu_extensions).
+//!
+//! The logical operators `!`, `&&` and `||` are supported, and evaluate to
+//! `0` or `1` as an `int`, or a `bool` in C++. As in C, the right operand of
+//! `&&` and `||` is only evaluated if the left operand doesn't decide the
+//! result, so `defined(A) && A > 2` evaluates to `0` if `A` is not defined.
+//! Otherwise, the right operand is parsed like the branch that is not
+//! selected.
+//!
+//! The comma operator is supported. As in C, the left operand is evaluated
+//! and the expression evaluates to the right operand, so `(FLAG, 0x40)`
+//! evaluates to `0x40` if `FLAG` is known. If the left operand can't be
+//! evaluated, for example because of an unknown identifier or a division by
+//! zero, neither can the expression. In permissive mode, enabled with
+//! [`with_permissive`](IdentifierParser::with_permissive), errors in the left
+//! operand are ignored as in the branch that is not selected, and a static
+//! assertion `_Static_assert(…)` can be the left operand and is skipped.
+//!
+//! String concatenation is supported, for string literals as well as
+//! identifiers with string values, but width prefixes are ignored; all
+//! strings are treated as narrow strings. A string can be subscripted with
+//! an integer, as in `"0123456789abcdef"[10]`, which yields a character. Other
+//! operators can't be applied to strings, because in C that would be
+//! arithmetic on the address of the string. This fails with
+//! [`ErrorKind::StringArithmetic`](crate::ErrorKind::StringArithmetic).
+//!
+//! The stringification operator `#` is supported when applied to a known
+//! identifier, and yields the spelling of a literal with the identifier's
+//! value. In the replacement list of an invoked function-like macro, `#`
+//! applied to a parameter yields the spelling of the argument, so that
+//! `STR(a + 1)` with `#define STR(x) #x` evaluates to `"a + 1"`.
+//! [`stringified`](IdentifierParser::stringified) gives the tokens that
+//! result from applying the operator, spelled as clang spells them.
+//!
+//! The preprocessor operator `defined`, as in `defined(FOO)` or `defined FOO`,
+//! evaluates to `1` if the identifier is known and to `0` otherwise, so that
+//! conditions of `#if` directives can be evaluated. An identifier whose value
+//! is [`EvalResult::Invalid`] is known. Integers in these conditions act as
+//! `intmax_t` or `uintmax_t`, which
+//! [`with_intmax_width`](IdentifierParser::with_intmax_width) selects.
+//!
+//! With [`with_cplusplus`](IdentifierParser::with_cplusplus), `true` and
+//! `false` are literals and comparisons have type `bool`, as in C++.
+//!
+//! Identifiers can be C++ qualified names such as `ns::NAME`, which are
+//! looked up by their full spelling, including any leading `::`.
+//!
+//! Invocations of function-like macros are expanded if the macros are given
+//! with [`with_fn_macros`](IdentifierParser::with_fn_macros). Predefined
+//! macros such as `__FILE__` and `__LINE__` are not known to the parser. To
+//! evaluate a macro such as `__FILE__ ":" STR(__LINE__)`, define `__FILE__`
+//! and `__LINE__` as identifiers.
+//!
+//! Operators and intrinsic functions that C doesn't have, such as the `MOD`
+//! operator of an assembler, can be added with
+//! [`with_operators`](IdentifierParser::with_operators).
+//!
+//! Tokens of kind [`Whitespace`](TokenKind::Whitespace) are skipped wherever
+//! they appear.
 //!
 //! Use the `IdentifierParser` to substitute identifiers found in expressions.
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::BuildHasher;
 use std::num::Wrapping;
 use std::ops::{
     AddAssign, BitAndAssign, BitOrAssign, BitXorAssign, DivAssign, MulAssign, RemAssign, ShlAssign,
     ShrAssign, SubAssign,
 };
 
+use crate::ast::{Expr, Symbols};
 use crate::literal::{self, CChar};
-use crate::token::{Kind as TokenKind, Token};
+use crate::target::{IntType, TargetInfo, Type};
+use crate::token::{primary_spelling, Kind as TokenKind, Span, Token};
 use crate::ToCexprResult;
 use nom::branch::alt;
-use nom::combinator::{complete, map, map_opt};
-use nom::multi::{fold_many0, many0, separated_list0};
-use nom::sequence::{delimited, pair, preceded};
+use nom::combinator::{complete, map, map_opt, opt, verify};
+use nom::multi::{fold_many0, many0, many1, separated_list0, separated_list1};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::*;
 
+/// The values of known identifiers, see [`IdentifierParser::from_resolver`].
+///
+/// This is implemented for maps from names to values, values with their
+/// types or arrays, for functions that look up names, and for pairs of
+/// resolvers, which look up names in the first one and then in the second
+/// one.
+pub trait Resolve {
+    /// The value of the identifier `name`, if it is known.
+    fn get(&self, name: &[u8]) -> Option<EvalResult>;
+
+    /// The type of the identifier `name`, if it is known and has a value. By
+    /// default, an integer has the type of an integer literal without a
+    /// suffix of the same value, the first of `int`, `long` and `long long`
+    /// that can represent it, so that `0xffffffff` is a `long` rather than
+    /// an `unsigned int`.
+    fn ty(&self, name: &[u8]) -> Option<Type> {
+        let _ = name;
+        None
+    }
+
+    /// The array `name`, if it is known. An array can only be subscripted, as
+    /// in `_ctype_table['A']`.
+    fn array(&self, name: &[u8]) -> Option<Array> {
+        let _ = name;
+        None
+    }
+}
+
+/// An array of integers that an identifier stands for, such as a table of
+/// character classes, see [`Resolve::array`].
+///
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use std::num::Wrapping;
+/// use cexpr::assert_full_parse;
+/// use cexpr::expr::{Array, EvalResult, IdentifierParser};
+/// use cexpr::target::IntType;
+/// use cexpr::token::tokenize;
+///
+/// let mut values = HashMap::new();
+/// values.insert(b"_U".to_vec(), EvalResult::Int(Wrapping(1)));
+/// let mut arrays = HashMap::new();
+/// let mut table = vec![0; 256];
+/// table[b'A' as usize] = 1;
+/// arrays.insert(b"_ctype_table".to_vec(), Array { elements: table, ty: IntType::UnsignedShort });
+/// let resolver = (values, arrays);
+/// let parser = IdentifierParser::from_resolver(&resolver);
+/// let tokens = tokenize(b"(_ctype_table['A'] & _U)");
+/// let (_, value) = assert_full_parse(parser.expr(&tokens)).unwrap();
+/// assert_eq!(value, EvalResult::Int(Wrapping(1)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Array {
+    /// The elements, which are converted to `ty` when they are used.
+    pub elements: Vec<i64>,
+    /// The type of the elements.
+    pub ty: IntType,
+}
+
+impl<S: BuildHasher> Resolve for HashMap<Vec<u8>, Array, S> {
+    fn get(&self, _: &[u8]) -> Option<EvalResult> {
+        None
+    }
+
+    fn array(&self, name: &[u8]) -> Option<Array> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+impl<A: Resolve, B: Resolve> Resolve for (A, B) {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        self.0.get(name).or_else(|| self.1.get(name))
+    }
+
+    fn ty(&self, name: &[u8]) -> Option<Type> {
+        match self.0.get(name) {
+            Some(_) => self.0.ty(name),
+            None => self.1.ty(name),
+        }
+    }
+
+    fn array(&self, name: &[u8]) -> Option<Array> {
+        self.0.array(name).or_else(|| self.1.array(name))
+    }
+}
+
+impl<S: BuildHasher> Resolve for HashMap<Vec<u8>, EvalResult, S> {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+/// Values with their types, such as those of
+/// [`Resolution::typed_values`](crate::macros::Resolution::typed_values), so
+/// that an identifier defined as `1u` is an `unsigned int` in expressions.
+impl<S: BuildHasher> Resolve for HashMap<Vec<u8>, (EvalResult, Type), S> {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        HashMap::get(self, name).map(|(value, _)| value.clone())
+    }
+
+    fn ty(&self, name: &[u8]) -> Option<Type> {
+        HashMap::get(self, name).map(|&(_, ty)| ty)
+    }
+}
+
+impl Resolve for BTreeMap<Vec<u8>, EvalResult> {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        BTreeMap::get(self, name).cloned()
+    }
+}
+
+impl<F: Fn(&[u8]) -> Option<EvalResult>> Resolve for F {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        self(name)
+    }
+}
+
+/// Finds the values of macros by evaluating their definitions when they are
+/// first used, so that macros don't need to be evaluated in the order they
+/// are defined in.
+///
+/// `definition` returns the replacement list of the object-like macro with
+/// a name, if there is one. The replacement list is evaluated as an
+/// expression with the same resolver, and the value is remembered. Macros
+/// whose replacement list can't be evaluated, including those that depend on
+/// themselves or on a chain of too many other macros, are unknown
+/// identifiers.
+///
+/// # Example
+/// ```
+/// use std::num::Wrapping;
+/// use cexpr::expr::{EvalResult, IdentifierParser, LazyResolver};
+/// use cexpr::token::tokenize;
+///
+/// let definition = |name: &[u8]| match name {
+///     b"B" => Some(tokenize(b"A * 2")),
+///     b"A" => Some(tokenize(b"3")),
+///     _ => None,
+/// };
+/// let resolver = LazyResolver::new(&definition);
+/// let parser = IdentifierParser::from_resolver(&resolver);
+/// let (_, value) = parser.expr(&tokenize(b"B + 1")).unwrap();
+/// assert_eq!(value, EvalResult::Int(Wrapping(7)));
+/// assert_eq!(resolver.into_values().len(), 2);
+/// ```
+pub struct LazyResolver<'a> {
+    definition: &'a dyn Fn(&[u8]) -> Option<Vec<Token>>,
+    target: TargetInfo,
+    /// The values and types of the macros that were evaluated, or `None` if
+    /// they couldn't be or are being evaluated.
+    #[allow(clippy::type_complexity)]
+    values: RefCell<HashMap<Vec<u8>, Option<(EvalResult, Type)>>>,
+    /// How many macros are being evaluated.
+    depth: Cell<usize>,
+}
+
+impl<'a> LazyResolver<'a> {
+    /// Create a resolver that finds the replacement lists of macros with
+    /// `definition`.
+    pub fn new(definition: &'a dyn Fn(&[u8]) -> Option<Vec<Token>>) -> LazyResolver<'a> {
+        LazyResolver {
+            definition,
+            target: TargetInfo::default(),
+            values: RefCell::default(),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Evaluate the replacement lists for the specified target instead of
+    /// the default one, see [`IdentifierParser::with_target`].
+    pub fn with_target(self, target: TargetInfo) -> LazyResolver<'a> {
+        LazyResolver { target, ..self }
+    }
+
+    /// The values of the macros that were evaluated.
+    pub fn into_values(self) -> HashMap<Vec<u8>, EvalResult> {
+        self.values
+            .into_inner()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?.0)))
+            .collect()
+    }
+
+    /// Evaluate the macro `name` if it wasn't before, and return its value
+    /// and type.
+    fn evaluate(&self, name: &[u8]) -> Option<(EvalResult, Type)> {
+        if let Some(value) = self.values.borrow().get(name) {
+            return value.clone();
+        }
+        // Evaluating a macro that uses another macro nests the parser, so
+        // limit how deeply that happens like other nesting.
+        if self.depth.get() >= MAX_DEPTH {
+            return None;
+        }
+        let tokens = (self.definition)(name)?;
+        self.values.borrow_mut().insert(name.to_vec(), None);
+        self.depth.set(self.depth.get() + 1);
+        let parser = IdentifierParser::from_resolver(self).with_target(self.target);
+        let value = crate::assert_full_parse(parser.expr_with_type(&tokens))
+            .ok()
+            .map(|(_, value)| value);
+        self.depth.set(self.depth.get() - 1);
+        self.values
+            .borrow_mut()
+            .insert(name.to_vec(), value.clone());
+        value
+    }
+}
+
+impl fmt::Debug for LazyResolver<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LazyResolver")
+            .field("definition", &"..")
+            .field("target", &self.target)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl Resolve for LazyResolver<'_> {
+    fn get(&self, name: &[u8]) -> Option<EvalResult> {
+        self.evaluate(name).map(|(value, _)| value)
+    }
+
+    fn ty(&self, name: &[u8]) -> Option<Type> {
+        self.evaluate(name).map(|(_, ty)| ty)
+    }
+}
+
 /// Expression parser/evaluator that supports identifiers.
-#[derive(Debug)]
 pub struct IdentifierParser<'ident> {
-    identifiers: &'ident HashMap<Vec<u8>, EvalResult>,
+    identifiers: &'ident dyn Resolve,
+    normalize: Option<Normalize<'ident>>,
+    target: TargetInfo,
+    strict_ternary: bool,
+    permissive: bool,
+    checked_arithmetic: bool,
+    cplusplus: bool,
+    c23: bool,
+    intmax_width: Option<IntmaxWidth>,
+    gnu_extensions: bool,
+    limits: Limits,
+    offsetof: Option<Offsetof<'ident>>,
+    sizeof: Option<Sizeof<'ident>>,
+    alignof: Option<Sizeof<'ident>>,
+    literals: Option<ParseLiteral<'ident>>,
+    fallback_literals: Option<ParseLiteral<'ident>>,
+    implicit_parentheses: bool,
+    fn_macros: Option<&'ident HashMap<Vec<u8>, FnMacro>>,
+    operators: Option<&'ident Operators>,
+}
+
+type Normalize<'a> = &'a dyn Fn(&[u8]) -> Vec<u8>;
+type Offsetof<'a> = &'a dyn Fn(&[Token], &[Designator<'_>]) -> Option<u64>;
+type Sizeof<'a> = &'a dyn Fn(&[Token]) -> Option<u64>;
+type ParseLiteral<'a> = &'a dyn Fn(&[u8]) -> Option<EvalResult>;
+
+impl<'ident> fmt::Debug for IdentifierParser<'ident> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentifierParser")
+            .field("identifiers", &"..")
+            .field("normalize", &self.normalize.map(|_| ".."))
+            .field("target", &self.target)
+            .field("strict_ternary", &self.strict_ternary)
+            .field("permissive", &self.permissive)
+            .field("checked_arithmetic", &self.checked_arithmetic)
+            .field("cplusplus", &self.cplusplus)
+            .field("c23", &self.c23)
+            .field("intmax_width", &self.intmax_width)
+            .field("gnu_extensions", &self.gnu_extensions)
+            .field("limits", &self.limits)
+            .field("offsetof", &self.offsetof.map(|_| ".."))
+            .field("sizeof", &self.sizeof.map(|_| ".."))
+            .field("alignof", &self.alignof.map(|_| ".."))
+            .field("literals", &self.literals.map(|_| ".."))
+            .field("fallback_literals", &self.fallback_literals.map(|_| ".."))
+            .field("implicit_parentheses", &self.implicit_parentheses)
+            .field("fn_macros", &self.fn_macros)
+            .field("operators", &self.operators)
+            .finish()
+    }
+}
+
+/// The width of `intmax_t` and `uintmax_t` in `#if` arithmetic, see
+/// [`IdentifierParser::with_intmax_width`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IntmaxWidth {
+    /// 32 bits, as in some older compilers.
+    Bits32,
+    /// 64 bits, as in current compilers.
+    Bits64,
+}
+
+/// Limits on the size of the input an `IdentifierParser` accepts.
+///
+/// Input exceeding a limit is rejected with [`ErrorKind::TooLarge`]. This
+/// allows evaluating untrusted input without unbounded memory use. The
+/// default is no limits.
+///
+/// [`ErrorKind::TooLarge`]: crate::ErrorKind::TooLarge
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// The maximum number of tokens in an expression or macro definition.
+    pub max_tokens: Option<usize>,
+    /// The maximum length in bytes of a single literal token.
+    pub max_literal_len: Option<usize>,
 }
 #[derive(Copy, Clone)]
-struct PRef<'a>(&'a IdentifierParser<'a>);
+struct PRef<'a> {
+    parser: &'a IdentifierParser<'a>,
+    log: &'a RefCell<EvalLog>,
+    /// The input is the expansion of an invocation of a function-like macro,
+    /// in which the invocations it contains were already expanded.
+    expanded: bool,
+    /// Only the types of values matter, e.g. when parsing the branch of a
+    /// conditional expression that is not selected.
+    dead: bool,
+    /// How deeply the current subexpression is nested.
+    depth: usize,
+}
+
+/// The maximum number of tokens the invocations of function-like macros in
+/// an expression may expand to, together. Every expansion can double the
+/// number of tokens, so without a limit a few macros could expand to more
+/// tokens than fit in memory.
+const MAX_EXPANSION: usize = 1 << 16;
+
+/// The maximum nesting depth of subexpressions. Parsing is recursive, so
+/// without a limit deeply nested input would overflow the stack. C only
+/// requires compilers to support 63 levels of nested parentheses.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+/// Information recorded while evaluating an expression, for the entry points
+/// that return more than just the result.
+#[derive(Default)]
+struct EvalLog {
+    dependencies: Vec<(Vec<u8>, EvalResult)>,
+    /// Only recorded if `Some`.
+    steps: Option<Vec<Step>>,
+    /// The positions are counted from the end of the input.
+    warnings: Vec<Warning>,
+    /// The types of the operands evaluated so far. Every operator replaces
+    /// the types of its operands by the type of its result.
+    types: Vec<Type>,
+}
+
+impl EvalLog {
+    fn mark(&self) -> (usize, usize, usize, usize) {
+        let steps = self.steps.as_ref().map_or(0, Vec::len);
+        (
+            self.dependencies.len(),
+            steps,
+            self.warnings.len(),
+            self.types.len(),
+        )
+    }
+
+    fn rollback(&mut self, (dependencies, steps, warnings, types): (usize, usize, usize, usize)) {
+        self.dependencies.truncate(dependencies);
+        if let Some(ref mut v) = self.steps {
+            v.truncate(steps);
+        }
+        self.warnings.truncate(warnings);
+        self.types.truncate(types);
+    }
+}
+
+/// Discards anything the inner parser logged if it fails.
+///
+/// Every alternative or repetition that may be backtracked out of must be
+/// wrapped in this, so that the log only reflects the successful parse.
+struct Checkpoint<'a, F> {
+    log: &'a RefCell<EvalLog>,
+    parser: F,
+}
+
+impl<'a, I, O, E, F: Parser<I, O, E>> Parser<I, O, E> for Checkpoint<'a, F> {
+    fn parse(&mut self, input: I) -> IResult<I, O, E> {
+        let mark = self.log.borrow().mark();
+        let res = self.parser.parse(input);
+        if res.is_err() {
+            self.log.borrow_mut().rollback(mark);
+        }
+        res
+    }
+}
 
 /// A shorthand for the type of cexpr expression evaluation results.
 pub type CResult<'a, R> = IResult<&'a [Token], R, crate::Error<&'a [Token]>>;
 
+/// The known identifiers, with their values, that were used to compute a
+/// result.
+pub type Dependencies = HashMap<Vec<u8>, EvalResult>;
+
+/// A valid construct that likely doesn't do what was intended, as reported by
+/// [`expr_with_warnings`](IdentifierParser::expr_with_warnings).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// The index of the token the warning is about.
+    pub position: usize,
+    /// What the warning is about.
+    pub kind: WarningKind,
+}
+
+/// The kinds of [`Warning`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WarningKind {
+    /// Comparisons are chained, as in `a < b < c`. This compares the result
+    /// of `a < b`, which is 0 or 1, to `c`. The position is that of the
+    /// second operator.
+    ChainedComparison,
+    /// An escape sequence in a literal has a value that is out of range,
+    /// and was truncated. The position is that of the literal.
+    EscapeOutOfRange,
+    /// An octal escape sequence in a literal is directly followed by a
+    /// decimal digit, as in `"\0001"`. Octal escapes take at most three
+    /// digits, so this is a NUL byte followed by `1`. The position is that of
+    /// the literal.
+    AmbiguousOctalEscape,
+    /// A negative number is shifted right. Whether this is an arithmetic
+    /// shift is implementation-defined; cexpr always does an arithmetic
+    /// shift. The position is that of the operator.
+    NegativeRightShift,
+    /// An operation on signed integers overflows, as in `2147483647 + 1`,
+    /// which is undefined behavior. cexpr wraps the result around, as clang
+    /// does, unless evaluating with
+    /// [`with_checked_arithmetic`](IdentifierParser::with_checked_arithmetic).
+    /// The position is that of the operator.
+    SignedOverflow,
+    /// A value is shifted by a negative count, or by the width of its type or
+    /// more, as in `1 << 32`, which is undefined behavior. cexpr shifts by 0
+    /// or by the width instead, unless evaluating with
+    /// [`with_checked_arithmetic`](IdentifierParser::with_checked_arithmetic).
+    /// The position is that of the operator.
+    InvalidShift,
+    /// An integer is cast to a signed type that can't represent it. The
+    /// result is implementation-defined; cexpr wraps it around. The position
+    /// is that of the cast.
+    SignedConversion,
+    /// An integer that is negative or over 127 is cast to `char`. Whether
+    /// `char` is signed is implementation-defined, see
+    /// [`TargetInfo::char_signed`]. The position is that of the cast.
+    CharSignedness,
+    /// The value of an enumerator, as computed by
+    /// [`enumerator`](IdentifierParser::enumerator), is not representable
+    /// as an `int`. C requires this; compilers that accept it as an
+    /// extension give the enumeration a wider type. The position is 0.
+    EnumeratorOutOfRange,
+}
+
+impl WarningKind {
+    /// Whether the warning is about a result that depends on
+    /// implementation-defined behavior, and could differ between compilers
+    /// or targets.
+    pub fn is_implementation_defined(self) -> bool {
+        matches!(
+            self,
+            WarningKind::NegativeRightShift
+                | WarningKind::SignedConversion
+                | WarningKind::CharSignedness
+        )
+    }
+}
+
+/// A single evaluation step, as recorded in a [`Trace`].
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub enum Step {
+    /// A unary operator was applied.
+    Unary {
+        op: String,
+        operand: EvalResult,
+        result: EvalResult,
+    },
+    /// A binary operator was applied.
+    Binary {
+        op: String,
+        lhs: EvalResult,
+        rhs: EvalResult,
+        result: EvalResult,
+    },
+    /// A value was converted to another type, either by a cast or implicitly
+    /// when an operator was applied to an integer and a floating point value.
+    Conversion {
+        ty: String,
+        implicit: bool,
+        value: EvalResult,
+        result: EvalResult,
+    },
+}
+
+/// The steps taken to evaluate an expression, in order.
+///
+/// The `Display` implementation renders one step per line.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Trace {
+    steps: Vec<Step>,
+}
+
+impl Trace {
+    /// The recorded steps.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+struct DisplayValue<'a>(&'a EvalResult);
+
+impl<'a> fmt::Display for DisplayValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self.0 {
+            EvalResult::Int(Wrapping(i)) => write!(f, "{}", i),
+            EvalResult::Float(v) => write!(f, "{:?}", v),
+            EvalResult::Char(CChar::Char(c)) => write!(f, "{:?}", c),
+            EvalResult::Char(CChar::Raw(i)) => write!(f, "'\\x{:x}'", i),
+            EvalResult::Str(ref s) => {
+                write!(f, "\"{}\"", String::from_utf8_lossy(s).escape_debug())
+            }
+            EvalResult::Invalid => write!(f, "<invalid>"),
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Step::Unary {
+                ref op,
+                ref operand,
+                ref result,
+            } => write!(
+                f,
+                "{}({}) = {}",
+                op,
+                DisplayValue(operand),
+                DisplayValue(result)
+            ),
+            Step::Binary {
+                ref op,
+                ref lhs,
+                ref rhs,
+                ref result,
+            } => write!(
+                f,
+                "{} {} {} = {}",
+                DisplayValue(lhs),
+                op,
+                DisplayValue(rhs),
+                DisplayValue(result)
+            ),
+            Step::Conversion {
+                ref ty,
+                implicit,
+                ref value,
+                ref result,
+            } => {
+                write!(
+                    f,
+                    "({}){} = {}",
+                    ty,
+                    DisplayValue(value),
+                    DisplayValue(result)
+                )?;
+                if implicit {
+                    write!(f, " (implicit)")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
 /// The result of parsing a literal or evaluating an expression.
+///
+/// An integer is stored as an `i64`, and a value of a 64-bit unsigned type
+/// as the `i64` with the same bits, so `0xFFFFFFFFFFFFFFFFULL` evaluates to
+/// `Int(Wrapping(-1))`. The evaluator keeps track of the types of values, so
+/// that such values are computed exactly, and
+/// [`int_value`](EvalResult::int_value) returns the value an integer stands
+/// for given its type.
 #[derive(Debug, Clone, PartialEq)]
 #[allow(missing_docs)]
 pub enum EvalResult {
@@ -57,6 +771,21 @@ pub enum EvalResult {
     Invalid,
 }
 
+/// Why a value could not be converted to an integer type, see
+/// [`EvalResult::to_target_int`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConversionError {
+    /// The value is not a number or a character, or it is an infinite or NaN
+    /// floating point number.
+    NotNumeric,
+    /// The conversion lost information, because the value was out of range
+    /// of the type or had a fractional part. `value` is the result of the
+    /// conversion, which wrapped around or was rounded toward zero.
+    #[allow(missing_docs)]
+    Truncated { value: i128 },
+}
+
 macro_rules! result_opt (
 	(fn $n:ident: $e:ident -> $t:ty) => (
 		#[allow(dead_code)]
@@ -77,13 +806,104 @@ impl EvalResult {
     result_opt!(fn as_char: Char -> CChar);
     result_opt!(fn as_str: Str -> Vec<u8>);
 
+    /// Convert this value to the integer type `ty` of `target`, like a C cast.
+    ///
+    /// Characters are converted from their code point or raw value. Returns
+    /// an error if the conversion is not possible or not lossless.
+    pub fn to_target_int(&self, ty: IntType, target: &TargetInfo) -> Result<i128, ConversionError> {
+        let (value, exact) = match *self {
+            EvalResult::Int(Wrapping(i)) => (i as i128, true),
+            EvalResult::Float(f) if f.is_finite() => (f as i128, f.fract() == 0.0),
+            EvalResult::Char(CChar::Char(c)) => (c as i128, true),
+            EvalResult::Char(CChar::Raw(c)) => (c as i128, true),
+            _ => return Err(ConversionError::NotNumeric),
+        };
+        let converted = ty.wrap(value, target);
+        if exact && converted == value {
+            Ok(converted)
+        } else {
+            Err(ConversionError::Truncated { value: converted })
+        }
+    }
+
+    /// The value of this integer if it has the type `ty` on `target`, such
+    /// as the one computed by
+    /// [`expr_with_type`](IdentifierParser::expr_with_type).
+    ///
+    /// Returns `None` if this is not an integer or `ty` is not an integer
+    /// type.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use cexpr::expr::IdentifierParser;
+    /// use cexpr::target::TargetInfo;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let idents = HashMap::new();
+    /// let parser = IdentifierParser::new(&idents);
+    /// let (_, (value, ty)) = parser.expr_with_type(&tokenize(b"~0ULL - 1")).unwrap();
+    /// assert_eq!(value.int_value(ty, &TargetInfo::default()), Some(u64::MAX as i128 - 1));
+    /// ```
+    pub fn int_value(&self, ty: Type, target: &TargetInfo) -> Option<i128> {
+        match (self, ty) {
+            (EvalResult::Int(Wrapping(i)), Type::Int(ty)) => Some(ty.wrap((*i).into(), target)),
+            _ => None,
+        }
+    }
+
     #[allow(clippy::wrong_self_convention)]
     fn as_numeric(self) -> Option<EvalResult> {
         match self {
-            EvalResult::Int(_) | EvalResult::Float(_) => Some(self),
+            EvalResult::Int(_) | EvalResult::Float(_) | EvalResult::Char(_) => Some(self),
+            _ => None,
+        }
+    }
+
+    /// The spelling of a C literal with this value.
+    pub(crate) fn spelling(&self) -> Option<Vec<u8>> {
+        match *self {
+            EvalResult::Int(Wrapping(i)) => Some(i.to_string().into_bytes()),
+            EvalResult::Float(f) if f.is_finite() => Some(format!("{:?}", f).into_bytes()),
+            EvalResult::Char(CChar::Raw(i)) => Some(format!("'\\x{:x}'", i).into_bytes()),
+            EvalResult::Char(c) => Some(quote(&Vec::from(c), b'\'')),
+            EvalResult::Str(ref s) => Some(quote(s, b'"')),
             _ => None,
         }
     }
+
+    /// The spelling of a C literal with this value and the type `ty`, which
+    /// has a suffix if the type is unsigned so that the value is exact.
+    fn typed_spelling(&self, ty: Option<Type>, target: &TargetInfo) -> Option<Vec<u8>> {
+        match (self, ty) {
+            (EvalResult::Int(_), Some(Type::Int(int))) => match int.unsigned_suffix(target) {
+                Some(suffix) => {
+                    let value = self.int_value(Type::Int(int), target)?;
+                    Some(format!("{}{}", value, suffix).into_bytes())
+                }
+                None => self.spelling(),
+            },
+            _ => self.spelling(),
+        }
+    }
+}
+
+/// Spell `s` as a literal delimited by `delim`, escaping where necessary.
+fn quote(s: &[u8], delim: u8) -> Vec<u8> {
+    let mut v = vec![delim];
+    for &c in s {
+        match c {
+            b'\\' => v.extend_from_slice(b"\\\\"),
+            b'\n' => v.extend_from_slice(b"\\n"),
+            b'\t' => v.extend_from_slice(b"\\t"),
+            b'\r' => v.extend_from_slice(b"\\r"),
+            _ if c == delim => v.extend_from_slice(&[b'\\', c]),
+            0..=0x1f | 0x7f => v.extend_from_slice(format!("\\{:03o}", c).as_bytes()),
+            _ => v.push(c),
+        }
+    }
+    v.push(delim);
+    v
 }
 
 impl From<Vec<u8>> for EvalResult {
@@ -92,13 +912,29 @@ impl From<Vec<u8>> for EvalResult {
     }
 }
 
+/// The binary operators, from highest to lowest precedence.
+pub(crate) const BINARY_OPERATORS: &[&str] = &[
+    "*", "/", "%", "+", "-", "<<", ">>", "<", ">", "<=", ">=", "==", "!=", "&", "^", "|", "&&",
+    "||",
+];
+
 // ===========================================
 // ============= Clang tokens ================
 // ===========================================
 
+/// Skip any whitespace tokens at the start of `input`.
+pub(crate) fn skip_whitespace(input: &[Token]) -> &[Token] {
+    let n = input
+        .iter()
+        .take_while(|token| token.kind == TokenKind::Whitespace)
+        .count();
+    &input[n..]
+}
+
 macro_rules! exact_token (
 	($k:ident, $c:expr) => ({
         move |input: &[Token]| {
+		let input = skip_whitespace(input);
 		if input.is_empty() {
 			let res: CResult<'_, &[u8]> = Err(crate::nom::Err::Incomplete(Needed::new($c.len())));
 			res
@@ -113,7 +949,8 @@ macro_rules! exact_token (
 	});
 );
 
-fn identifier_token(input: &[Token]) -> CResult<'_, &[u8]> {
+pub(crate) fn identifier_token(input: &[Token]) -> CResult<'_, &[u8]> {
+    let input = skip_whitespace(input);
     if input.is_empty() {
         let res: CResult<'_, &[u8]> = Err(nom::Err::Incomplete(Needed::new(1)));
         res
@@ -126,12 +963,53 @@ fn identifier_token(input: &[Token]) -> CResult<'_, &[u8]> {
     }
 }
 
-fn p(c: &'static str) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
-    exact_token!(Punctuation, c.as_bytes())
+pub(crate) fn keyword_token(input: &[Token]) -> CResult<'_, &[u8]> {
+    let input = skip_whitespace(input);
+    match input.split_first() {
+        None => Err(nom::Err::Incomplete(Needed::new(1))),
+        Some((token, rest)) if token.kind == TokenKind::Keyword => Ok((rest, &token.raw[..])),
+        Some(_) => Err(crate::nom::Err::Error(
+            (input, crate::ErrorKind::TypedToken(TokenKind::Keyword)).into(),
+        )),
+    }
+}
+
+/// Match the punctuator `c`, which may be spelled as a digraph.
+pub(crate) fn p(c: &'static str) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
+    move |input| {
+        let input = skip_whitespace(input);
+        match input.split_first() {
+            None => Err(nom::Err::Incomplete(Needed::new(c.len()))),
+            Some((token, rest))
+                if token.kind == TokenKind::Punctuation
+                    && primary_spelling(&token.raw) == c.as_bytes() =>
+            {
+                Ok((rest, c.as_bytes()))
+            }
+            Some(_) => Err(nom::Err::Error(
+                (
+                    input,
+                    crate::ErrorKind::ExactToken(TokenKind::Punctuation, c.as_bytes()),
+                )
+                    .into(),
+            )),
+        }
+    }
+}
+
+pub(crate) fn identifier(c: &'static str) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
+    exact_token!(Identifier, c.as_bytes())
 }
 
-fn one_of_punctuation(c: &'static [&'static str]) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
+pub(crate) fn keyword(c: &'static str) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
+    exact_token!(Keyword, c.as_bytes())
+}
+
+pub(crate) fn one_of_punctuation(
+    c: &'static [&'static str],
+) -> impl Fn(&[Token]) -> CResult<'_, &[u8]> {
     move |input| {
+        let input = skip_whitespace(input);
         if input.is_empty() {
             let min = c
                 .iter()
@@ -139,10 +1017,11 @@ fn one_of_punctuation(c: &'static [&'static str]) -> impl Fn(&[Token]) -> CResul
                 .min()
                 .expect("at least one option");
             Err(crate::nom::Err::Incomplete(Needed::new(min)))
-        } else if input[0].kind == TokenKind::Punctuation
-            && c.iter().any(|opt| opt.as_bytes() == &input[0].raw[..])
-        {
-            Ok((&input[1..], &input[0].raw[..]))
+        } else if let Some(opt) = c.iter().find(|opt| {
+            input[0].kind == TokenKind::Punctuation
+                && opt.as_bytes() == primary_spelling(&input[0].raw)
+        }) {
+            Ok((&input[1..], opt.as_bytes()))
         } else {
             Err(crate::nom::Err::Error(
                 (
@@ -202,6 +1081,7 @@ impl<'a> DivAssign<&'a EvalResult> for EvalResult {
     fn div_assign(&mut self, rhs: &'a EvalResult) {
         use self::EvalResult::*;
         *self = match (&*self, rhs) {
+            (&Int(_), &Int(Wrapping(0))) => Invalid,
             (&Int(a), &Int(b)) => Int(a / b),
             (&Float(a), &Int(b)) => Float(a / (b.0 as f64)),
             (&Int(a), &Float(b)) => Float(a.0 as f64 / b),
@@ -226,6 +1106,7 @@ impl<'a> RemAssign<&'a EvalResult> for EvalResult {
     fn rem_assign(&mut self, rhs: &'a EvalResult) {
         use self::EvalResult::*;
         *self = match (&*self, rhs) {
+            (&Int(_), &Int(Wrapping(0))) => Invalid,
             (&Int(a), &Int(b)) => Int(a % b),
             (&Float(a), &Int(b)) => Float(a % (b.0 as f64)),
             (&Int(a), &Float(b)) => Float(a.0 as f64 % b),
@@ -276,10 +1157,177 @@ fn unary_op(input: (&[u8], EvalResult)) -> Option<EvalResult> {
         (b'~', Int(i)) => Some(Int(!i)),
         (b'~', Float(_)) => None,
         (b'~', _) => unreachable!("non-numeric unary op"),
+        (b'!', i) => Some(Int(Wrapping(!truthy(&i) as i64))),
         _ => unreachable!("invalid unary op"),
     }
 }
 
+/// The value used in place of anything that fails to evaluate in an
+/// unselected branch, if errors are ignored.
+const PLACEHOLDER: EvalResult = EvalResult::Int(Wrapping(0));
+
+fn compare(op: &[u8], lhs: &EvalResult, rhs: &EvalResult) -> EvalResult {
+    use self::EvalResult::*;
+    let ordering = match (lhs, rhs) {
+        (&Int(a), &Int(b)) => Some(a.cmp(&b)),
+        (&Float(a), &Int(b)) => a.partial_cmp(&(b.0 as f64)),
+        (&Int(a), &Float(b)) => (a.0 as f64).partial_cmp(&b),
+        (&Float(a), &Float(b)) => a.partial_cmp(&b),
+        _ => return Invalid,
+    };
+    comparison(op, ordering)
+}
+
+/// The result of the comparison `op` of operands ordered by `ordering`,
+/// which is `None` if they are unordered.
+fn comparison(op: &[u8], ordering: Option<std::cmp::Ordering>) -> EvalResult {
+    use std::cmp::Ordering::*;
+    let result = match op {
+        b"<" => ordering == Some(Less),
+        b">" => ordering == Some(Greater),
+        b"<=" => ordering == Some(Less) || ordering == Some(Equal),
+        b">=" => ordering == Some(Greater) || ordering == Some(Equal),
+        b"==" => ordering == Some(Equal),
+        b"!=" => ordering != Some(Equal),
+        _ => unreachable!("invalid comparison op"),
+    };
+    EvalResult::Int(Wrapping(result as i64))
+}
+
+/// The floating point value of the integer `i` of type `ty`, where values of
+/// 64-bit unsigned types are represented by the `i64` with the same bits.
+fn int_to_float(ty: Type, i: i64, target: &TargetInfo) -> f64 {
+    match ty {
+        Type::Int(ty) if !ty.is_signed(target) => i as u64 as f64,
+        _ => i as f64,
+    }
+}
+
+/// The value of the character `value` of type `ty` as an integer, from its
+/// code point or raw value, or `value` itself if it is not a character.
+fn char_to_int(value: EvalResult, ty: Type, target: &TargetInfo) -> EvalResult {
+    let c = match value {
+        EvalResult::Char(CChar::Char(c)) => i128::from(u32::from(c)),
+        EvalResult::Char(CChar::Raw(c)) => i128::from(c),
+        value => return value,
+    };
+    let c = match ty {
+        Type::Int(ty) => ty.wrap(c, target),
+        _ => c,
+    };
+    EvalResult::Int(Wrapping(c as i64))
+}
+
+/// Apply the binary operator `op` to the integers `lhs` and `rhs` as values
+/// of the unsigned type `ty`: the operands are converted to `ty`, except for
+/// the count of a shift, and so is the result, which wraps around.
+fn unsigned_op(op: &[u8], ty: IntType, lhs: i64, rhs: i64, target: &TargetInfo) -> EvalResult {
+    let a = ty.wrap(lhs.into(), target);
+    let b = match op {
+        // Shifts by the width of the type or more are undefined.
+        b"<<" | b">>" => i128::from(rhs) & 127,
+        _ => ty.wrap(rhs.into(), target),
+    };
+    let result = match op {
+        b"+" => a + b,
+        b"-" => a - b,
+        b"*" => a.wrapping_mul(b),
+        b"/" | b"%" if b == 0 => return EvalResult::Invalid,
+        b"/" => a / b,
+        b"%" => a % b,
+        b"&" => a & b,
+        b"|" => a | b,
+        b"^" => a ^ b,
+        b"<<" => a << b,
+        b">>" => a >> b,
+        op => return comparison(op, Some(a.cmp(&b))),
+    };
+    EvalResult::Int(Wrapping(ty.wrap(result, target) as i64))
+}
+
+/// The value of the count `count` of a shift, of type `count_ty`, if it is
+/// not valid for shifting a value of type `ty` because it is negative or not
+/// less than the width of `ty`.
+fn invalid_shift(ty: IntType, count_ty: Type, count: i64, target: &TargetInfo) -> Option<i128> {
+    let count = match count_ty.promoted() {
+        Type::Int(count_ty) => count_ty.wrap(count.into(), target),
+        _ => count.into(),
+    };
+    // Values are never wider than 64 bits, so neither are shifts.
+    if (0..i128::from(ty.width(target).min(64))).contains(&count) {
+        None
+    } else {
+        Some(count)
+    }
+}
+
+/// The result of the shift `op` of `lhs`, of type `ty`, by the invalid
+/// `count`, which is clamped to between 0 and the width of `ty`.
+fn clamped_shift(op: &[u8], ty: IntType, lhs: i64, count: i128, target: &TargetInfo) -> EvalResult {
+    let result = if count < 0 {
+        i128::from(lhs)
+    } else if op == b">>" && ty.is_signed(target) && lhs < 0 {
+        -1
+    } else {
+        0
+    };
+    EvalResult::Int(Wrapping(ty.wrap(result, target) as i64))
+}
+
+/// Whether applying the binary operator `op` to the integers `lhs` and `rhs`
+/// of the signed type `ty` overflows, because the result is not
+/// representable in `ty`. Shifts by a negative count or by the width of the
+/// type or more are not considered.
+fn overflows(op: &[u8], ty: IntType, lhs: i64, rhs: i64, target: &TargetInfo) -> bool {
+    let (a, b) = (i128::from(lhs), i128::from(rhs));
+    let exact = match op {
+        b"+" => a + b,
+        b"-" => a - b,
+        b"*" => a * b,
+        // `INT_MIN % -1` is undefined because `INT_MIN / -1` is.
+        b"/" | b"%" if b != 0 => a / b,
+        b"<<" if (0..i128::from(ty.width(target).min(64))).contains(&b) => a << b,
+        _ => return false,
+    };
+    ty.wrap(exact, target) != exact
+}
+
+/// The value of a chain of binary operators, or the operator at which it
+/// failed and why.
+type Folded<'t> = Result<EvalResult, (&'t [Token], crate::ErrorKind)>;
+
+/// The result of parsing a chain of binary operators up to `rest`.
+fn folded<'t>(rest: &'t [Token], acc: Folded<'t>) -> CResult<'t, EvalResult> {
+    match acc {
+        Ok(acc) => Ok((rest, acc)),
+        Err(e) => Err(Err::Failure(e.into())),
+    }
+}
+
+/// The result of parsing a chain of binary operators from `input`, which
+/// follows the first operand, up to `rest`, which must be numeric.
+fn numeric_folded<'t>(
+    input: &'t [Token],
+    rest: &'t [Token],
+    acc: Folded<'t>,
+) -> CResult<'t, EvalResult> {
+    let (rest, acc) = folded(rest, acc)?;
+    match acc.as_numeric() {
+        Some(acc) => Ok((rest, acc)),
+        None => Err(Err::Error((input, crate::ErrorKind::Unsupported).into())),
+    }
+}
+
+fn truthy(value: &EvalResult) -> bool {
+    match *value {
+        EvalResult::Int(Wrapping(i)) => i != 0,
+        EvalResult::Float(f) => f != 0.0,
+        EvalResult::Char(CChar::Char(c)) => c != '\0',
+        EvalResult::Char(CChar::Raw(c)) => c != 0,
+        _ => false,
+    }
+}
+
 fn numeric<I: Clone, E: nom::error::ParseError<I>, F>(
     f: F,
 ) -> impl FnMut(I) -> nom::IResult<I, EvalResult, E>
@@ -292,110 +1340,1309 @@ where
 impl<'a> PRef<'a> {
     fn unary(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
         alt((
-            delimited(p("("), |i| self.numeric_expr(i), p(")")),
-            numeric(|i| self.literal(i)),
-            numeric(|i| self.identifier(i)),
-            map_opt(
-                pair(one_of_punctuation(&["+", "-", "~"][..]), |i| self.unary(i)),
-                unary_op,
-            ),
+            self.checkpoint(|i| self.cast(i)),
+            self.checkpoint(|i| self.named_cast(i)),
+            self.checkpoint(|i| self.offsetof(i)),
+            self.checkpoint(|i| self.sizeof(i)),
+            self.checkpoint(|i| self.choose_expr(i)),
+            self.checkpoint(|i| self.defined(i)),
+            self.checkpoint(|i| self.intrinsic(i)),
+            self.checkpoint(|i| self.custom_prefix(i)),
+            self.checkpoint(delimited(p("("), |i| self.nested(i)?.comma(i), p(")"))),
+            self.checkpoint(numeric(|i| self.literal(i))),
+            self.checkpoint(|i| self.array_element(i)),
+            self.checkpoint(map_opt(
+                |i| self.identifier(i),
+                |v| self.unchecked(v.as_numeric()),
+            )),
+            self.checkpoint(|i| self.unary_operator(i)),
         ))(input)
     }
 
-    fn mul_div_rem(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.unary(input)?;
-        fold_many0(
-            pair(complete(one_of_punctuation(&["*", "/", "%"][..])), |i| {
-                self.unary(i)
-            }),
-            move || acc.clone(),
-            |mut acc, (op, val): (&[u8], EvalResult)| {
-                match op[0] as char {
-                    '*' => acc *= &val,
-                    '/' => acc /= &val,
-                    '%' => acc %= &val,
-                    _ => unreachable!(),
-                };
-                acc
-            },
-        )(input)
+    // +operand, -operand, ~operand, !operand
+    fn unary_operator(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (rest, (op, val)) = pair(one_of_punctuation(&["+", "-", "~", "!"][..]), |i| {
+            self.nested(i)?.unary(i)
+        })(input)?;
+        let ty = self.log.borrow().types.last().copied();
+        let val = char_to_int(val, ty.unwrap_or(Type::Int(IntType::Int)), &self.target);
+        let result = match self.unchecked(unary_op((op, val.clone()))) {
+            Some(result) => result,
+            None => return Err(Err::Error((input, crate::ErrorKind::Unsupported).into())),
+        };
+        let mut ty = Type::Int(IntType::Int);
+        self.typed(|types| {
+            let operand = pop(types);
+            ty = match op {
+                b"!" => Type::Int(self.comparison_type()),
+                _ => operand.promoted(),
+            };
+            types.push(ty);
+        });
+        let result = self.wrap(ty, result);
+        if let (b"-", Type::Int(int), EvalResult::Int(Wrapping(i))) = (op, ty, &val) {
+            let exact = -i128::from(*i);
+            if !self.dead && int.is_signed(&self.target) && int.wrap(exact, &self.target) != exact {
+                self.overflow(input).map_err(|e| Err::Failure(e.into()))?;
+            }
+        }
+        self.trace(|| Step::Unary {
+            op: String::from_utf8_lossy(op).into_owned(),
+            operand: val,
+            result: result.clone(),
+        });
+        Ok((rest, result))
     }
 
-    fn add_sub(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.mul_div_rem(input)?;
-        fold_many0(
-            pair(complete(one_of_punctuation(&["+", "-"][..])), |i| {
-                self.mul_div_rem(i)
-            }),
-            move || acc.clone(),
-            |mut acc, (op, val): (&[u8], EvalResult)| {
-                match op[0] as char {
-                    '+' => acc += &val,
-                    '-' => acc -= &val,
-                    _ => unreachable!(),
-                };
-                acc
-            },
-        )(input)
+    /// Report that the operation at `input` overflowed, which fails with
+    /// [`with_checked_arithmetic`](IdentifierParser::with_checked_arithmetic).
+    fn overflow(self, input: &[Token]) -> Result<(), (&[Token], crate::ErrorKind)> {
+        self.warn(input, WarningKind::SignedOverflow);
+        if self.checked_arithmetic {
+            Err((skip_whitespace(input), crate::ErrorKind::Overflow))
+        } else {
+            Ok(())
+        }
     }
 
-    fn shl_shr(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.add_sub(input)?;
-        numeric(fold_many0(
-            pair(complete(one_of_punctuation(&["<<", ">>"][..])), |i| {
-                self.add_sub(i)
-            }),
-            move || acc.clone(),
-            |mut acc, (op, val): (&[u8], EvalResult)| {
-                match op {
-                    b"<<" => acc <<= &val,
-                    b">>" => acc >>= &val,
-                    _ => unreachable!(),
-                };
-                acc
+    /// Report that the shift at `input` has an invalid count, which fails
+    /// with [`with_checked_arithmetic`](IdentifierParser::with_checked_arithmetic).
+    fn invalid_shift(self, input: &[Token]) -> Result<(), (&[Token], crate::ErrorKind)> {
+        self.warn(input, WarningKind::InvalidShift);
+        if self.checked_arithmetic {
+            Err((skip_whitespace(input), crate::ErrorKind::InvalidShift))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check the result `res` of parsing the right operand `input` of a
+    /// binary operator.
+    ///
+    /// An operator must be followed by an operand, so if that fails, the
+    /// expression fails there instead of ending before the operator.
+    fn operand<'t>(
+        self,
+        input: &'t [Token],
+        res: CResult<'t, EvalResult>,
+    ) -> CResult<'t, EvalResult> {
+        match res {
+            Err(Err::Error(_)) if self.dead().string(input).is_ok() => Err(Err::Failure(
+                (skip_whitespace(input), crate::ErrorKind::StringArithmetic).into(),
+            )),
+            Err(Err::Error(e)) => Err(Err::Failure(e)),
+            res => res,
+        }
+    }
+
+    fn mul_div_rem<'t>(self, input: &'t [Token]) -> CResult<'t, EvalResult> {
+        let (input, acc) = self.unary(input)?;
+        let (rest, acc) = fold_many0(
+            self.checkpoint(|i| {
+                let (rest, (op, val)) = pair(
+                    complete(|i| self.operator(i, &["*", "/", "%"], Precedence::Multiplicative)),
+                    |i| self.operand(i, self.unary(i)),
+                )(i)?;
+                Ok((rest, (i, op, val)))
+            }),
+            move || Ok(acc.clone()),
+            |acc: Folded<'t>, (i, op, val)| {
+                self.infix(i, op, acc?, val, |acc, val| {
+                    self.binary(i, op.0, acc, &val, |acc, val| match op.0[0] as char {
+                        '*' => *acc *= val,
+                        '/' => *acc /= val,
+                        '%' => *acc %= val,
+                        _ => unreachable!(),
+                    })
+                })
             },
-        ))(input)
+        )(input)?;
+        folded(rest, acc)
     }
 
-    fn and(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.shl_shr(input)?;
-        numeric(fold_many0(
-            preceded(complete(p("&")), |i| self.shl_shr(i)),
-            move || acc.clone(),
-            |mut acc, val: EvalResult| {
-                acc &= &val;
-                acc
+    fn add_sub<'t>(self, input: &'t [Token]) -> CResult<'t, EvalResult> {
+        let (input, acc) = self.mul_div_rem(input)?;
+        let (rest, acc) = fold_many0(
+            self.checkpoint(|i| {
+                let (rest, (op, val)) = pair(
+                    complete(|i| self.operator(i, &["+", "-"], Precedence::Additive)),
+                    |i| self.operand(i, self.mul_div_rem(i)),
+                )(i)?;
+                Ok((rest, (i, op, val)))
+            }),
+            move || Ok(acc.clone()),
+            |acc: Folded<'t>, (i, op, val)| {
+                self.infix(i, op, acc?, val, |acc, val| {
+                    self.binary(i, op.0, acc, &val, |acc, val| match op.0[0] as char {
+                        '+' => *acc += val,
+                        '-' => *acc -= val,
+                        _ => unreachable!(),
+                    })
+                })
             },
-        ))(input)
+        )(input)?;
+        folded(rest, acc)
     }
 
-    fn xor(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.and(input)?;
-        numeric(fold_many0(
-            preceded(complete(p("^")), |i| self.and(i)),
-            move || acc.clone(),
-            |mut acc, val: EvalResult| {
-                acc ^= &val;
-                acc
+    fn shl_shr<'t>(self, input: &'t [Token]) -> CResult<'t, EvalResult> {
+        let (input, acc) = self.add_sub(input)?;
+        let (rest, acc) = fold_many0(
+            self.checkpoint(|i| {
+                let (rest, (op, val)) = pair(
+                    complete(|i| self.operator(i, &["<<", ">>"], Precedence::Shift)),
+                    |i| self.operand(i, self.add_sub(i)),
+                )(i)?;
+                Ok((rest, (i, op, val)))
+            }),
+            || Ok(acc.clone()),
+            |acc: Folded<'t>, (i, op, val)| {
+                let acc = acc?;
+                if let ((b">>", None), EvalResult::Int(Wrapping(lhs))) = (op, &acc) {
+                    if *lhs < 0 && !self.dead && !self.unsigned_operand(1) {
+                        self.warn(i, WarningKind::NegativeRightShift);
+                    }
+                }
+                self.infix(i, op, acc, val, |acc, val| {
+                    self.binary(i, op.0, acc, &val, |acc, val| match op.0 {
+                        b"<<" => *acc <<= val,
+                        b">>" => *acc >>= val,
+                        _ => unreachable!(),
+                    })
+                })
             },
-        ))(input)
+        )(input)?;
+        numeric_folded(input, rest, acc)
+    }
+
+    /// A chain of the comparison operators `ops` of `level`, with operands
+    /// parsed by `operand`.
+    fn comparison<'t>(
+        self,
+        input: &'t [Token],
+        ops: &'static [&'static str],
+        level: Precedence,
+        operand: fn(PRef<'a>, &'t [Token]) -> CResult<'t, EvalResult>,
+    ) -> CResult<'t, EvalResult> {
+        let (input, acc) = operand(self, input)?;
+        let mut chained = false;
+        let (rest, acc) = fold_many0(
+            self.checkpoint(|i| {
+                let (rest, (op, val)) = pair(complete(|i| self.operator(i, ops, level)), |i| {
+                    self.operand(i, operand(self, i))
+                })(i)?;
+                if chained {
+                    self.warn(i, WarningKind::ChainedComparison);
+                }
+                chained = true;
+                Ok((rest, (i, op, val)))
+            }),
+            || Ok(acc.clone()),
+            |acc: Folded<'t>, (i, op, val)| {
+                self.infix(i, op, acc?, val, |acc, val| {
+                    self.binary(i, op.0, acc, &val, |acc, val| {
+                        *acc = compare(op.0, acc, val)
+                    })
+                })
+            },
+        )(input)?;
+        numeric_folded(input, rest, acc)
+    }
+
+    fn relational(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.comparison(
+            input,
+            &["<", ">", "<=", ">="],
+            Precedence::Relational,
+            PRef::shl_shr,
+        )
+    }
+
+    fn equality(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.comparison(input, &["==", "!="], Precedence::Equality, PRef::relational)
+    }
+
+    /// A chain of the bitwise operator `op` of `level`, with operands parsed
+    /// by `operand`, applied with `f`.
+    fn bitwise<'t>(
+        self,
+        input: &'t [Token],
+        op: &'static [&'static str],
+        level: Precedence,
+        operand: fn(PRef<'a>, &'t [Token]) -> CResult<'t, EvalResult>,
+        f: fn(&mut EvalResult, &EvalResult),
+    ) -> CResult<'t, EvalResult> {
+        let (input, acc) = operand(self, input)?;
+        let (rest, acc) = fold_many0(
+            self.checkpoint(|i| {
+                let (rest, (op, val)) = pair(complete(|i| self.operator(i, op, level)), |i| {
+                    self.operand(i, operand(self, i))
+                })(i)?;
+                Ok((rest, (i, op, val)))
+            }),
+            || Ok(acc.clone()),
+            |acc: Folded<'t>, (i, op, val)| {
+                self.infix(i, op, acc?, val, |acc, val| {
+                    self.binary(i, op.0, acc, &val, f)
+                })
+            },
+        )(input)?;
+        numeric_folded(input, rest, acc)
+    }
+
+    fn and(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.bitwise(
+            input,
+            &["&"],
+            Precedence::BitAnd,
+            PRef::equality,
+            |acc, val| *acc &= val,
+        )
+    }
+
+    fn xor(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.bitwise(input, &["^"], Precedence::BitXor, PRef::and, |acc, val| {
+            *acc ^= val
+        })
     }
 
     fn or(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        let (input, acc) = self.xor(input)?;
-        numeric(fold_many0(
-            preceded(complete(p("|")), |i| self.xor(i)),
-            move || acc.clone(),
-            |mut acc, val: EvalResult| {
-                acc |= &val;
-                acc
+        self.bitwise(input, &["|"], Precedence::BitOr, PRef::xor, |acc, val| {
+            *acc |= val
+        })
+    }
+
+    /// A chain of the logical operator `op`, with operands parsed by
+    /// `operand`. The right operand is parsed like an unselected branch if
+    /// the left one already decides the result, so that `0 && 1 / 0` has the
+    /// value `0`.
+    fn logical<'t>(
+        self,
+        mut input: &'t [Token],
+        op: &'static str,
+        operand: fn(PRef<'a>, &'t [Token]) -> CResult<'t, EvalResult>,
+    ) -> CResult<'t, EvalResult> {
+        let (rest, mut acc) = operand(self, input)?;
+        input = rest;
+        loop {
+            let rest = match complete(p(op))(input) {
+                Ok((rest, _)) => rest,
+                Err(_) => return Ok((input, acc)),
+            };
+            // `||` is decided by a true left operand, and `&&` by a false one.
+            let decided = truthy(&acc) == (op == "||");
+            let parser = if decided { self.dead() } else { self };
+            let (rest, val) = self.operand(rest, operand(parser, rest))?;
+            self.typed(|types| {
+                pop(types);
+                pop(types);
+                types.push(Type::Int(self.comparison_type()));
+            });
+            let result = EvalResult::Int(Wrapping(if decided {
+                (op == "||") as i64
+            } else {
+                truthy(&val) as i64
+            }));
+            if !decided {
+                self.trace(|| Step::Binary {
+                    op: op.into(),
+                    lhs: acc,
+                    rhs: val,
+                    result: result.clone(),
+                });
+            }
+            acc = result;
+            input = rest;
+        }
+    }
+
+    fn logical_and(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.logical(input, "&&", PRef::or)
+    }
+
+    fn logical_or(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        self.logical(input, "||", PRef::logical_and)
+    }
+
+    fn ternary(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (input, cond) = self.logical_or(input)?;
+        let selected = truthy(&cond);
+        let (then, otherwise) = if selected {
+            (self, self.dead())
+        } else {
+            (self.dead(), self)
+        };
+        let (input, branches) = opt(self.checkpoint(tuple((
+            complete(p("?")),
+            |i| match p(":")(i) {
+                Ok(_) if self.gnu_extensions => Ok((i, None)),
+                _ => map(|i| then.nested(i)?.comma(i), Some)(i),
             },
-        ))(input)
+            p(":"),
+            |i| otherwise.nested(i)?.ternary(i),
+        ))))(input)?;
+        match branches {
+            None => Ok((input, cond)),
+            Some((_, a, _, b)) => {
+                // `cond ?: b` is `cond ? cond : b`, with `cond` evaluated
+                // once.
+                let elvis = a.is_none();
+                let a = a.unwrap_or_else(|| cond.clone());
+                let (value, other) = if selected { (a, b) } else { (b, a) };
+                let mut ty = Type::Int(IntType::Int);
+                let mut value_ty = ty;
+                self.typed(|types| {
+                    let (b, a) = (pop(types), pop(types));
+                    value_ty = if selected { a } else { b };
+                    if !elvis {
+                        pop(types);
+                    }
+                    // In C++, branches of the same type aren't promoted.
+                    ty = if self.cplusplus && a == b {
+                        a
+                    } else {
+                        a.common(b, &self.target)
+                    };
+                    types.push(ty);
+                });
+                // Only a character of either branch stays one.
+                let value = match other {
+                    EvalResult::Char(_) => value,
+                    _ => char_to_int(value, value_ty, &self.target),
+                };
+                let value = self.promote(value, value_ty, &other);
+                Ok((input, self.wrap(ty, value)))
+            }
+        }
     }
 
     #[inline(always)]
     fn numeric_expr(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        self.or(input)
+        self.ternary(input)
+    }
+
+    /// Operands separated by the comma operator, evaluating to the last one.
+    fn comma(self, mut input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        loop {
+            if self.permissive {
+                if let Ok((rest, _)) = pair(static_assertion, complete(p(",")))(input) {
+                    input = rest;
+                    continue;
+                }
+            }
+            let (rest, value) = match self.checkpoint(|i| self.ternary(i)).parse(input) {
+                Ok(res) => res,
+                // In permissive mode, the value of an operand followed by a
+                // comma doesn't matter, so parse it like an unselected branch.
+                Err(e) if self.permissive && !self.dead => {
+                    let left = pair(|i| self.dead().ternary(i), complete(p(",")));
+                    match self.checkpoint(left).parse(input) {
+                        Ok((rest, _)) => {
+                            self.typed(|types| {
+                                pop(types);
+                            });
+                            input = rest;
+                            continue;
+                        }
+                        Err(_) => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
+            };
+            match complete(p(","))(rest) {
+                Ok((rest, _)) => {
+                    self.typed(|types| {
+                        pop(types);
+                    });
+                    input = rest;
+                }
+                Err(_) => return Ok((rest, value)),
+            }
+        }
+    }
+
+    /// Convert the integer `value` to `ty` if that is an integer type, so
+    /// that it wraps around. Signed types wrap around like unsigned ones,
+    /// as clang does when it evaluates an expression that overflows.
+    fn wrap(self, ty: Type, value: EvalResult) -> EvalResult {
+        match (ty, value) {
+            (Type::Int(ty), EvalResult::Int(Wrapping(i))) => {
+                EvalResult::Int(Wrapping(ty.wrap(i.into(), &self.target) as i64))
+            }
+            (_, value) => value,
+        }
+    }
+
+    /// Whether the operand that was evaluated `n` operands before the last
+    /// one has an unsigned type after the integer promotions.
+    fn unsigned_operand(self, n: usize) -> bool {
+        let types = &self.log.borrow().types;
+        matches!(types.iter().rev().nth(n).map(|ty| ty.promoted()), Some(Type::Int(ty)) if !ty.is_signed(&self.target))
+    }
+
+    /// Convert `value` of type `ty` to a floating point value if `other` is
+    /// one.
+    fn promote(self, value: EvalResult, ty: Type, other: &EvalResult) -> EvalResult {
+        match (value, other) {
+            (EvalResult::Int(Wrapping(i)), &EvalResult::Float(_)) => {
+                let result = EvalResult::Float(int_to_float(ty, i, &self.target));
+                self.trace(|| Step::Conversion {
+                    ty: "double".into(),
+                    implicit: true,
+                    value: EvalResult::Int(Wrapping(i)),
+                    result: result.clone(),
+                });
+                result
+            }
+            (value, _) => value,
+        }
+    }
+
+    /// Apply the binary operator `op`, implemented by `f`, to `lhs` and `rhs`.
+    fn binary<'t>(
+        self,
+        input: &'t [Token],
+        op: &[u8],
+        mut lhs: EvalResult,
+        rhs: &EvalResult,
+        f: impl FnOnce(&mut EvalResult, &EvalResult),
+    ) -> Folded<'t> {
+        // The type the operands are converted to, and that of the result
+        // unless this is a comparison.
+        let mut operands = Type::Int(IntType::Int);
+        let (mut lhs_ty, mut rhs_ty) = (operands, operands);
+        self.typed(|types| {
+            let (rhs, lhs) = (pop(types), pop(types));
+            lhs_ty = lhs;
+            rhs_ty = rhs;
+            operands = match op {
+                b"<<" | b">>" => lhs.promoted(),
+                _ => lhs.common(rhs, &self.target),
+            };
+            types.push(match op {
+                b"<" | b">" | b"<=" | b">=" | b"==" | b"!=" => Type::Int(self.comparison_type()),
+                _ => operands,
+            });
+        });
+        lhs = char_to_int(lhs, lhs_ty, &self.target);
+        let rhs = &char_to_int(rhs.clone(), rhs_ty, &self.target);
+        // Only the type of an unselected branch matters, so don't let its
+        // values cause failures such as division by zero.
+        let one = EvalResult::Int(Wrapping(1));
+        let rhs = match *rhs {
+            EvalResult::Int(_) if self.dead => &one,
+            _ => rhs,
+        };
+        let orig = lhs.clone();
+        lhs = self.promote(lhs, lhs_ty, rhs);
+        let rhs = &self.promote(rhs.clone(), rhs_ty, &lhs);
+        // An integer division by zero fails at its operator.
+        if let (b"/" | b"%", Type::Int(_), EvalResult::Int(Wrapping(0))) = (op, operands, rhs) {
+            return Err((skip_whitespace(input), crate::ErrorKind::DivisionByZero));
+        }
+        let invalid_shift = match (operands, rhs) {
+            (Type::Int(ty), &EvalResult::Int(Wrapping(b)))
+                if !self.dead && matches!(op, b"<<" | b">>") =>
+            {
+                invalid_shift(ty, rhs_ty, b, &self.target)
+            }
+            _ => None,
+        };
+        match (operands, &lhs, rhs) {
+            (Type::Int(ty), &EvalResult::Int(Wrapping(a)), _) if invalid_shift.is_some() => {
+                self.invalid_shift(input)?;
+                lhs = clamped_shift(op, ty, a, invalid_shift.unwrap_or(0), &self.target);
+            }
+            (Type::Int(ty), &EvalResult::Int(Wrapping(a)), &EvalResult::Int(Wrapping(b)))
+                if !ty.is_signed(&self.target) =>
+            {
+                lhs = unsigned_op(op, ty, a, b, &self.target);
+            }
+            (Type::Int(ty), &EvalResult::Int(Wrapping(a)), &EvalResult::Int(Wrapping(b)))
+                if !self.dead && overflows(op, ty, a, b, &self.target) =>
+            {
+                f(&mut lhs, rhs);
+                lhs = self.wrap(operands, lhs);
+                self.overflow(input)?;
+            }
+            _ => {
+                f(&mut lhs, rhs);
+                lhs = self.wrap(operands, lhs);
+            }
+        }
+        if lhs == EvalResult::Invalid && self.lenient() {
+            lhs = PLACEHOLDER;
+        }
+        self.trace(|| Step::Binary {
+            op: String::from_utf8_lossy(op).into_owned(),
+            lhs: orig,
+            rhs: rhs.clone(),
+            result: lhs.clone(),
+        });
+        Ok(lhs)
+    }
+}
+
+// ===================================
+// ============= Casts ===============
+// ===================================
+
+/// Convert `value` to `ty` like a cast.
+fn convert(ty: IntType, value: EvalResult, target: &TargetInfo) -> Option<EvalResult> {
+    let value = match char_to_int(value, Type::Int(IntType::Int), target) {
+        EvalResult::Int(Wrapping(i)) => i as i128,
+        EvalResult::Float(f) => f as i128,
+        _ => return None,
+    };
+    Some(EvalResult::Int(Wrapping(ty.wrap(value, target) as i64)))
+}
+
+/// A basic integer type spelled with the keywords in `words`, such as
+/// `unsigned long int`, in any order.
+fn basic_type(words: &[&[u8]]) -> Option<IntType> {
+    if words == [b"_Bool"] {
+        return Some(IntType::Bool);
+    }
+    let (mut signed, mut unsigned, mut char, mut short, mut int, mut long) = (0, 0, 0, 0, 0, 0);
+    for word in words {
+        match *word {
+            b"signed" => signed += 1,
+            b"unsigned" => unsigned += 1,
+            b"char" => char += 1,
+            b"short" => short += 1,
+            b"int" => int += 1,
+            b"long" => long += 1,
+            _ => return None,
+        }
+    }
+    let sizes = char + short + (long > 0) as u32;
+    if signed + unsigned > 1 || int > 1 || char + int > 1 || sizes > 1 || long > 2 {
+        return None;
+    }
+    Some(match (char, short, long, signed, unsigned) {
+        (1, _, _, 0, 0) => IntType::Char,
+        (1, _, _, 1, _) => IntType::SignedChar,
+        (1, _, _, _, _) => IntType::UnsignedChar,
+        (_, 1, _, _, 0) => IntType::Short,
+        (_, 1, _, _, _) => IntType::UnsignedShort,
+        (_, _, 1, _, 0) => IntType::Long,
+        (_, _, 1, _, _) => IntType::UnsignedLong,
+        (_, _, 2, _, 0) => IntType::LongLong,
+        (_, _, 2, _, _) => IntType::UnsignedLongLong,
+        (_, _, _, _, 0) => IntType::Int,
+        _ => IntType::UnsignedInt,
+    })
+}
+
+/// The integer type a standard `typedef` such as `size_t` stands for.
+///
+/// The exact-width types stand for the basic type of the same width, and
+/// `bool` stands for `_Bool`.
+fn typedef_type(name: &[u8]) -> Option<IntType> {
+    Some(match name {
+        b"size_t" => IntType::SizeT,
+        b"ssize_t" => IntType::SSizeT,
+        b"uintptr_t" => IntType::UIntPtrT,
+        b"intptr_t" => IntType::IntPtrT,
+        b"ptrdiff_t" => IntType::PtrDiffT,
+        b"bool" => IntType::Bool,
+        b"int8_t" => IntType::SignedChar,
+        b"uint8_t" => IntType::UnsignedChar,
+        b"int16_t" => IntType::Short,
+        b"uint16_t" => IntType::UnsignedShort,
+        b"int32_t" => IntType::Int,
+        b"uint32_t" => IntType::UnsignedInt,
+        b"int64_t" => IntType::LongLong,
+        b"uint64_t" => IntType::UnsignedLongLong,
+        _ => return None,
+    })
+}
+
+fn is_qualifier(word: &[u8]) -> bool {
+    matches!(word, b"const" | b"volatile" | b"restrict")
+}
+
+/// Whether a type name can start with `token`: a keyword such as `int` or
+/// `struct`, or a standard `typedef` such as `size_t`.
+fn starts_type_name(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Keyword => matches!(
+            &token.raw[..],
+            b"char"
+                | b"const"
+                | b"double"
+                | b"enum"
+                | b"float"
+                | b"int"
+                | b"long"
+                | b"restrict"
+                | b"short"
+                | b"signed"
+                | b"struct"
+                | b"union"
+                | b"unsigned"
+                | b"void"
+                | b"volatile"
+                | b"_Atomic"
+                | b"_Bool"
+                | b"_Complex"
+        ),
+        TokenKind::Identifier => typedef_type(&token.raw).is_some(),
+        _ => false,
+    }
+}
+
+/// The name of a type spelled with `words`, including any qualifiers.
+fn type_spelling(words: &[&[u8]]) -> String {
+    words
+        .iter()
+        .map(|word| String::from_utf8_lossy(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A type that casts are supported to, with its spelling.
+pub(crate) fn cast_type(input: &[Token]) -> CResult<'_, (String, IntType)> {
+    let qualifiers = || many0(verify(keyword_token, |word: &[u8]| is_qualifier(word)));
+    alt((
+        map_opt(
+            tuple((qualifiers(), identifier_token, qualifiers())),
+            |(before, ident, after)| {
+                let ty = typedef_type(ident)?;
+                let words: Vec<&[u8]> =
+                    before.into_iter().chain(Some(ident)).chain(after).collect();
+                Some((type_spelling(&words), ty))
+            },
+        ),
+        map_opt(many1(keyword_token), |words| {
+            let unqualified: Vec<&[u8]> = words
+                .iter()
+                .copied()
+                .filter(|word| !is_qualifier(word))
+                .collect();
+            if unqualified.is_empty() {
+                return None;
+            }
+            let ty = basic_type(&unqualified)?;
+            Some((type_spelling(&words), ty))
+        }),
+    ))(input)
+}
+
+impl<'a> PRef<'a> {
+    fn cast(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let typeof_cast = |i| {
+            let (rest, mut ty) = self.typeof_type(i)?;
+            // The closing parenthesis tells `cast_type` where the type ends.
+            ty.push((TokenKind::Punctuation, &b")"[..]).into());
+            let cast = match terminated(cast_type, p(")"))(&ty) {
+                Ok(([], cast)) => Some(cast),
+                _ => None,
+            };
+            cast.map(|cast| (rest, cast))
+                .ok_or_else(|| Err::Error((i, crate::ErrorKind::Unsupported).into()))
+        };
+        map_opt(
+            pair(
+                delimited(p("("), alt((cast_type, typeof_cast)), p(")")),
+                |i| self.nested(i)?.unary(i),
+            ),
+            |((name, ty), val)| self.convert_explicitly(input, name, ty, val),
+        )(input)
+    }
+
+    // static_cast<type>(expr), const_cast<type>(expr), reinterpret_cast<type>(expr)
+    fn named_cast(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (rest, (op, (name, ty), val)) = tuple((
+            alt((
+                identifier("static_cast"),
+                identifier("const_cast"),
+                identifier("reinterpret_cast"),
+            )),
+            delimited(p("<"), cast_type, p(">")),
+            delimited(p("("), |i| self.nested(i)?.comma(i), p(")")),
+        ))(input)?;
+        if op == b"reinterpret_cast" && !self.lenient() {
+            return Err(Err::Failure((input, crate::ErrorKind::NotConstant).into()));
+        }
+        match self.convert_explicitly(input, name, ty, val) {
+            Some(result) => Ok((rest, result)),
+            None => Err(Err::Error((input, crate::ErrorKind::Unsupported).into())),
+        }
+    }
+
+    /// Convert the operand `value` of a cast at `input` to the type `ty`,
+    /// which is spelled `name`.
+    fn convert_explicitly(
+        self,
+        input: &[Token],
+        name: String,
+        ty: IntType,
+        val: EvalResult,
+    ) -> Option<EvalResult> {
+        let result = self.unchecked(convert(ty, val.clone(), &self.target))?;
+        self.typed(|types| {
+            pop(types);
+            types.push(Type::Int(ty));
+        });
+        match (&val, &result) {
+            (EvalResult::Int(from), EvalResult::Int(to)) if !self.dead => {
+                if ty == IntType::Char && !(0..=127).contains(&from.0) {
+                    self.warn(input, WarningKind::CharSignedness);
+                } else if ty.is_signed(&self.target) && from != to {
+                    self.warn(input, WarningKind::SignedConversion);
+                }
+            }
+            _ => {}
+        }
+        self.trace(|| Step::Conversion {
+            ty: name,
+            implicit: false,
+            value: val,
+            result: result.clone(),
+        });
+        Some(result)
+    }
+}
+
+// ======================================
+// ========= Custom operators ===========
+// ======================================
+
+/// The precedence level of a custom infix operator, named after the C
+/// operators of the same level, see [`Operators::insert_infix`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Precedence {
+    /// `*`, `/` and `%`.
+    Multiplicative,
+    /// `+` and `-`.
+    Additive,
+    /// `<<` and `>>`.
+    Shift,
+    /// `<`, `>`, `<=` and `>=`.
+    Relational,
+    /// `==` and `!=`.
+    Equality,
+    /// `&`.
+    BitAnd,
+    /// `^`.
+    BitXor,
+    /// `|`.
+    BitOr,
+}
+
+type Fold = Box<dyn Fn(&[EvalResult]) -> Option<EvalResult>>;
+
+/// Operators and intrinsic functions that C doesn't have, which can be used
+/// in expressions evaluated by an [`IdentifierParser`] with
+/// [`with_operators`](IdentifierParser::with_operators).
+///
+/// An operator is a single punctuation or identifier token, such as `MOD`
+/// or `@`. Operators spelled like one of C's operators are only used where
+/// C's operator doesn't apply: `-` can't be made an infix operator, but it
+/// can be an infix operator of another precedence level than C's `-`.
+///
+/// The value of each operator or intrinsic is computed by a fold function,
+/// which receives the values of the operands and returns `None` if it can't
+/// be applied to them, in which case the expression fails with
+/// [`ErrorKind::Unsupported`](crate::ErrorKind::Unsupported). The result
+/// has the type of a literal with the same value.
+///
+/// The parsers in [`ast`](crate::ast) don't know custom operators, so they
+/// can't be used in [`partial_expr`](IdentifierParser::partial_expr) or
+/// [`symbolic_expr`](IdentifierParser::symbolic_expr).
+///
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use std::num::Wrapping;
+/// use cexpr::expr::{EvalResult, IdentifierParser, Operators, Precedence};
+/// use cexpr::token::tokenize;
+///
+/// let mut operators = Operators::new();
+/// operators.insert_infix(b"MOD", Precedence::Multiplicative, |a, b| match (a, b) {
+///     (EvalResult::Int(a), EvalResult::Int(b)) if b.0 != 0 => {
+///         Some(EvalResult::Int(Wrapping(a.0.rem_euclid(b.0))))
+///     }
+///     _ => None,
+/// });
+/// operators.insert_intrinsic(b"HI", |args| match args {
+///     [EvalResult::Int(Wrapping(i))] => Some(EvalResult::Int(Wrapping((i >> 16) & 0xffff))),
+///     _ => None,
+/// });
+/// let idents = HashMap::new();
+/// let parser = IdentifierParser::new(&idents).with_operators(&operators);
+/// let (_, value) = parser.expr(&tokenize(b"HI(0x12345678) + -7 MOD 4")).unwrap();
+/// assert_eq!(value, EvalResult::Int(Wrapping(0x1235)));
+/// ```
+#[derive(Default)]
+pub struct Operators {
+    prefix: HashMap<Vec<u8>, Fold>,
+    infix: HashMap<Vec<u8>, (Precedence, Fold)>,
+    intrinsics: HashMap<Vec<u8>, Fold>,
+}
+
+impl fmt::Debug for Operators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |map: Vec<&Vec<u8>>| {
+            let mut names: Vec<_> = map
+                .into_iter()
+                .map(|n| String::from_utf8_lossy(n).into_owned())
+                .collect();
+            names.sort_unstable();
+            names
+        };
+        f.debug_struct("Operators")
+            .field("prefix", &names(self.prefix.keys().collect()))
+            .field("infix", &names(self.infix.keys().collect()))
+            .field("intrinsics", &names(self.intrinsics.keys().collect()))
+            .finish()
+    }
+}
+
+impl Operators {
+    /// No custom operators or intrinsics.
+    pub fn new() -> Operators {
+        Operators::default()
+    }
+
+    /// Add the prefix operator `name`, as in `name x`, which binds like the
+    /// unary operators of C.
+    pub fn insert_prefix(
+        &mut self,
+        name: &[u8],
+        fold: impl Fn(&EvalResult) -> Option<EvalResult> + 'static,
+    ) {
+        let fold: Fold = Box::new(move |operands| fold(&operands[0]));
+        self.prefix.insert(name.to_vec(), fold);
+    }
+
+    /// Add the infix operator `name`, as in `x name y`, which binds like the
+    /// C operators of `precedence` and is left associative like them.
+    pub fn insert_infix(
+        &mut self,
+        name: &[u8],
+        precedence: Precedence,
+        fold: impl Fn(&EvalResult, &EvalResult) -> Option<EvalResult> + 'static,
+    ) {
+        let fold: Fold = Box::new(move |operands| fold(&operands[0], &operands[1]));
+        self.infix.insert(name.to_vec(), (precedence, fold));
+    }
+
+    /// Add the intrinsic function `name`, as in `name(x, y)`, which receives
+    /// the values of its arguments. The arguments must be numeric. An intrinsic
+    /// takes precedence over identifiers and function-like macros of the same
+    /// name.
+    pub fn insert_intrinsic(
+        &mut self,
+        name: &[u8],
+        fold: impl Fn(&[EvalResult]) -> Option<EvalResult> + 'static,
+    ) {
+        self.intrinsics.insert(name.to_vec(), Box::new(fold));
+    }
+}
+
+/// The punctuation or identifier token at the start of `input`, if `find`
+/// finds something by its spelling.
+fn custom_token<T>(
+    input: &[Token],
+    find: impl FnOnce(&[u8]) -> Option<T>,
+) -> Option<(&[Token], &[u8], T)> {
+    let input = skip_whitespace(input);
+    let token = input.first()?;
+    match token.kind {
+        TokenKind::Punctuation | TokenKind::Identifier => {
+            Some((&input[1..], &token.raw[..], find(&token.raw)?))
+        }
+        _ => None,
+    }
+}
+
+impl<'a> PRef<'a> {
+    /// One of the C operators `ops`, or a custom infix operator of `level`
+    /// with its fold function.
+    fn operator<'t>(
+        self,
+        input: &'t [Token],
+        ops: &'static [&'static str],
+        level: Precedence,
+    ) -> CResult<'t, (&'t [u8], Option<&'a Fold>)> {
+        let err = match one_of_punctuation(ops)(input) {
+            Ok((rest, op)) => return Ok((rest, (op, None))),
+            Err(Err::Error(e)) => e,
+            Err(e) => return Err(e),
+        };
+        let operators = match self.operators {
+            Some(operators) => operators,
+            None => return Err(Err::Error(err)),
+        };
+        let found = custom_token(input, |name| match operators.infix.get(name) {
+            Some((precedence, fold)) if *precedence == level => Some(fold),
+            _ => None,
+        });
+        match found {
+            Some((rest, op, fold)) => Ok((rest, (op, Some(fold)))),
+            None => Err(Err::Error(err)),
+        }
+    }
+
+    /// Apply the fold function `fold` of the custom operator or intrinsic at
+    /// `input` to `operands`, failing at `input` if it can't be applied.
+    fn fold<'t>(
+        self,
+        input: &'t [Token],
+        fold: &Fold,
+        operands: &[EvalResult],
+    ) -> Result<EvalResult, (&'t [Token], crate::ErrorKind)> {
+        let result = self
+            .unchecked(fold(operands))
+            .ok_or((skip_whitespace(input), crate::ErrorKind::Unsupported))?;
+        self.typed(|types| {
+            for _ in operands {
+                pop(types);
+            }
+            types.push(value_type(&result, &self.target));
+        });
+        Ok(result)
+    }
+
+    /// Apply the infix operator `op` at `input`, with `fold` if it is a
+    /// custom operator and with `builtin` otherwise.
+    fn infix<'t>(
+        self,
+        input: &'t [Token],
+        (op, fold): (&[u8], Option<&Fold>),
+        lhs: EvalResult,
+        rhs: EvalResult,
+        builtin: impl FnOnce(EvalResult, EvalResult) -> Folded<'t>,
+    ) -> Folded<'t> {
+        let fold = match fold {
+            Some(fold) => fold,
+            None => return builtin(lhs, rhs),
+        };
+        let operands = [lhs, rhs];
+        let result = self.fold(input, fold, &operands)?;
+        let [lhs, rhs] = operands;
+        self.trace(|| Step::Binary {
+            op: String::from_utf8_lossy(op).into_owned(),
+            lhs,
+            rhs,
+            result: result.clone(),
+        });
+        Ok(result)
+    }
+
+    // op operand, for a custom prefix operator
+    fn custom_prefix(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let operators = self.operators;
+        let found = operators.and_then(|ops| {
+            custom_token(input, |name| match name {
+                b"+" | b"-" | b"~" | b"!" => None,
+                _ => ops.prefix.get(name),
+            })
+        });
+        let (rest, op, fold) = match found {
+            Some(found) => found,
+            None => {
+                return Err(Err::Error(
+                    (skip_whitespace(input), crate::ErrorKind::Unsupported).into(),
+                ))
+            }
+        };
+        let (rest, operand) = self.operand(rest, self.nested(input)?.unary(rest))?;
+        let operands = [operand];
+        let result = self
+            .fold(input, fold, &operands)
+            .map_err(|e| Err::Failure(e.into()))?;
+        let [operand] = operands;
+        self.trace(|| Step::Unary {
+            op: String::from_utf8_lossy(op).into_owned(),
+            operand,
+            result: result.clone(),
+        });
+        Ok((rest, result))
+    }
+
+    // name(args), for an intrinsic
+    fn intrinsic(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let operators = self.operators;
+        let found = operators.and_then(|ops| custom_token(input, |name| ops.intrinsics.get(name)));
+        let (rest, fold) = match found {
+            Some((rest, _, fold)) if p("(")(rest).is_ok() => (rest, fold),
+            _ => {
+                return Err(Err::Error(
+                    (skip_whitespace(input), crate::ErrorKind::Unsupported).into(),
+                ))
+            }
+        };
+        let inner = self.nested(input)?;
+        let (rest, args) = preceded(
+            p("("),
+            alt((
+                map(p(")"), |_| vec![]),
+                terminated(
+                    separated_list1(p(","), |i| self.operand(i, inner.ternary(i))),
+                    p(")"),
+                ),
+            )),
+        )(rest)?;
+        let result = self
+            .fold(input, fold, &args)
+            .map_err(|e| Err::Failure(e.into()))?;
+        Ok((rest, result))
+    }
+}
+
+// ======================================
+// ============= Builtins ===============
+// ======================================
+
+/// A step in the member designator of `__builtin_offsetof`, see
+/// [`IdentifierParser::with_offsetof`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Designator<'a> {
+    /// `.member`, or the member the designator starts with.
+    Member(&'a [u8]),
+    /// `[index]`.
+    Index(i64),
+}
+
+/// The tokens of a type name, up to the next `,` or `)` that is not in
+/// parentheses.
+pub(crate) fn type_name(input: &[Token]) -> CResult<'_, &[Token]> {
+    let input = skip_whitespace(input);
+    let mut depth = 0usize;
+    for (n, token) in input.iter().enumerate() {
+        if token.kind != TokenKind::Punctuation {
+            continue;
+        }
+        match &token.raw[..] {
+            b"(" => depth += 1,
+            b")" if depth > 0 => depth -= 1,
+            b"," | b")" if n == 0 => {
+                return Err(Err::Error(
+                    (input, crate::ErrorKind::TypedToken(TokenKind::Identifier)).into(),
+                ))
+            }
+            b"," | b")" => return Ok((&input[n..], &input[..n])),
+            _ => {}
+        }
+    }
+    Err(Err::Incomplete(Needed::new(1)))
+}
+
+/// The size in bytes of the type spelled with `ty` on `target`, if it is a
+/// type whose size is described by the target.
+fn builtin_size(ty: &[Token], target: &TargetInfo) -> Option<u64> {
+    let ty: Vec<&Token> = ty
+        .iter()
+        .filter(|token| !(token.kind == TokenKind::Keyword && is_qualifier(&token.raw)))
+        .collect();
+    let bits = match &ty[..] {
+        [] => return None,
+        [rest @ .., last] if last.kind == TokenKind::Punctuation => match &last.raw[..] {
+            b"*" if !rest.is_empty() => target.pointer_width,
+            _ => return None,
+        },
+        [token] if token.kind == TokenKind::Identifier => typedef_type(&token.raw)?.width(target),
+        [token] if token.kind == TokenKind::Keyword && &token.raw[..] == b"float" => 32,
+        [token] if token.kind == TokenKind::Keyword && &token.raw[..] == b"double" => 64,
+        words if words.iter().all(|token| token.kind == TokenKind::Keyword) => {
+            let words: Vec<&[u8]> = words.iter().map(|token| &token.raw[..]).collect();
+            basic_type(&words)?.width(target)
+        }
+        _ => return None,
+    };
+    Some(u64::from(bits / 8))
+}
+
+impl<'a> PRef<'a> {
+    fn designator(self, input: &'_ [Token]) -> CResult<'_, Designator<'_>> {
+        alt((
+            map(preceded(p("."), identifier_token), Designator::Member),
+            map_opt(
+                delimited(p("["), |i| self.nested(i)?.numeric_expr(i), p("]")),
+                |index| match index {
+                    EvalResult::Int(Wrapping(i)) => {
+                        self.typed(|types| {
+                            pop(types);
+                        });
+                        Some(Designator::Index(i))
+                    }
+                    _ => None,
+                },
+            ),
+        ))(input)
+    }
+
+    // __builtin_offsetof(type, member.designator[index])
+    fn offsetof(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (rest, (ty, first, designators)) = preceded(
+            identifier("__builtin_offsetof"),
+            delimited(
+                p("("),
+                tuple((
+                    type_name,
+                    preceded(p(","), identifier_token),
+                    many0(self.checkpoint(complete(|i| self.designator(i)))),
+                )),
+                p(")"),
+            ),
+        )(input)?;
+        let mut path = vec![Designator::Member(first)];
+        path.extend(designators);
+        let ty: Vec<Token> = ty
+            .iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .cloned()
+            .collect();
+        let offset = self
+            .offsetof
+            .and_then(|layout| layout(&ty, &path))
+            .map(|offset| EvalResult::Int(Wrapping(offset as i64)));
+        match self.unchecked(offset) {
+            Some(offset) => {
+                self.typed(|types| types.push(Type::Int(IntType::SizeT)));
+                Ok((rest, offset))
+            }
+            None => Err(Err::Failure(
+                (input, crate::ErrorKind::UnknownLayout).into(),
+            )),
+        }
+    }
+
+    // sizeof(type), _Alignof(type), alignof(type)
+    fn sizeof(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let type_name = map(type_name, |ty| {
+            ty.iter()
+                .filter(|token| token.kind != TokenKind::Whitespace)
+                .cloned()
+                .collect()
+        });
+        let (rest, (op, ty)) = pair(
+            alt((
+                keyword("sizeof"),
+                keyword("_Alignof"),
+                verify(identifier("alignof"), |_: &[u8]| self.c23 || self.cplusplus),
+            )),
+            delimited(p("("), alt((|i| self.typeof_type(i), type_name)), p(")")),
+        )(input)?;
+        let builtin = builtin_size(&ty, &self.target);
+        let size = || self.sizeof.and_then(|layout| layout(&ty)).or(builtin);
+        let size = match op {
+            b"sizeof" => size(),
+            // The types cexpr knows are aligned to their size.
+            _ => self
+                .alignof
+                .and_then(|layout| layout(&ty))
+                .or_else(|| builtin.and_then(|_| size())),
+        }
+        .map(|size| EvalResult::Int(Wrapping(size as i64)));
+        match self.unchecked(size) {
+            Some(size) => {
+                self.typed(|types| types.push(Type::Int(IntType::SizeT)));
+                Ok((rest, size))
+            }
+            None => Err(Err::Failure(
+                (input, crate::ErrorKind::UnknownLayout).into(),
+            )),
+        }
+    }
+
+    // typeof(type), typeof(expr), typeof_unqual(type), typeof_unqual(expr)
+    /// The tokens of the type that `typeof` at `input` names.
+    fn typeof_type(self, input: &'_ [Token]) -> CResult<'_, Vec<Token>> {
+        if !self.c23 {
+            return Err(Err::Error((input, crate::ErrorKind::Unsupported).into()));
+        }
+        let (operand, op) = terminated(
+            alt((identifier("typeof"), identifier("typeof_unqual"))),
+            p("("),
+        )(input)?;
+        let operand = skip_whitespace(operand);
+        let (rest, mut ty) = match operand.first() {
+            Some(token) if starts_type_name(token) => {
+                let (rest, ty) = type_name(operand)?;
+                let ty: Vec<Token> = ty.iter().filter(|t| !is_whitespace(t)).cloned().collect();
+                (rest, ty)
+            }
+            _ => {
+                let (rest, _) = self.dead().nested(operand)?.comma(operand)?;
+                let mut ty = None;
+                self.typed(|types| ty = Some(pop(types)));
+                match ty.and_then(Type::spelling) {
+                    Some(spelling) => (rest, crate::token::tokenize(spelling.as_bytes())),
+                    None => {
+                        return Err(Err::Failure(
+                            (operand, crate::ErrorKind::Unsupported).into(),
+                        ))
+                    }
+                }
+            }
+        };
+        if op == b"typeof_unqual" {
+            // Only the qualifiers of the type itself, not those of the type
+            // a pointer points to.
+            let start = ty
+                .iter()
+                .rposition(|t| &t.raw[..] == b"*")
+                .map_or(0, |i| i + 1);
+            let unqualified: Vec<Token> = ty
+                .split_off(start)
+                .into_iter()
+                .filter(|t| !(t.kind == TokenKind::Keyword && is_qualifier(&t.raw)))
+                .collect();
+            ty.extend(unqualified);
+        }
+        let (rest, _) = p(")")(rest)?;
+        Ok((rest, ty))
+    }
+
+    // __builtin_choose_expr(const_exp, exp1, exp2)
+    fn choose_expr(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (input, selected) = preceded(
+            pair(identifier("__builtin_choose_expr"), p("(")),
+            map_opt(
+                |i| self.nested(i)?.numeric_expr(i),
+                |cond| match cond {
+                    EvalResult::Int(Wrapping(i)) => Some(i != 0),
+                    _ => None,
+                },
+            ),
+        )(input)?;
+        let (then, otherwise) = if selected {
+            (self, self.dead())
+        } else {
+            (self.dead(), self)
+        };
+        let (input, (a, b)) = pair(
+            preceded(p(","), |i| then.nested(i)?.numeric_expr(i)),
+            delimited(p(","), |i| otherwise.nested(i)?.numeric_expr(i), p(")")),
+        )(input)?;
+        self.typed(|types| {
+            let (b, a) = (pop(types), pop(types));
+            pop(types);
+            types.push(if selected { a } else { b });
+        });
+        Ok((input, if selected { a } else { b }))
+    }
+
+    // defined(identifier), defined identifier
+    fn defined(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let name = || alt((qualified_name, map(keyword_token, Cow::Borrowed)));
+        let (rest, name) = preceded(
+            identifier("defined"),
+            alt((delimited(p("("), name(), p(")")), name())),
+        )(input)?;
+        let found = self.lookup(&name);
+        if let Some((name, r)) = &found {
+            self.log
+                .borrow_mut()
+                .dependencies
+                .push((name.clone(), r.clone()));
+        }
+        self.typed(|types| types.push(Type::Int(IntType::Int)));
+        Ok((rest, EvalResult::Int(Wrapping(found.is_some() as i64))))
     }
 }
 
@@ -403,107 +2650,924 @@ impl<'a> PRef<'a> {
 // ============= Literals and identifiers ================
 // =======================================================
 
+/// An identifier, or a C++ qualified name such as `ns::NAME`, which is
+/// spelled with `::` between its parts and any leading `::`.
+pub(crate) fn qualified_name(input: &[Token]) -> CResult<'_, Cow<'_, [u8]>> {
+    let (mut rest, global) = opt(p("::"))(input)?;
+    let (r, first) = identifier_token(rest)?;
+    rest = r;
+    if global.is_none() && p("::")(rest).is_err() {
+        return Ok((rest, Cow::Borrowed(first)));
+    }
+    let mut name = global.map_or(vec![], <[u8]>::to_vec);
+    name.extend_from_slice(first);
+    while let Ok((r, part)) = preceded(p("::"), identifier_token)(rest) {
+        name.extend_from_slice(b"::");
+        name.extend_from_slice(part);
+        rest = r;
+    }
+    Ok((rest, Cow::Owned(name)))
+}
+
 impl<'a> PRef<'a> {
+    /// An invocation of a function-like macro, as in `VERSION(1, 2)`.
+    fn invocation(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let input = skip_whitespace(input);
+        let (rest, name) = identifier_token(input)?;
+        let definition = match self.fn_macros {
+            Some(macros) if !self.expanded => macros.get(name).map(|d| (macros, d)),
+            _ => None,
+        };
+        let (macros, definition) = match definition {
+            Some(definition) => definition,
+            None => {
+                return Err(Err::Error(
+                    (input, crate::ErrorKind::UnknownIdentifier).into(),
+                ))
+            }
+        };
+        let (rest, args) = arguments(rest, definition.variadic())?;
+        let mut expansion = Expansion {
+            macros,
+            painted: vec![],
+            tokens: 0,
+        };
+        let tokens = expansion
+            .invocation(name, definition, &args)
+            .map_err(|kind| Err::Failure((input, kind).into()))?;
+        let inner = PRef {
+            expanded: true,
+            ..self.nested(input)?
+        };
+        let warnings = self.log.borrow().warnings.len();
+        let res = crate::assert_full_parse(inner.expr(&tokens));
+        // Warnings about the expansion are about the invocation.
+        for warning in &mut self.log.borrow_mut().warnings[warnings..] {
+            warning.position = input.len();
+        }
+        match res {
+            Ok((_, value)) => Ok((rest, value)),
+            Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(Err::Failure((input, e.error).into())),
+            Err(Err::Incomplete(_)) => Err(Err::Failure(
+                (input, crate::ErrorKind::UnexpectedToken).into(),
+            )),
+        }
+    }
+
     fn identifier(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        match self.invocation(input) {
+            Err(Err::Error(_)) => {}
+            res => return res,
+        }
+        let input = skip_whitespace(input);
+        let (rest, raw) = qualified_name(input)?;
+        if let Some((name, r)) = self.lookup(&raw) {
+            let ty = self.identifier_type(&name, &r);
+            self.log.borrow_mut().dependencies.push((name, r.clone()));
+            self.typed(|types| types.push(ty));
+            Ok((rest, self.intmax(r)))
+        } else if let (true, b"true" | b"false") = (self.cplusplus, &raw[..]) {
+            self.typed(|types| types.push(Type::Int(IntType::Bool)));
+            Ok((
+                rest,
+                EvalResult::Int(Wrapping((&raw[..] == b"true") as i64)),
+            ))
+        } else if self.lenient() {
+            self.typed(|types| types.push(Type::Int(IntType::Int)));
+            Ok((rest, PLACEHOLDER))
+        } else {
+            Err(Err::Error(
+                (input, crate::ErrorKind::UnknownIdentifier).into(),
+            ))
+        }
+    }
+
+    // array[index], for an array of the resolver
+    fn array_element(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let input = skip_whitespace(input);
+        let (rest, raw) = qualified_name(input)?;
+        let array = match self.lookup_array(&raw) {
+            Some(array) => array,
+            None => {
+                return Err(Err::Error(
+                    (input, crate::ErrorKind::UnknownIdentifier).into(),
+                ))
+            }
+        };
+        // Without a subscript, the array is the address of its first
+        // element, unless the name also has a value.
+        let rest = match complete(p("["))(rest) {
+            Ok((rest, _)) => rest,
+            Err(e) if self.lookup(&raw).is_some() => return Err(e),
+            Err(_) => return Err(Err::Failure((input, crate::ErrorKind::NotConstant).into())),
+        };
+        let (rest, index) =
+            match nom::sequence::terminated(|i| self.nested(i)?.expr(i), p("]"))(rest) {
+                Ok(subscript) => subscript,
+                Err(Err::Error(e)) => return Err(Err::Failure(e)),
+                Err(e) => return Err(e),
+            };
+        let element = match index {
+            EvalResult::Float(_) => None,
+            index => index
+                .to_target_int(IntType::LongLong, &self.target)
+                .ok()
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| array.elements.get(i)),
+        };
+        let element = element.map(|&element| {
+            EvalResult::Int(Wrapping(array.ty.wrap(element.into(), &self.target) as i64))
+        });
+        match self.unchecked(element) {
+            Some(element) => {
+                self.typed(|types| {
+                    pop(types);
+                    types.push(Type::Int(array.ty));
+                });
+                Ok((rest, element))
+            }
+            // Subscripts out of bounds are undefined behavior.
+            None => Err(Err::Failure((input, crate::ErrorKind::NotConstant).into())),
+        }
+    }
+
+    /// Find the array `name` like [`lookup`](PRef::lookup).
+    fn lookup_array(self, name: &[u8]) -> Option<Array> {
+        let name = &crate::token::splice_lines(name)[..];
+        self.identifiers
+            .array(name)
+            .or_else(|| self.identifiers.array(&(self.normalize?)(name)))
+    }
+
+    /// The type of the identifier `name` with the value `value`, where `name`
+    /// is the name returned by [`lookup`](PRef::lookup).
+    fn identifier_type(self, name: &[u8], value: &EvalResult) -> Type {
+        self.identifiers
+            .ty(name)
+            .unwrap_or_else(|| value_type(value, &self.target))
+    }
+
+    /// Find the value of the identifier `name`, and the name it is known by.
+    fn lookup(self, name: &[u8]) -> Option<(Vec<u8>, EvalResult)> {
+        let name = &crate::token::splice_lines(name)[..];
+        if let Some(r) = self.identifiers.get(name) {
+            return Some((name.to_vec(), r));
+        }
+        let name = (self.normalize?)(name);
+        let r = self.identifiers.get(&name)?;
+        Some((name, r))
+    }
+
+    fn literal(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let input = skip_whitespace(input);
         match input.split_first() {
             None => Err(Err::Incomplete(Needed::new(1))),
             Some((
                 &Token {
-                    kind: TokenKind::Identifier,
+                    kind: TokenKind::Literal,
                     ref raw,
+                    ..
                 },
                 rest,
             )) => {
-                if let Some(r) = self.identifiers.get(&raw[..]) {
-                    Ok((rest, r.clone()))
-                } else {
-                    Err(Err::Error(
-                        (input, crate::ErrorKind::UnknownIdentifier).into(),
-                    ))
+                if let Some(max) = self.limits.max_literal_len {
+                    if raw.len() > max {
+                        return Err(Err::Failure((input, crate::ErrorKind::TooLarge).into()));
+                    }
+                }
+                if let Some(value) = self.literals.and_then(|parse| parse(raw)) {
+                    return Ok((rest, self.custom_literal(value)));
+                }
+                let error = match literal::parse_checked(raw) {
+                    Ok((_, (result, _))) if !literal::fits_target(raw, &result, &self.target) => {
+                        crate::ErrorKind::InvalidLiteral
+                    }
+                    Ok((_, (result, _))) if self.exceeds_uintmax(&result) => {
+                        crate::ErrorKind::LiteralOverflow
+                    }
+                    Ok((_, (result, notes))) => {
+                        if notes.truncated {
+                            self.warn(input, WarningKind::EscapeOutOfRange);
+                        }
+                        if notes.ambiguous_octal {
+                            self.warn(input, WarningKind::AmbiguousOctalEscape);
+                        }
+                        let ty = literal::literal_type(raw, &result, &self.target);
+                        let ty = self.intmax_type(ty, !self.exceeds_intmax(&result));
+                        self.typed(|types| types.push(ty));
+                        return Ok((rest, result));
+                    }
+                    Err(Err::Error(e)) if e.error == crate::ErrorKind::LiteralOverflow => e.error,
+                    _ => crate::ErrorKind::InvalidLiteral,
+                };
+                match self.fallback_literals.and_then(|parse| parse(raw)) {
+                    Some(value) => Ok((rest, self.custom_literal(value))),
+                    None => Err(Err::Error((input, error).into())),
                 }
             }
             Some(_) => Err(Err::Error(
-                (input, crate::ErrorKind::TypedToken(TokenKind::Identifier)).into(),
+                (input, crate::ErrorKind::TypedToken(TokenKind::Literal)).into(),
             )),
         }
     }
 
-    fn literal(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        match input.split_first() {
-            None => Err(Err::Incomplete(Needed::new(1))),
-            Some((
-                &Token {
-                    kind: TokenKind::Literal,
-                    ref raw,
-                },
-                rest,
-            )) => match literal::parse(raw) {
-                Ok((_, result)) => Ok((rest, result)),
-                _ => Err(Err::Error((input, crate::ErrorKind::InvalidLiteral).into())),
-            },
-            Some(_) => Err(Err::Error(
-                (input, crate::ErrorKind::TypedToken(TokenKind::Literal)).into(),
-            )),
+    /// The value of a literal that a custom literal parser gave, with the
+    /// type of an identifier with that value.
+    fn custom_literal(self, value: EvalResult) -> EvalResult {
+        let ty = value_type(&value, &self.target);
+        self.typed(|types| types.push(ty));
+        self.intmax(value)
+    }
+
+    fn string(self, input: &'_ [Token]) -> CResult<'_, Vec<u8>> {
+        alt((
+            self.checkpoint(map_opt(|i| self.literal(i), EvalResult::as_str)),
+            self.checkpoint(map_opt(|i| self.identifier(i), EvalResult::as_str)),
+            self.checkpoint(|i| self.stringify(i)),
+        ))(input)
+        .to_cexpr_result()
+    }
+
+    // #identifier, for function-like macro parameters
+    fn stringify(self, input: &'_ [Token]) -> CResult<'_, Vec<u8>> {
+        map_opt(preceded(p("#"), |i| self.identifier(i)), |v| v.spelling())(input)
+    }
+
+    /// `base`, optionally followed by a subscript `[index]` if it is a string.
+    fn subscripted<'t, F>(self, input: &'t [Token], base: F) -> CResult<'t, EvalResult>
+    where
+        F: FnMut(&'t [Token]) -> CResult<'t, EvalResult>,
+    {
+        map_opt(
+            pair(
+                base,
+                opt(self.checkpoint(delimited(
+                    complete(p("[")),
+                    move |i| self.nested(i)?.comma(i),
+                    p("]"),
+                ))),
+            ),
+            move |(value, index)| match index {
+                None => Some(value),
+                Some(index) => {
+                    let c = self.index(value, index)?;
+                    self.typed(|types| {
+                        pop(types);
+                        pop(types);
+                        types.push(Type::Int(IntType::Char));
+                    });
+                    Some(c)
+                }
+            },
+        )(input)
+    }
+
+    fn index(self, value: EvalResult, index: EvalResult) -> Option<EvalResult> {
+        let s = value.as_str()?;
+        // The terminating NUL can be subscripted too.
+        let c = match index {
+            EvalResult::Int(Wrapping(i)) if i >= 0 && i as u64 <= s.len() as u64 => {
+                s.get(i as usize).copied().unwrap_or(0)
+            }
+            _ => return self.unchecked(None),
+        };
+        Some(EvalResult::Char(CChar::from(c)))
+    }
+
+    // "string1" "string2" etc...
+    fn concat_str(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        map(
+            pair(|i| self.string(i), many0(complete(|i| self.string(i)))),
+            |(first, v)| {
+                self.typed(|types| {
+                    for _ in 0..=v.len() {
+                        pop(types);
+                    }
+                    types.push(Type::Str);
+                });
+                Vec::into_iter(v)
+                    .fold(first, |mut s, elem| {
+                        Vec::extend_from_slice(&mut s, Vec::<u8>::as_slice(&elem));
+                        s
+                    })
+                    .into()
+            },
+        )(input)
+        .to_cexpr_result()
+    }
+
+    fn expr(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
+        let (rest, value) = alt((
+            self.checkpoint(|i| self.comma(i)),
+            self.checkpoint(|i| {
+                self.subscripted(i, delimited(p("("), |i| self.nested(i)?.expr(i), p(")")))
+            }),
+            self.checkpoint(|i| self.subscripted(i, |i| self.concat_str(i))),
+            |i| self.literal(i),
+            |i| self.identifier(i),
+        ))(input)
+        .to_cexpr_result()?;
+        // Explain why "abc" + 1 or 1 + "abc" can't be evaluated, instead of
+        // just failing to parse the operator.
+        let rest = skip_whitespace(rest);
+        if let Ok((operand, _)) = complete(one_of_punctuation(BINARY_OPERATORS))(rest) {
+            let string_operand = || self.dead().string(operand).is_ok();
+            if matches!(value, EvalResult::Str(_)) || string_operand() {
+                return Err(Err::Failure(
+                    (rest, crate::ErrorKind::StringArithmetic).into(),
+                ));
+            }
+        }
+        Ok((rest, value))
+    }
+
+    fn macro_definition(self, input: &'_ [Token]) -> CResult<'_, (&'_ [u8], EvalResult)> {
+        pair(identifier_token, |i| self.expr(i))(input)
+    }
+}
+
+/// A static assertion, `_Static_assert(condition, message)`, of which the
+/// operands are only parsed as far as needed to find their end.
+fn static_assertion(input: &[Token]) -> CResult<'_, ()> {
+    let (input, _) = pair(keyword("_Static_assert"), p("("))(input)?;
+    let mut depth = 0usize;
+    for (n, token) in input.iter().enumerate() {
+        if token.kind != TokenKind::Punctuation {
+            continue;
+        }
+        match &token.raw[..] {
+            b"(" => depth += 1,
+            b")" if depth > 0 => depth -= 1,
+            b")" => return Ok((&input[n + 1..], ())),
+            _ => {}
+        }
+    }
+    Err(Err::Incomplete(Needed::new(1)))
+}
+
+/// The type of the last operand evaluated.
+fn pop(types: &mut Vec<Type>) -> Type {
+    types.pop().expect("the type of an operand")
+}
+
+/// The type of an identifier with the value `value`: an integer gets the
+/// type of a decimal literal with the same value.
+fn value_type(value: &EvalResult, target: &TargetInfo) -> Type {
+    match *value {
+        EvalResult::Int(Wrapping(i)) => [IntType::Int, IntType::Long]
+            .iter()
+            .copied()
+            .find(|ty| ty.wrap(i as i128, target) == i as i128)
+            .map_or(Type::Int(IntType::LongLong), Type::Int),
+        EvalResult::Float(_) => Type::Double,
+        EvalResult::Str(_) => Type::Str,
+        _ => Type::Int(IntType::Int),
+    }
+}
+
+impl<'a> PRef<'a> {
+    fn checkpoint<F>(self, parser: F) -> Checkpoint<'a, F> {
+        Checkpoint {
+            log: self.log,
+            parser,
+        }
+    }
+
+    /// Enter a subexpression of `input`, failing if it is nested too deeply.
+    fn nested(self, input: &[Token]) -> Result<PRef<'a>, Err<crate::Error<&[Token]>>> {
+        if self.depth >= MAX_DEPTH {
+            Err(Err::Failure((input, crate::ErrorKind::TooDeep).into()))
+        } else {
+            Ok(PRef {
+                depth: self.depth + 1,
+                ..self
+            })
+        }
+    }
+
+    fn dead(self) -> PRef<'a> {
+        PRef { dead: true, ..self }
+    }
+
+    /// Whether errors are ignored, because this is an unselected branch and
+    /// the parser is not in strict mode.
+    fn lenient(self) -> bool {
+        self.dead && !self.strict_ternary
+    }
+
+    /// The type of the result of a comparison.
+    fn comparison_type(self) -> IntType {
+        if self.cplusplus {
+            IntType::Bool
+        } else {
+            IntType::Int
+        }
+    }
+
+    /// Replace a failed evaluation with a placeholder if errors are ignored.
+    fn unchecked(self, value: Option<EvalResult>) -> Option<EvalResult> {
+        match value {
+            None if self.lenient() => Some(PLACEHOLDER),
+            value => value,
+        }
+    }
+
+    fn warn(self, input: &[Token], kind: WarningKind) {
+        self.log.borrow_mut().warnings.push(Warning {
+            position: skip_whitespace(input).len(),
+            kind,
+        });
+    }
+
+    /// Update the types of the operands evaluated so far.
+    fn typed(self, f: impl FnOnce(&mut Vec<Type>)) {
+        let types = &mut self.log.borrow_mut().types;
+        f(types);
+        // Each update pushes at most one type, that of the last operand.
+        if let Some(ty) = types.last_mut() {
+            *ty = self.intmax_type(*ty, true);
+        }
+    }
+
+    /// The type an operand of type `ty` acts as: `ty` itself, or in `#if`
+    /// arithmetic `intmax_t` if `ty` is signed after the integer promotions
+    /// and `signed` is set, and `uintmax_t` otherwise.
+    fn intmax_type(self, ty: Type, signed: bool) -> Type {
+        let int = match (self.intmax_width, ty) {
+            (Some(_), Type::Int(int)) => int,
+            _ => return ty,
+        };
+        let signed = signed && int.promoted().is_signed(&self.target);
+        Type::Int(match (self.intmax_width, signed) {
+            (Some(IntmaxWidth::Bits32), true) => IntType::Int,
+            (Some(IntmaxWidth::Bits32), false) => IntType::UnsignedInt,
+            (_, true) => IntType::LongLong,
+            (_, false) => IntType::UnsignedLongLong,
+        })
+    }
+
+    /// Whether the integer `value` of a literal is too large for `intmax_t`,
+    /// so that it is a `uintmax_t` in `#if` arithmetic. Literals too large
+    /// for `long long` are already unsigned.
+    fn exceeds_intmax(self, value: &EvalResult) -> bool {
+        match (self.intmax_width, value) {
+            (Some(IntmaxWidth::Bits32), &EvalResult::Int(Wrapping(i))) => i > i64::from(i32::MAX),
+            _ => false,
+        }
+    }
+
+    /// Whether the integer `value` of a literal is too large for `uintmax_t`,
+    /// as a preprocessor rejects it. The values of literals are never
+    /// negative, unless they are too large for `long long`.
+    fn exceeds_uintmax(self, value: &EvalResult) -> bool {
+        match (self.intmax_width, value) {
+            (Some(IntmaxWidth::Bits32), &EvalResult::Int(Wrapping(i))) => u32::try_from(i).is_err(),
+            _ => false,
+        }
+    }
+
+    /// The value `value` of the operand that was evaluated last, converted
+    /// to the type it acts as, see [`intmax_type`](PRef::intmax_type).
+    fn intmax(self, value: EvalResult) -> EvalResult {
+        match (self.intmax_width, self.log.borrow().types.last()) {
+            (Some(_), Some(&ty)) => self.wrap(ty, value),
+            _ => value,
+        }
+    }
+
+    fn trace(self, step: impl FnOnce() -> Step) {
+        if self.dead {
+            return;
+        }
+        if let Some(ref mut steps) = self.log.borrow_mut().steps {
+            steps.push(step());
+        }
+    }
+}
+
+impl<'a> ::std::ops::Deref for PRef<'a> {
+    type Target = IdentifierParser<'a>;
+    fn deref(&self) -> &IdentifierParser<'a> {
+        self.parser
+    }
+}
+
+impl<'ident> IdentifierParser<'ident> {
+    fn with_log<'a>(&'a self, log: &'a RefCell<EvalLog>) -> PRef<'a> {
+        PRef {
+            parser: self,
+            log,
+            expanded: false,
+            dead: false,
+            depth: 0,
+        }
+    }
+
+    /// Create a new `IdentifierParser` with a set of known identifiers. When
+    /// a known identifier is encountered during parsing, it is substituted
+    /// for the value specified.
+    pub fn new(identifiers: &HashMap<Vec<u8>, EvalResult>) -> IdentifierParser<'_> {
+        IdentifierParser::from_resolver(identifiers)
+    }
+
+    /// Create a new `IdentifierParser` that finds the values of identifiers
+    /// with `identifiers`, like [`new`](IdentifierParser::new) does with a
+    /// map.
+    ///
+    /// # Example
+    /// ```
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let resolve = |name: &[u8]| {
+    ///     let n = name.strip_prefix(b"BIT")?;
+    ///     let n: i64 = std::str::from_utf8(n).ok()?.parse().ok()?;
+    ///     Some(EvalResult::Int(Wrapping(1 << n)))
+    /// };
+    /// let parser = IdentifierParser::from_resolver(&resolve);
+    /// let (_, value) = parser.expr(&tokenize(b"BIT0 | BIT4")).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(17)));
+    /// ```
+    pub fn from_resolver(identifiers: &dyn Resolve) -> IdentifierParser<'_> {
+        IdentifierParser {
+            identifiers,
+            normalize: None,
+            target: TargetInfo::default(),
+            strict_ternary: false,
+            permissive: false,
+            checked_arithmetic: false,
+            cplusplus: false,
+            c23: false,
+            intmax_width: None,
+            gnu_extensions: false,
+            limits: Limits::default(),
+            offsetof: None,
+            sizeof: None,
+            alignof: None,
+            literals: None,
+            fallback_literals: None,
+            implicit_parentheses: true,
+            fn_macros: None,
+            operators: None,
+        }
+    }
+
+    /// Evaluate target-dependent expressions, such as casts to `size_t`, for
+    /// the specified target instead of the default one.
+    pub fn with_target(self, target: TargetInfo) -> IdentifierParser<'ident> {
+        IdentifierParser { target, ..self }
+    }
+
+    /// In strict mode, the branch of a conditional expression that is not
+    /// selected, and the right operand of `&&` or `||` if it is not
+    /// evaluated, must not contain unknown identifiers or type errors, even
+    /// though it is not evaluated. This is useful for linting.
+    pub fn with_strict_ternary(self, strict: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            strict_ternary: strict,
+            ..self
+        }
+    }
+
+    /// In permissive mode, the left operand of a comma operator is parsed like
+    /// the branch of a conditional expression that is not selected, so unknown
+    /// identifiers and errors in it are ignored unless the parser is also in
+    /// strict mode. A static assertion `_Static_assert(…)` followed by a comma
+    /// is skipped without evaluating it. This allows evaluating macros such as
+    /// `(_Static_assert_helper, 8)`, whose value doesn't depend on the left
+    /// operand.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::{Kind, Token};
+    ///
+    /// let idents = HashMap::new();
+    /// let input: Vec<Token> = vec![
+    ///     (Kind::Punctuation, &b"("[..]).into(),
+    ///     (Kind::Identifier, &b"_Static_assert_helper"[..]).into(),
+    ///     (Kind::Punctuation, &b","[..]).into(),
+    ///     (Kind::Literal, &b"8"[..]).into(),
+    ///     (Kind::Punctuation, &b")"[..]).into(),
+    /// ];
+    /// assert!(IdentifierParser::new(&idents).expr(&input).is_err());
+    /// let parser = IdentifierParser::new(&idents).with_permissive(true);
+    /// let (_, value) = parser.expr(&input).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(8)));
+    /// ```
+    pub fn with_permissive(self, permissive: bool) -> IdentifierParser<'ident> {
+        IdentifierParser { permissive, ..self }
+    }
+
+    /// In checked mode, an operation on signed integers that overflows, such
+    /// as `2147483647 + 1`, fails with
+    /// [`ErrorKind::Overflow`](crate::ErrorKind::Overflow) at its operator,
+    /// instead of wrapping around. Either way, it is reported as a
+    /// [`WarningKind::SignedOverflow`]. Likewise, a shift by a negative count
+    /// or by the width of the shifted type or more fails with
+    /// [`ErrorKind::InvalidShift`](crate::ErrorKind::InvalidShift) instead of
+    /// clamping the count, and is reported as a
+    /// [`WarningKind::InvalidShift`]. Operations in operands that are not
+    /// evaluated, such as the branch of a conditional expression that is not
+    /// selected, don't overflow.
+    pub fn with_checked_arithmetic(self, checked: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            checked_arithmetic: checked,
+            ..self
+        }
+    }
+
+    /// Evaluate expressions as C++ rather than C: `true` and `false` are
+    /// literals of type `bool`, comparisons have type `bool` instead of
+    /// `int`, and a conditional expression whose branches have the same type
+    /// has that type. The values are the same either way.
+    pub fn with_cplusplus(self, enabled: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            cplusplus: enabled,
+            ..self
+        }
+    }
+
+    /// Accept the spellings of C23: `alignof(type)` as well as
+    /// `_Alignof(type)`, and `typeof(operand)` and `typeof_unqual(operand)` as
+    /// the type of a cast, `sizeof` or `alignof`. The operand of `typeof` is
+    /// either a type or an expression, which is not evaluated. `alignof` is
+    /// also accepted in C++ mode.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let idents = HashMap::new();
+    /// let input = tokenize(b"(typeof(1u))-1 > sizeof(typeof(long long))");
+    /// assert!(IdentifierParser::new(&idents).expr(&input).is_err());
+    /// let parser = IdentifierParser::new(&idents).with_c23(true);
+    /// let (_, value) = parser.expr(&input).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(1)));
+    /// ```
+    pub fn with_c23(self, enabled: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            c23: enabled,
+            ..self
+        }
+    }
+
+    /// Evaluate integers as in the condition of an `#if` directive, where
+    /// every integer type acts as `intmax_t` or `uintmax_t`, with the width
+    /// `width` in bits, instead of the C types of the operands. The width is
+    /// 64 bits for current compilers and 32 bits for some older ones, so that
+    /// `1 << 40` is a valid shift with one and not the other. `None`
+    /// evaluates integers with their C types, which is the default.
+    ///
+    /// In [`expr_with_type`](IdentifierParser::expr_with_type), integers have
+    /// the type `long long` or `unsigned long long` with a width of 64 bits,
+    /// and `int` or `unsigned int` with a width of 32 bits. A literal that
+    /// only fits in `uintmax_t` is unsigned, and one that doesn't fit in
+    /// either type fails with
+    /// [`ErrorKind::LiteralOverflow`](crate::ErrorKind::LiteralOverflow). The
+    /// value of an identifier that doesn't fit wraps around.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser, IntmaxWidth};
+    /// use cexpr::token::{Kind, Token};
+    ///
+    /// let idents = HashMap::new();
+    /// let input: Vec<Token> = vec![
+    ///     (Kind::Literal, &b"0x7fffffff"[..]).into(),
+    ///     (Kind::Punctuation, &b"+"[..]).into(),
+    ///     (Kind::Literal, &b"1"[..]).into(),
+    ///     (Kind::Punctuation, &b">"[..]).into(),
+    ///     (Kind::Literal, &b"0"[..]).into(),
+    /// ];
+    /// let parser = IdentifierParser::new(&idents).with_intmax_width(Some(IntmaxWidth::Bits64));
+    /// let (_, value) = parser.expr(&input).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(1)));
+    /// let parser = IdentifierParser::new(&idents).with_intmax_width(Some(IntmaxWidth::Bits32));
+    /// let (_, value) = parser.expr(&input).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(0)));
+    /// ```
+    pub fn with_intmax_width(self, width: Option<IntmaxWidth>) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            intmax_width: width,
+            ..self
+        }
+    }
+
+    /// Accept the GNU extension `a ?: b` of the conditional operator, which
+    /// evaluates to `a` if it is non-zero and to `b` otherwise, like
+    /// `a ? a : b` but evaluating `a` only once.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let idents = HashMap::new();
+    /// let parser = IdentifierParser::new(&idents).with_gnu_extensions(true);
+    /// let (_, value) = parser.expr(&tokenize(b"0 ?: 4096")).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(4096)));
+    /// ```
+    pub fn with_gnu_extensions(self, enabled: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            gnu_extensions: enabled,
+            ..self
+        }
+    }
+
+    /// Look up identifiers that aren't known by the name `normalize` returns
+    /// for them, for example to ignore case or a common prefix.
+    ///
+    /// The dependencies of an expression are reported by the name that was
+    /// found.
+    pub fn with_normalization(
+        self,
+        normalize: &'ident dyn Fn(&[u8]) -> Vec<u8>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            normalize: Some(normalize),
+            ..self
         }
     }
 
-    fn string(self, input: &'_ [Token]) -> CResult<'_, Vec<u8>> {
-        alt((
-            map_opt(|i| self.literal(i), EvalResult::as_str),
-            map_opt(|i| self.identifier(i), EvalResult::as_str),
-        ))(input)
-        .to_cexpr_result()
+    /// Evaluate `__builtin_offsetof(type, designator)` by calling `layout`
+    /// with the tokens of the type and the steps of the designator.
+    ///
+    /// For example, `__builtin_offsetof(struct s, a.b[2])` calls `layout`
+    /// with the tokens `struct s` and the designator
+    /// `[Member(b"a"), Member(b"b"), Index(2)]`. `layout` returns the offset
+    /// in bytes, or `None` if it is not known.
+    #[allow(clippy::type_complexity)]
+    pub fn with_offsetof(
+        self,
+        layout: &'ident dyn Fn(&[Token], &[Designator<'_>]) -> Option<u64>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            offsetof: Some(layout),
+            ..self
+        }
     }
 
-    // "string1" "string2" etc...
-    fn concat_str(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        map(
-            pair(|i| self.string(i), many0(complete(|i| self.string(i)))),
-            |(first, v)| {
-                Vec::into_iter(v)
-                    .fold(first, |mut s, elem| {
-                        Vec::extend_from_slice(&mut s, Vec::<u8>::as_slice(&elem));
-                        s
-                    })
-                    .into()
-            },
-        )(input)
-        .to_cexpr_result()
+    /// Evaluate `sizeof(type)` by calling `layout` with the tokens of the
+    /// type.
+    ///
+    /// `layout` returns the size in bytes, or `None` to use the size cexpr
+    /// knows for the type, if any. This allows providing the sizes of other
+    /// types, such as structures, as well as overriding the sizes of the
+    /// basic types for targets that differ from [`TargetInfo`].
+    pub fn with_sizeof(
+        self,
+        layout: &'ident dyn Fn(&[Token]) -> Option<u64>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            sizeof: Some(layout),
+            ..self
+        }
     }
 
-    fn expr(self, input: &'_ [Token]) -> CResult<'_, EvalResult> {
-        alt((
-            |i| self.numeric_expr(i),
-            delimited(p("("), |i| self.expr(i), p(")")),
-            |i| self.concat_str(i),
-            |i| self.literal(i),
-            |i| self.identifier(i),
-        ))(input)
-        .to_cexpr_result()
+    /// Evaluate `_Alignof(type)` and `alignof(type)` by calling `layout` with
+    /// the tokens of the type.
+    ///
+    /// `layout` returns the alignment in bytes, or `None` if it is not known.
+    /// The types whose size cexpr knows, as described for
+    /// [`with_sizeof`](IdentifierParser::with_sizeof), are aligned to their
+    /// size by default.
+    pub fn with_alignof(
+        self,
+        layout: &'ident dyn Fn(&[Token]) -> Option<u64>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            alignof: Some(layout),
+            ..self
+        }
     }
 
-    fn macro_definition(self, input: &'_ [Token]) -> CResult<'_, (&'_ [u8], EvalResult)> {
-        pair(identifier_token, |i| self.expr(i))(input)
+    /// Evaluate literals by calling `parse` with their spelling before
+    /// parsing them as C literals.
+    ///
+    /// `parse` returns the value of the literal, or `None` to parse it as a C
+    /// literal. This allows supporting the literal syntax of vendor
+    /// toolchains, or overriding the values of standard literals. An integer
+    /// has the type of a decimal literal with its value.
+    ///
+    /// # Example
+    /// ```
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// // Fixed-point literals such as `0y1.5`, with 8 fractional bits.
+    /// let fixed = |raw: &[u8]| {
+    ///     let value: f64 = std::str::from_utf8(raw.strip_prefix(b"0y")?).ok()?.parse().ok()?;
+    ///     Some(EvalResult::Int(Wrapping((value * 256.0) as i64)))
+    /// };
+    /// let idents = Default::default();
+    /// let parser = IdentifierParser::new(&idents).with_literal_parser(&fixed);
+    /// let (_, value) = parser.expr(&tokenize(b"0y1.5 + 1")).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(385)));
+    /// ```
+    pub fn with_literal_parser(
+        self,
+        parse: &'ident dyn Fn(&[u8]) -> Option<EvalResult>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            literals: Some(parse),
+            ..self
+        }
     }
-}
 
-impl<'a> ::std::ops::Deref for PRef<'a> {
-    type Target = IdentifierParser<'a>;
-    fn deref(&self) -> &IdentifierParser<'a> {
-        self.0
+    /// Evaluate literals that are not valid C literals by calling `parse`
+    /// with their spelling, like
+    /// [`with_literal_parser`](IdentifierParser::with_literal_parser) but
+    /// after parsing them as C literals fails, including if their value is
+    /// too large. If `parse` returns `None`, the literal is invalid.
+    pub fn with_fallback_literal_parser(
+        self,
+        parse: &'ident dyn Fn(&[u8]) -> Option<EvalResult>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            fallback_literals: Some(parse),
+            ..self
+        }
     }
-}
 
-impl<'ident> IdentifierParser<'ident> {
-    fn as_ref(&self) -> PRef<'_> {
-        PRef(self)
+    /// Whether [`substitute`](IdentifierParser::substitute) puts negative
+    /// numbers in parentheses. The default is `true`.
+    ///
+    /// Without parentheses, the tokens are those a C preprocessor would
+    /// produce by substituting the definition of the identifier, such as
+    /// `#define NEG -1`, textually.
+    pub fn with_implicit_parentheses(self, enabled: bool) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            implicit_parentheses: enabled,
+            ..self
+        }
     }
 
-    /// Create a new `IdentifierParser` with a set of known identifiers. When
-    /// a known identifier is encountered during parsing, it is substituted
-    /// for the value specified.
-    pub fn new(identifiers: &HashMap<Vec<u8>, EvalResult>) -> IdentifierParser<'_> {
-        IdentifierParser { identifiers }
+    /// Reject input that exceeds the specified limits.
+    pub fn with_limits(self, limits: Limits) -> IdentifierParser<'ident> {
+        IdentifierParser { limits, ..self }
+    }
+
+    /// Evaluate invocations of the function-like macros in `macros`, such as
+    /// `VERSION(1, 2)`, by replacing the parameters by the arguments and
+    /// evaluating the result.
+    ///
+    /// A function-like macro that is not followed by arguments is looked up
+    /// as an identifier.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, FnMacro, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = HashMap::new();
+    /// let (name, version) = FnMacro::parse(&tokenize(b"VERSION(a, b) ((a) << 8 | (b))")).unwrap();
+    /// macros.insert(name, version);
+    /// let idents = HashMap::new();
+    /// let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    /// let (_, value) = parser.expr(&tokenize(b"VERSION(1, 2 + 1)")).unwrap();
+    /// assert_eq!(value, EvalResult::Int(Wrapping(0x103)));
+    /// ```
+    pub fn with_fn_macros(
+        self,
+        macros: &'ident HashMap<Vec<u8>, FnMacro>,
+    ) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            fn_macros: Some(macros),
+            ..self
+        }
+    }
+
+    /// Evaluate the custom prefix and infix operators and intrinsic functions
+    /// in `operators`, see [`Operators`].
+    pub fn with_operators(self, operators: &'ident Operators) -> IdentifierParser<'ident> {
+        IdentifierParser {
+            operators: Some(operators),
+            ..self
+        }
+    }
+
+    fn check_limits<'a>(&self, input: &'a [Token]) -> Result<(), Err<crate::Error<&'a [Token]>>> {
+        crate::token::check(input)?;
+        match self.limits.max_tokens {
+            Some(max) if input.len() > max => Err(Err::Failure(
+                (&input[max..], crate::ErrorKind::TooLarge).into(),
+            )),
+            _ => Ok(()),
+        }
     }
 
     /// Parse and evaluate an expression of a list of tokens.
@@ -511,7 +3575,9 @@ impl<'ident> IdentifierParser<'ident> {
     /// Returns an error if the input is not a valid expression or if the token
     /// stream contains comments, keywords or unknown identifiers.
     pub fn expr<'a>(&self, input: &'a [Token]) -> CResult<'a, EvalResult> {
-        self.as_ref().expr(input)
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        self.with_log(&log).expr(input)
     }
 
     /// Parse and evaluate a macro definition from a list of tokens.
@@ -535,7 +3601,366 @@ impl<'ident> IdentifierParser<'ident> {
     /// #define NEGATIVE_THREE(IDENTIFIER)  -3
     /// ```
     pub fn macro_definition<'a>(&self, input: &'a [Token]) -> CResult<'a, (&'a [u8], EvalResult)> {
-        crate::assert_full_parse(self.as_ref().macro_definition(input))
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        crate::assert_full_parse(self.with_log(&log).macro_definition(input))
+    }
+
+    /// Parse and evaluate an expression of a list of tokens, like
+    /// [`expr`](IdentifierParser::expr), and also return the known
+    /// identifiers whose values were used to compute the result.
+    pub fn expr_with_dependencies<'a>(
+        &self,
+        input: &'a [Token],
+    ) -> CResult<'a, (EvalResult, Dependencies)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        let (rest, value) = self.with_log(&log).expr(input)?;
+        let dependencies = log.into_inner().dependencies.into_iter().collect();
+        Ok((rest, (value, dependencies)))
+    }
+
+    /// Parse and evaluate an expression of a list of tokens, like
+    /// [`expr`](IdentifierParser::expr), and also return a trace of the
+    /// steps taken to compute the result.
+    ///
+    /// This is intended for debugging differences between the results of
+    /// cexpr and a C compiler.
+    pub fn expr_with_trace<'a>(&self, input: &'a [Token]) -> CResult<'a, (EvalResult, Trace)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog {
+            steps: Some(Vec::new()),
+            ..EvalLog::default()
+        });
+        let (rest, value) = self.with_log(&log).expr(input)?;
+        let steps = log.into_inner().steps.unwrap_or_default();
+        Ok((rest, (value, Trace { steps })))
+    }
+
+    /// Parse and evaluate an expression like
+    /// [`expr_with_type`](IdentifierParser::expr_with_type), and also record
+    /// the steps taken like [`expr_with_trace`](IdentifierParser::expr_with_trace).
+    pub(crate) fn expr_with_type_and_trace<'a>(
+        &self,
+        input: &'a [Token],
+    ) -> CResult<'a, (EvalResult, Type, Trace)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog {
+            steps: Some(Vec::new()),
+            ..EvalLog::default()
+        });
+        let (rest, value) = self.with_log(&log).expr(input)?;
+        let log = log.into_inner();
+        let (mut types, steps) = (log.types, log.steps.unwrap_or_default());
+        Ok((rest, (value, pop(&mut types), Trace { steps })))
+    }
+
+    /// Parse an expression of a list of tokens without evaluating it, like
+    /// [`validate`] but with the identifiers and settings of this parser, and
+    /// return whether the whole list is an expression.
+    pub(crate) fn parses(&self, input: &[Token]) -> bool {
+        let log = RefCell::new(EvalLog::default());
+        let result = self
+            .check_limits(input)
+            .and_then(|()| self.with_log(&log).dead().expr(input));
+        crate::assert_full_parse(result).is_ok()
+    }
+
+    /// Parse and evaluate an expression of a list of tokens, like
+    /// [`expr`](IdentifierParser::expr), and also return warnings about
+    /// constructs that are valid but likely don't do what was intended, or
+    /// whose result is implementation-defined, see
+    /// [`WarningKind::is_implementation_defined`].
+    pub fn expr_with_warnings<'a>(
+        &self,
+        input: &'a [Token],
+    ) -> CResult<'a, (EvalResult, Vec<Warning>)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        let (rest, value) = self.with_log(&log).expr(input)?;
+        let mut warnings = log.into_inner().warnings;
+        for warning in &mut warnings {
+            warning.position = input.len() - warning.position;
+        }
+        Ok((rest, (value, warnings)))
+    }
+
+    /// Parse and evaluate an expression of a list of tokens, like
+    /// [`expr`](IdentifierParser::expr), and also return the type C gives
+    /// the expression on the target.
+    ///
+    /// This is intended for choosing the type of a constant generated from
+    /// the expression. The value is not converted to the type, see
+    /// [`EvalResult::to_target_int`] for that.
+    pub fn expr_with_type<'a>(&self, input: &'a [Token]) -> CResult<'a, (EvalResult, Type)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        let (rest, value) = self.with_log(&log).expr(input)?;
+        let mut types = log.into_inner().types;
+        debug_assert_eq!(types.len(), 1, "the type of the expression");
+        Ok((rest, (value, pop(&mut types))))
+    }
+
+    /// Parse an expression of a list of tokens, and evaluate as much of it as
+    /// possible.
+    ///
+    /// Unlike [`expr`](IdentifierParser::expr), this doesn't fail if the
+    /// expression can't be evaluated, for example because it uses unknown
+    /// identifiers. Instead, it returns the structure of the expression as
+    /// [`ast::parse`] would, where each subexpression that can be evaluated
+    /// is replaced by its value. If the whole expression can be evaluated,
+    /// that is a single [`Expr::Literal`]. The identifiers that remain are
+    /// added to `symbols`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use cexpr::assert_full_parse;
+    /// use cexpr::ast::{Expr, Symbols};
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut idents = HashMap::new();
+    /// idents.insert(b"SHIFT".to_vec(), EvalResult::Int(std::num::Wrapping(2)));
+    /// let parser = IdentifierParser::new(&idents);
+    /// let mut symbols = Symbols::new();
+    /// let tokens = tokenize(b"FOO << (SHIFT + 1)");
+    /// let (_, expr) = assert_full_parse(parser.partial_expr(&tokens, &mut symbols)).unwrap();
+    /// assert_eq!(expr, Expr::Binary {
+    ///     op: "<<".into(),
+    ///     lhs: Box::new(Expr::Identifier(symbols.intern(b"FOO"))),
+    ///     rhs: Box::new(Expr::Literal(EvalResult::Int(std::num::Wrapping(3)))),
+    /// });
+    /// ```
+    ///
+    /// [`ast::parse`]: crate::ast::parse
+    /// [`Expr::Literal`]: crate::ast::Expr::Literal
+    pub fn partial_expr<'a>(&self, input: &'a [Token], symbols: &mut Symbols) -> CResult<'a, Expr> {
+        self.check_limits(input)?;
+        crate::ast::partial(input, symbols, |tokens| {
+            crate::assert_full_parse(self.expr(tokens))
+                .ok()
+                .map(|(_, value)| value)
+        })
+    }
+
+    /// Parse a C expression from a list of tokens like
+    /// [`partial_expr`](IdentifierParser::partial_expr), but only replace the
+    /// parts whose value is the same for each of `targets`. Without targets,
+    /// the target of this parser is used. The identifiers that remain are
+    /// added to `symbols`.
+    ///
+    /// The parts that depend on the target, such as `sizeof(long)` or casts
+    /// to `size_t`, remain, so that the result is an expression of those. A
+    /// binding generator can emit such an expression once, with the
+    /// target-dependent constants selected by the target it is compiled for,
+    /// instead of a value for each target.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use cexpr::assert_full_parse;
+    /// use cexpr::ast::{Expr, Symbols};
+    /// use cexpr::expr::{EvalResult, IdentifierParser};
+    /// use cexpr::target::TargetInfo;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let idents = HashMap::new();
+    /// let parser = IdentifierParser::new(&idents);
+    /// let targets = [
+    ///     TargetInfo { pointer_width: 32, ..Default::default() },
+    ///     TargetInfo { pointer_width: 64, ..Default::default() },
+    /// ];
+    /// let tokens = tokenize(b"sizeof(long) * (sizeof(int) + 1)");
+    /// let mut symbols = Symbols::new();
+    /// let expr = parser.symbolic_expr(&targets, &tokens, &mut symbols);
+    /// let (_, expr) = assert_full_parse(expr).unwrap();
+    /// assert_eq!(expr, Expr::Binary {
+    ///     op: "*".into(),
+    ///     lhs: Box::new(Expr::Sizeof("long".into())),
+    ///     rhs: Box::new(Expr::Literal(EvalResult::Int(std::num::Wrapping(5)))),
+    /// });
+    /// ```
+    pub fn symbolic_expr<'a>(
+        &self,
+        targets: &[TargetInfo],
+        input: &'a [Token],
+        symbols: &mut Symbols,
+    ) -> CResult<'a, Expr> {
+        self.check_limits(input)?;
+        let parsers: Vec<IdentifierParser<'_>> = targets
+            .iter()
+            .map(|&target| IdentifierParser { target, ..*self })
+            .collect();
+        crate::ast::partial(input, symbols, |tokens| {
+            let mut values = parsers.iter().map(|parser| {
+                crate::assert_full_parse(parser.expr(tokens))
+                    .ok()
+                    .map(|(_, value)| value)
+            });
+            let first = values.next().unwrap_or_else(|| {
+                crate::assert_full_parse(self.expr(tokens))
+                    .ok()
+                    .map(|(_, value)| value)
+            })?;
+            // Floating point values are compared by representation, so that a
+            // NaN is the same for each target.
+            let same = |value: &EvalResult| match (value, &first) {
+                (EvalResult::Float(a), EvalResult::Float(b)) => a.to_bits() == b.to_bits(),
+                _ => *value == first,
+            };
+            if values.all(|value| matches!(value, Some(value) if same(&value))) {
+                Some(first)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Compute the value of an enumerator of an enumeration, following the
+    /// rules of C.
+    ///
+    /// `initializer` is the expression after the `=`, or empty if there is
+    /// none. Without an initializer, the value is that of the `previous`
+    /// enumerator plus one, or 0 for the first enumerator. An initializer
+    /// must evaluate to an integer or a character. To use earlier
+    /// enumerators in initializers, add them to the known identifiers.
+    ///
+    /// Also returns the warnings [`expr_with_warnings`](IdentifierParser::expr_with_warnings)
+    /// would, and [`WarningKind::EnumeratorOutOfRange`] if the value is not
+    /// representable as an `int` of the target.
+    pub fn enumerator<'a>(
+        &self,
+        previous: Option<i64>,
+        initializer: &'a [Token],
+    ) -> CResult<'a, (i64, Vec<Warning>)> {
+        let (rest, value, mut warnings) = if initializer.is_empty() {
+            let value = previous.map_or(0, |previous| previous.wrapping_add(1));
+            (initializer, value, vec![])
+        } else {
+            let (rest, (value, warnings)) =
+                crate::assert_full_parse(self.expr_with_warnings(initializer))?;
+            let value = match value {
+                EvalResult::Int(Wrapping(i)) => i,
+                EvalResult::Char(CChar::Char(c)) => c as i64,
+                EvalResult::Char(CChar::Raw(c)) => c as i64,
+                _ => {
+                    return Err(Err::Failure(
+                        (initializer, crate::ErrorKind::NotInteger).into(),
+                    ))
+                }
+            };
+            (rest, value, warnings)
+        };
+        let overflow = previous == Some(i64::MAX) && initializer.is_empty();
+        if overflow || IntType::Int.wrap(value as i128, &self.target) != value as i128 {
+            warnings.push(Warning {
+                position: 0,
+                kind: WarningKind::EnumeratorOutOfRange,
+            });
+        }
+        Ok((rest, (value, warnings)))
+    }
+
+    /// Parse and evaluate a macro definition from a list of tokens, like
+    /// [`macro_definition`](IdentifierParser::macro_definition), and also
+    /// return the known identifiers whose values were used to compute the
+    /// replacement.
+    pub fn macro_definition_with_dependencies<'a>(
+        &self,
+        input: &'a [Token],
+    ) -> CResult<'a, (&'a [u8], EvalResult, Dependencies)> {
+        self.check_limits(input)?;
+        let log = RefCell::new(EvalLog::default());
+        let (rest, (ident, value)) =
+            crate::assert_full_parse(self.with_log(&log).macro_definition(input))?;
+        let dependencies = log.into_inner().dependencies.into_iter().collect();
+        Ok((rest, (ident, value, dependencies)))
+    }
+
+    /// The tokens of `input` with the stringification operator `#` applied to
+    /// known identifiers, as it is in expressions. Each `#` followed by a
+    /// known identifier is replaced by a string literal, spelled as clang
+    /// spells the stringification of a literal with the identifier's value:
+    /// with a `\` before each `"` and `\`. This allows comparing the tokens
+    /// against those clang gives for the expansion of a function-like macro.
+    ///
+    /// Returns `None` if `#` is followed by an unknown identifier, or by an
+    /// identifier whose value can't be spelled as a literal.
+    pub fn stringified(&self, input: &[Token]) -> Option<Vec<Token>> {
+        let log = RefCell::new(EvalLog::default());
+        let mut tokens = vec![];
+        let mut rest = input;
+        while let Some((token, tail)) = rest.split_first() {
+            match tail.split_first() {
+                Some((ident, tail))
+                    if token.kind == TokenKind::Punctuation
+                        && &token.raw[..] == b"#"
+                        && ident.kind == TokenKind::Identifier =>
+                {
+                    let (_, value) = self.with_log(&log).lookup(&ident.raw)?;
+                    let spelling = quote(&value.spelling()?, b'"');
+                    tokens.push((TokenKind::Literal, &spelling[..]).into());
+                    rest = tail;
+                }
+                _ => {
+                    tokens.push(token.clone());
+                    rest = tail;
+                }
+            }
+        }
+        Some(tokens)
+    }
+
+    /// Replace the known identifiers in a list of tokens by literals with
+    /// their values, without evaluating anything.
+    ///
+    /// A negative number is replaced by `(`, `-`, a literal and `)`, so that
+    /// the result parses the same way as the input, unless this is disabled
+    /// with [`with_implicit_parentheses`](IdentifierParser::with_implicit_parentheses).
+    /// An identifier of an unsigned type, as given by [`Resolve::ty`], is
+    /// replaced by a literal with a suffix such as `ull`, so that its value
+    /// and signedness are kept. Identifiers whose values can't be spelled as
+    /// a literal, unknown identifiers, operands of the stringification
+    /// operator `#` and all other tokens are left alone.
+    pub fn substitute(&self, input: &[Token]) -> Vec<Token> {
+        let log = RefCell::new(EvalLog::default());
+        let parser = self.with_log(&log);
+        let mut output = Vec::with_capacity(input.len());
+        let mut stringified = false;
+        for token in input {
+            let spelling = match token.kind {
+                TokenKind::Identifier if !stringified => {
+                    parser.lookup(&token.raw).and_then(|(name, value)| {
+                        value.typed_spelling(self.identifiers.ty(&name), &self.target)
+                    })
+                }
+                _ => None,
+            };
+            if token.kind != TokenKind::Whitespace {
+                stringified =
+                    token.kind == TokenKind::Punctuation && primary_spelling(&token.raw) == b"#";
+            }
+            let start = output.len();
+            match spelling {
+                Some(s) if s.starts_with(b"-") && self.implicit_parentheses => {
+                    output.push((TokenKind::Punctuation, &b"("[..]).into());
+                    output.push((TokenKind::Punctuation, &b"-"[..]).into());
+                    output.push((TokenKind::Literal, &s[1..]).into());
+                    output.push((TokenKind::Punctuation, &b")"[..]).into());
+                }
+                Some(s) if s.starts_with(b"-") => {
+                    output.push((TokenKind::Punctuation, &b"-"[..]).into());
+                    output.push((TokenKind::Literal, &s[1..]).into());
+                }
+                Some(s) => output.push((TokenKind::Literal, &s[..]).into()),
+                None => output.push(token.clone()),
+            }
+            for substituted in &mut output[start..] {
+                substituted.span = token.span;
+            }
+        }
+        output
     }
 }
 
@@ -559,10 +3984,53 @@ pub fn macro_definition(input: &[Token]) -> CResult<'_, (&'_ [u8], EvalResult)>
     IdentifierParser::new(&HashMap::new()).macro_definition(input)
 }
 
+/// Replace the known identifiers in a list of tokens by literals with their
+/// values, without evaluating anything.
+///
+/// See [`IdentifierParser::substitute`] for details.
+pub fn substitute(input: &[Token], identifiers: &HashMap<Vec<u8>, EvalResult>) -> Vec<Token> {
+    IdentifierParser::new(identifiers).substitute(input)
+}
+
+/// Why a list of tokens is not a valid expression, see [`validate`].
+#[derive(Debug)]
+pub enum Diagnostic {
+    /// The input ended before the expression was complete.
+    Incomplete,
+    /// The token at `position` was not expected.
+    #[allow(missing_docs)]
+    Unexpected {
+        position: usize,
+        error: crate::ErrorKind,
+    },
+}
+
+/// Check whether a list of tokens is a valid expression, without evaluating
+/// it.
+///
+/// Identifiers are allowed and are assumed to be integer constants, so this
+/// does not require the values of any identifiers. Errors that depend on the
+/// values, such as division by zero, are not reported.
+pub fn validate(input: &[Token]) -> Result<(), Diagnostic> {
+    let identifiers = HashMap::new();
+    let parser = IdentifierParser::new(&identifiers);
+    let log = RefCell::new(EvalLog::default());
+    let result = crate::token::check(input).and_then(|()| parser.with_log(&log).dead().expr(input));
+    match crate::assert_full_parse(result) {
+        Ok(_) => Ok(()),
+        Err(Err::Incomplete(_)) => Err(Diagnostic::Incomplete),
+        Err(Err::Error(e)) | Err(Err::Failure(e)) => Err(Diagnostic::Unexpected {
+            position: e.position(input),
+            error: e.error,
+        }),
+    }
+}
+
 /// Parse a functional macro declaration from a list of tokens.
 ///
-/// Returns the identifier for the macro and the argument list (in order). The
-/// input should not include `#define`. The actual definition is not parsed and
+/// Returns the identifier for the macro and the argument list (in order), with
+/// `...` as the last parameter of a variadic macro. The input should not
+/// include `#define`. The actual definition is not parsed and
 /// may be obtained from the unparsed data returned.
 ///
 /// Returns an error if the input is not a functional macro or if the token
@@ -599,12 +4067,554 @@ pub fn macro_definition(input: &[Token]) -> CResult<'_, (&'_ [u8], EvalResult)>
 /// assert_eq!(evaluated, EvalResult::Str(b"testsuffix".to_vec()));
 /// ```
 pub fn fn_macro_declaration(input: &[Token]) -> CResult<'_, (&[u8], Vec<&[u8]>)> {
-    pair(
-        identifier_token,
-        delimited(
-            p("("),
-            separated_list0(p(","), identifier_token),
-            p(")"),
-        ),
-    )(input)
+    pair(identifier_token, delimited(p("("), parameters, p(")")))(input)
+}
+
+/// The parameters of a function-like macro, optionally followed by `...`.
+fn parameters(input: &[Token]) -> CResult<'_, Vec<&[u8]>> {
+    let (input, mut params) = separated_list0(p(","), identifier_token)(input)?;
+    let (input, variadic) = if params.is_empty() {
+        opt(p("..."))(input)?
+    } else {
+        opt(preceded(p(","), p("...")))(input)?
+    };
+    params.extend(variadic);
+    Ok((input, params))
+}
+
+/// A function-like macro, which can be invoked in expressions evaluated by an
+/// [`IdentifierParser`] with [`with_fn_macros`](IdentifierParser::with_fn_macros).
+///
+/// In the replacement list of a variadic macro, `__VA_ARGS__` stands for the
+/// variadic arguments, and `__VA_OPT__(tokens)` for `tokens` if there are
+/// any variadic arguments and for nothing otherwise, as in C23.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FnMacro {
+    /// The names of the parameters, with `...` as the last parameter of a
+    /// variadic macro.
+    pub params: Vec<Vec<u8>>,
+    /// The replacement list.
+    pub body: Vec<Token>,
+}
+
+impl FnMacro {
+    /// Parse a function-like macro from a list of tokens like
+    /// [`fn_macro_declaration`], and return its name and the macro.
+    pub fn parse(input: &[Token]) -> Option<(Vec<u8>, FnMacro)> {
+        let (body, (name, params)) = fn_macro_declaration(input).ok()?;
+        let params = params.into_iter().map(<[u8]>::to_vec).collect();
+        Some((
+            name.to_vec(),
+            FnMacro {
+                params,
+                body: body.to_vec(),
+            },
+        ))
+    }
+
+    /// The replacement list with each parameter replaced by the tokens of the
+    /// corresponding argument in `args`.
+    ///
+    /// A parameter that follows the stringification operator `#` is replaced,
+    /// together with the `#`, by a string literal of the spelling of the
+    /// argument. The tokens around the token pasting operator `##` are
+    /// joined into one token, so that `a ## b` with the arguments `0x1` and
+    /// `0` is `0x10`. If an argument next to `##` is empty, the other one is
+    /// left as it is.
+    ///
+    /// The arguments of a variadic macro after those of the named parameters
+    /// are joined with commas to form the variadic argument. It may be
+    /// omitted, as in C23.
+    ///
+    /// Returns [`ErrorKind::ArgumentCount`] if the number of arguments is
+    /// wrong, and [`ErrorKind::InvalidPaste`] if tokens that are pasted
+    /// together don't form a single token.
+    ///
+    /// [`ErrorKind::ArgumentCount`]: crate::ErrorKind::ArgumentCount
+    /// [`ErrorKind::InvalidPaste`]: crate::ErrorKind::InvalidPaste
+    pub fn expand(&self, args: &[&[Token]]) -> Result<Vec<Token>, crate::ErrorKind> {
+        Ok(self.expand_spaced(args)?.0)
+    }
+
+    /// The spelling of the [expansion](FnMacro::expand) with the arguments
+    /// `args`, as `clang -E` prints it.
+    ///
+    /// Tokens are separated by a space where they were separated by
+    /// whitespace in the replacement list or the arguments. The first token
+    /// of an argument is separated from the previous token as the parameter
+    /// was, a token formed with `##` as its left operand was, and a string
+    /// formed with `#` as the `#` was. A space is also put between tokens
+    /// that would otherwise form a different token, as in `- -1`. The
+    /// variadic arguments are spelled as `a, b`.
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::expr::FnMacro;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let (_, neg) = FnMacro::parse(&tokenize(b"NEG(x) (-x)")).unwrap();
+    /// let arg = tokenize(b"-1");
+    /// assert_eq!(neg.expansion_spelling(&[&arg]).unwrap(), "(- -1)");
+    /// let (_, cat) = FnMacro::parse(&tokenize(b"CAT(a, b) { a ## b, #b }")).unwrap();
+    /// let (a, b) = (tokenize(b"x"), tokenize(b"1 +  1"));
+    /// assert_eq!(cat.expansion_spelling(&[&a, &b]).unwrap(), "{ x1 + 1, \"1 + 1\" }");
+    /// ```
+    pub fn expansion_spelling(&self, args: &[&[Token]]) -> Result<String, crate::ErrorKind> {
+        let (tokens, spaces) = self.expand_spaced(args)?;
+        let mut spelling: Vec<u8> = vec![];
+        let mut previous: Option<&Token> = None;
+        for (token, &space) in tokens.iter().zip(&spaces) {
+            if is_whitespace(token) {
+                continue;
+            }
+            let raw = crate::token::splice_lines(&token.raw);
+            if let Some(previous) = previous {
+                if space || !separate(&crate::token::splice_lines(&previous.raw), &raw) {
+                    spelling.push(b' ');
+                }
+            }
+            spelling.extend_from_slice(&raw);
+            previous = Some(token);
+        }
+        Ok(String::from_utf8_lossy(&spelling).into_owned())
+    }
+
+    /// The expansion with the arguments `args`, and whether each token is
+    /// preceded by whitespace.
+    fn expand_spaced(
+        &self,
+        args: &[&[Token]],
+    ) -> Result<(Vec<Token>, Vec<bool>), crate::ErrorKind> {
+        let variadic: Vec<Token>;
+        let mut args = args.to_vec();
+        if let Some(i) = self.variadic() {
+            if args.len() > i + 1 {
+                // The variadic arguments are joined as `a, b`: the commas
+                // follow the previous token directly, and the first token of
+                // each argument is separated from them.
+                let mut joined: Vec<Token> = args[i].to_vec();
+                for arg in &args[i + 1..] {
+                    let mut comma: Token = (TokenKind::Punctuation, &b","[..]).into();
+                    comma.span = joined.last().and_then(|t| t.span).map(|span| Span {
+                        start: span.end,
+                        end: span.end + 1,
+                        line: span.line,
+                        column: span.column + (span.end - span.start),
+                    });
+                    joined.push(comma);
+                    let first = joined.len();
+                    joined.extend(arg.iter().cloned());
+                    if let Some(token) = joined.get_mut(first) {
+                        token.span = None;
+                    }
+                }
+                variadic = joined;
+                args.truncate(i);
+                args.push(&variadic);
+            }
+        }
+        self.substitute_spaced(&args, &args)
+    }
+
+    /// The index of the parameter `...` of a variadic macro.
+    pub(crate) fn variadic(&self) -> Option<usize> {
+        match self.params.last() {
+            Some(param) if param == b"..." => Some(self.params.len() - 1),
+            _ => None,
+        }
+    }
+
+    /// The replacement list with each parameter replaced by the corresponding
+    /// argument in `expanded`, or in `args` where it is stringified or pasted.
+    fn substitute(
+        &self,
+        args: &[&[Token]],
+        expanded: &[&[Token]],
+    ) -> Result<Vec<Token>, crate::ErrorKind> {
+        Ok(self.substitute_spaced(args, expanded)?.0)
+    }
+
+    /// Like [`substitute`](FnMacro::substitute), and also return whether each
+    /// token is preceded by whitespace.
+    fn substitute_spaced(
+        &self,
+        args: &[&[Token]],
+        expanded: &[&[Token]],
+    ) -> Result<(Vec<Token>, Vec<bool>), crate::ErrorKind> {
+        // `F()` has one empty argument, or no arguments if `F` has no
+        // parameters.
+        let (mut args, mut expanded) = match args {
+            [arg] if arg.is_empty() && self.params.is_empty() => (vec![], vec![]),
+            args => (args.to_vec(), expanded.to_vec()),
+        };
+        if self.variadic() == Some(args.len()) {
+            args.push(&[]);
+            expanded.push(&[]);
+        }
+        if args.len() != self.params.len() {
+            return Err(crate::ErrorKind::ArgumentCount);
+        }
+        let body: Cow<'_, [Token]> = if self.body.iter().any(is_whitespace) {
+            Cow::Owned(
+                self.body
+                    .iter()
+                    .filter(|t| !is_whitespace(t))
+                    .cloned()
+                    .collect(),
+            )
+        } else {
+            Cow::Borrowed(&self.body)
+        };
+        let body_spaces = spaces(&self.body, false);
+        let body_spaces: Vec<bool> = self
+            .body
+            .iter()
+            .zip(body_spaces)
+            .filter(|(t, _)| !is_whitespace(t))
+            .map(|(_, space)| space)
+            .collect();
+        let (mut tokens, mut spaces) = (vec![], vec![]);
+        self.replace(
+            &body,
+            &body_spaces,
+            &args,
+            &expanded,
+            &mut tokens,
+            &mut spaces,
+        )?;
+        Ok((tokens, spaces))
+    }
+
+    /// Append `body` with its parameters replaced to `tokens`, and whether
+    /// each token is preceded by whitespace to `spaces`. `body_spaces` says
+    /// whether each token of `body` is.
+    fn replace(
+        &self,
+        body: &[Token],
+        body_spaces: &[bool],
+        args: &[&[Token]],
+        expanded: &[&[Token]],
+        tokens: &mut Vec<Token>,
+        spaces: &mut Vec<bool>,
+    ) -> Result<(), crate::ErrorKind> {
+        let variadic = self.variadic();
+        let param = |token: &Token| match (token.kind, &token.raw[..]) {
+            (TokenKind::Identifier, b"__VA_ARGS__") => variadic,
+            (TokenKind::Identifier, raw) => self.params.iter().position(|p| p[..] == *raw),
+            _ => None,
+        };
+        // Whether the previous operand was empty, if it is followed by `##`.
+        let mut paste = None;
+        let mut previous_empty = true;
+        // Whether the next token is preceded by whitespace, because it follows
+        // an empty argument whose parameter was.
+        let mut next_space = false;
+        let mut i = 0;
+        while i < body.len() {
+            let token = &body[i];
+            let space = body_spaces[i];
+            i += 1;
+            if is_paste(token) {
+                if i == 1 || i == body.len() {
+                    return Err(crate::ErrorKind::InvalidPaste);
+                }
+                paste = Some(previous_empty);
+                continue;
+            }
+            let stringify =
+                token.kind == TokenKind::Punctuation && primary_spelling(&token.raw) == b"#";
+            let mut operand_spaces = None;
+            let operand: Cow<'_, [Token]> = match (token.kind, &token.raw[..], variadic) {
+                (TokenKind::Identifier, b"__VA_OPT__", Some(v)) => {
+                    match arguments(&body[i..], Some(0)) {
+                        Ok((rest, content)) => {
+                            // The content starts after the `(`.
+                            let start = i + 1;
+                            i = body.len() - rest.len();
+                            let (mut replaced, mut replaced_spaces) = (vec![], vec![]);
+                            if expanded[v].iter().any(|token| !is_whitespace(token)) {
+                                self.replace(
+                                    content[0],
+                                    &body_spaces[start..],
+                                    args,
+                                    expanded,
+                                    &mut replaced,
+                                    &mut replaced_spaces,
+                                )?;
+                            }
+                            if let Some(first) = replaced.iter().position(|t| !is_whitespace(t)) {
+                                replaced_spaces[first] = space;
+                            }
+                            operand_spaces = Some(replaced_spaces);
+                            Cow::Owned(replaced)
+                        }
+                        Err(_) => Cow::Borrowed(std::slice::from_ref(token)),
+                    }
+                }
+                _ => match (body.get(i).and_then(param), param(token)) {
+                    (Some(p), _) if stringify => {
+                        i += 1;
+                        let spelling = spell_argument(args[p]);
+                        Cow::Owned(vec![(TokenKind::Literal, &spelling[..]).into()])
+                    }
+                    // Arguments are pasted as they are, without expanding
+                    // the macros in them.
+                    (_, Some(p))
+                        if paste.is_some()
+                            || matches!(body.get(i), Some(next) if is_paste(next)) =>
+                    {
+                        Cow::Borrowed(trim_whitespace(args[p]))
+                    }
+                    (_, Some(p)) => Cow::Borrowed(expanded[p]),
+                    (_, None) => Cow::Borrowed(std::slice::from_ref(token)),
+                },
+            };
+            let mut operand_spaces =
+                operand_spaces.unwrap_or_else(|| self::spaces(&operand, space));
+            match operand.iter().position(|t| !is_whitespace(t)) {
+                Some(first) => operand_spaces[first] |= std::mem::take(&mut next_space),
+                None => next_space |= space,
+            }
+            previous_empty = operand.is_empty() && paste.unwrap_or(true);
+            match (paste.take(), operand.split_first()) {
+                (Some(false), Some((first, rest))) => {
+                    let last = tokens.pop().ok_or(crate::ErrorKind::InvalidPaste)?;
+                    tokens.push(paste_pair(&last, first)?);
+                    tokens.extend(rest.iter().cloned());
+                    spaces.extend_from_slice(&operand_spaces[1..]);
+                }
+                _ => {
+                    tokens.extend(operand.iter().cloned());
+                    spaces.extend(operand_spaces);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether each token of `tokens` is preceded by whitespace, where the first
+/// token that is not whitespace is if `first` is. Tokens without a
+/// [`Span`](crate::token::Span) are taken to be separated, as in
+/// [`token::spelling`](crate::token::spelling).
+fn spaces(tokens: &[Token], first: bool) -> Vec<bool> {
+    let mut previous: Option<&Token> = None;
+    tokens
+        .iter()
+        .map(|token| {
+            let space = match previous {
+                _ if is_whitespace(token) => true,
+                None => first,
+                Some(previous) => {
+                    is_whitespace(previous)
+                        || !matches!(
+                            (previous.span, token.span),
+                            (Some(previous), Some(span)) if previous.end == span.start
+                        )
+                }
+            };
+            previous = match previous {
+                None if is_whitespace(token) => None,
+                _ => Some(token),
+            };
+            space
+        })
+        .collect()
+}
+
+/// Whether the tokens spelled `a` and `b` stay separate tokens when they are
+/// spelled without whitespace between them.
+fn separate(a: &[u8], b: &[u8]) -> bool {
+    let mut raw = a.to_vec();
+    raw.extend_from_slice(b);
+    let tokens = crate::token::tokenize(&raw);
+    matches!(&tokens[..], [x, y] if x.raw[..] == *a && y.raw[..] == *b)
+}
+
+/// Whether `token` is whitespace or a comment, which the preprocessor
+/// treats as whitespace.
+fn is_whitespace(token: &Token) -> bool {
+    matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment)
+}
+
+fn trim_whitespace(tokens: &[Token]) -> &[Token] {
+    let start = tokens.iter().position(|t| !is_whitespace(t));
+    let end = tokens.iter().rposition(|t| !is_whitespace(t));
+    match (start, end) {
+        (Some(start), Some(end)) => &tokens[start..=end],
+        _ => &[],
+    }
+}
+
+fn is_paste(token: &Token) -> bool {
+    token.kind == TokenKind::Punctuation && primary_spelling(&token.raw) == b"##"
+}
+
+/// The token that `a` and `b` form when they are pasted together with `##`.
+fn paste_pair(a: &Token, b: &Token) -> Result<Token, crate::ErrorKind> {
+    let mut raw = a.raw.to_vec();
+    raw.extend_from_slice(&b.raw);
+    let mut tokens = crate::token::tokenize(&raw);
+    match (tokens.pop(), tokens.is_empty()) {
+        (Some(token), true) if token.kind != TokenKind::Comment => Ok(Token {
+            span: None,
+            ..token
+        }),
+        _ => Err(crate::ErrorKind::InvalidPaste),
+    }
+}
+
+/// The replacement list of an object-like macro, with the tokens around each
+/// `##` pasted together.
+pub(crate) fn paste(tokens: &[Token]) -> Result<Cow<'_, [Token]>, crate::ErrorKind> {
+    if !tokens.iter().any(is_paste) {
+        return Ok(Cow::Borrowed(tokens));
+    }
+    let definition = FnMacro {
+        params: vec![],
+        body: tokens.to_vec(),
+    };
+    definition.expand(&[]).map(Cow::Owned)
+}
+
+/// Expands invocations of function-like macros like the C preprocessor.
+struct Expansion<'a> {
+    macros: &'a HashMap<Vec<u8>, FnMacro>,
+    /// The macros whose expansions are being expanded. As in C, a macro is
+    /// not expanded again in its own expansion, so that `REC(x)` defined as
+    /// `REC(x)` expands to `REC(x)`.
+    painted: Vec<&'a [u8]>,
+    /// The number of tokens all invocations expanded to so far.
+    tokens: usize,
+}
+
+impl<'a> Expansion<'a> {
+    /// The expansion of the invocation of `definition` with the name `name`
+    /// and the arguments `args`.
+    fn invocation(
+        &mut self,
+        name: &'a [u8],
+        definition: &FnMacro,
+        args: &[&[Token]],
+    ) -> Result<Vec<Token>, crate::ErrorKind> {
+        if self.painted.len() >= MAX_DEPTH {
+            return Err(crate::ErrorKind::TooDeep);
+        }
+        // The arguments are expanded before they are substituted, except
+        // where they are stringified.
+        let expanded = args
+            .iter()
+            .map(|arg| self.tokens(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let expanded: Vec<&[Token]> = expanded.iter().map(|arg| &arg[..]).collect();
+        let body = definition.substitute(args, &expanded)?;
+        self.tokens += body.len();
+        if self.tokens > MAX_EXPANSION {
+            return Err(crate::ErrorKind::TooLarge);
+        }
+        self.painted.push(name);
+        let result = self.tokens(&body);
+        self.painted.pop();
+        result
+    }
+
+    /// `input` with all invocations of function-like macros expanded.
+    fn tokens(&mut self, input: &[Token]) -> Result<Vec<Token>, crate::ErrorKind> {
+        let mut output = vec![];
+        let mut i = 0;
+        while i < input.len() {
+            let token = &input[i];
+            i += 1;
+            let invocation = match token.kind {
+                TokenKind::Identifier if !self.painted.contains(&&token.raw[..]) => self
+                    .macros
+                    .get_key_value(&token.raw[..])
+                    .and_then(|(name, definition)| {
+                        let (rest, args) = arguments(&input[i..], definition.variadic()).ok()?;
+                        Some((name, definition, rest, args))
+                    }),
+                _ => None,
+            };
+            match invocation {
+                Some((name, definition, rest, args)) => {
+                    output.extend(self.invocation(name, definition, &args)?);
+                    i = input.len() - rest.len();
+                }
+                None => output.push(token.clone()),
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// The arguments of an invocation of a function-like macro, from the `(` to
+/// the `)`. Commas in parentheses don't separate arguments, and neither do
+/// those after the first `variadic` arguments, which are the variadic argument
+/// of a variadic macro.
+fn arguments(input: &[Token], variadic: Option<usize>) -> CResult<'_, Vec<&[Token]>> {
+    let (input, _) = complete(p("("))(input)?;
+    let mut args = vec![];
+    let (mut start, mut depth) = (0, 0);
+    for (i, token) in input.iter().enumerate() {
+        if token.kind != TokenKind::Punctuation {
+            continue;
+        }
+        match &token.raw[..] {
+            b"(" => depth += 1,
+            b")" if depth > 0 => depth -= 1,
+            b")" => {
+                args.push(&input[start..i]);
+                return Ok((&input[i + 1..], args));
+            }
+            b"," if depth == 0 && variadic != Some(args.len()) => {
+                args.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Err(Err::Failure(
+        (
+            input,
+            crate::ErrorKind::ExactToken(TokenKind::Punctuation, b")"),
+        )
+            .into(),
+    ))
+}
+
+/// A string literal of the spelling of the tokens of a macro argument, as the
+/// stringification operator `#` gives. Tokens that were separated by
+/// whitespace or comments are separated by a space, and `"` and `\` in
+/// string and character literals are escaped.
+fn spell_argument(arg: &[Token]) -> Vec<u8> {
+    let mut spelling = vec![b'"'];
+    let mut previous: Option<&Token> = None;
+    let mut space = false;
+    for token in arg {
+        if is_whitespace(token) {
+            space = true;
+            continue;
+        }
+        if let Some(previous) = previous {
+            space |= !matches!(
+                (previous.span, token.span),
+                (Some(previous), Some(span)) if previous.end == span.start
+            );
+        }
+        if space && previous.is_some() {
+            spelling.push(b' ');
+        }
+        let quoted =
+            token.kind == TokenKind::Literal && token.raw.iter().any(|&c| c == b'"' || c == b'\'');
+        for &c in &token.raw[..] {
+            if quoted && (c == b'"' || c == b'\\') {
+                spelling.push(b'\\');
+            }
+            spelling.push(c);
+        }
+        previous = Some(token);
+        space = false;
+    }
+    spelling.push(b'"');
+    spelling
 }
@@ -0,0 +1,443 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Evaluating the macros of a whole header.
+//!
+//! The header is not preprocessed: every `#define` directive is evaluated in
+//! the order it appears, regardless of any conditional directives around it,
+//! and macros that evaluated successfully are available as identifiers to
+//! later macros. Function-like macros are not evaluated themselves, but
+//! later macros can invoke them, as in
+//!
+//! ```c
+//! #define KB(x) ((x) * 1024)
+//! #define BUF_SIZE KB(4)
+//! ```
+
+use std::collections::HashMap;
+
+use crate::expr::{EvalResult, FnMacro, IdentifierParser};
+use crate::nom;
+use crate::target::TargetInfo;
+use crate::token::Kind::*;
+use crate::token::{primary_spelling, Span, Token};
+use crate::ErrorKind;
+
+/// Why a macro was not evaluated, see [`statistics`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The macro is function-like.
+    FunctionLike,
+    /// The replacement list is a compile-time assertion, which fails to
+    /// compile with a negative array size, see [`is_assertion`].
+    Assertion,
+    /// The replacement list is empty, as in `#define FOO`.
+    Empty,
+    /// The macro is an include guard: its replacement list is empty, and it
+    /// is defined right after an `#ifndef` or `#if !defined` directive that
+    /// tests it, as in
+    ///
+    /// ```c
+    /// #ifndef FOO_H
+    /// #define FOO_H
+    /// ```
+    IncludeGuard,
+    /// The replacement list uses the token pasting operator `##` in a way
+    /// that doesn't form a valid token.
+    TokenPasting,
+    /// The replacement list is a statement or part of one, such as
+    /// `do { ... } while (0)`.
+    StatementLike,
+    /// The replacement list uses an identifier that is not a macro that was
+    /// evaluated before.
+    UnknownIdentifier,
+    /// The replacement list divides an integer by zero, as in `(1 / 0)`.
+    DivisionByZero,
+    /// The replacement list could not be evaluated for any other reason.
+    Unsupported,
+}
+
+/// The number of macros in a header that were evaluated or skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statistics {
+    /// The number of macros that were evaluated.
+    pub evaluated: usize,
+    /// The number of macros that were skipped, for each reason.
+    pub skipped: HashMap<SkipReason, usize>,
+    /// The macros that were defined again with a different definition.
+    pub redefinitions: Vec<Redefinition>,
+}
+
+/// A macro that is defined again with a different definition, which C
+/// doesn't allow.
+///
+/// Definitions are the same if they have the same parameters, and their
+/// replacement lists have the same tokens, separated by whitespace in the
+/// same places. Definitions in different branches of a conditional
+/// directive, and definitions separated by an `#undef` of the macro, don't
+/// conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redefinition {
+    /// The name of the macro.
+    pub name: Vec<u8>,
+    /// The location of the `#define` directive of the previous definition.
+    pub previous: Span,
+    /// The location of the `#define` directive that redefines the macro.
+    pub redefined: Span,
+}
+
+impl Statistics {
+    /// The reasons macros were skipped for, the most frequent first. Reasons
+    /// that are equally frequent are in the order they are declared in.
+    pub fn by_frequency(&self) -> Vec<(SkipReason, usize)> {
+        let mut reasons: Vec<_> = self.skipped.iter().map(|(&r, &n)| (r, n)).collect();
+        reasons.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        reasons
+    }
+}
+
+/// Evaluate all `#define` directives in `header`, count why macros were
+/// skipped, and find the macros that are redefined.
+///
+/// # Example
+/// ```
+/// use cexpr::header::{statistics, SkipReason};
+///
+/// let header = b"#define A 1\n#define B (A + C)\n#define F(x) x\n#define G(x) x\n";
+/// let stats = statistics(header, Default::default());
+/// assert_eq!(stats.evaluated, 1);
+/// assert_eq!(stats.by_frequency(), [
+///     (SkipReason::FunctionLike, 2),
+///     (SkipReason::UnknownIdentifier, 1),
+/// ]);
+/// ```
+pub fn statistics(header: &[u8], target: TargetInfo) -> Statistics {
+    let mut identifiers = HashMap::new();
+    let mut fn_macros = HashMap::new();
+    let mut stats = Statistics::default();
+    let mut definitions = Definitions::default();
+    for (line, guard) in directives(header) {
+        if guard {
+            *stats.skipped.entry(SkipReason::IncludeGuard).or_insert(0) += 1;
+            continue;
+        }
+        let (name, params, tokens) = match crate::parse_define(line) {
+            Some(define) => define,
+            None => {
+                definitions.directive(line);
+                continue;
+            }
+        };
+        definitions.define(span(header, line), &name, &params, &tokens);
+        if is_assertion(&tokens) {
+            *stats.skipped.entry(SkipReason::Assertion).or_insert(0) += 1;
+            continue;
+        }
+        if let Some(params) = params {
+            *stats.skipped.entry(SkipReason::FunctionLike).or_insert(0) += 1;
+            fn_macros.insert(
+                name,
+                FnMacro {
+                    params,
+                    body: tokens,
+                },
+            );
+            continue;
+        }
+        let result = match skip_reason(&tokens) {
+            Some(reason) => Err(reason),
+            None => evaluate(&identifiers, &fn_macros, target, &tokens),
+        };
+        match result {
+            Ok(value) => {
+                stats.evaluated += 1;
+                identifiers.insert(name, value);
+            }
+            Err(reason) => *stats.skipped.entry(reason).or_insert(0) += 1,
+        }
+    }
+    stats.redefinitions = definitions.redefinitions;
+    stats
+}
+
+/// The definitions of the macros of a header as its directives are read, to
+/// find the macros that are redefined.
+#[derive(Debug, Default)]
+pub(crate) struct Definitions {
+    /// The conditional directives around the current directive, as the
+    /// number of each group of `#if` ... `#endif` and of the branch in it.
+    branches: Vec<(usize, usize)>,
+    groups: usize,
+    macros: HashMap<Vec<u8>, Definition>,
+    pub(crate) redefinitions: Vec<Redefinition>,
+}
+
+#[derive(Debug)]
+struct Definition {
+    params: Option<Vec<Vec<u8>>>,
+    tokens: Vec<Token>,
+    span: Span,
+    branches: Vec<(usize, usize)>,
+}
+
+impl Definitions {
+    /// Read the `#define` directive at `span`.
+    pub(crate) fn define(
+        &mut self,
+        span: Span,
+        name: &[u8],
+        params: &Option<Vec<Vec<u8>>>,
+        tokens: &[Token],
+    ) {
+        let definition = Definition {
+            params: params.clone(),
+            tokens: tokens.to_vec(),
+            span,
+            branches: self.branches.clone(),
+        };
+        if let Some(previous) = self.macros.insert(name.to_vec(), definition) {
+            let exclusive = previous
+                .branches
+                .iter()
+                .zip(&self.branches)
+                .find(|(a, b)| a != b)
+                .is_some_and(|(a, b)| a.0 == b.0);
+            if !exclusive && !same_definition(&previous, params, tokens) {
+                self.redefinitions.push(Redefinition {
+                    name: name.to_vec(),
+                    previous: previous.span,
+                    redefined: span,
+                });
+            }
+        }
+    }
+
+    /// Read the directive `line`, which is not a `#define` directive.
+    pub(crate) fn directive(&mut self, line: &[u8]) {
+        let tokens = directive_tokens(line);
+        let spellings: Vec<&[u8]> = tokens.iter().map(|t| primary_spelling(&t.raw)).collect();
+        match &spellings[..] {
+            [b"#", b"if" | b"ifdef" | b"ifndef", ..] => {
+                self.branches.push((self.groups, 0));
+                self.groups += 1;
+            }
+            [b"#", b"elif" | b"elifdef" | b"elifndef" | b"else", ..] => {
+                if let Some((_, branch)) = self.branches.last_mut() {
+                    *branch += 1;
+                }
+            }
+            [b"#", b"endif", ..] => {
+                self.branches.pop();
+            }
+            [b"#", b"undef", name, ..] => {
+                self.macros.remove(*name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether `previous` and the definition with `params` and `tokens` are the
+/// same, see [`Redefinition`].
+fn same_definition(previous: &Definition, params: &Option<Vec<Vec<u8>>>, tokens: &[Token]) -> bool {
+    let separated = |tokens: &[Token]| -> Vec<bool> {
+        tokens
+            .windows(2)
+            .map(|pair| match (pair[0].span, pair[1].span) {
+                (Some(a), Some(b)) => a.end < b.start,
+                _ => true,
+            })
+            .collect()
+    };
+    previous.params == *params
+        && previous.tokens.len() == tokens.len()
+        && previous
+            .tokens
+            .iter()
+            .zip(tokens)
+            .all(|(a, b)| a.raw == b.raw)
+        && separated(&previous.tokens) == separated(tokens)
+}
+
+/// The location of `line` in `header`, of which it is a part.
+pub(crate) fn span(header: &[u8], line: &[u8]) -> Span {
+    let start = line.as_ptr() as usize - header.as_ptr() as usize;
+    let before = &header[..start];
+    let line_start = before
+        .iter()
+        .rposition(|&c| c == b'\n')
+        .map_or(0, |i| i + 1);
+    Span {
+        start,
+        end: start + line.len(),
+        line: before.iter().filter(|&&c| c == b'\n').count() + 1,
+        column: start - line_start + 1,
+    }
+}
+
+/// The logical lines of `header` that start with `#` or `%:`, joined with the
+/// lines that continue them.
+pub(crate) fn lines(header: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut start = 0;
+    std::iter::from_fn(move || loop {
+        if start >= header.len() {
+            return None;
+        }
+        let line = &header[start..];
+        let end = crate::line_end(line);
+        start += end + 1;
+        let line = &line[..end];
+        let text = line.iter().position(|c| !c.is_ascii_whitespace());
+        let text = &line[text.unwrap_or(end)..];
+        if text.starts_with(b"#") || text.starts_with(b"%:") {
+            return Some(line);
+        }
+    })
+}
+
+/// The directives of `header`, as [`lines`] gives them, each with whether it
+/// defines an include guard, see [`SkipReason::IncludeGuard`].
+pub(crate) fn directives(header: &[u8]) -> impl Iterator<Item = (&[u8], bool)> {
+    let mut tested = None;
+    lines(header).map(move |line| {
+        let guard = match tested.take() {
+            Some(name) => matches!(
+                crate::parse_define(line),
+                Some((defined, None, tokens)) if defined == name && tokens.is_empty()
+            ),
+            None => false,
+        };
+        tested = tested_name(line);
+        (line, guard)
+    })
+}
+
+/// The macro that the conditional directive `line` tests to be undefined, as
+/// `#ifndef FOO_H` and `#if !defined(FOO_H)` do.
+fn tested_name(line: &[u8]) -> Option<Vec<u8>> {
+    let tokens = directive_tokens(line);
+    let spellings: Vec<&[u8]> = tokens.iter().map(|t| primary_spelling(&t.raw)).collect();
+    let name = match &spellings[..] {
+        [b"#", b"ifndef", _] => 2,
+        [b"#", b"if", b"!", b"defined", _] => 4,
+        [b"#", b"if", b"!", b"defined", b"(", _, b")"] => 5,
+        _ => return None,
+    };
+    let name = &tokens[name];
+    if name.kind == Identifier {
+        Some(name.raw.to_vec())
+    } else {
+        None
+    }
+}
+
+/// The tokens of the directive `line`, without comments.
+fn directive_tokens(line: &[u8]) -> Vec<Token> {
+    crate::token::tokenize(line)
+        .into_iter()
+        .filter(|t| t.kind != Comment)
+        .collect()
+}
+
+/// Whether the replacement list `tokens` is a compile-time assertion that
+/// declares an array whose size is negative if the assertion fails, as in
+///
+/// ```c
+/// #define ASSERT(e) ((void)sizeof(char[(e) ? 1 : -1]))
+/// #define BUILD_BUG_ON(e) ((void)sizeof(char[1 - 2 * !!(e)]))
+/// ```
+///
+/// or a bit-field whose width is negative, as in
+/// `sizeof(struct { int:-!!(e); })`.
+///
+/// # Example
+/// ```
+/// use cexpr::header::is_assertion;
+/// use cexpr::token::tokenize;
+///
+/// assert!(is_assertion(&tokenize(b"typedef char check[sizeof(long) == 8 ? 1 : -1]")));
+/// assert!(!is_assertion(&tokenize(b"table[i ? 1 : 2]")));
+/// ```
+pub fn is_assertion(tokens: &[Token]) -> bool {
+    let tokens: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| t.kind != Whitespace && t.kind != Comment)
+        .collect();
+    let is = |i: usize, raw: &[u8]| matches!(tokens.get(i), Some(t) if t.kind == Punctuation && primary_spelling(&t.raw) == raw);
+    let literal = |i: usize| matches!(tokens.get(i), Some(t) if t.kind == Literal);
+    // `-!(e)`, `-!!(e)` or `- 2 * !!(e)`
+    let negated = |i: usize| {
+        is(i, b"-") && (is(i + 1, b"!") || (literal(i + 1) && is(i + 2, b"*") && is(i + 3, b"!")))
+    };
+    let mut arrays = 0usize;
+    for i in 0..tokens.len() {
+        if is(i, b"[") && i > 0 && matches!(tokens[i - 1].kind, Identifier | Keyword) {
+            arrays += 1;
+        } else if is(i, b"]") {
+            arrays = arrays.saturating_sub(1);
+        }
+        let size = arrays > 0
+            && (negated(i) || ((is(i, b"?") || is(i, b":")) && is(i + 1, b"-") && literal(i + 2)));
+        let width = is(i, b":") && negated(i + 1);
+        if size || width {
+            return true;
+        }
+    }
+    false
+}
+
+/// Why a macro can be skipped without evaluating it.
+fn skip_reason(tokens: &[Token]) -> Option<SkipReason> {
+    const STATEMENTS: &[&[u8]] = &[
+        b"break",
+        b"case",
+        b"continue",
+        b"default",
+        b"do",
+        b"else",
+        b"for",
+        b"goto",
+        b"if",
+        b"return",
+        b"switch",
+        b"while",
+    ];
+    let is = |kind, raw: &[&[u8]]| {
+        tokens
+            .iter()
+            .any(|t| t.kind == kind && raw.contains(&primary_spelling(&t.raw)))
+    };
+    if tokens.is_empty() {
+        Some(SkipReason::Empty)
+    } else if is(Punctuation, &[b";", b"{", b"}"]) || is(Keyword, STATEMENTS) {
+        Some(SkipReason::StatementLike)
+    } else {
+        None
+    }
+}
+
+fn evaluate(
+    identifiers: &HashMap<Vec<u8>, EvalResult>,
+    fn_macros: &HashMap<Vec<u8>, FnMacro>,
+    target: TargetInfo,
+    tokens: &[Token],
+) -> Result<EvalResult, SkipReason> {
+    let tokens = crate::expr::paste(tokens).map_err(|_| SkipReason::TokenPasting)?;
+    let parser = IdentifierParser::new(identifiers)
+        .with_target(target)
+        .with_fn_macros(fn_macros);
+    match crate::assert_full_parse(parser.expr(&tokens)) {
+        Ok((_, value)) => Ok(value),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => match e.error {
+            ErrorKind::UnknownIdentifier => Err(SkipReason::UnknownIdentifier),
+            ErrorKind::DivisionByZero => Err(SkipReason::DivisionByZero),
+            _ => Err(SkipReason::Unsupported),
+        },
+        Err(_) => Err(SkipReason::Unsupported),
+    }
+}
@@ -13,6 +13,21 @@
 //!
 //! The main entry point for is [`token::parse`], which parses a byte string and returns its
 //! evaluated value.
+//! The structure of an expression can be parsed without evaluating it with
+//! [`ast::parse`].
+//! Macros that use each other can be evaluated together, regardless of the
+//! order they are defined in, with a [`macros::MacroSet`].
+//!
+//! None of the public functions panic on invalid input, no matter how malformed; they return an
+//! error instead. Expressions nested more than 64 levels deep are rejected with
+//! [`ErrorKind::TooDeep`].
+//!
+//! An [`Error`] says what went wrong as an [`ErrorKind`], and where: its
+//! [`position`](Error::position) is the index of the token at which parsing
+//! failed. When an operand of a binary operator fails to parse, the whole
+//! expression fails there, so `1 + UNKNOWN` fails with
+//! [`ErrorKind::UnknownIdentifier`] at the index of `UNKNOWN`, rather than
+//! ending the expression before the `+`.
 #![warn(rust_2018_idioms)]
 #![warn(missing_docs)]
 #![allow(deprecated)]
@@ -21,12 +36,172 @@ pub mod nom {
     //! nom's result types, re-exported.
     pub use nom::{error::ErrorKind, error::Error, Err, IResult, Needed};
 }
+pub mod ast;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod chars;
+pub mod compat;
+#[cfg(feature = "compiler")]
+pub mod compiler;
 pub mod expr;
+pub mod header;
 pub mod literal;
+pub mod macros;
+pub mod report;
+pub mod target;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod token;
 
+use std::fmt;
+
+/// The grammar and features supported by the built crate, see
+/// [`capabilities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The supported unary operators.
+    pub unary_operators: &'static [&'static str],
+    /// The supported binary operators, from highest to lowest precedence.
+    pub binary_operators: &'static [&'static str],
+    /// Whether the conditional operator `?:` is supported.
+    pub conditional_operator: bool,
+    /// Whether the GNU form `a ?: b` of the conditional operator is
+    /// supported, see [`IdentifierParser::with_gnu_extensions`].
+    ///
+    /// [`IdentifierParser::with_gnu_extensions`]: expr::IdentifierParser::with_gnu_extensions
+    pub gnu_conditional_operator: bool,
+    /// Whether the comma operator is supported.
+    pub comma_operator: bool,
+    /// Whether static assertions in the left operand of a comma operator are
+    /// skipped in permissive mode, see
+    /// [`IdentifierParser::with_permissive`].
+    ///
+    /// [`IdentifierParser::with_permissive`]: expr::IdentifierParser::with_permissive
+    pub static_assertions: bool,
+    /// Whether the preprocessor operator `defined` is supported.
+    pub defined_operator: bool,
+    /// Whether integers can be evaluated as `intmax_t` of 32 or 64 bits, as
+    /// in `#if` conditions, see [`IdentifierParser::with_intmax_width`].
+    ///
+    /// [`IdentifierParser::with_intmax_width`]: expr::IdentifierParser::with_intmax_width
+    pub intmax_arithmetic: bool,
+    /// Whether `sizeof(type)` is supported.
+    pub sizeof_operator: bool,
+    /// Whether `_Alignof(type)` is supported, also spelled `alignof(type)` in
+    /// C23 mode, see [`IdentifierParser::with_c23`].
+    ///
+    /// [`IdentifierParser::with_c23`]: expr::IdentifierParser::with_c23
+    pub alignof_operator: bool,
+    /// Whether `typeof` and `typeof_unqual` are supported as the type of a
+    /// cast, `sizeof` or `alignof` in C23 mode, see
+    /// [`IdentifierParser::with_c23`].
+    ///
+    /// [`IdentifierParser::with_c23`]: expr::IdentifierParser::with_c23
+    pub typeof_operator: bool,
+    /// Whether `__builtin_offsetof(type, member)` is supported, see
+    /// [`IdentifierParser::with_offsetof`].
+    ///
+    /// [`IdentifierParser::with_offsetof`]: expr::IdentifierParser::with_offsetof
+    pub offsetof_builtin: bool,
+    /// Whether `__builtin_choose_expr(cond, a, b)` is supported.
+    pub choose_expr_builtin: bool,
+    /// The types that values can be cast to.
+    pub cast_types: &'static [&'static str],
+    /// Whether the C++ casts `static_cast`, `const_cast` and
+    /// `reinterpret_cast` are supported.
+    pub named_casts: bool,
+    /// Whether adjacent strings are concatenated.
+    pub string_concatenation: bool,
+    /// Whether strings can be subscripted, as in `"0123456789abcdef"[10]`.
+    pub string_subscripts: bool,
+    /// Whether the stringification operator `#` is supported.
+    pub stringification: bool,
+    /// Whether the token pasting operator `##` is supported in macros, see
+    /// [`MacroSet`](macros::MacroSet).
+    pub token_pasting: bool,
+    /// Whether digraphs such as `<:` are accepted as punctuators.
+    pub digraphs: bool,
+    /// Whether binary integer literals such as `0b101` are supported.
+    pub binary_literals: bool,
+    /// Whether hexadecimal floating point literals such as `0x1p3` are
+    /// supported.
+    pub hexadecimal_float_literals: bool,
+    /// Whether the `cache` feature is enabled.
+    pub cache: bool,
+    /// Whether the `compiler` feature is enabled.
+    pub compiler: bool,
+    /// Whether the `testing` feature is enabled.
+    pub testing: bool,
+}
+
+/// Describe the grammar and features supported by the built crate.
+///
+/// This allows tools to adapt to the cexpr version they are built with, for
+/// example to explain why a macro could not be evaluated.
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        unary_operators: &["+", "-", "~", "!"],
+        binary_operators: expr::BINARY_OPERATORS,
+        conditional_operator: true,
+        gnu_conditional_operator: true,
+        comma_operator: true,
+        static_assertions: true,
+        defined_operator: true,
+        intmax_arithmetic: true,
+        sizeof_operator: true,
+        alignof_operator: true,
+        typeof_operator: true,
+        offsetof_builtin: true,
+        choose_expr_builtin: true,
+        cast_types: &[
+            "size_t",
+            "ssize_t",
+            "uintptr_t",
+            "intptr_t",
+            "ptrdiff_t",
+            "_Bool",
+            "bool",
+            "int8_t",
+            "uint8_t",
+            "int16_t",
+            "uint16_t",
+            "int32_t",
+            "uint32_t",
+            "int64_t",
+            "uint64_t",
+            "char",
+            "signed char",
+            "unsigned char",
+            "short",
+            "unsigned short",
+            "int",
+            "unsigned",
+            "long",
+            "unsigned long",
+            "long long",
+            "unsigned long long",
+        ],
+        named_casts: true,
+        string_concatenation: true,
+        string_subscripts: true,
+        stringification: true,
+        token_pasting: true,
+        digraphs: true,
+        binary_literals: true,
+        hexadecimal_float_literals: false,
+        cache: cfg!(feature = "cache"),
+        compiler: cfg!(feature = "compiler"),
+        testing: cfg!(feature = "testing"),
+    }
+}
+
 /// Parsing errors specific to C parsing
-#[derive(Debug)]
+///
+/// These don't depend on the version of nom that cexpr is built with: errors
+/// of nom's parsers are reported as [`ErrorKind::Unsupported`], or as
+/// [`ErrorKind::InvalidLiteral`] when parsing a literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ErrorKind {
     /// Expected the specified token
     ExactToken(token::Kind, &'static [u8]),
@@ -34,6 +209,9 @@ pub enum ErrorKind {
     ExactTokens(token::Kind, &'static [&'static str]),
     /// Expected a token of the specified kind
     TypedToken(token::Kind),
+    /// Expected one of several tokens that can't be listed together, such as
+    /// any of the tokens that can start an operand in `1 + )`.
+    UnexpectedToken,
     /// An unknown identifier was encountered
     UnknownIdentifier,
     /// An invalid literal was encountered.
@@ -41,15 +219,92 @@ pub enum ErrorKind {
     /// When encountered, this generally means a bug exists in the data that
     /// was passed in or the parsing logic.
     InvalidLiteral,
+    /// An integer literal is too large for any integer type, because its
+    /// value doesn't fit in 64 bits.
+    LiteralOverflow,
     /// A full parse was requested, but data was left over after parsing finished.
     Partial,
-    /// An error occurred in an underlying nom parser.
-    Parser(nom::ErrorKind),
+    /// The layout of a type is not known, for example when evaluating
+    /// `__builtin_offsetof` without
+    /// [`with_offsetof`](expr::IdentifierParser::with_offsetof), or `sizeof`
+    /// of a structure without
+    /// [`with_sizeof`](expr::IdentifierParser::with_sizeof).
+    UnknownLayout,
+    /// A string is an operand of a binary operator, as in `"abc" + 1`. In C,
+    /// this is arithmetic on the address of the string, which has no value
+    /// that can be evaluated.
+    StringArithmetic,
+    /// An expression that must have an integer value, such as the
+    /// initializer of an enumerator, has a different value.
+    NotInteger,
+    /// The expression is not a constant expression, such as
+    /// `reinterpret_cast<uintptr_t>(0x1000)` in C++.
+    NotConstant,
+    /// The expression is nested too deeply to be parsed.
+    TooDeep,
+    /// The input exceeds one of the configured [`expr::Limits`], or
+    /// invocations of function-like macros expand to too many tokens.
+    TooLarge,
+    /// A function-like macro is invoked with the wrong number of arguments.
+    ArgumentCount,
+    /// Tokens pasted together with `##` don't form a single token, as in
+    /// `+ ## 1`, or `##` is at the start or end of a replacement list.
+    InvalidPaste,
+    /// A token of the input is not valid, such as one with an empty
+    /// spelling, see [`Token::validate`](token::Token::validate).
+    InvalidToken,
+    /// An integer is divided by zero, as in `1 / 0` or `1 % 0`, outside of an
+    /// operand that isn't evaluated.
+    DivisionByZero,
+    /// An operation on signed integers overflows, as in `2147483647 + 1`,
+    /// while evaluating with
+    /// [`with_checked_arithmetic`](expr::IdentifierParser::with_checked_arithmetic).
+    Overflow,
+    /// A value is shifted by a negative count, or by the width of its type or
+    /// more, as in `1 << 32`, while evaluating with
+    /// [`with_checked_arithmetic`](expr::IdentifierParser::with_checked_arithmetic).
+    InvalidShift,
+    /// The tokens are not a construct that cexpr can evaluate, or an operand
+    /// has a value that can't be used, such as an identifier whose value is
+    /// [`EvalResult::Invalid`](expr::EvalResult::Invalid).
+    Unsupported,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ErrorKind::ExactToken(_, raw) => {
+                write!(f, "expected `{}`", String::from_utf8_lossy(raw))
+            }
+            ErrorKind::ExactTokens(_, raw) => write!(f, "expected one of `{}`", raw.join("`, `")),
+            ErrorKind::TypedToken(kind) => {
+                write!(f, "expected {}", format!("{:?}", kind).to_lowercase())
+            }
+            ErrorKind::UnexpectedToken => f.write_str("unexpected token"),
+            ErrorKind::UnknownIdentifier => f.write_str("unknown identifier"),
+            ErrorKind::InvalidLiteral => f.write_str("invalid literal"),
+            ErrorKind::LiteralOverflow => f.write_str("integer literal is too large"),
+            ErrorKind::Partial => f.write_str("unexpected token after the expression"),
+            ErrorKind::UnknownLayout => f.write_str("unknown type layout"),
+            ErrorKind::StringArithmetic => f.write_str("arithmetic on a string"),
+            ErrorKind::NotInteger => f.write_str("not an integer"),
+            ErrorKind::NotConstant => f.write_str("not a constant expression"),
+            ErrorKind::TooDeep => f.write_str("expression is nested too deeply"),
+            ErrorKind::TooLarge => f.write_str("input exceeds a limit"),
+            ErrorKind::ArgumentCount => f.write_str("wrong number of macro arguments"),
+            ErrorKind::InvalidPaste => f.write_str("token pasting does not give a valid token"),
+            ErrorKind::InvalidToken => f.write_str("invalid token"),
+            ErrorKind::DivisionByZero => f.write_str("division by zero"),
+            ErrorKind::Overflow => f.write_str("signed integer overflow"),
+            ErrorKind::InvalidShift => f.write_str("shift count is negative or too large"),
+            ErrorKind::Unsupported => f.write_str("unsupported expression"),
+        }
+    }
 }
 
 impl From<nom::ErrorKind> for ErrorKind {
-    fn from(k: nom::ErrorKind) -> Self {
-        ErrorKind::Parser(k)
+    fn from(_: nom::ErrorKind) -> Self {
+        ErrorKind::Unsupported
     }
 }
 
@@ -61,7 +316,7 @@ impl From<u32> for ErrorKind {
 
 /// Parsing errors specific to C parsing.
 ///
-/// This is a superset of `(I, nom::ErrorKind)` that includes the additional errors specified by
+/// Errors of nom's parsers, `(I, nom::ErrorKind)`, convert to this, see
 /// [`ErrorKind`].
 #[derive(Debug)]
 pub struct Error<I> {
@@ -71,6 +326,35 @@ pub struct Error<I> {
     pub error: ErrorKind,
 }
 
+impl<T> Error<&[T]> {
+    /// The index in `input` of the token or byte at which the error occurred,
+    /// where `input` is the input that was parsed.
+    pub fn position(&self, input: &[T]) -> usize {
+        input.len() - self.input.len()
+    }
+}
+
+impl Error<&[token::Token]> {
+    /// The location of the token at which the error occurred, if the tokens
+    /// have locations. This is `None` if the error occurred at the end of the
+    /// input.
+    pub fn span(&self) -> Option<token::Span> {
+        let input = self.input;
+        let token = input
+            .iter()
+            .find(|token| token.kind != token::Kind::Whitespace)?;
+        token.span
+    }
+}
+
+impl<I> fmt::Display for Error<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<I: fmt::Debug> std::error::Error for Error<I> {}
+
 impl<I> From<(I, nom::ErrorKind)> for Error<I> {
     fn from(e: (I, nom::ErrorKind)) -> Self {
         Self::from((e.0, ErrorKind::from(e.1)))
@@ -95,7 +379,7 @@ impl<I> From<::nom::error::Error<I>> for Error<I> {
     }
 }
 
-impl<I> ::nom::error::ParseError<I> for Error<I> {
+impl<I: ::nom::InputLength> ::nom::error::ParseError<I> for Error<I> {
     fn from_error_kind(input: I, kind: nom::ErrorKind) -> Self {
         Self {
             input,
@@ -106,6 +390,112 @@ impl<I> ::nom::error::ParseError<I> for Error<I> {
     fn append(_: I, _: nom::ErrorKind, other: Self) -> Self {
         other
     }
+
+    /// Of the errors of two alternatives, keep the one that occurred further
+    /// into the input, because that alternative came closest to parsing it.
+    /// At the same position, an error about the token that was found, such as
+    /// an unknown identifier, is kept over an error about the tokens that were
+    /// expected, which is kept over an error of an underlying nom parser,
+    /// [`ErrorKind::Unsupported`].
+    fn or(self, other: Self) -> Self {
+        let rank = |e: &Self| {
+            let specific = match e.error {
+                ErrorKind::Unsupported => 0,
+                ErrorKind::ExactToken(..)
+                | ErrorKind::ExactTokens(..)
+                | ErrorKind::TypedToken(_)
+                | ErrorKind::UnexpectedToken => 1,
+                _ => 2,
+            };
+            (std::cmp::Reverse(e.input.input_len()), specific)
+        };
+        match (rank(&self), rank(&other)) {
+            (a, b) if a > b => self,
+            (a, b) if a == b && b.1 == 1 && self.error != other.error => Self {
+                input: other.input,
+                error: ErrorKind::UnexpectedToken,
+            },
+            _ => other,
+        }
+    }
+}
+
+/// Parse a `#define` directive from a line of C source.
+///
+/// Returns the name of the macro, its parameters if it is a function-like
+/// macro, and the tokens of its replacement list without comments. A
+/// variadic macro has `...` as its last parameter. The line may be continued
+/// with escaped newlines; anything after the first other newline is ignored.
+///
+/// Returns `None` if the line is not a well-formed `#define` directive.
+///
+/// # Example
+/// ```
+/// use cexpr::token::Kind::*;
+///
+/// let (name, params, tokens) = cexpr::parse_define(b"#define FOO(x) ((x)+1)").unwrap();
+/// assert_eq!(name, b"FOO");
+/// assert_eq!(params, Some(vec![b"x".to_vec()]));
+/// assert_eq!(tokens.len(), 7);
+/// assert_eq!(tokens[2].kind, Identifier);
+///
+/// let (_, params, _) = cexpr::parse_define(b"#define FOO (x)").unwrap();
+/// assert_eq!(params, None);
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn parse_define(line: &[u8]) -> Option<(Vec<u8>, Option<Vec<Vec<u8>>>, Vec<token::Token>)> {
+    use token::Kind::*;
+
+    let line = &line[..line_end(line)];
+    let mut spans = token::spans(line);
+    spans.retain(|(kind, _)| *kind != Comment);
+    let raw = |i: usize| {
+        spans
+            .get(i)
+            .map(|(kind, span)| (*kind, &line[span.clone()]))
+    };
+
+    let hash = raw(0).map(|(kind, raw)| (kind, token::primary_spelling(raw)));
+    let name = match (hash, raw(1), raw(2)) {
+        (
+            Some((Punctuation, b"#")),
+            Some((Identifier, b"define")),
+            Some((Identifier | Keyword, name)),
+        ) => name.to_vec(),
+        _ => return None,
+    };
+    let mut body = 3;
+    let mut params = None;
+    // Only a parenthesis directly after the name starts a parameter list.
+    if matches!(raw(3), Some((Punctuation, b"("))) && spans[3].1.start == spans[2].1.end {
+        let mut list = vec![];
+        body = 4;
+        loop {
+            match raw(body)? {
+                (Punctuation, b")") if list.is_empty() => break,
+                (Identifier, param) | (Keyword, param) => list.push(param.to_vec()),
+                (Punctuation, b"...") => list.push(b"...".to_vec()),
+                _ => return None,
+            }
+            body += 1;
+            match raw(body)? {
+                (Punctuation, b")") => break,
+                (Punctuation, b",") if list.last().map(|p| &p[..]) != Some(b"...") => body += 1,
+                _ => return None,
+            }
+        }
+        body += 1;
+        params = Some(list);
+    }
+    let tokens = token::located(line, spans.split_off(body));
+    Some((name, params, tokens))
+}
+
+/// The position of the first newline in `line` that is not escaped.
+fn line_end(line: &[u8]) -> usize {
+    (0..line.len())
+        .find(|&i| line[i] == b'\n' && !line[..i].ends_with(b"\\") && !line[..i].ends_with(b"\\\r"))
+        .unwrap_or(line.len())
 }
 
 // in lieu of https://github.com/Geal/nom/issues/1010
@@ -15,27 +15,46 @@
 //! `char`, literal bytes with the high byte set are converted into the raw
 //! representation. Escape sequences are supported. If hex and octal escapes
 //! map to an ASCII character, that is used, otherwise, the raw encoding is
-//! used, including for values over 255. Unicode escapes are checked for
-//! validity and mapped to `char`. Character sequences are not supported. Width
-//! prefixes are ignored.
+//! used, including for values over 255. Hex escapes take as many digits as
+//! follow them; values that don't fit in 64 bits wrap around. Unicode escapes
+//! are checked for validity and mapped to `char`. Character sequences are not
+//! supported.
+//!
+//! A character literal with a width prefix may also contain a UTF-8 encoded
+//! character, and its value must fit in one code unit of its type, as a C
+//! compiler requires: `u'\u20ac'` is valid, but `u'\U0001f600'` is not
+//! because it doesn't fit in 16 bits. A `u8` literal must be ASCII, or an
+//! octal or hex escape that fits in a byte. `U` and `L` literals can hold any
+//! character, and escapes up to 32 bits. The expression evaluator also checks
+//! that `L` literals fit in the
+//! [`wchar_width`](crate::target::TargetInfo::wchar_width) of its target, so
+//! that `L'\x10000'` fails on a target with a 16-bit `wchar_t`.
 //!
 //! # strings
 //! Strings are interpreted as byte vectors. Escape sequences are supported. If
 //! hex and octal escapes map onto multi-byte characters, they are truncated to
-//! one 8-bit character. Unicode escapes are converted into their UTF-8
-//! encoding. Width prefixes are ignored.
+//! one 8-bit character. Octal escapes take at most three digits, so
+//! `"\0001"` is a NUL byte followed by `1`. Unicode escapes are converted
+//! into their UTF-8 encoding. Width prefixes are ignored.
 //!
 //! # integers
 //! Integers are read into `i64`. Binary, octal, decimal and hexadecimal are
 //! all supported. If the literal value is between `i64::MAX` and `u64::MAX`,
-//! it is bit-cast to `i64`. Values over `u64::MAX` cannot be parsed. Width and
-//! sign suffixes are ignored. Sign prefixes are not supported.
+//! it is bit-cast to `i64`. Values over `u64::MAX` cannot be parsed, and fail
+//! with [`ErrorKind::LiteralOverflow`](crate::ErrorKind::LiteralOverflow).
+//! Width and sign suffixes are ignored. Sign prefixes are not supported.
 //!
 //! # real numbers
 //! Reals are read into `f64`. Width suffixes are ignored. Sign prefixes are
 //! not supported in the significand. Hexadecimal floating points are not
 //! supported.
+//!
+//! # user-defined literals
+//! C++ headers may use literals with a user-defined suffix, such as `10_ms`.
+//! These are not valid C, so [`parse`] rejects them, but
+//! [`parse_user_defined`] returns their value along with the suffix.
 
+use std::borrow::Cow;
 use std::char;
 use std::str::{self, FromStr};
 
@@ -43,13 +62,15 @@ use nom::branch::alt;
 use nom::bytes::complete::is_not;
 use nom::bytes::complete::tag;
 use nom::character::complete::{char, one_of};
-use nom::combinator::{complete, map, map_opt, opt, recognize};
+use nom::combinator::{complete, map, map_opt, opt, recognize, verify};
 use nom::multi::{fold_many0, many0, many1, many_m_n};
 use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::*;
 
+use crate::chars::Class;
 use crate::expr::EvalResult;
-use crate::ToCexprResult;
+use crate::target::{IntType, TargetInfo, Type};
+use crate::token::{Kind, Token};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// Representation of a C character
@@ -63,36 +84,32 @@ pub enum CChar {
 impl From<u8> for CChar {
     fn from(i: u8) -> CChar {
         match i {
-            0..=0x7f => CChar::Char(i as u8 as char),
+            0..=0x7f => CChar::Char(i as char),
             _ => CChar::Raw(i as u64),
         }
     }
 }
 
 // A non-allocating version of this would be nice...
-impl std::convert::Into<Vec<u8>> for CChar {
-    fn into(self) -> Vec<u8> {
-        match self {
+impl From<CChar> for Vec<u8> {
+    fn from(c: CChar) -> Vec<u8> {
+        match c {
             CChar::Char(c) => {
                 let mut s = String::with_capacity(4);
                 s.extend(&[c]);
                 s.into_bytes()
             }
-            CChar::Raw(i) => {
-                let mut v = Vec::with_capacity(1);
-                v.push(i as u8);
-                v
-            }
+            CChar::Raw(i) => vec![i as u8],
         }
     }
 }
 
 /// ensures the child parser consumes the whole input
-pub fn full<I: Clone, O, F>(
+pub fn full<I, O, F>(
     f: F,
 ) -> impl Fn(I) -> nom::IResult<I, O>
 where
-    I: nom::InputLength,
+    I: Clone + nom::InputLength,
     F: Fn(I) -> nom::IResult<I, O>,
 {
     move |input| {
@@ -128,20 +145,31 @@ macro_rules! byte {
 	}}
 }
 
+fn class(c: Class, i: &[u8]) -> nom::IResult<&[u8], u8> {
+    match i.split_first() {
+        Some((&b, rest)) if c.contains(b) => Ok((rest, b)),
+        Some(_) => Err(nom::Err::Error(nom::error::Error::new(
+            i,
+            nom::error::ErrorKind::OneOf,
+        ))),
+        None => Err(nom::Err::Incomplete(Needed::new(1))),
+    }
+}
+
 fn binary(i: &[u8]) -> nom::IResult<&[u8], u8> {
-    byte!(b'0'..=b'1')(i)
+    class(Class::BINARY, i)
 }
 
 fn octal(i: &[u8]) -> nom::IResult<&[u8], u8> {
-    byte!(b'0'..=b'7')(i)
+    class(Class::OCTAL, i)
 }
 
 fn decimal(i: &[u8]) -> nom::IResult<&[u8], u8> {
-    byte!(b'0'..=b'9')(i)
+    class(Class::DECIMAL, i)
 }
 
 fn hexadecimal(i: &[u8]) -> nom::IResult<&[u8], u8> {
-    byte!(b'0' ..= b'9' | b'a' ..= b'f' | b'A' ..= b'F')(i)
+    class(Class::HEXADECIMAL, i)
 }
 
 // ========================================
@@ -161,14 +189,45 @@ fn escape2char(c: char) -> CChar {
     })
 }
 
-fn c_raw_escape(n: Vec<u8>, radix: u32) -> Option<CChar> {
-    str::from_utf8(&n)
-        .ok()
-        .and_then(|i| u64::from_str_radix(i, radix).ok())
-        .map(|i| match i {
-            0..=0x7f => CChar::Char(i as u8 as char),
-            _ => CChar::Raw(i),
-        })
+/// Things worth a warning in a literal that still has a value.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Notes {
+    /// An escape sequence had a value that was out of range, and was
+    /// truncated.
+    pub truncated: bool,
+    /// An octal escape sequence was directly followed by a decimal digit, as
+    /// in `"\0001"`. Octal escapes end after at most three digits, so this is
+    /// a NUL byte followed by `1`, but it's easily misread.
+    pub ambiguous_octal: bool,
+}
+
+impl std::ops::BitOr for Notes {
+    type Output = Notes;
+
+    fn bitor(self, other: Notes) -> Notes {
+        Notes {
+            truncated: self.truncated || other.truncated,
+            ambiguous_octal: self.ambiguous_octal || other.ambiguous_octal,
+        }
+    }
+}
+
+/// Also returns whether the value didn't fit in 64 bits and wrapped around.
+fn c_raw_escape(n: Vec<u8>, radix: u32) -> Option<(CChar, bool)> {
+    let mut value = 0u64;
+    let mut overflow = false;
+    for digit in n {
+        let digit = (digit as char).to_digit(radix)?;
+        let (v, o1) = value.overflowing_mul(radix as u64);
+        let (v, o2) = v.overflowing_add(digit as u64);
+        value = v;
+        overflow |= o1 || o2;
+    }
+    let c = match value {
+        0..=0x7f => CChar::Char(value as u8 as char),
+        _ => CChar::Raw(value),
+    };
+    Some((c, overflow))
 }
 
 fn c_unicode_escape(n: Vec<u8>) -> Option<CChar> {
@@ -179,23 +238,53 @@ fn c_unicode_escape(n: Vec<u8>) -> Option<CChar> {
         .map(CChar::Char)
 }
 
-fn escaped_char(i: &[u8]) -> nom::IResult<&[u8], CChar> {
+/// Octal escapes take up to three digits. Whatever follows is a separate
+/// character, even if it is a digit.
+fn octal_escape(i: &[u8]) -> nom::IResult<&[u8], (CChar, Notes)> {
+    let (rest, (c, truncated)) = map_opt(many_m_n(1, 3, octal), |v| c_raw_escape(v, 8))(i)?;
+    let notes = Notes {
+        truncated,
+        ambiguous_octal: matches!(rest.first(), Some(&b) if Class::DECIMAL.contains(b)),
+    };
+    Ok((rest, (c, notes)))
+}
+
+fn escaped_char(i: &[u8]) -> nom::IResult<&[u8], (CChar, Notes)> {
+    let plain = |c| (c, Notes::default());
+    let wrapped = |(c, truncated)| {
+        (
+            c,
+            Notes {
+                truncated,
+                ..Notes::default()
+            },
+        )
+    };
     preceded(
         char('\\'),
         alt((
-            map(one_of(r#"'"?\"#), CChar::Char),
-            map(one_of("abfnrtv"), escape2char),
-            map_opt(many_m_n(1, 3, octal), |v| c_raw_escape(v, 8)),
-            map_opt(preceded(char('x'), many1(hexadecimal)), |v| {
-                c_raw_escape(v, 16)
-            }),
-            map_opt(
-                preceded(char('u'), many_m_n(4, 4, hexadecimal)),
-                c_unicode_escape,
+            map(map(one_of(r#"'"?\"#), CChar::Char), plain),
+            map(map(one_of("abfnrtv"), escape2char), plain),
+            octal_escape,
+            map(
+                map_opt(preceded(char('x'), many1(hexadecimal)), |v| {
+                    c_raw_escape(v, 16)
+                }),
+                wrapped,
             ),
-            map_opt(
-                preceded(char('U'), many_m_n(8, 8, hexadecimal)),
-                c_unicode_escape,
+            map(
+                map_opt(
+                    preceded(char('u'), many_m_n(4, 4, hexadecimal)),
+                    c_unicode_escape,
+                ),
+                plain,
+            ),
+            map(
+                map_opt(
+                    preceded(char('U'), many_m_n(8, 8, hexadecimal)),
+                    c_unicode_escape,
+                ),
+                plain,
             ),
         )),
     )(i)
@@ -205,29 +294,73 @@ fn c_width_prefix(i: &[u8]) -> nom::IResult<&[u8], &[u8]> {
     alt((tag("u8"), tag("u"), tag("U"), tag("L")))(i)
 }
 
-fn c_char(i: &[u8]) -> nom::IResult<&[u8], CChar> {
-    delimited(
-        terminated(opt(c_width_prefix), char('\'')),
-        alt((
-            escaped_char,
-            map(byte!(0 ..= 91 /* \=92 */ | 93 ..= 255), CChar::from),
-        )),
-        char('\''),
-    )(i)
+/// A character spelled as its UTF-8 encoding.
+fn utf8_char(i: &[u8]) -> nom::IResult<&[u8], (CChar, Notes)> {
+    (1..=i.len().min(4))
+        .find_map(|n| {
+            let c = str::from_utf8(&i[..n]).ok()?.chars().next()?;
+            Some((&i[n..], (CChar::Char(c), Notes::default())))
+        })
+        .ok_or_else(|| nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Char)))
+}
+
+/// Whether a character literal with the width prefix `prefix` can have the
+/// value `c`, which must fit in one code unit of the literal's type.
+fn fits_prefix(prefix: Option<&[u8]>, c: CChar) -> bool {
+    let (max_char, max_raw) = match prefix {
+        None => return true,
+        Some(b"u8") => (0x7f, 0xff),
+        Some(b"u") => (0xffff, 0xffff),
+        Some(_) => (u64::MAX, 0xffff_ffff),
+    };
+    match c {
+        CChar::Char(c) => c as u64 <= max_char,
+        CChar::Raw(i) => i <= max_raw,
+    }
 }
 
-fn c_string(i: &[u8]) -> nom::IResult<&[u8], Vec<u8>> {
+fn c_char(i: &[u8]) -> nom::IResult<&[u8], (CChar, Notes)> {
+    let (i, prefix) = terminated(opt(c_width_prefix), char('\''))(i)?;
+    let byte = map(byte!(0 ..= 91 /* \=92 */ | 93 ..= 255), |c| {
+        (CChar::from(c), Notes::default())
+    });
+    let (i, (c, notes)) = match prefix {
+        None => alt((escaped_char, byte))(i)?,
+        Some(_) => verify(alt((escaped_char, utf8_char)), |&(c, _)| {
+            fits_prefix(prefix, c)
+        })(i)?,
+    };
+    let (i, _) = char('\'')(i)?;
+    Ok((i, (c, notes)))
+}
+
+/// Escape sequences with a value that doesn't fit in a byte are noted as
+/// truncated.
+fn c_string(i: &[u8]) -> nom::IResult<&[u8], (Vec<u8>, Notes)> {
     delimited(
         alt((preceded(c_width_prefix, char('"')), char('"'))),
         fold_many0(
             alt((
-                map(escaped_char, |c: CChar| c.into()),
-                map(is_not([b'\\', b'"']), |c: &[u8]| c.into()),
+                map(escaped_char, |(c, notes): (CChar, Notes)| {
+                    let truncated = match c {
+                        CChar::Raw(i) => i > 0xff,
+                        CChar::Char(_) => false,
+                    };
+                    let notes = notes
+                        | Notes {
+                            truncated,
+                            ..Notes::default()
+                        };
+                    (c.into(), notes)
+                }),
+                map(is_not([b'\\', b'"']), |c: &[u8]| {
+                    (c.into(), Notes::default())
+                }),
             )),
-            Vec::new,
-            |mut v: Vec<u8>, res: Vec<u8>| {
+            || (Vec::new(), Notes::default()),
+            |(mut v, n): (Vec<u8>, Notes), (res, notes): (Vec<u8>, Notes)| {
                 v.extend_from_slice(&res);
-                v
+                (v, n | notes)
             },
         ),
         char('"'),
@@ -252,34 +385,38 @@ fn take_ul(input: &[u8]) -> IResult<&[u8], &[u8]> {
     }
 }
 
-fn c_int(i: &[u8]) -> nom::IResult<&[u8], i64> {
-    map(
-        terminated(
-            alt((
-                map_opt(preceded(tag("0x"), many1(complete(hexadecimal))), |v| {
-                    c_int_radix(v, 16)
-                }),
-                map_opt(preceded(tag("0X"), many1(complete(hexadecimal))), |v| {
-                    c_int_radix(v, 16)
-                }),
-                map_opt(preceded(tag("0b"), many1(complete(binary))), |v| {
-                    c_int_radix(v, 2)
-                }),
-                map_opt(preceded(tag("0B"), many1(complete(binary))), |v| {
-                    c_int_radix(v, 2)
-                }),
-                map_opt(preceded(char('0'), many1(complete(octal))), |v| {
-                    c_int_radix(v, 8)
-                }),
-                map_opt(many1(complete(decimal)), |v| c_int_radix(v, 10)),
-                |input| Err(crate::nom::Err::Error(nom::error::Error::new(input, crate::nom::ErrorKind::Fix))),
-            )),
-            opt(take_ul),
-        ),
-        |i| i as i64,
+/// The digits of an integer literal and their radix, regardless of whether
+/// the value fits in 64 bits.
+fn c_int_digits(i: &[u8]) -> nom::IResult<&[u8], (Vec<u8>, u32)> {
+    terminated(
+        alt((
+            map(preceded(tag("0x"), many1(complete(hexadecimal))), |v| {
+                (v, 16)
+            }),
+            map(preceded(tag("0X"), many1(complete(hexadecimal))), |v| {
+                (v, 16)
+            }),
+            map(preceded(tag("0b"), many1(complete(binary))), |v| (v, 2)),
+            map(preceded(tag("0B"), many1(complete(binary))), |v| (v, 2)),
+            map(preceded(char('0'), many1(complete(octal))), |v| (v, 8)),
+            map(many1(complete(decimal)), |v| (v, 10)),
+            |input| {
+                Err(crate::nom::Err::Error(nom::error::Error::new(
+                    input,
+                    crate::nom::ErrorKind::Fix,
+                )))
+            },
+        )),
+        opt(take_ul),
     )(i)
 }
 
+fn c_int(i: &[u8]) -> nom::IResult<&[u8], i64> {
+    map_opt(c_int_digits, |(v, radix)| {
+        c_int_radix(v, radix).map(|i| i as i64)
+    })(i)
+}
+
 // ==============================
 // ======== parse floats ========
 // ==============================
@@ -342,20 +479,187 @@ fn c_float(i: &[u8]) -> nom::IResult<&[u8], f64> {
 // ======== main interface ========
 // ================================
 
-fn one_literal(input: &[u8]) -> nom::IResult<&[u8], EvalResult, crate::Error<&[u8]>> {
+fn one_literal(input: &[u8]) -> nom::IResult<&[u8], (EvalResult, Notes), crate::Error<&[u8]>> {
     alt((
-        map(full(c_char), EvalResult::Char),
-        map(full(c_int), |i| EvalResult::Int(::std::num::Wrapping(i))),
-        map(full(c_float), EvalResult::Float),
-        map(full(c_string), EvalResult::Str),
+        map(full(c_char), |(c, notes)| (EvalResult::Char(c), notes)),
+        map(full(c_int), |i| {
+            (EvalResult::Int(::std::num::Wrapping(i)), Notes::default())
+        }),
+        map(full(c_float), |f| (EvalResult::Float(f), Notes::default())),
+        map(full(c_string), |(s, notes)| (EvalResult::Str(s), notes)),
     ))(input)
-    .to_cexpr_result()
+    .map_err(|e| e.map(|e| (e.input, crate::ErrorKind::InvalidLiteral).into()))
 }
 
 /// Parse a C literal.
 ///
 /// The input must contain exactly the representation of a single literal
-/// token, and in particular no whitespace or sign prefixes.
+/// token, and in particular no whitespace or sign prefixes. Escaped newlines
+/// are removed first, see [`splice_lines`](crate::token::splice_lines).
 pub fn parse(input: &[u8]) -> IResult<&[u8], EvalResult, crate::Error<&[u8]>> {
-    crate::assert_full_parse(one_literal(input))
+    parse_checked(input).map(|(rest, (value, _))| (rest, value))
+}
+
+/// Parse a C literal, or a C++ literal with a user-defined suffix.
+///
+/// Returns the value of the literal without the suffix, and the suffix,
+/// which is empty for a C literal. The suffix is the shortest identifier at
+/// the end of the input that leaves a valid C literal, so `0x1f_ms` is
+/// `0x1f` with the suffix `_ms`.
+///
+/// # Example
+/// ```
+/// use cexpr::expr::EvalResult;
+/// use cexpr::literal::parse_user_defined;
+///
+/// let (_, (value, suffix)) = parse_user_defined(b"2.5_km").unwrap();
+/// assert_eq!(value, EvalResult::Float(2.5));
+/// assert_eq!(suffix, b"_km");
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn parse_user_defined(
+    input: &[u8],
+) -> IResult<&[u8], (EvalResult, &[u8]), crate::Error<&[u8]>> {
+    let error = match parse(input) {
+        Ok((rest, value)) => return Ok((rest, (value, &input[input.len()..]))),
+        Err(e) => e,
+    };
+    let start = input
+        .iter()
+        .rposition(|&c| !Class::IDENTIFIER.contains(c))
+        .map_or(0, |i| i + 1);
+    (start.max(1)..input.len())
+        .filter(|&i| Class::IDENTIFIER_START.contains(input[i]))
+        .rev()
+        .find_map(|i| {
+            let (_, value) = parse(&input[..i]).ok()?;
+            Some((&input[input.len()..], (value, &input[i..])))
+        })
+        .ok_or(error)
+}
+
+/// Whether `input` is spelled like an integer literal, regardless of whether
+/// its value fits in 64 bits.
+fn is_integer(input: &[u8]) -> bool {
+    let spliced = crate::token::splice_lines(input);
+    let parser = full(c_int_digits);
+    parser(&spliced).is_ok()
+}
+
+/// Like `parse`, but also return anything about the literal that is worth a
+/// warning.
+pub(crate) fn parse_checked(
+    input: &[u8],
+) -> IResult<&[u8], (EvalResult, Notes), crate::Error<&[u8]>> {
+    let res = match crate::token::splice_lines(input) {
+        Cow::Borrowed(_) => crate::assert_full_parse(one_literal(input)),
+        Cow::Owned(spliced) => match crate::assert_full_parse(one_literal(&spliced)) {
+            Ok((_, value)) => Ok((&input[input.len()..], value)),
+            Err(e) => Err(e.map(|e| crate::Error {
+                input,
+                error: e.error,
+            })),
+        },
+    };
+    match res {
+        Err(_) if is_integer(input) => Err(Err::Error(
+            (input, crate::ErrorKind::LiteralOverflow).into(),
+        )),
+        res => res,
+    }
+}
+
+/// Whether the literal `input` with the value `value` fits in its type on
+/// `target`. Only wide character literals such as `L'a'` depend on the
+/// target, because the width of `wchar_t` does.
+pub(crate) fn fits_target(input: &[u8], value: &EvalResult, target: &TargetInfo) -> bool {
+    match *value {
+        EvalResult::Char(c) if input.starts_with(b"L") => {
+            let c = match c {
+                CChar::Char(c) => c as u64,
+                CChar::Raw(i) => i,
+            };
+            target.wchar_width >= 64 || c >> target.wchar_width == 0
+        }
+        _ => true,
+    }
+}
+
+/// The C type of the literal `input` with the value `value`, on `target`.
+///
+/// An integer literal has the first type in the list C gives for its suffix
+/// that can represent its value. A decimal literal without a `u` suffix only
+/// gets signed types. Literals too large for any of the types get `unsigned
+/// long long`. Character literals have type `int`, unless they have a width
+/// prefix: `u8'a'` has type `unsigned char`, `u'a'` has type `unsigned short`
+/// and `U'a'` has type `unsigned int`, as the `char8_t`, `char16_t` and
+/// `char32_t` they stand for. `L'a'` has the type of `wchar_t` on `target`.
+pub(crate) fn literal_type(input: &[u8], value: &EvalResult, target: &TargetInfo) -> Type {
+    use crate::target::IntType::*;
+    match *value {
+        EvalResult::Int(i) => {
+            let suffix = input
+                .iter()
+                .rev()
+                .take_while(|c| matches!(c, b'u' | b'U' | b'l' | b'L'))
+                .collect::<Vec<_>>();
+            let unsigned = suffix.iter().any(|c| matches!(c, b'u' | b'U'));
+            let long = suffix.len() - unsigned as usize;
+            let decimal = input[0] != b'0';
+            let candidates: &[IntType] = match (unsigned, long, decimal) {
+                (false, 0, true) => &[Int, Long, LongLong],
+                (false, 0, false) => &[
+                    Int,
+                    UnsignedInt,
+                    Long,
+                    UnsignedLong,
+                    LongLong,
+                    UnsignedLongLong,
+                ],
+                (false, 1, true) => &[Long, LongLong],
+                (false, 1, false) => &[Long, UnsignedLong, LongLong, UnsignedLongLong],
+                (false, _, true) => &[LongLong],
+                (false, _, false) => &[LongLong, UnsignedLongLong],
+                (true, 0, _) => &[UnsignedInt, UnsignedLong, UnsignedLongLong],
+                (true, 1, _) => &[UnsignedLong, UnsignedLongLong],
+                (true, _, _) => &[UnsignedLongLong],
+            };
+            let value = i.0 as u64 as i128;
+            let ty = candidates
+                .iter()
+                .copied()
+                .find(|ty| ty.wrap(value, target) == value)
+                .unwrap_or(UnsignedLongLong);
+            Type::Int(ty)
+        }
+        EvalResult::Float(_) => match input.last() {
+            Some(b'f') | Some(b'F') => Type::Float,
+            Some(b'l') | Some(b'L') => Type::LongDouble,
+            _ => Type::Double,
+        },
+        EvalResult::Str(_) => Type::Str,
+        EvalResult::Char(_) if input.starts_with(b"u8") => Type::Int(UnsignedChar),
+        EvalResult::Char(_) if input.starts_with(b"u") => Type::Int(UnsignedShort),
+        EvalResult::Char(_) if input.starts_with(b"U") => Type::Int(UnsignedInt),
+        EvalResult::Char(_) if input.starts_with(b"L") && target.wchar_width == 16 => {
+            Type::Int(UnsignedShort)
+        }
+        _ => Type::Int(Int),
+    }
+}
+
+/// Parse all literal tokens in a list of tokens.
+///
+/// Returns the index of each literal token along with its value. Tokens that
+/// are not valid literals have the value `EvalResult::Invalid`.
+pub fn parse_tokens(tokens: &[Token]) -> Vec<(usize, EvalResult)> {
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.kind == Kind::Literal)
+        .map(|(i, token)| {
+            let value = parse(&token.raw).map_or(EvalResult::Invalid, |(_, v)| v);
+            (i, value)
+        })
+        .collect()
 }
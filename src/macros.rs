@@ -0,0 +1,870 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Evaluating a set of object-like macros that use each other.
+//!
+//! Unlike [`header::statistics`], which evaluates the macros of a header in
+//! the order they are defined in, a [`MacroSet`] can evaluate macros that are
+//! used before they are defined, such as in
+//!
+//! ```c
+//! #define FLAGS (FLAG_A | FLAG_B)
+//! #define FLAG_A 1
+//! #define FLAG_B 2
+//! ```
+//!
+//! The macros can invoke function-like macros that are added to the set
+//! with [`MacroSet::insert_fn_macro`].
+//!
+//! For headers too large to keep every replacement list in memory, a
+//! [`Stream`] evaluates macros in the order they are defined instead, and
+//! gives the outcome of each one as it goes.
+//!
+//! [`header::statistics`]: crate::header::statistics
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::expr::{CResult, Dependencies, EvalResult, FnMacro, IdentifierParser, Step};
+use crate::header::{Definitions, Redefinition};
+use crate::nom;
+use crate::report::{MacroReport, Report};
+use crate::target::{TargetInfo, Type};
+use crate::token::{Kind as TokenKind, Token};
+use crate::ErrorKind;
+
+/// What happened when a macro of a [`MacroSet`] was evaluated.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Outcome {
+    /// The macro evaluated to a value.
+    Evaluated(EvalResult),
+    /// The macro could not be evaluated, for the reason given. A macro that
+    /// uses another macro that could not be evaluated fails with
+    /// [`ErrorKind::UnknownIdentifier`].
+    Invalid(ErrorKind),
+    /// The macro uses itself, directly or through other macros.
+    Cyclic,
+}
+
+/// A set of object-like macros, which are evaluated together.
+///
+/// # Example
+/// ```
+/// use std::num::Wrapping;
+/// use cexpr::expr::EvalResult;
+/// use cexpr::macros::{MacroSet, Outcome};
+/// use cexpr::token::tokenize;
+///
+/// let mut macros = MacroSet::new();
+/// macros.insert(b"FLAGS".to_vec(), tokenize(b"(FLAG_A | FLAG_B)"));
+/// macros.insert(b"FLAG_A".to_vec(), tokenize(b"1"));
+/// macros.insert(b"FLAG_B".to_vec(), tokenize(b"2"));
+/// macros.insert(b"LOOP".to_vec(), tokenize(b"LOOP + 1"));
+/// let resolution = macros.resolve();
+/// assert_eq!(
+///     resolution.outcomes[&b"FLAGS"[..]],
+///     Outcome::Evaluated(EvalResult::Int(Wrapping(3)))
+/// );
+/// assert_eq!(resolution.cyclic(), [&b"LOOP"[..]]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MacroSet {
+    macros: HashMap<Vec<u8>, Vec<Token>>,
+    fn_macros: HashMap<Vec<u8>, FnMacro>,
+    target: TargetInfo,
+    permissive: bool,
+    c23: bool,
+    statistics: bool,
+    redefinitions: Vec<Redefinition>,
+}
+
+impl MacroSet {
+    /// Create an empty set.
+    pub fn new() -> MacroSet {
+        MacroSet::default()
+    }
+
+    /// The macros defined in `header`. As in [`header::statistics`], every
+    /// `#define` directive is read, regardless of any conditional directives
+    /// around it. A macro that is defined more than once has its last
+    /// definition, and definitions that conflict are listed in
+    /// [`redefinitions`](MacroSet::redefinitions). Include guards are left
+    /// out, see [`SkipReason::IncludeGuard`].
+    ///
+    /// [`header::statistics`]: crate::header::statistics
+    /// [`SkipReason::IncludeGuard`]: crate::header::SkipReason::IncludeGuard
+    pub fn from_header(header: &[u8]) -> MacroSet {
+        let mut macros = MacroSet::new();
+        let mut definitions = Definitions::default();
+        for (line, _) in crate::header::directives(header).filter(|&(_, guard)| !guard) {
+            let define = crate::parse_define(line);
+            match &define {
+                Some((name, params, tokens)) => {
+                    definitions.define(crate::header::span(header, line), name, params, tokens)
+                }
+                None => definitions.directive(line),
+            }
+            match define {
+                Some((name, None, tokens)) => {
+                    macros.fn_macros.remove(&name);
+                    macros.insert(name, tokens);
+                }
+                Some((name, Some(params), body)) => {
+                    macros.macros.remove(&name);
+                    macros.insert_fn_macro(name, FnMacro { params, body });
+                }
+                None => {}
+            }
+        }
+        macros.redefinitions = definitions.redefinitions;
+        macros
+    }
+
+    /// The macros that [`from_header`](MacroSet::from_header) found defined
+    /// again with a different definition, in the order of the
+    /// redefinitions.
+    pub fn redefinitions(&self) -> &[Redefinition] {
+        &self.redefinitions
+    }
+
+    /// Evaluate the macros for the specified target instead of the default
+    /// one, see [`IdentifierParser::with_target`].
+    pub fn with_target(self, target: TargetInfo) -> MacroSet {
+        MacroSet { target, ..self }
+    }
+
+    /// Evaluate the macros in permissive mode, see
+    /// [`IdentifierParser::with_permissive`].
+    pub fn with_permissive(self, permissive: bool) -> MacroSet {
+        MacroSet { permissive, ..self }
+    }
+
+    /// Evaluate the macros with the spellings of C23, see
+    /// [`IdentifierParser::with_c23`].
+    pub fn with_c23(self, enabled: bool) -> MacroSet {
+        MacroSet {
+            c23: enabled,
+            ..self
+        }
+    }
+
+    /// Record how long each macro takes to evaluate and the operators it
+    /// applies in the [`statistics`](Resolution::statistics) of a
+    /// [`resolve`](MacroSet::resolve), to find the macros that are slow to
+    /// evaluate.
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::macros::MacroSet;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new().with_statistics(true);
+    /// macros.insert(b"MASK".to_vec(), tokenize(b"~(1 << 4) & -1"));
+    /// let statistics = &macros.resolve().statistics[&b"MASK"[..]];
+    /// assert_eq!(statistics.unary_operators["~"], 1);
+    /// assert_eq!(statistics.binary_operators["<<"], 1);
+    /// assert_eq!(statistics.binary_operators["&"], 1);
+    /// ```
+    pub fn with_statistics(self, enabled: bool) -> MacroSet {
+        MacroSet {
+            statistics: enabled,
+            ..self
+        }
+    }
+
+    /// Add the macro `name` with the replacement list `tokens`. If the set
+    /// already has a macro with that name, it is replaced, and its
+    /// replacement list is returned.
+    pub fn insert(&mut self, name: Vec<u8>, tokens: Vec<Token>) -> Option<Vec<Token>> {
+        self.macros.insert(name, tokens)
+    }
+
+    /// Add the function-like macro `name`, which the macros of the set can
+    /// invoke. It is not evaluated itself. If the set already has a
+    /// function-like macro with that name, it is replaced and returned.
+    ///
+    /// # Example
+    /// ```
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::{EvalResult, FnMacro};
+    /// use cexpr::macros::{MacroSet, Outcome};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new();
+    /// let (name, kb) = FnMacro::parse(&tokenize(b"KB(x) ((x) * UNIT)")).unwrap();
+    /// macros.insert_fn_macro(name, kb);
+    /// macros.insert(b"BUF_SIZE".to_vec(), tokenize(b"KB(4)"));
+    /// macros.insert(b"UNIT".to_vec(), tokenize(b"1024"));
+    /// assert_eq!(
+    ///     macros.resolve().outcomes[&b"BUF_SIZE"[..]],
+    ///     Outcome::Evaluated(EvalResult::Int(Wrapping(4096)))
+    /// );
+    /// ```
+    pub fn insert_fn_macro(&mut self, name: Vec<u8>, definition: FnMacro) -> Option<FnMacro> {
+        self.fn_macros.insert(name, definition)
+    }
+
+    /// The replacement list of the object-like macro `name`.
+    pub fn get(&self, name: &[u8]) -> Option<&[Token]> {
+        self.macros.get(name).map(|tokens| &tokens[..])
+    }
+
+    /// The spelling of the replacement list of the object-like macro `name`,
+    /// see [`token::spelling`]. This can be shown to the user for macros that
+    /// could not be evaluated.
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::macros::MacroSet;
+    ///
+    /// let macros = MacroSet::from_header(b"#define ONE 1\n#define BAD (ONE +  /* + */ )");
+    /// let resolution = macros.resolve();
+    /// assert_eq!(resolution.invalid(), [b"BAD"]);
+    /// assert_eq!(macros.spelling(b"BAD").unwrap(), "(ONE + )");
+    /// ```
+    ///
+    /// [`token::spelling`]: crate::token::spelling
+    pub fn spelling(&self, name: &[u8]) -> Option<String> {
+        self.get(name).map(crate::token::spelling)
+    }
+
+    /// What to show about the object-like macro `name`, for example when
+    /// hovering over it in an editor.
+    ///
+    /// Only `name` and the macros it uses are evaluated, so this is cheaper
+    /// than [`resolve`](MacroSet::resolve) for a single macro.
+    ///
+    /// # Example
+    /// ```
+    /// use std::num::Wrapping;
+    /// use cexpr::expr::EvalResult;
+    /// use cexpr::macros::{MacroSet, Outcome};
+    /// use cexpr::target::{IntType, Type};
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new();
+    /// macros.insert(b"FLAGS".to_vec(), tokenize(b"(FLAG |  2u)"));
+    /// macros.insert(b"FLAG".to_vec(), tokenize(b"1"));
+    /// let hover = macros.hover(b"FLAGS").unwrap();
+    /// assert_eq!(hover.value, Outcome::Evaluated(EvalResult::Int(Wrapping(3))));
+    /// assert_eq!(hover.ty, Some(Type::Int(IntType::UnsignedInt)));
+    /// assert_eq!(hover.expansion, "(FLAG | 2u)");
+    /// assert_eq!(hover.dependencies[&b"FLAG"[..]], EvalResult::Int(Wrapping(1)));
+    /// ```
+    pub fn hover(&self, name: &[u8]) -> Option<HoverInfo> {
+        let (name, tokens) = self.macros.get_key_value(name)?;
+        let resolution = self.resolve_macros(self.target, vec![&name[..]]);
+        let values = resolution.typed_values();
+        let dependencies = match crate::expr::paste(tokens) {
+            Ok(tokens) if values.contains_key(name) => {
+                let parser = self.parser(self.target, &values);
+                match parser.expr_with_dependencies(&tokens) {
+                    Ok((_, (_, dependencies))) => dependencies,
+                    Err(_) => Dependencies::new(),
+                }
+            }
+            _ => Dependencies::new(),
+        };
+        Some(HoverInfo {
+            value: resolution.outcomes[name].clone(),
+            ty: resolution.types.get(name).copied(),
+            expansion: crate::token::spelling(tokens),
+            dependencies,
+        })
+    }
+
+    /// The function-like macro `name`.
+    pub fn get_fn_macro(&self, name: &[u8]) -> Option<&FnMacro> {
+        self.fn_macros.get(name)
+    }
+
+    /// The number of object-like macros in the set.
+    pub fn len(&self) -> usize {
+        self.macros.len()
+    }
+
+    /// Whether the set has no macros.
+    pub fn is_empty(&self) -> bool {
+        self.macros.is_empty()
+    }
+
+    /// Evaluate all macros in the set.
+    ///
+    /// A macro is evaluated once all macros of the set it uses are, with
+    /// their values. This is repeated until no more macros can be evaluated.
+    /// Then, the macros that remain are on a cycle of macros that use each
+    /// other, or use such a macro. The former are [`Outcome::Cyclic`], and
+    /// the latter are evaluated with those unknown.
+    pub fn resolve(&self) -> Resolution {
+        self.resolve_for(self.target)
+    }
+
+    /// Evaluate all macros in the set like [`resolve`](MacroSet::resolve),
+    /// and describe how each of them evaluates, including the warnings about
+    /// it and the macros it uses. The report can be written as JSON, see
+    /// [`report`](crate::report).
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::macros::MacroSet;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new();
+    /// macros.insert(b"A".to_vec(), tokenize(b"1u"));
+    /// macros.insert(b"B".to_vec(), tokenize(b"(A << 31 >> 31)"));
+    /// let report = macros.report();
+    /// assert_eq!(report.macros[1].name, b"B");
+    /// assert_eq!(report.macros[1].dependencies, [b"A"]);
+    /// assert!(report.to_json().contains(r#"{"name":"B","outcome":"evaluated","value":{"int":1}"#));
+    /// ```
+    pub fn report(&self) -> Report {
+        let resolution = self.resolve();
+        let values = resolution.typed_values();
+        let parser = self.parser(self.target, &values);
+        let mut names: Vec<&Vec<u8>> = resolution.outcomes.keys().collect();
+        names.sort_unstable();
+        let macros = names
+            .into_iter()
+            .map(|name| {
+                let outcome = resolution.outcomes[name].clone();
+                let mut report = MacroReport::new(name.clone(), outcome);
+                let tokens = match (&report.outcome, crate::expr::paste(&self.macros[name])) {
+                    (Outcome::Evaluated(_), Ok(tokens)) => tokens,
+                    _ => return report,
+                };
+                report.ty = resolution.types.get(name).copied();
+                if let Ok((_, (_, warnings))) = parser.expr_with_warnings(&tokens) {
+                    report.warnings = warnings.into_iter().map(|w| w.kind).collect();
+                }
+                if let Ok((_, (_, dependencies))) = parser.expr_with_dependencies(&tokens) {
+                    report.dependencies = dependencies.into_keys().collect();
+                    report.dependencies.sort_unstable();
+                }
+                report
+            })
+            .collect();
+        Report::new(self.target, macros)
+    }
+
+    /// Evaluate all macros in the set for each of `targets`, and return the
+    /// macros whose outcome is not the same for all of them, in order of
+    /// their names.
+    ///
+    /// Outcomes differ if they depend on the properties of the target that
+    /// [`TargetInfo`] describes, such as the width of `size_t`.
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::macros::MacroSet;
+    /// use cexpr::target::TargetInfo;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new();
+    /// macros.insert(b"MAX".to_vec(), tokenize(b"(size_t)-1"));
+    /// macros.insert(b"ONE".to_vec(), tokenize(b"(size_t)1"));
+    /// let targets = [
+    ///     TargetInfo { pointer_width: 32, ..Default::default() },
+    ///     TargetInfo { pointer_width: 64, ..Default::default() },
+    /// ];
+    /// let differences = macros.differences(&targets);
+    /// assert_eq!(differences.len(), 1);
+    /// assert_eq!(differences[0].name, b"MAX");
+    /// ```
+    pub fn differences(&self, targets: &[TargetInfo]) -> Vec<Difference> {
+        let resolutions: Vec<Resolution> = targets
+            .iter()
+            .map(|&target| self.resolve_for(target))
+            .collect();
+        let mut names: Vec<&Vec<u8>> = self.macros.keys().collect();
+        names.sort_unstable();
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let outcomes: Vec<Outcome> = resolutions
+                    .iter()
+                    .map(|resolution| resolution.outcomes[name].clone())
+                    .collect();
+                if outcomes.windows(2).all(|pair| same(&pair[0], &pair[1])) {
+                    None
+                } else {
+                    Some(Difference {
+                        name: name.clone(),
+                        outcomes,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Evaluate the macros of the set, and return a [`Stream`] that
+    /// evaluates more macros with their values, without keeping the
+    /// replacement lists of any of them.
+    pub fn into_stream(self) -> Stream {
+        let values = self.resolve().typed_values();
+        Stream {
+            set: MacroSet {
+                macros: HashMap::new(),
+                ..self
+            },
+            values,
+        }
+    }
+
+    pub(crate) fn resolve_for(&self, target: TargetInfo) -> Resolution {
+        self.resolve_macros(target, self.macros.keys().map(|name| &name[..]).collect())
+    }
+
+    /// Evaluate the macros `names` of the set, and the macros they use.
+    fn resolve_macros<'a>(&'a self, target: TargetInfo, mut names: Vec<&'a [u8]>) -> Resolution {
+        let mut uses: HashMap<&[u8], Vec<&[u8]>> = HashMap::new();
+        while let Some(name) = names.pop() {
+            if !uses.contains_key(name) {
+                let used = self.uses(&self.macros[name]);
+                names.extend(&used);
+                uses.insert(name, used);
+            }
+        }
+        let mut pending: Vec<&[u8]> = uses.keys().cloned().collect();
+        pending.sort_unstable();
+        let mut values = HashMap::new();
+        let mut resolution = Resolution::default();
+        while !pending.is_empty() {
+            let before = pending.len();
+            pending.retain(|&name| {
+                if uses[name]
+                    .iter()
+                    .any(|&used| !resolution.outcomes.contains_key(used))
+                {
+                    return true;
+                }
+                let tokens = &self.macros[name];
+                let (outcome, ty) = if self.statistics {
+                    let (outcome, ty, statistics) =
+                        self.evaluate_with_statistics(target, &values, tokens);
+                    resolution.statistics.insert(name.to_vec(), statistics);
+                    (outcome, ty)
+                } else {
+                    self.evaluate(target, &values, tokens)
+                };
+                if let (Outcome::Evaluated(value), Some(ty)) = (&outcome, ty) {
+                    values.insert(name.to_vec(), (value.clone(), ty));
+                }
+                if let Some(ty) = ty {
+                    resolution.types.insert(name.to_vec(), ty);
+                }
+                resolution.outcomes.insert(name.to_vec(), outcome);
+                false
+            });
+            if pending.len() == before {
+                let cyclic: Vec<&[u8]> = pending
+                    .iter()
+                    .cloned()
+                    .filter(|&name| reaches(name, &uses, &resolution.outcomes))
+                    .collect();
+                for &name in &cyclic {
+                    resolution.outcomes.insert(name.to_vec(), Outcome::Cyclic);
+                }
+                pending.retain(|name| !cyclic.contains(name));
+            }
+        }
+        resolution
+    }
+
+    /// The macros of the set that `tokens` use, after the tokens around `##`
+    /// are pasted together, including those used by the function-like macros
+    /// they invoke.
+    fn uses(&self, tokens: &[Token]) -> Vec<&[u8]> {
+        let tokens = crate::expr::paste(tokens).unwrap_or_default();
+        let mut identifiers: Vec<&[u8]> = tokens
+            .iter()
+            .filter(|token| token.kind == TokenKind::Identifier)
+            .map(|token| &token.raw[..])
+            .collect();
+        let mut invoked = HashSet::new();
+        let mut uses = vec![];
+        while let Some(identifier) = identifiers.pop() {
+            if let Some((name, _)) = self.macros.get_key_value(identifier) {
+                uses.push(&name[..]);
+            }
+            if let Some((name, definition)) = self.fn_macros.get_key_value(identifier) {
+                if invoked.insert(name) {
+                    identifiers.extend(
+                        definition
+                            .body
+                            .iter()
+                            .filter(|token| token.kind == TokenKind::Identifier)
+                            .map(|token| &token.raw[..])
+                            .filter(|&raw| !definition.params.iter().any(|p| p[..] == *raw)),
+                    );
+                }
+            }
+        }
+        uses.sort_unstable();
+        uses.dedup();
+        uses
+    }
+
+    /// Evaluate `tokens` with the values of the macros that were evaluated
+    /// before, and return the type of the value if there is one.
+    pub(crate) fn evaluate(
+        &self,
+        target: TargetInfo,
+        values: &HashMap<Vec<u8>, (EvalResult, Type)>,
+        tokens: &[Token],
+    ) -> (Outcome, Option<Type>) {
+        let tokens = match crate::expr::paste(tokens) {
+            Ok(tokens) => tokens,
+            Err(error) => return (Outcome::Invalid(error), None),
+        };
+        outcome(self.parser(target, values).expr_with_type(&tokens))
+    }
+
+    /// Evaluate `tokens` like [`evaluate`](MacroSet::evaluate), and return
+    /// how long that took and the operators that were applied.
+    fn evaluate_with_statistics(
+        &self,
+        target: TargetInfo,
+        values: &HashMap<Vec<u8>, (EvalResult, Type)>,
+        tokens: &[Token],
+    ) -> (Outcome, Option<Type>, Statistics) {
+        let mut statistics = Statistics::default();
+        let parser = self.parser(target, values);
+        let start = Instant::now();
+        let tokens = crate::expr::paste(tokens);
+        if let Ok(tokens) = &tokens {
+            parser.parses(tokens);
+        }
+        statistics.parse = start.elapsed();
+        let tokens = match tokens {
+            Ok(tokens) => tokens,
+            Err(error) => return (Outcome::Invalid(error), None, statistics),
+        };
+        let start = Instant::now();
+        let result = parser.expr_with_type_and_trace(&tokens);
+        statistics.evaluation = start.elapsed();
+        let (result, trace) = match result {
+            Ok((rest, (value, ty, trace))) => (Ok((rest, (value, ty))), Some(trace)),
+            Err(e) => (Err(e), None),
+        };
+        let (outcome, ty) = outcome(result);
+        if let (Outcome::Evaluated(_), Some(trace)) = (&outcome, trace) {
+            for step in trace.steps() {
+                match step {
+                    Step::Unary { op, .. } => {
+                        *statistics.unary_operators.entry(op.clone()).or_default() += 1
+                    }
+                    Step::Binary { op, .. } => {
+                        *statistics.binary_operators.entry(op.clone()).or_default() += 1
+                    }
+                    Step::Conversion {
+                        implicit: false, ..
+                    } => statistics.casts += 1,
+                    Step::Conversion { .. } => {}
+                }
+            }
+        }
+        (outcome, ty, statistics)
+    }
+
+    /// The parser that evaluates the macros of the set, with the values of
+    /// the macros that were evaluated before.
+    fn parser<'a>(
+        &'a self,
+        target: TargetInfo,
+        values: &'a HashMap<Vec<u8>, (EvalResult, Type)>,
+    ) -> IdentifierParser<'a> {
+        IdentifierParser::from_resolver(values)
+            .with_target(target)
+            .with_permissive(self.permissive)
+            .with_c23(self.c23)
+            .with_fn_macros(&self.fn_macros)
+    }
+}
+
+/// The outcome of evaluating a macro to `result`, and the type of the value
+/// if there is one.
+fn outcome(result: CResult<'_, (EvalResult, Type)>) -> (Outcome, Option<Type>) {
+    match crate::assert_full_parse(result) {
+        Ok((_, (value, ty))) => (Outcome::Evaluated(value), Some(ty)),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => (Outcome::Invalid(e.error), None),
+        Err(nom::Err::Incomplete(_)) => (Outcome::Invalid(ErrorKind::UnexpectedToken), None),
+    }
+}
+
+/// Whether `a` and `b` are the same outcome, where floating point values are
+/// the same if they have the same representation, so that a NaN is the same
+/// as itself.
+fn same(a: &Outcome, b: &Outcome) -> bool {
+    match (a, b) {
+        (Outcome::Evaluated(EvalResult::Float(a)), Outcome::Evaluated(EvalResult::Float(b))) => {
+            a.to_bits() == b.to_bits()
+        }
+        _ => a == b,
+    }
+}
+
+/// A macro whose outcome depends on the target, see
+/// [`MacroSet::differences`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// The name of the macro.
+    pub name: Vec<u8>,
+    /// The outcome for each target, in the order the targets were given.
+    pub outcomes: Vec<Outcome>,
+}
+
+/// What to show about a macro, see [`MacroSet::hover`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoverInfo {
+    /// The outcome of evaluating the macro.
+    pub value: Outcome,
+    /// The type of the value, if the macro was evaluated, see
+    /// [`Resolution::types`].
+    pub ty: Option<Type>,
+    /// The spelling of the replacement list, see [`MacroSet::spelling`].
+    pub expansion: String,
+    /// The macros whose values were used to compute the value, see
+    /// [`IdentifierParser::expr_with_dependencies`]. This is empty if the
+    /// macro was not evaluated.
+    pub dependencies: Dependencies,
+}
+
+/// Whether the macro `name` uses itself through macros that weren't
+/// evaluated yet.
+fn reaches(
+    name: &[u8],
+    uses: &HashMap<&[u8], Vec<&[u8]>>,
+    outcomes: &HashMap<Vec<u8>, Outcome>,
+) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = uses[name].clone();
+    while let Some(used) = stack.pop() {
+        if used == name {
+            return true;
+        }
+        if !outcomes.contains_key(used) && seen.insert(used) {
+            stack.extend(&uses[used]);
+        }
+    }
+    false
+}
+
+/// The outcomes of evaluating the macros of a [`MacroSet`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution {
+    /// The outcome of each macro.
+    pub outcomes: HashMap<Vec<u8>, Outcome>,
+    /// The type of each macro that was evaluated, as
+    /// [`expr_with_type`](IdentifierParser::expr_with_type) gives it. The
+    /// type of a macro that uses another macro follows from the type of that
+    /// one, so that `FLAGS` is an `unsigned int` with `#define FLAG 1u` and
+    /// `#define FLAGS (FLAG | 2)`.
+    pub types: HashMap<Vec<u8>, Type>,
+    /// The statistics of each macro that was evaluated or could not be, if
+    /// they were recorded, see [`MacroSet::with_statistics`]. Cyclic macros
+    /// are not evaluated, and have no statistics.
+    pub statistics: HashMap<Vec<u8>, Statistics>,
+}
+
+/// How long a macro took to evaluate, and the operators it applied, see
+/// [`MacroSet::with_statistics`].
+///
+/// cexpr evaluates an expression while it parses it, so the macro is parsed
+/// once without being evaluated to measure the time parsing takes, and then
+/// evaluated. The durations include the time taken to expand function-like
+/// macros, but not that of evaluating the object-like macros the macro uses,
+/// which are evaluated before it. The operators applied in an unselected
+/// branch of a conditional operator are not counted, and neither are those
+/// of a macro that could not be evaluated.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Statistics {
+    /// The time taken to paste the tokens around `##` and parse the macro.
+    pub parse: Duration,
+    /// The time taken to parse and evaluate the macro.
+    pub evaluation: Duration,
+    /// The number of times each unary operator was applied, by its spelling.
+    pub unary_operators: HashMap<String, usize>,
+    /// The number of times each binary operator was applied, by its
+    /// spelling.
+    pub binary_operators: HashMap<String, usize>,
+    /// The number of casts.
+    pub casts: usize,
+}
+
+impl Resolution {
+    /// The values of the macros that were evaluated, which can be used as
+    /// the identifiers of an [`IdentifierParser`].
+    pub fn values(&self) -> HashMap<Vec<u8>, EvalResult> {
+        self.outcomes
+            .iter()
+            .filter_map(|(name, outcome)| match outcome {
+                Outcome::Evaluated(value) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The values of the macros that were evaluated with their types, which
+    /// can be used as the identifiers of an [`IdentifierParser`] so that the
+    /// types of expressions using them follow from their types.
+    pub fn typed_values(&self) -> HashMap<Vec<u8>, (EvalResult, Type)> {
+        self.outcomes
+            .iter()
+            .filter_map(|(name, outcome)| match (outcome, self.types.get(name)) {
+                (Outcome::Evaluated(value), Some(&ty)) => Some((name.clone(), (value.clone(), ty))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The names of the macros that could not be evaluated, in order.
+    pub fn invalid(&self) -> Vec<&[u8]> {
+        self.names(|outcome| matches!(outcome, Outcome::Invalid(_)))
+    }
+
+    /// The names of the macros that use themselves, in order.
+    pub fn cyclic(&self) -> Vec<&[u8]> {
+        self.names(|outcome| *outcome == Outcome::Cyclic)
+    }
+
+    fn names(&self, f: impl Fn(&Outcome) -> bool) -> Vec<&[u8]> {
+        let mut names: Vec<&[u8]> = self
+            .outcomes
+            .iter()
+            .filter(|(_, outcome)| f(outcome))
+            .map(|(name, _)| &name[..])
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Evaluates macros one at a time as they are defined, for headers that are
+/// too large to keep all of their macros in memory, see
+/// [`MacroSet::into_stream`].
+///
+/// As in [`header::statistics`], each macro is evaluated when it is defined,
+/// with the values that the macros it uses have at that point: a macro that
+/// uses one that is defined later fails with
+/// [`ErrorKind::UnknownIdentifier`], and redefining a macro doesn't change
+/// the outcome of the macros that use it. A macro that uses itself is
+/// [`Outcome::Cyclic`].
+///
+/// Only the name, value and type of each macro that was evaluated and the
+/// definition of each function-like macro are kept. The replacement list of
+/// an object-like macro is discarded once it is evaluated, and so is the
+/// name of a macro that could not be evaluated, so the memory used grows
+/// with the number of macros that evaluate and not the size of the header.
+/// [`retained_bytes`](Stream::retained_bytes) tells how much that is.
+///
+/// # Example
+/// ```
+/// use std::num::Wrapping;
+/// use cexpr::expr::EvalResult;
+/// use cexpr::macros::{MacroSet, Outcome};
+///
+/// let mut stream = MacroSet::new().into_stream();
+/// let header = b"#define A 1\n#define KB(x) ((x) * 1024)\n#define B KB(A)\n#define C D\n";
+/// let outcomes: Vec<_> = stream.header(header).collect();
+/// assert_eq!(outcomes, [
+///     (b"A".to_vec(), Outcome::Evaluated(EvalResult::Int(Wrapping(1)))),
+///     (b"B".to_vec(), Outcome::Evaluated(EvalResult::Int(Wrapping(1024)))),
+///     (b"C".to_vec(), Outcome::Invalid(cexpr::ErrorKind::UnknownIdentifier)),
+/// ]);
+/// assert_eq!(stream.value(b"B"), Some(&EvalResult::Int(Wrapping(1024))));
+/// ```
+///
+/// [`header::statistics`]: crate::header::statistics
+#[derive(Debug, Clone)]
+pub struct Stream {
+    /// The function-like macros and the target. Object-like macros are only
+    /// in it while they are evaluated.
+    set: MacroSet,
+    values: HashMap<Vec<u8>, (EvalResult, Type)>,
+}
+
+impl Stream {
+    /// Read the `#define` directive `line`, and evaluate the macro if it is
+    /// object-like. Other lines, and function-like macros, give `None`.
+    pub fn define(&mut self, line: &[u8]) -> Option<(Vec<u8>, Outcome)> {
+        let (name, params, tokens) = crate::parse_define(line)?;
+        self.values.remove(&name);
+        if let Some(params) = params {
+            self.set.fn_macros.insert(
+                name,
+                FnMacro {
+                    params,
+                    body: tokens,
+                },
+            );
+            return None;
+        }
+        self.set.fn_macros.remove(&name);
+        self.set.macros.insert(name.clone(), tokens);
+        let cyclic = self.set.uses(&self.set.macros[&name]).contains(&&name[..]);
+        let tokens = self.set.macros.remove(&name).unwrap_or_default();
+        if cyclic {
+            return Some((name, Outcome::Cyclic));
+        }
+        let (outcome, ty) = self.set.evaluate(self.set.target, &self.values, &tokens);
+        if let (Outcome::Evaluated(value), Some(ty)) = (&outcome, ty) {
+            self.values.insert(name.clone(), (value.clone(), ty));
+        }
+        Some((name, outcome))
+    }
+
+    /// Read the `#define` directives of `header` in order, and give the
+    /// outcome of each object-like macro as it is evaluated. Include guards
+    /// are left out, as in [`MacroSet::from_header`].
+    pub fn header<'a>(
+        &'a mut self,
+        header: &'a [u8],
+    ) -> impl Iterator<Item = (Vec<u8>, Outcome)> + 'a {
+        crate::header::directives(header)
+            .filter(|&(_, guard)| !guard)
+            .filter_map(move |(line, _)| self.define(line))
+    }
+
+    /// The value of the macro `name`, if it was evaluated.
+    pub fn value(&self, name: &[u8]) -> Option<&EvalResult> {
+        self.values.get(name).map(|(value, _)| value)
+    }
+
+    /// The number of bytes of the names, values and function-like macros
+    /// that the stream keeps, not counting the overhead of the hash maps
+    /// they are in.
+    pub fn retained_bytes(&self) -> usize {
+        let values: usize = self
+            .values
+            .iter()
+            .map(|(name, (value, _))| {
+                let payload = match value {
+                    EvalResult::Str(s) => s.len(),
+                    _ => 0,
+                };
+                name.len() + std::mem::size_of::<(EvalResult, Type)>() + payload
+            })
+            .sum();
+        let fn_macros: usize = self
+            .set
+            .fn_macros
+            .iter()
+            .map(|(name, definition)| {
+                let params: usize = definition.params.iter().map(|p| p.len()).sum();
+                let body: usize = definition
+                    .body
+                    .iter()
+                    .map(|token| std::mem::size_of::<Token>() + token.raw.len())
+                    .sum();
+                name.len() + params + body
+            })
+            .sum();
+        values + fn_macros
+    }
+}
@@ -0,0 +1,758 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! A machine-readable report of how the macros of a [`MacroSet`] evaluate.
+//!
+//! [`MacroSet::report`] describes the outcome, value, type, warnings and
+//! dependencies of every macro as a [`Report`], which
+//! [`to_json`](Report::to_json) writes as JSON. The JSON has one macro per
+//! line, in order of their names, so that the reports of different versions
+//! of cexpr, or for different targets, can be compared with `diff`:
+//!
+//! ```text
+//! {"version":1,"target":{"pointer_width":64,"long_width":64,"wchar_width":32,"char_signed":true},"macros":[
+//! {"name":"FLAG","outcome":"evaluated","value":{"int":1},"type":"unsigned int","warnings":[],"dependencies":[]},
+//! {"name":"FLAGS","outcome":"evaluated","value":{"int":3},"type":"unsigned int","warnings":[],"dependencies":["FLAG"]},
+//! {"name":"LOOP","outcome":"cyclic"}
+//! ]}
+//! ```
+//!
+//! A value is one of `{"int":n}`, `{"float":x}`, `{"char":n}` for a character
+//! with the code point `n`, `{"raw_char":n}` for a character that is not a
+//! Unicode scalar value, `{"str":s}` or `null` for
+//! [`EvalResult::Invalid`]. A float that is not finite is the string `"NaN"`,
+//! `"inf"` or `"-inf"`. Names and the bytes of strings are written as the
+//! characters with the same codes, so that bytes that aren't UTF-8 are kept.
+//! An `"invalid"` outcome has an `"error"`, the name of the [`ErrorKind`] in
+//! snake case, such as `"unknown_identifier"`, and warnings are named the
+//! same way after [`WarningKind`]s. The errors about an expected token also
+//! have the `"kind"` of the token, such as `"punctuation"`, and all but
+//! `"typed_token"` have the `"expected"` spelling, or list of spellings.
+//!
+//! [`Report::from_json`] reads the JSON back. As it has the width of `long`,
+//! the target read back has no [`long_width`](TargetInfo::long_width) if
+//! `long` is as wide as a pointer.
+//!
+//! The format is identified by [`VERSION`], which is incremented whenever it
+//! changes, including when a name is added.
+//!
+//! [`MacroSet`]: crate::macros::MacroSet
+//! [`MacroSet::report`]: crate::macros::MacroSet::report
+
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::num::Wrapping;
+
+use crate::expr::{EvalResult, WarningKind, BINARY_OPERATORS};
+use crate::literal::CChar;
+use crate::macros::Outcome;
+use crate::target::{IntType, TargetInfo, Type};
+use crate::token::Kind;
+use crate::ErrorKind;
+
+/// The version of the format of reports.
+pub const VERSION: u32 = 1;
+
+/// How the macros of a [`MacroSet`](crate::macros::MacroSet) evaluate, see
+/// [`MacroSet::report`](crate::macros::MacroSet::report).
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Report {
+    /// The version of the format, [`VERSION`].
+    pub version: u32,
+    /// The target the macros were evaluated for.
+    pub target: TargetInfo,
+    /// Each macro, in order of their names.
+    pub macros: Vec<MacroReport>,
+}
+
+/// How a macro evaluates, see [`Report`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct MacroReport {
+    /// The name of the macro.
+    pub name: Vec<u8>,
+    /// The outcome of evaluating the macro.
+    pub outcome: Outcome,
+    /// The type of the value, if the macro was evaluated.
+    pub ty: Option<Type>,
+    /// The warnings about the evaluation, in the order they were found.
+    pub warnings: Vec<WarningKind>,
+    /// The macros whose values were used to compute the value, in order.
+    pub dependencies: Vec<Vec<u8>>,
+}
+
+impl Report {
+    pub(crate) fn new(target: TargetInfo, macros: Vec<MacroReport>) -> Report {
+        Report {
+            version: VERSION,
+            target,
+            macros,
+        }
+    }
+
+    /// The report as JSON, in the format described in the
+    /// [module documentation](self).
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let target = &self.target;
+        let _ = write!(json, "{{\"version\":{},", self.version);
+        let _ = write!(
+            json,
+            "\"target\":{{\"pointer_width\":{},\"long_width\":{},",
+            target.pointer_width,
+            target.long_width(),
+        );
+        let _ = writeln!(
+            json,
+            "\"wchar_width\":{},\"char_signed\":{}}},\"macros\":[",
+            target.wchar_width, target.char_signed,
+        );
+        for (i, report) in self.macros.iter().enumerate() {
+            if i > 0 {
+                json.push_str(",\n");
+            }
+            report.write_json(&mut json);
+        }
+        if !self.macros.is_empty() {
+            json.push('\n');
+        }
+        json.push_str("]}\n");
+        json
+    }
+
+    /// Read a report written by [`to_json`](Report::to_json).
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::macros::MacroSet;
+    /// use cexpr::report::Report;
+    /// use cexpr::token::tokenize;
+    ///
+    /// let mut macros = MacroSet::new();
+    /// macros.insert(b"A".to_vec(), tokenize(b"(B + 1)"));
+    /// macros.insert(b"B".to_vec(), tokenize(b"1"));
+    /// let report = macros.report();
+    /// assert_eq!(Report::from_json(&report.to_json()), Ok(report));
+    /// ```
+    pub fn from_json(json: &str) -> Result<Report, ReadError> {
+        let mut parser = Parser { json, at: 0 };
+        let report = parser.value()?;
+        parser.whitespace();
+        if parser.at < json.len() {
+            return Err(ReadError::Invalid(parser.at));
+        }
+
+        let version = report.field("version")?;
+        match version.number::<u64>()? {
+            n if n == u64::from(VERSION) => {}
+            n => return Err(ReadError::Version(n)),
+        }
+        let target = report.field("target")?;
+        let pointer_width = target.field("pointer_width")?.number()?;
+        let long_width = target.field("long_width")?.number()?;
+        let target = TargetInfo {
+            pointer_width,
+            long_width: Some(long_width).filter(|&width| width != pointer_width),
+            wchar_width: target.field("wchar_width")?.number()?,
+            char_signed: target.field("char_signed")?.boolean()?,
+        };
+        let macros = report
+            .field("macros")?
+            .array()?
+            .iter()
+            .map(MacroReport::read_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Report::new(target, macros))
+    }
+}
+
+/// Why [`Report::from_json`] could not read a report.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReadError {
+    /// The report is in a different version of the format, given here.
+    Version(u64),
+    /// The JSON is malformed, or is not a report, at this byte offset.
+    Invalid(usize),
+}
+
+impl MacroReport {
+    pub(crate) fn new(name: Vec<u8>, outcome: Outcome) -> MacroReport {
+        MacroReport {
+            name,
+            outcome,
+            ty: None,
+            warnings: vec![],
+            dependencies: vec![],
+        }
+    }
+
+    fn write_json(&self, json: &mut String) {
+        json.push_str("{\"name\":");
+        write_string(json, &self.name);
+        match &self.outcome {
+            Outcome::Evaluated(value) => {
+                json.push_str(",\"outcome\":\"evaluated\",\"value\":");
+                write_value(json, value);
+                json.push_str(",\"type\":");
+                match self.ty {
+                    Some(ty) => write_string(json, type_name(ty).as_bytes()),
+                    None => json.push_str("null"),
+                }
+                json.push_str(",\"warnings\":[");
+                for (i, &warning) in self.warnings.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    write_string(json, warning_name(warning).as_bytes());
+                }
+                json.push_str("],\"dependencies\":[");
+                for (i, dependency) in self.dependencies.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    write_string(json, dependency);
+                }
+                json.push(']');
+            }
+            Outcome::Invalid(error) => {
+                json.push_str(",\"outcome\":\"invalid\",\"error\":");
+                write_string(json, error_name(error).as_bytes());
+                let kind = match *error {
+                    ErrorKind::ExactToken(kind, _)
+                    | ErrorKind::ExactTokens(kind, _)
+                    | ErrorKind::TypedToken(kind) => kind,
+                    _ => return json.push('}'),
+                };
+                json.push_str(",\"kind\":");
+                write_string(json, kind_name(kind).as_bytes());
+                match *error {
+                    ErrorKind::ExactToken(_, raw) => {
+                        json.push_str(",\"expected\":");
+                        write_string(json, raw);
+                    }
+                    ErrorKind::ExactTokens(_, list) => {
+                        json.push_str(",\"expected\":[");
+                        for (i, spelling) in list.iter().enumerate() {
+                            if i > 0 {
+                                json.push(',');
+                            }
+                            write_string(json, spelling.as_bytes());
+                        }
+                        json.push(']');
+                    }
+                    _ => {}
+                }
+            }
+            Outcome::Cyclic => json.push_str(",\"outcome\":\"cyclic\""),
+        }
+        json.push('}');
+    }
+
+    fn read_json(value: &Value<'_>) -> Result<MacroReport, ReadError> {
+        let name = value.field("name")?.string()?.to_vec();
+        let outcome = value.field("outcome")?;
+        let mut report = MacroReport::new(name, Outcome::Cyclic);
+        match outcome.string()? {
+            b"evaluated" => {
+                report.outcome = Outcome::Evaluated(read_value(value.field("value")?)?);
+                let ty = value.field("type")?;
+                report.ty = match ty.json {
+                    Json::Null => None,
+                    _ => Some(find(TYPES, ty, |&ty| type_name(ty))?),
+                };
+                for warning in value.field("warnings")?.array()? {
+                    let warning = find(WARNINGS, warning, |&warning| warning_name(warning))?;
+                    report.warnings.push(warning);
+                }
+                for dependency in value.field("dependencies")?.array()? {
+                    report.dependencies.push(dependency.string()?.to_vec());
+                }
+            }
+            b"invalid" => report.outcome = Outcome::Invalid(read_error(value)?),
+            b"cyclic" => {}
+            _ => return Err(outcome.invalid()),
+        }
+        Ok(report)
+    }
+}
+
+fn write_value(json: &mut String, value: &EvalResult) {
+    match value {
+        EvalResult::Int(i) => {
+            let _ = write!(json, "{{\"int\":{}}}", i.0);
+        }
+        EvalResult::Float(f) if f.is_nan() => json.push_str("{\"float\":\"NaN\"}"),
+        EvalResult::Float(f) if f.is_infinite() => {
+            let sign = if *f < 0.0 { "-" } else { "" };
+            let _ = write!(json, "{{\"float\":\"{}inf\"}}", sign);
+        }
+        EvalResult::Float(f) => {
+            let _ = write!(json, "{{\"float\":{:?}}}", f);
+        }
+        EvalResult::Char(CChar::Char(c)) => {
+            let _ = write!(json, "{{\"char\":{}}}", u32::from(*c));
+        }
+        EvalResult::Char(CChar::Raw(c)) => {
+            let _ = write!(json, "{{\"raw_char\":{}}}", c);
+        }
+        EvalResult::Str(s) => {
+            json.push_str("{\"str\":");
+            write_string(json, s);
+            json.push('}');
+        }
+        EvalResult::Invalid => json.push_str("null"),
+    }
+}
+
+/// Write `bytes` as a JSON string of the characters with the same codes.
+fn write_string(json: &mut String, bytes: &[u8]) {
+    json.push('"');
+    for &b in bytes {
+        match b {
+            b'"' => json.push_str("\\\""),
+            b'\\' => json.push_str("\\\\"),
+            0x20..=0x7e => json.push(char::from(b)),
+            _ => {
+                let _ = write!(json, "\\u{:04x}", b);
+            }
+        }
+    }
+    json.push('"');
+}
+
+fn read_value(value: &Value<'_>) -> Result<EvalResult, ReadError> {
+    let fields = match &value.json {
+        Json::Null => return Ok(EvalResult::Invalid),
+        Json::Object(fields) if fields.len() == 1 => fields,
+        _ => return Err(value.invalid()),
+    };
+    let (name, field) = (&fields[0].0[..], &fields[0].1);
+    Ok(match name {
+        b"int" => EvalResult::Int(Wrapping(field.number()?)),
+        b"float" => EvalResult::Float(match &field.json {
+            Json::Str(s) if s == b"NaN" => f64::NAN,
+            Json::Str(s) if s == b"inf" => f64::INFINITY,
+            Json::Str(s) if s == b"-inf" => f64::NEG_INFINITY,
+            _ => field.number()?,
+        }),
+        b"char" => {
+            let c = char::from_u32(field.number()?).ok_or_else(|| field.invalid())?;
+            EvalResult::Char(CChar::Char(c))
+        }
+        b"raw_char" => EvalResult::Char(CChar::Raw(field.number()?)),
+        b"str" => EvalResult::Str(field.string()?.to_vec()),
+        _ => return Err(value.invalid()),
+    })
+}
+
+fn read_error(value: &Value<'_>) -> Result<ErrorKind, ReadError> {
+    let error = find(ERRORS, value.field("error")?, error_name)?;
+    let kind = || find(KINDS, value.field("kind")?, |&kind| kind_name(kind));
+    Ok(match error {
+        ErrorKind::ExactToken(..) => {
+            let expected = value.field("expected")?;
+            let raw = find(SPELLINGS, expected, |&spelling| spelling)?;
+            ErrorKind::ExactToken(kind()?, raw.as_bytes())
+        }
+        ErrorKind::ExactTokens(..) => {
+            let expected = value.field("expected")?;
+            let spellings = expected.array()?;
+            let list = PUNCTUATION_LISTS.iter().find(|list| {
+                list.len() == spellings.len()
+                    && list
+                        .iter()
+                        .zip(spellings)
+                        .all(|(a, b)| matches!(&b.json, Json::Str(b) if a.as_bytes() == &b[..]))
+            });
+            ErrorKind::ExactTokens(kind()?, list.ok_or_else(|| expected.invalid())?)
+        }
+        ErrorKind::TypedToken(_) => ErrorKind::TypedToken(kind()?),
+        error => error,
+    })
+}
+
+/// The item of `items` with the name given by `value`.
+fn find<T: Clone>(
+    items: &[T],
+    value: &Value<'_>,
+    name: impl Fn(&T) -> &'static str,
+) -> Result<T, ReadError> {
+    let s = value.string()?;
+    let item = items.iter().find(|&item| name(item).as_bytes() == s);
+    item.cloned().ok_or_else(|| value.invalid())
+}
+
+/// Every type, to read their names.
+const TYPES: &[Type] = &[
+    Type::Int(IntType::Bool),
+    Type::Int(IntType::Char),
+    Type::Int(IntType::SignedChar),
+    Type::Int(IntType::UnsignedChar),
+    Type::Int(IntType::Short),
+    Type::Int(IntType::UnsignedShort),
+    Type::Int(IntType::Int),
+    Type::Int(IntType::UnsignedInt),
+    Type::Int(IntType::Long),
+    Type::Int(IntType::UnsignedLong),
+    Type::Int(IntType::LongLong),
+    Type::Int(IntType::UnsignedLongLong),
+    Type::Int(IntType::SizeT),
+    Type::Int(IntType::SSizeT),
+    Type::Int(IntType::UIntPtrT),
+    Type::Int(IntType::IntPtrT),
+    Type::Int(IntType::PtrDiffT),
+    Type::Float,
+    Type::Double,
+    Type::LongDouble,
+    Type::Str,
+];
+
+/// Every error, with placeholders for the tokens of the errors about an
+/// expected token, which are read separately.
+const ERRORS: &[ErrorKind] = &[
+    ErrorKind::ExactToken(Kind::Punctuation, b""),
+    ErrorKind::ExactTokens(Kind::Punctuation, &[]),
+    ErrorKind::TypedToken(Kind::Punctuation),
+    ErrorKind::UnexpectedToken,
+    ErrorKind::UnknownIdentifier,
+    ErrorKind::InvalidLiteral,
+    ErrorKind::LiteralOverflow,
+    ErrorKind::Partial,
+    ErrorKind::UnknownLayout,
+    ErrorKind::StringArithmetic,
+    ErrorKind::NotInteger,
+    ErrorKind::NotConstant,
+    ErrorKind::TooDeep,
+    ErrorKind::TooLarge,
+    ErrorKind::ArgumentCount,
+    ErrorKind::InvalidPaste,
+    ErrorKind::InvalidToken,
+    ErrorKind::DivisionByZero,
+    ErrorKind::Overflow,
+    ErrorKind::InvalidShift,
+    ErrorKind::Unsupported,
+];
+
+/// Every warning, to read their names.
+const WARNINGS: &[WarningKind] = &[
+    WarningKind::ChainedComparison,
+    WarningKind::EscapeOutOfRange,
+    WarningKind::AmbiguousOctalEscape,
+    WarningKind::NegativeRightShift,
+    WarningKind::SignedOverflow,
+    WarningKind::InvalidShift,
+    WarningKind::SignedConversion,
+    WarningKind::CharSignedness,
+    WarningKind::EnumeratorOutOfRange,
+];
+
+const KINDS: &[Kind] = &[
+    Kind::Punctuation,
+    Kind::Keyword,
+    Kind::Identifier,
+    Kind::Literal,
+    Kind::Comment,
+    Kind::Whitespace,
+];
+
+/// The tokens that the parsers expect, to read `"exact_token"` errors.
+const SPELLINGS: &[&str] = &[
+    "(",
+    ")",
+    ",",
+    "?",
+    ":",
+    "[",
+    "]",
+    ".",
+    "#",
+    "&",
+    "^",
+    "|",
+    "__builtin_choose_expr",
+    "__builtin_offsetof",
+    "typeof",
+    "typeof_unqual",
+    "sizeof",
+    "_Alignof",
+    "alignof",
+    "_Static_assert",
+    "defined",
+    "::",
+    "static_cast",
+    "const_cast",
+    "reinterpret_cast",
+    "<",
+    ">",
+    "...",
+];
+
+/// The lists of punctuation that the parsers expect one of, to read
+/// `"exact_tokens"` errors.
+const PUNCTUATION_LISTS: &[&[&str]] = &[
+    &["+", "-", "~", "!"],
+    &["*", "/", "%"],
+    &["+", "-"],
+    &["<<", ">>"],
+    &["<", ">", "<=", ">="],
+    &["==", "!="],
+    &["&"],
+    &["^"],
+    &["|"],
+    BINARY_OPERATORS,
+];
+
+fn type_name(ty: Type) -> &'static str {
+    ty.spelling().unwrap_or("char[]")
+}
+
+fn error_name(error: &ErrorKind) -> &'static str {
+    match error {
+        ErrorKind::ExactToken(..) => "exact_token",
+        ErrorKind::ExactTokens(..) => "exact_tokens",
+        ErrorKind::TypedToken(_) => "typed_token",
+        ErrorKind::UnexpectedToken => "unexpected_token",
+        ErrorKind::UnknownIdentifier => "unknown_identifier",
+        ErrorKind::InvalidLiteral => "invalid_literal",
+        ErrorKind::LiteralOverflow => "literal_overflow",
+        ErrorKind::Partial => "partial",
+        ErrorKind::UnknownLayout => "unknown_layout",
+        ErrorKind::StringArithmetic => "string_arithmetic",
+        ErrorKind::NotInteger => "not_integer",
+        ErrorKind::NotConstant => "not_constant",
+        ErrorKind::TooDeep => "too_deep",
+        ErrorKind::TooLarge => "too_large",
+        ErrorKind::ArgumentCount => "argument_count",
+        ErrorKind::InvalidPaste => "invalid_paste",
+        ErrorKind::InvalidToken => "invalid_token",
+        ErrorKind::DivisionByZero => "division_by_zero",
+        ErrorKind::Overflow => "overflow",
+        ErrorKind::InvalidShift => "invalid_shift",
+        ErrorKind::Unsupported => "unsupported",
+    }
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Punctuation => "punctuation",
+        Kind::Keyword => "keyword",
+        Kind::Identifier => "identifier",
+        Kind::Literal => "literal",
+        Kind::Comment => "comment",
+        Kind::Whitespace => "whitespace",
+    }
+}
+
+fn warning_name(warning: WarningKind) -> &'static str {
+    match warning {
+        WarningKind::ChainedComparison => "chained_comparison",
+        WarningKind::EscapeOutOfRange => "escape_out_of_range",
+        WarningKind::AmbiguousOctalEscape => "ambiguous_octal_escape",
+        WarningKind::NegativeRightShift => "negative_right_shift",
+        WarningKind::SignedOverflow => "signed_overflow",
+        WarningKind::InvalidShift => "invalid_shift",
+        WarningKind::SignedConversion => "signed_conversion",
+        WarningKind::CharSignedness => "char_signedness",
+        WarningKind::EnumeratorOutOfRange => "enumerator_out_of_range",
+    }
+}
+
+/// A JSON value, at the byte offset `at`.
+struct Value<'a> {
+    at: usize,
+    json: Json<'a>,
+}
+
+enum Json<'a> {
+    Null,
+    Bool(bool),
+    Number(&'a str),
+    /// A string of characters below `U+0100`, as the bytes with the same
+    /// codes.
+    Str(Vec<u8>),
+    Array(Vec<Value<'a>>),
+    Object(Vec<(Vec<u8>, Value<'a>)>),
+}
+
+impl<'a> Value<'a> {
+    fn invalid(&self) -> ReadError {
+        ReadError::Invalid(self.at)
+    }
+
+    fn field(&self, name: &str) -> Result<&Value<'a>, ReadError> {
+        match &self.json {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name.as_bytes())
+                .map(|(_, value)| value)
+                .ok_or_else(|| self.invalid()),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool, ReadError> {
+        match self.json {
+            Json::Bool(b) => Ok(b),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self) -> Result<T, ReadError> {
+        match self.json {
+            Json::Number(n) => n.parse().map_err(|_| self.invalid()),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn string(&self) -> Result<&[u8], ReadError> {
+        match &self.json {
+            Json::Str(s) => Ok(s),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn array(&self) -> Result<&[Value<'a>], ReadError> {
+        match &self.json {
+            Json::Array(values) => Ok(values),
+            _ => Err(self.invalid()),
+        }
+    }
+}
+
+/// A parser of the JSON `json`, at the byte offset `at`.
+struct Parser<'a> {
+    json: &'a str,
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn invalid(&self) -> ReadError {
+        ReadError::Invalid(self.at)
+    }
+
+    fn whitespace(&mut self) {
+        let rest = &self.json[self.at..];
+        self.at += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Skip `s`, if the input continues with it.
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.json[self.at..].starts_with(s);
+        if found {
+            self.at += s.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, ReadError> {
+        self.whitespace();
+        let at = self.at;
+        let json = if self.eat("null") {
+            Json::Null
+        } else if self.eat("true") {
+            Json::Bool(true)
+        } else if self.eat("false") {
+            Json::Bool(false)
+        } else if self.json[at..].starts_with('"') {
+            Json::Str(self.string()?)
+        } else if self.eat("[") {
+            let mut values = vec![];
+            self.whitespace();
+            if !self.eat("]") {
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    if self.eat("]") {
+                        break;
+                    } else if !self.eat(",") {
+                        return Err(self.invalid());
+                    }
+                }
+            }
+            Json::Array(values)
+        } else if self.eat("{") {
+            let mut fields = vec![];
+            self.whitespace();
+            if !self.eat("}") {
+                loop {
+                    self.whitespace();
+                    let name = self.string()?;
+                    self.whitespace();
+                    if !self.eat(":") {
+                        return Err(self.invalid());
+                    }
+                    fields.push((name, self.value()?));
+                    self.whitespace();
+                    if self.eat("}") {
+                        break;
+                    } else if !self.eat(",") {
+                        return Err(self.invalid());
+                    }
+                }
+            }
+            Json::Object(fields)
+        } else {
+            let rest = &self.json[at..];
+            let len = rest
+                .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(self.invalid());
+            }
+            self.at += len;
+            Json::Number(&rest[..len])
+        };
+        Ok(Value { at, json })
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, ReadError> {
+        if !self.eat("\"") {
+            return Err(self.invalid());
+        }
+        let mut bytes = vec![];
+        loop {
+            let start = self.at;
+            let mut chars = self.json[start..].chars();
+            let c = match chars.next() {
+                Some('"') => {
+                    self.at += 1;
+                    return Ok(bytes);
+                }
+                Some('\\') => {
+                    let c = match chars.next() {
+                        Some('u') => {
+                            let hex = self.json.get(start + 2..start + 6);
+                            self.at += 4;
+                            hex.filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => Some(u32::from(c)),
+                        Some('b') => Some(0x08),
+                        Some('f') => Some(0x0c),
+                        Some('n') => Some(0x0a),
+                        Some('r') => Some(0x0d),
+                        Some('t') => Some(0x09),
+                        _ => None,
+                    };
+                    self.at += 2;
+                    c
+                }
+                Some(c) if c >= ' ' => {
+                    self.at += c.len_utf8();
+                    Some(u32::from(c))
+                }
+                _ => None,
+            };
+            match c.and_then(|c| u8::try_from(c).ok()) {
+                Some(b) => bytes.push(b),
+                None => return Err(ReadError::Invalid(start)),
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Description of the compilation target.
+//!
+//! Some C expressions evaluate differently depending on the target they are
+//! compiled for. For example, `(size_t)-1` is `0xffffffff` on a target with
+//! 32-bit pointers but `0xffffffffffffffff` on a target with 64-bit pointers.
+//! The `TargetInfo` type describes the properties of the target that cexpr
+//! takes into account.
+
+/// Properties of the compilation target that affect expression evaluation.
+///
+/// The default is a target with 64-bit pointers and `long`, which is the
+/// [`DataModel::LP64`], a 32-bit `wchar_t` and a signed `char`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetInfo {
+    /// The width of a pointer in bits.
+    ///
+    /// This is also the width of `size_t`, `ssize_t`, `uintptr_t`, `intptr_t`
+    /// and `ptrdiff_t`.
+    pub pointer_width: u32,
+    /// The width of `long` in bits, if it is not as wide as a pointer.
+    ///
+    /// `long` is 32 bits wide on 64-bit Windows, see [`DataModel::LLP64`],
+    /// and as wide as a pointer on most other targets.
+    pub long_width: Option<u32>,
+    /// The width of `wchar_t` in bits, which is the type of wide character
+    /// literals such as `L'a'`.
+    ///
+    /// `wchar_t` is a 16-bit `unsigned short` on Windows, and a 32-bit `int`
+    /// on most other targets. Any other width is taken to be an `int`.
+    pub wchar_width: u32,
+    /// Whether `char` is signed.
+    ///
+    /// `char` is signed on x86, and unsigned on most ARM, PowerPC and RISC-V
+    /// targets other than Apple's and Windows.
+    pub char_signed: bool,
+}
+
+impl Default for TargetInfo {
+    fn default() -> TargetInfo {
+        TargetInfo {
+            pointer_width: 64,
+            long_width: None,
+            wchar_width: 32,
+            char_signed: true,
+        }
+    }
+}
+
+impl TargetInfo {
+    /// The width of `long` in bits.
+    pub fn long_width(&self) -> u32 {
+        self.long_width.unwrap_or(self.pointer_width)
+    }
+}
+
+/// The widths of `int`, `long` and pointers of a family of targets.
+///
+/// # Example
+/// ```
+/// use cexpr::target::{DataModel, IntType, TargetInfo};
+///
+/// let windows = TargetInfo::from(DataModel::LLP64);
+/// assert_eq!(IntType::Long.width(&windows), 32);
+/// assert_eq!(IntType::SizeT.width(&windows), 64);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DataModel {
+    /// 32-bit `int`, `long` and pointers, as on most 32-bit targets.
+    ILP32,
+    /// 32-bit `int`, and 64-bit `long` and pointers, as on 64-bit Unix-like
+    /// targets.
+    LP64,
+    /// 32-bit `int` and `long`, and 64-bit pointers, as on 64-bit Windows.
+    LLP64,
+}
+
+/// A target with the widths of `model`, and otherwise the default
+/// properties. On Windows, `wchar_t` is 16 bits wide, which needs to be set
+/// separately.
+impl From<DataModel> for TargetInfo {
+    fn from(model: DataModel) -> TargetInfo {
+        let (pointer_width, long_width) = match model {
+            DataModel::ILP32 => (32, None),
+            DataModel::LP64 => (64, None),
+            DataModel::LLP64 => (64, Some(32)),
+        };
+        TargetInfo {
+            pointer_width,
+            long_width,
+            ..TargetInfo::default()
+        }
+    }
+}
+
+/// A C integer type.
+///
+/// The width of some types depends on the target, see [`IntType::width`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IntType {
+    /// `_Bool`, or `bool` in C++.
+    Bool,
+    /// `char`, which is signed if [`TargetInfo::char_signed`] is set.
+    Char,
+    /// `signed char`.
+    SignedChar,
+    /// `unsigned char`.
+    UnsignedChar,
+    /// `short`.
+    Short,
+    /// `unsigned short`.
+    UnsignedShort,
+    /// `int`.
+    Int,
+    /// `unsigned int`.
+    UnsignedInt,
+    /// `long`.
+    Long,
+    /// `unsigned long`.
+    UnsignedLong,
+    /// `long long`.
+    LongLong,
+    /// `unsigned long long`.
+    UnsignedLongLong,
+    /// `size_t`.
+    SizeT,
+    /// `ssize_t`.
+    SSizeT,
+    /// `uintptr_t`.
+    UIntPtrT,
+    /// `intptr_t`.
+    IntPtrT,
+    /// `ptrdiff_t`.
+    PtrDiffT,
+}
+
+impl IntType {
+    /// The width of this type in bits.
+    ///
+    /// `_Bool` and `char` are 8 bits, `short` is 16 bits, `int` is 32 bits and
+    /// `long long` is 64 bits wide. `long` is as wide as
+    /// [`TargetInfo::long_width`], and the pointer-sized types are as wide as a
+    /// pointer on `target`.
+    pub fn width(self, target: &TargetInfo) -> u32 {
+        use self::IntType::*;
+        match self {
+            Bool | Char | SignedChar | UnsignedChar => 8,
+            Short | UnsignedShort => 16,
+            Int | UnsignedInt => 32,
+            LongLong | UnsignedLongLong => 64,
+            Long | UnsignedLong => target.long_width(),
+            SizeT | SSizeT | UIntPtrT | IntPtrT | PtrDiffT => target.pointer_width,
+        }
+    }
+
+    /// Whether this type is signed on `target`.
+    pub fn is_signed(self, target: &TargetInfo) -> bool {
+        use self::IntType::*;
+        match self {
+            Char => target.char_signed,
+            SignedChar | Short | Int | Long | LongLong | SSizeT | IntPtrT | PtrDiffT => true,
+            Bool | UnsignedChar | UnsignedShort | UnsignedInt | UnsignedLong | UnsignedLongLong
+            | SizeT | UIntPtrT => false,
+        }
+    }
+
+    /// The C spelling of this type.
+    pub(crate) fn spelling(self) -> &'static str {
+        use self::IntType::*;
+        match self {
+            Bool => "_Bool",
+            Char => "char",
+            SignedChar => "signed char",
+            UnsignedChar => "unsigned char",
+            Short => "short",
+            UnsignedShort => "unsigned short",
+            Int => "int",
+            UnsignedInt => "unsigned int",
+            Long => "long",
+            UnsignedLong => "unsigned long",
+            LongLong => "long long",
+            UnsignedLongLong => "unsigned long long",
+            SizeT => "size_t",
+            SSizeT => "ssize_t",
+            UIntPtrT => "uintptr_t",
+            IntPtrT => "intptr_t",
+            PtrDiffT => "ptrdiff_t",
+        }
+    }
+
+    /// Convert `value` to this type, wrapping around if it is out of range.
+    /// Any value other than `0` converts to `1` as a `_Bool`.
+    ///
+    /// Values are never wider than 64 bits, so wider types are treated as 64
+    /// bits wide.
+    pub(crate) fn wrap(self, value: i128, target: &TargetInfo) -> i128 {
+        if self == IntType::Bool {
+            return (value != 0) as i128;
+        }
+        let modulus = 1i128 << self.width(target).min(64);
+        let value = value.rem_euclid(modulus);
+        if self.is_signed(target) && value >= modulus / 2 {
+            value - modulus
+        } else {
+            value
+        }
+    }
+
+    /// The suffix of an integer literal with the type of this type after the
+    /// integer promotions, if that is unsigned: `ul` for `size_t` on 64-bit
+    /// Unix-like targets, for example.
+    pub(crate) fn unsigned_suffix(self, target: &TargetInfo) -> Option<&'static str> {
+        use self::IntType::*;
+        match self.promoted().underlying(target) {
+            UnsignedInt => Some("u"),
+            UnsignedLong => Some("ul"),
+            UnsignedLongLong => Some("ull"),
+            _ => None,
+        }
+    }
+
+    /// The rank of this type in the usual arithmetic conversions. The
+    /// pointer-sized types rank at least as high as `long`.
+    fn rank(self) -> u8 {
+        use self::IntType::*;
+        match self {
+            Bool => 0,
+            Char | SignedChar | UnsignedChar => 1,
+            Short | UnsignedShort => 2,
+            Int | UnsignedInt => 3,
+            Long | UnsignedLong | SizeT | SSizeT | UIntPtrT | IntPtrT | PtrDiffT => 4,
+            LongLong | UnsignedLongLong => 5,
+        }
+    }
+
+    /// The basic type a `typedef` such as `size_t` stands for on `target`:
+    /// `long` if that is as wide as a pointer, and `long long` otherwise.
+    fn underlying(self, target: &TargetInfo) -> IntType {
+        use self::IntType::*;
+        let long = target.long_width() == target.pointer_width;
+        match self {
+            SizeT | UIntPtrT if long => UnsignedLong,
+            SSizeT | IntPtrT | PtrDiffT if long => Long,
+            SizeT | UIntPtrT => UnsignedLongLong,
+            SSizeT | IntPtrT | PtrDiffT => LongLong,
+            ty => ty,
+        }
+    }
+
+    /// The unsigned type with the same rank as this basic type.
+    fn to_unsigned(self) -> IntType {
+        use self::IntType::*;
+        match self {
+            Char | SignedChar => UnsignedChar,
+            Short => UnsignedShort,
+            Int => UnsignedInt,
+            Long => UnsignedLong,
+            LongLong => UnsignedLongLong,
+            ty => ty,
+        }
+    }
+
+    /// The type of this type after the integer promotions: types narrower
+    /// than `int` become `int`.
+    pub(crate) fn promoted(self) -> IntType {
+        if self.rank() < IntType::Int.rank() {
+            IntType::Int
+        } else {
+            self
+        }
+    }
+
+    /// The common type of operands of type `self` and `other`, according to
+    /// the usual arithmetic conversions.
+    ///
+    /// If the common type is that of one of the operands, that type is
+    /// returned as spelled, so `size_t` and `int` have the common type
+    /// `size_t`.
+    fn common(self, other: IntType, target: &TargetInfo) -> IntType {
+        let (a, b) = (self.promoted(), other.promoted());
+        let (ua, ub) = (a.underlying(target), b.underlying(target));
+        let common = if ua == ub {
+            ua
+        } else if ua.is_signed(target) == ub.is_signed(target) {
+            if ua.rank() >= ub.rank() {
+                ua
+            } else {
+                ub
+            }
+        } else {
+            let (signed, unsigned) = if ua.is_signed(target) {
+                (ua, ub)
+            } else {
+                (ub, ua)
+            };
+            if unsigned.rank() >= signed.rank() {
+                unsigned
+            } else if signed.width(target) > unsigned.width(target) {
+                signed
+            } else {
+                signed.to_unsigned()
+            }
+        };
+        if ua == common {
+            a
+        } else if ub == common {
+            b
+        } else {
+            common
+        }
+    }
+}
+
+/// The C type of a value, see
+/// [`IdentifierParser::expr_with_type`](crate::expr::IdentifierParser::expr_with_type).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Type {
+    /// An integer type.
+    Int(IntType),
+    /// `float`.
+    Float,
+    /// `double`.
+    Double,
+    /// `long double`.
+    LongDouble,
+    /// An array of `char`, the type of a string literal.
+    Str,
+}
+
+impl Type {
+    /// The type of this type after the integer promotions.
+    pub(crate) fn promoted(self) -> Type {
+        match self {
+            Type::Int(ty) => Type::Int(ty.promoted()),
+            ty => ty,
+        }
+    }
+
+    /// The C spelling of this type, unless it is an array.
+    pub(crate) fn spelling(self) -> Option<&'static str> {
+        match self {
+            Type::Int(ty) => Some(ty.spelling()),
+            Type::Float => Some("float"),
+            Type::Double => Some("double"),
+            Type::LongDouble => Some("long double"),
+            Type::Str => None,
+        }
+    }
+
+    /// The common type of operands of type `self` and `other`, according to
+    /// the usual arithmetic conversions.
+    pub(crate) fn common(self, other: Type, target: &TargetInfo) -> Type {
+        use self::Type::*;
+        match (self, other) {
+            (Int(a), Int(b)) => Int(a.common(b, target)),
+            (LongDouble, _) | (_, LongDouble) => LongDouble,
+            (Double, _) | (_, Double) => Double,
+            (Float, _) | (_, Float) => Float,
+            (Str, _) | (_, Str) => Str,
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Validating cexpr against the macros of a C header.
+//!
+//! This module is only available with the `testing` feature.
+//!
+//! A [`Checker`] evaluates macro definitions in the order they appear in a
+//! header and compares the results against expected values. Successfully
+//! evaluated macros are available as identifiers to later macros. The
+//! definitions need to be supplied by the caller, for example by tokenizing
+//! the header with libclang, as cexpr's own test suite does.
+//!
+//! The expected values are either given as a table, or encoded in the macro
+//! names, see [`expected_from_name`]. The include guard of the header can be
+//! left out of the check, see [`Checker::with_header`]. A macro that is
+//! defined again with a different definition is reported as a
+//! [`Failure::Conflict`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::num::Wrapping;
+use std::path::Path;
+use std::str::{self, FromStr};
+
+use crate::assert_full_parse;
+use crate::expr::{fn_macro_declaration, EvalResult, IdentifierParser};
+use crate::literal::CChar;
+use crate::token::Token;
+
+/// A macro that did not evaluate as expected.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(missing_docs)]
+pub enum Failure {
+    /// The macro did not evaluate to the expected value. `actual` is `None` if
+    /// evaluation failed.
+    Value {
+        name: Vec<u8>,
+        expected: EvalResult,
+        actual: Option<EvalResult>,
+    },
+    /// The macro was defined before, with the same definition.
+    Redefined { name: Vec<u8> },
+    /// The macro was defined before with a different definition, which C
+    /// doesn't allow. Definitions are the same if they have the same tokens.
+    /// `previous` and `redefined` are the numbers of the two definitions in
+    /// the order they were checked, starting at 1.
+    Conflict {
+        name: Vec<u8>,
+        previous: usize,
+        redefined: usize,
+    },
+    /// The macro name does not encode an expected value.
+    InvalidName { name: Vec<u8> },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Failure::Value {
+                ref name,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "{}: expected {:?}, got {:?}",
+                String::from_utf8_lossy(name),
+                expected,
+                actual
+            ),
+            Failure::Redefined { ref name } => {
+                write!(f, "{}: duplicate definition", String::from_utf8_lossy(name))
+            }
+            Failure::Conflict {
+                ref name,
+                previous,
+                redefined,
+            } => write!(
+                f,
+                "{}: definition {} conflicts with definition {}",
+                String::from_utf8_lossy(name),
+                redefined,
+                previous
+            ),
+            Failure::InvalidName { ref name } => write!(
+                f,
+                "{}: name does not encode an expected value",
+                String::from_utf8_lossy(name)
+            ),
+        }
+    }
+}
+
+/// Evaluates macro definitions in order and compares them against expected
+/// values.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    identifiers: HashMap<Vec<u8>, EvalResult>,
+    expected: Option<HashMap<Vec<u8>, EvalResult>>,
+    header: Option<String>,
+    definitions: HashMap<Vec<u8>, (usize, Vec<Token>)>,
+    checked: usize,
+}
+
+impl Checker {
+    /// Check macros against a table of expected values.
+    ///
+    /// An expected value of `EvalResult::Invalid` means that evaluation is
+    /// expected to fail. Macros that are not in the table are evaluated but
+    /// not checked. All macros are treated as object-like macros, since
+    /// function-like macros can't be told apart by their tokens.
+    pub fn new(expected: HashMap<Vec<u8>, EvalResult>) -> Checker {
+        Checker {
+            identifiers: HashMap::new(),
+            expected: Some(expected),
+            header: None,
+            definitions: HashMap::new(),
+            checked: 0,
+        }
+    }
+
+    /// Check macros against the values encoded in their names, as described
+    /// for [`expected_from_name`].
+    pub fn from_names() -> Checker {
+        Checker {
+            identifiers: HashMap::new(),
+            expected: None,
+            header: None,
+            definitions: HashMap::new(),
+            checked: 0,
+        }
+    }
+
+    /// Skip the include guard of the header `file` instead of checking it,
+    /// see [`is_include_guard`].
+    pub fn with_header(mut self, file: &str) -> Checker {
+        self.header = Some(file.to_owned());
+        self
+    }
+
+    /// The macros that were evaluated successfully so far, and their values.
+    pub fn identifiers(&self) -> &HashMap<Vec<u8>, EvalResult> {
+        &self.identifiers
+    }
+
+    /// Evaluate the definition of the macro `name` and compare it against
+    /// the expected value. A definition that conflicts with an earlier
+    /// definition of the macro fails with [`Failure::Conflict`] without being
+    /// evaluated.
+    ///
+    /// The tokens should include the macro name, but not `#define`.
+    pub fn check(&mut self, name: &[u8], tokens: &[Token]) -> Result<(), Failure> {
+        if let Some(ref file) = self.header {
+            if is_include_guard(file, name, tokens) {
+                return Ok(());
+            }
+        }
+
+        self.checked += 1;
+        let definition = (self.checked, tokens.to_vec());
+        if let Some((previous, before)) = self.definitions.insert(name.to_owned(), definition) {
+            if before != tokens {
+                return Err(Failure::Conflict {
+                    name: name.to_owned(),
+                    previous,
+                    redefined: self.checked,
+                });
+            }
+        }
+
+        let (expected, function_like) = match self.expected {
+            Some(ref table) => (table.get(name).cloned(), false),
+            None => match expected_from_name(name) {
+                Some(expected) => (Some(expected), name.starts_with(b"Fn_")),
+                None => {
+                    return Err(Failure::InvalidName {
+                        name: name.to_owned(),
+                    })
+                }
+            },
+        };
+
+        let actual = if function_like {
+            self.function_like(tokens, expected.as_ref())
+        } else {
+            IdentifierParser::new(&self.identifiers)
+                .macro_definition(tokens)
+                .ok()
+                .map(|(_, (_, value))| value)
+        };
+
+        match (expected, actual) {
+            (Some(EvalResult::Invalid), None) | (None, None) => Ok(()),
+            (Some(ref expected), Some(ref actual)) if expected != actual => Err(Failure::Value {
+                name: name.to_owned(),
+                expected: expected.clone(),
+                actual: Some(actual.clone()),
+            }),
+            (_, Some(actual)) => match self.identifiers.insert(name.to_owned(), actual) {
+                Some(_) => Err(Failure::Redefined {
+                    name: name.to_owned(),
+                }),
+                None => Ok(()),
+            },
+            (Some(expected), None) => Err(Failure::Value {
+                name: name.to_owned(),
+                expected,
+                actual: None,
+            }),
+        }
+    }
+
+    /// Evaluate a function-like macro by substituting each parameter with a
+    /// value derived from its name: for an expected integer the name is
+    /// decoded like the value in an `Int_` macro name, for an expected string
+    /// the name itself is used.
+    fn function_like(&self, tokens: &[Token], expected: Option<&EvalResult>) -> Option<EvalResult> {
+        let (body, (_, args)) = fn_macro_declaration(tokens).ok()?;
+        let mut identifiers = self.identifiers.clone();
+        for arg in args {
+            let value = match expected {
+                Some(EvalResult::Int(_)) => decode_int(arg)?,
+                Some(EvalResult::Str(_)) => EvalResult::Str(arg.to_owned()),
+                _ => return None,
+            };
+            identifiers.insert(arg.to_owned(), value);
+        }
+        assert_full_parse(IdentifierParser::new(&identifiers).expr(body))
+            .ok()
+            .map(|(_, value)| value)
+    }
+}
+
+/// Whether the macro `name` is the include guard of the header `file`.
+///
+/// An include guard has an empty replacement list, and its name is the file
+/// name in upper case with every character other than a letter or digit
+/// replaced by an underscore. Leading and trailing underscores are ignored,
+/// so `FOO_H`, `_FOO_H` and `__FOO_H__` are all guards of `include/foo.h`.
+///
+/// The tokens should include the macro name, as for [`Checker::check`].
+pub fn is_include_guard(file: &str, name: &[u8], tokens: &[Token]) -> bool {
+    let file = match Path::new(file).file_name().and_then(|f| f.to_str()) {
+        Some(file) => file,
+        None => return false,
+    };
+    let guard: Vec<u8> = file
+        .bytes()
+        .map(|c| match c {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => c.to_ascii_uppercase(),
+            _ => b'_',
+        })
+        .collect();
+    let trim = |name: &[u8]| {
+        let start = name.iter().position(|&c| c != b'_').unwrap_or(name.len());
+        let end = name
+            .iter()
+            .rposition(|&c| c != b'_')
+            .map_or(start, |i| i + 1);
+        name[start..end].to_vec()
+    };
+    let empty = matches!(tokens, [only] if &*only.raw == name);
+    empty && !guard.is_empty() && trim(name) == trim(&guard)
+}
+
+/// Decode the expected value of a macro from its name.
+///
+/// The name consists of a type prefix and a value, separated by an
+/// underscore:
+///
+/// * `Int_`: the value is a decimal integer, with `n` for a minus sign.
+///   Underscores are ignored.
+/// * `Float_`: the value is a decimal number, with `n` for a minus sign and
+///   `p` for the decimal point.
+/// * `Str_`: the value is the string itself, with `U` followed by 6 hex
+///   digits for a Unicode character.
+/// * `CharChar_`: the value is a Unicode code point in decimal.
+/// * `CharRaw_`: the value is a raw character value in decimal.
+///
+/// Names with any other prefix are expected to fail evaluation, and are
+/// decoded as `EvalResult::Invalid`. Names of function-like macros start with
+/// an additional `Fn_` prefix.
+///
+/// Returns `None` if the name doesn't follow this scheme.
+pub fn expected_from_name(name: &[u8]) -> Option<EvalResult> {
+    fn split(name: &[u8]) -> Option<(&[u8], &[u8])> {
+        let pos = name.iter().position(|c| *c == b'_')?;
+        Some((&name[..pos], &name[(pos + 1)..]))
+    }
+
+    let (mut expected, mut value) = split(name)?;
+    if expected == b"Fn" {
+        let (e, v) = split(value)?;
+        expected = e;
+        value = v;
+    }
+
+    match expected {
+        b"Str" => decode_str(value),
+        b"Int" => decode_int(value),
+        b"Float" => str::from_utf8(value)
+            .ok()
+            .map(|s| s.replace('n', "-").replace('p', "."))
+            .and_then(|v| f64::from_str(&v).ok())
+            .map(EvalResult::Float),
+        b"CharRaw" => str::from_utf8(value)
+            .ok()
+            .and_then(|v| u64::from_str(v).ok())
+            .map(CChar::Raw)
+            .map(EvalResult::Char),
+        b"CharChar" => str::from_utf8(value)
+            .ok()
+            .and_then(|v| u32::from_str(v).ok())
+            .and_then(std::char::from_u32)
+            .map(CChar::Char)
+            .map(EvalResult::Char),
+        _ => Some(EvalResult::Invalid),
+    }
+}
+
+fn decode_int(value: &[u8]) -> Option<EvalResult> {
+    str::from_utf8(value)
+        .ok()
+        .map(|s| s.replace('n', "-"))
+        .map(|s| s.replace('_', ""))
+        .and_then(|v| i64::from_str(&v).ok())
+        .map(Wrapping)
+        .map(EvalResult::Int)
+}
+
+fn decode_str(value: &[u8]) -> Option<EvalResult> {
+    let mut splits = value.split(|c| *c == b'U');
+    let mut s = Vec::with_capacity(value.len());
+    s.extend_from_slice(splits.next()?);
+    for split in splits {
+        if split.len() < 6 {
+            return None;
+        }
+        let (chr, rest) = split.split_at(6);
+        let chr = u32::from_str_radix(str::from_utf8(chr).ok()?, 16).ok()?;
+        write!(s, "{}", std::char::from_u32(chr)?).ok()?;
+        s.extend_from_slice(rest);
+    }
+    Some(EvalResult::Str(s))
+}
@@ -7,7 +7,21 @@
 // except according to those terms.
 //! Representation of a C token
 //!
-//! This is designed to map onto a libclang CXToken.
+//! This is designed to map onto a libclang CXToken. Tokens can also be
+//! obtained from C source with [`tokenize`], updated after an edit with
+//! [`retokenize`], or read incrementally with
+//! [`tokenize_partial`], and put in a canonical form with
+//! [`normalize`]. They can be stored with [`encode`] and read back with
+//! [`decode`].
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::num::Wrapping;
+use std::ops::Range;
+
+use crate::chars::Class;
+use crate::expr::EvalResult;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -17,15 +31,101 @@ pub enum Kind {
     Identifier,
     Literal,
     Comment,
+    /// Whitespace, including escaped newlines, as returned by
+    /// [`tokenize_with_whitespace`]. The expression parser skips whitespace
+    /// tokens.
+    Whitespace,
 }
 
 /// A single token in a C expression.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Tokens are equal if they have the same kind and spelling, regardless of
+/// where they come from.
+#[derive(Debug, Clone)]
 pub struct Token {
     /// The type of this token.
     pub kind: Kind,
     /// The bytes that make up the token.
     pub raw: Box<[u8]>,
+    /// Where the token is in the source it was read from, if known.
+    pub span: Option<Span>,
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Token) -> bool {
+        self.kind == other.kind && self.raw == other.raw
+    }
+}
+
+impl Eq for Token {}
+
+impl Token {
+    /// Check that this token is one that [`tokenize_with_whitespace`] could
+    /// give: its spelling is a single token of its kind, and in particular
+    /// not empty and without NUL bytes. Identifiers and keywords are not
+    /// told apart, since other tokenizers such as libclang's know different
+    /// keywords. Escaped newlines are allowed anywhere in the spelling.
+    ///
+    /// The entry points of the expression parsers check this for their
+    /// input, and fail with [`ErrorKind::InvalidToken`] otherwise.
+    ///
+    /// # Example
+    /// ```
+    /// use cexpr::token::{InvalidToken, Kind, Token};
+    ///
+    /// let token = |kind, raw: &str| Token::from((kind, raw.as_bytes()));
+    /// assert_eq!(token(Kind::Literal, "1\\\n2").validate(), Ok(()));
+    /// assert_eq!(token(Kind::Literal, "").validate(), Err(InvalidToken::Empty));
+    /// assert_eq!(token(Kind::Literal, "1\01").validate(), Err(InvalidToken::Nul(1)));
+    /// assert_eq!(token(Kind::Literal, "1 + 1").validate(), Err(InvalidToken::Spelling));
+    /// assert_eq!(token(Kind::Identifier, "1").validate(), Err(InvalidToken::Spelling));
+    /// ```
+    ///
+    /// [`ErrorKind::InvalidToken`]: crate::ErrorKind::InvalidToken
+    pub fn validate(&self) -> Result<(), InvalidToken> {
+        if self.raw.is_empty() {
+            return Err(InvalidToken::Empty);
+        }
+        if let Some(i) = self.raw.iter().position(|&c| c == 0) {
+            return Err(InvalidToken::Nul(i));
+        }
+        // Whitespace may consist of nothing but escaped newlines.
+        let raw = match self.kind {
+            Kind::Whitespace => Cow::Borrowed(&self.raw[..]),
+            _ => splice_lines(&self.raw),
+        };
+        let same_kind = |kind| match (self.kind, kind) {
+            (Kind::Identifier, Kind::Keyword) | (Kind::Keyword, Kind::Identifier) => true,
+            (a, b) => a == b,
+        };
+        match &tokenize_with_whitespace(&raw)[..] {
+            [token] if same_kind(token.kind) && token.raw[..] == raw[..] => Ok(()),
+            _ => Err(InvalidToken::Spelling),
+        }
+    }
+}
+
+/// Why a token is not valid, see [`Token::validate`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidToken {
+    /// The spelling is empty.
+    Empty,
+    /// The spelling contains a NUL byte at the given index.
+    Nul(usize),
+    /// The spelling is not a single token of the kind of the token.
+    Spelling,
+}
+
+/// Fail with [`ErrorKind::InvalidToken`](crate::ErrorKind::InvalidToken) at
+/// the first token of `input` that is not valid.
+pub(crate) fn check(input: &[Token]) -> Result<(), crate::nom::Err<crate::Error<&[Token]>>> {
+    match input.iter().position(|token| token.validate().is_err()) {
+        Some(i) => Err(crate::nom::Err::Failure(
+            (&input[i..], crate::ErrorKind::InvalidToken).into(),
+        )),
+        None => Ok(()),
+    }
 }
 
 impl<'a> From<(Kind, &'a [u8])> for Token {
@@ -33,12 +133,979 @@ impl<'a> From<(Kind, &'a [u8])> for Token {
         Token {
             kind,
             raw: value.to_owned().into_boxed_slice(),
+            span: None,
         }
     }
 }
 
+/// The location of a token in C source.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The offset of the first byte of the token.
+    pub start: usize,
+    /// The offset of the byte after the token.
+    pub end: usize,
+    /// The line the token starts on, counting from 1.
+    pub line: usize,
+    /// The column the token starts in, in bytes, counting from 1.
+    pub column: usize,
+}
+
 /// Remove all comment tokens from a vector of tokens
 pub fn remove_comments(v: &mut Vec<Token>) -> &mut Vec<Token> {
     v.retain(|t| t.kind != Kind::Comment);
     v
 }
+
+/// Remove the escaped newlines from the spelling of a token.
+///
+/// C joins lines that end in a backslash before splitting the source into
+/// tokens, but the spelling of a token may still contain the escaped
+/// newline, for example when obtained from libclang. The spelling
+/// `1\<newline>2` is the number `12`.
+pub fn splice_lines(raw: &[u8]) -> Cow<'_, [u8]> {
+    if !raw.contains(&b'\\') {
+        return Cow::Borrowed(raw);
+    }
+    let mut spliced = Vec::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some((&c, tail)) = rest.split_first() {
+        match (c, tail) {
+            (b'\\', [b'\n', tail @ ..]) | (b'\\', [b'\r', b'\n', tail @ ..]) => rest = tail,
+            _ => {
+                spliced.push(c);
+                rest = tail;
+            }
+        }
+    }
+    if spliced.len() == raw.len() {
+        Cow::Borrowed(raw)
+    } else {
+        Cow::Owned(spliced)
+    }
+}
+
+/// The spelling of a list of tokens, for showing it to the user, for example
+/// the replacement list of a macro that could not be evaluated.
+///
+/// Comments and whitespace are replaced by a single space, and escaped
+/// newlines are removed. Tokens without a [`Span`], which don't say whether
+/// they were separated in the source, are separated by a space.
+///
+/// # Example
+/// ```
+/// use cexpr::token::{spelling, tokenize};
+///
+/// let tokens = tokenize(b"(FLAG_A | /* reserved */ \\\n  FLAG_B)");
+/// assert_eq!(spelling(&tokens), "(FLAG_A | FLAG_B)");
+/// ```
+pub fn spelling(tokens: &[Token]) -> String {
+    let mut spelling = vec![];
+    let mut previous: Option<&Token> = None;
+    let mut space = false;
+    for token in tokens {
+        if token.kind == Kind::Comment || token.kind == Kind::Whitespace {
+            space = true;
+            continue;
+        }
+        if let Some(previous) = previous {
+            let adjacent = matches!(
+                (previous.span, token.span),
+                (Some(previous), Some(span)) if previous.end == span.start
+            );
+            if space || !adjacent {
+                spelling.push(b' ');
+            }
+        }
+        spelling.extend_from_slice(&splice_lines(&token.raw));
+        previous = Some(token);
+        space = false;
+    }
+    String::from_utf8_lossy(&spelling).into_owned()
+}
+
+/// Put a list of tokens in a canonical form, so that lists that only differ
+/// in spelling compare equal.
+///
+/// Comments and whitespace are removed, and digraphs such as `<:` are
+/// respelled as the punctuators they stand for. Integer literals are spelled
+/// in decimal if they were written in decimal and in lowercase hexadecimal
+/// otherwise, since that affects their type, with the suffix `u`, `ul` or
+/// `ull`. Floating point literals and character and string literals without
+/// a width prefix are respelled from their values. Literals that can't be
+/// parsed are left alone.
+///
+/// Parentheses are removed if they can't change how the tokens parse: around
+/// the whole list, around a single literal, around an identifier that isn't
+/// followed by an operand, as it would be in a cast, and around a
+/// parenthesized expression. Parentheses following an identifier, `)` or `]`
+/// are never removed, since they could be those of a function call or macro
+/// invocation, and neither are parentheses around a top-level `,`.
+pub fn normalize(input: &[Token]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = input
+        .iter()
+        .filter(|token| token.kind != Kind::Comment && token.kind != Kind::Whitespace)
+        .map(|token| match token.kind {
+            Kind::Literal => match normalize_literal(&token.raw) {
+                Some(raw) => Token {
+                    raw: raw.into_boxed_slice(),
+                    ..token.clone()
+                },
+                None => token.clone(),
+            },
+            Kind::Punctuation => Token {
+                raw: primary_spelling(&token.raw).into(),
+                ..token.clone()
+            },
+            _ => token.clone(),
+        })
+        .collect();
+    while let Some((open, close)) = redundant_parentheses(&tokens) {
+        tokens.remove(close);
+        tokens.remove(open);
+    }
+    tokens
+}
+
+/// Merge punctuation tokens that follow each other into the longest
+/// punctuators they form, for token sources that split operators such as
+/// `>>` into `>` `>`.
+///
+/// The tokens are merged by maximal munch, as [`tokenize`] would read their
+/// spellings, but a token is never split: `-` `->` stays as it is. Tokens
+/// are only merged if nothing separates them, so not across whitespace or
+/// comment tokens, nor if both have a [`Span`] and the spans don't touch.
+/// The merged token has a span if all the tokens it was merged from have
+/// one.
+///
+/// None of the parsers do this themselves, since the tokens of `a > > b`
+/// without spans are indistinguishable from those of `a >> b` split apart.
+///
+/// # Example
+/// ```
+/// use cexpr::token::{merge_punctuation, tokenize, Kind::*, Token};
+///
+/// let split: Vec<Token> = [(Identifier, "a"), (Punctuation, ">"), (Punctuation, ">"), (Literal, "1")]
+///     .iter()
+///     .map(|&(kind, raw)| (kind, raw.as_bytes()).into())
+///     .collect();
+/// assert_eq!(merge_punctuation(&split), tokenize(b"a >> 1"));
+/// ```
+pub fn merge_punctuation(input: &[Token]) -> Vec<Token> {
+    let adjacent = |a: &Token, b: &Token| {
+        a.kind == Kind::Punctuation
+            && b.kind == Kind::Punctuation
+            && match (a.span, b.span) {
+                (Some(a), Some(b)) => a.end == b.start,
+                _ => true,
+            }
+    };
+    let longest = PUNCTUATION.iter().map(|p| p.len()).max().unwrap_or(1);
+    let mut tokens = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let mut run = 1;
+        while run < longest
+            && i + run < input.len()
+            && adjacent(&input[i + run - 1], &input[i + run])
+        {
+            run += 1;
+        }
+        let merged = (2..=run).rev().find_map(|n| {
+            let raw: Vec<u8> = input[i..i + n]
+                .iter()
+                .flat_map(|token| token.raw.iter().cloned())
+                .collect();
+            if PUNCTUATION.iter().any(|p| p.as_bytes() == &raw[..]) {
+                Some((n, raw))
+            } else {
+                None
+            }
+        });
+        match merged {
+            Some((n, raw)) => {
+                let parts = &input[i..i + n];
+                let span = match (parts[0].span, parts[n - 1].span) {
+                    (Some(first), Some(last)) if parts.iter().all(|t| t.span.is_some()) => {
+                        Some(Span {
+                            end: last.end,
+                            ..first
+                        })
+                    }
+                    _ => None,
+                };
+                tokens.push(Token {
+                    kind: Kind::Punctuation,
+                    raw: raw.into_boxed_slice(),
+                    span,
+                });
+                i += n;
+            }
+            None => {
+                tokens.push(input[i].clone());
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+fn normalize_literal(raw: &[u8]) -> Option<Vec<u8>> {
+    let (_, value) = crate::literal::parse(raw).ok()?;
+    match value {
+        EvalResult::Int(Wrapping(i)) => {
+            let suffix = raw
+                .iter()
+                .rev()
+                .take_while(|c| matches!(c, b'u' | b'U' | b'l' | b'L'))
+                .collect::<Vec<_>>();
+            let i = i as u64;
+            let mut s = if i == 0 || raw[0] != b'0' {
+                i.to_string()
+            } else {
+                format!("0x{:x}", i)
+            };
+            if suffix.iter().any(|c| matches!(c, b'u' | b'U')) {
+                s.push('u');
+            }
+            for _ in suffix.iter().filter(|c| matches!(c, b'l' | b'L')) {
+                s.push('l');
+            }
+            Some(s.into_bytes())
+        }
+        EvalResult::Float(_) => {
+            let mut s = value.spelling()?;
+            match raw.last() {
+                Some(b'f') | Some(b'F') => s.push(b'f'),
+                Some(b'l') | Some(b'L') => s.push(b'l'),
+                _ => {}
+            }
+            Some(s)
+        }
+        EvalResult::Char(_) | EvalResult::Str(_) if matches!(raw[0], b'\'' | b'"') => {
+            value.spelling()
+        }
+        _ => None,
+    }
+}
+
+fn is_punctuation(token: &Token, p: &str) -> bool {
+    token.kind == Kind::Punctuation && primary_spelling(&token.raw) == p.as_bytes()
+}
+
+/// Whether `token` can start an operand, so that a parenthesized identifier
+/// before it could be a cast.
+fn starts_operand(token: &Token) -> bool {
+    match token.kind {
+        Kind::Punctuation => ["(", "+", "-", "~", "!", "*", "&", "++", "--"]
+            .iter()
+            .any(|p| is_punctuation(token, p)),
+        Kind::Comment => false,
+        _ => true,
+    }
+}
+
+/// The depths of parentheses in `tokens` after each token, relative to
+/// the start.
+fn depths(tokens: &[Token]) -> impl Iterator<Item = isize> + '_ {
+    tokens.iter().scan(0, |depth, token| {
+        if is_punctuation(token, "(") {
+            *depth += 1;
+        } else if is_punctuation(token, ")") {
+            *depth -= 1;
+        }
+        Some(*depth)
+    })
+}
+
+/// The first pair of matching parentheses in `tokens` that can be removed,
+/// see [`normalize`].
+fn redundant_parentheses(tokens: &[Token]) -> Option<(usize, usize)> {
+    let mut open = vec![];
+    for (close, token) in tokens.iter().enumerate() {
+        if is_punctuation(token, "(") {
+            open.push(close);
+        } else if is_punctuation(token, ")") {
+            let open = open.pop()?;
+            if redundant(tokens, open, close) {
+                return Some((open, close));
+            }
+        }
+    }
+    None
+}
+
+fn redundant(tokens: &[Token], open: usize, close: usize) -> bool {
+    let inner = &tokens[open + 1..close];
+    if let Some(before) = open.checked_sub(1).map(|i| &tokens[i]) {
+        if before.kind == Kind::Identifier
+            || is_punctuation(before, ")")
+            || is_punctuation(before, "]")
+        {
+            return false;
+        }
+    }
+    let comma = inner
+        .iter()
+        .zip(depths(inner))
+        .any(|(token, depth)| depth == 0 && is_punctuation(token, ","));
+    if inner.is_empty() || comma {
+        return false;
+    }
+    if open == 0 && close == tokens.len() - 1 {
+        return true;
+    }
+    match inner {
+        [token] if token.kind == Kind::Literal => true,
+        [token] if token.kind == Kind::Identifier => {
+            !matches!(tokens.get(close + 1), Some(after) if starts_operand(after))
+        }
+        // The first `(` is matched by the last `)` if the depth only
+        // returns to zero at the end.
+        [first, ..] if is_punctuation(first, "(") => {
+            !depths(inner).take(inner.len() - 1).any(|depth| depth == 0)
+                && depths(inner).last() == Some(0)
+        }
+        _ => false,
+    }
+}
+
+/// The C11 keywords.
+const KEYWORDS: &[&str] = &[
+    "auto",
+    "break",
+    "case",
+    "char",
+    "const",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "typedef",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+    "_Alignas",
+    "_Alignof",
+    "_Atomic",
+    "_Bool",
+    "_Complex",
+    "_Generic",
+    "_Imaginary",
+    "_Noreturn",
+    "_Static_assert",
+    "_Thread_local",
+];
+
+/// Punctuators, longest first so that the longest match is found first.
+const PUNCTUATION: &[&str] = &[
+    "%:%:", "...", "<<=", ">>=", "->", "::", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&",
+    "||", "*=", "/=", "%=", "+=", "-=", "&=", "^=", "|=", "##", "<:", ":>", "<%", "%>", "%:", "[",
+    "]", "(", ")", "{", "}", ".", "&", "*", "+", "-", "~", "!", "/", "%", "<", ">", "^", "|", "?",
+    ":", ";", "=", ",", "#",
+];
+
+/// The punctuator that `raw` is spelled as, with digraphs replaced by the
+/// punctuator they stand for.
+pub(crate) fn primary_spelling(raw: &[u8]) -> &[u8] {
+    match raw {
+        b"<:" => b"[",
+        b":>" => b"]",
+        b"<%" => b"{",
+        b"%>" => b"}",
+        b"%:" => b"#",
+        b"%:%:" => b"##",
+        _ => raw,
+    }
+}
+
+/// Split C source into tokens.
+///
+/// Whitespace, including escaped newlines, separates tokens and is not
+/// returned. The input is not preprocessed, so for example directives are
+/// returned as a `#` token followed by the directive name. Malformed input
+/// never fails: an unterminated comment, string or character literal extends
+/// to the end of the input, and a byte that doesn't start any token is
+/// returned as a punctuation token by itself.
+///
+/// Each token has the [`Span`] it was read from.
+pub fn tokenize(input: &[u8]) -> Vec<Token> {
+    located(input, spans(input))
+}
+
+/// Like [`tokenize`], but also return each run of whitespace as a token of
+/// kind [`Kind::Whitespace`], so that the input can be reproduced exactly by
+/// concatenating the tokens.
+pub fn tokenize_with_whitespace(input: &[u8]) -> Vec<Token> {
+    located(input, scan(input, true, usize::MAX).spans)
+}
+
+/// The tokens read by [`tokenize_partial`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialTokens {
+    /// The tokens that were read.
+    pub tokens: Vec<Token>,
+    /// The indices in `tokens` of the comments, string literals and character
+    /// literals that are not terminated.
+    pub incomplete: Vec<usize>,
+    /// The offset in the input after the last token that was read. This is
+    /// the end of the input, unless reading stopped because the fuel ran out.
+    pub end: usize,
+}
+
+/// Like [`tokenize`], but read at most `fuel` tokens, and report which tokens
+/// are incomplete.
+///
+/// This is intended for editors, which need to tokenize lines as they are
+/// typed. The tokens are the same as those [`tokenize`] returns for the
+/// input up to [`end`](PartialTokens::end).
+///
+/// # Example
+/// ```
+/// use cexpr::token::tokenize_partial;
+///
+/// let partial = tokenize_partial(b"#define GREETING \"hel", 10);
+/// assert_eq!(&*partial.tokens[3].raw, b"\"hel");
+/// assert_eq!(partial.incomplete, [3]);
+///
+/// let partial = tokenize_partial(b"1 + 2", 2);
+/// assert_eq!(partial.tokens.len(), 2);
+/// assert_eq!(partial.end, 3);
+/// ```
+pub fn tokenize_partial(input: &[u8], fuel: usize) -> PartialTokens {
+    let scan = scan(input, false, fuel);
+    PartialTokens {
+        tokens: located(input, scan.spans),
+        incomplete: scan.incomplete,
+        end: scan.end,
+    }
+}
+
+/// A change to C source, see [`retokenize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The bytes of the old source that were replaced.
+    pub replaced: Range<usize>,
+    /// The number of bytes they were replaced with.
+    pub inserted: usize,
+}
+
+/// Update the tokens of C source after it was edited.
+///
+/// `old` are the tokens [`tokenize`] returned for the source before the
+/// `edit`, and `input` is the source after it. Only the part of `input` from
+/// the token before the edit until the tokens are the same as before the edit
+/// is split into tokens again; the other tokens are copied from `old`, with
+/// their spans moved. The result is the same as that of [`tokenize`].
+///
+/// If any token in `old` has no span, all of `input` is split into tokens.
+///
+/// # Example
+/// ```
+/// use cexpr::token::{retokenize, tokenize, Edit};
+///
+/// let old = tokenize(b"#define A 1\n#define B 2\n");
+/// // Replace `1` by `(1 + 2)`.
+/// let edit = Edit { replaced: 10..11, inserted: 7 };
+/// let new = retokenize(&old, &edit, b"#define A (1 + 2)\n#define B 2\n");
+/// assert_eq!(new, tokenize(b"#define A (1 + 2)\n#define B 2\n"));
+/// assert_eq!(new[10].span.unwrap().start, 26);
+/// ```
+pub fn retokenize(old: &[Token], edit: &Edit, input: &[u8]) -> Vec<Token> {
+    let spans = match old
+        .iter()
+        .map(|token| token.span)
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(spans) => spans,
+        None => return tokenize(input),
+    };
+    // A token can depend on the bytes after it: it may continue into the
+    // edit if it ends where the edit starts, and punctuation is found by
+    // looking at up to four bytes, so `..` becomes `...` if a `.` is added.
+    let kept = spans
+        .iter()
+        .position(|span| span.end.max(span.start + 3) >= edit.replaced.start)
+        .unwrap_or(spans.len());
+    let mut lines = match kept.checked_sub(1) {
+        Some(i) => Lines {
+            line: spans[i].line,
+            line_start: spans[i].start + 1 - spans[i].column,
+            pos: spans[i].start,
+        },
+        None => Lines::default(),
+    };
+    let delta = edit.inserted as isize - edit.replaced.len() as isize;
+    let inserted_end = edit.replaced.start + edit.inserted;
+
+    let mut tokens = old[..kept].to_vec();
+    let mut pos = kept.checked_sub(1).map_or(0, |i| spans[i].end);
+    let mut next = kept;
+    let synced = loop {
+        pos = input.len() - skip_whitespace(&input[pos..]).len();
+        if pos == input.len() {
+            break None;
+        }
+        if pos >= inserted_end {
+            // Splitting the rest of the input from the start of a token
+            // after the edit gives the same tokens as before.
+            while next < spans.len()
+                && (spans[next].start < edit.replaced.end
+                    || (spans[next].start as isize + delta) < pos as isize)
+            {
+                next += 1;
+            }
+            if next < spans.len() && spans[next].start as isize + delta == pos as isize {
+                break Some(next);
+            }
+        }
+        let (kind, len, _) = next_token(&input[pos..]);
+        tokens.push(lines.token(input, kind, pos..pos + len));
+        pos += len;
+    };
+    if let Some(next) = synced {
+        let first = spans[next];
+        let moved = lines.span_in(input, pos..pos + first.end - first.start);
+        let lines_moved = moved.line as isize - first.line as isize;
+        let columns_moved = moved.column as isize - first.column as isize;
+        tokens.extend(old[next..].iter().zip(&spans[next..]).map(|(token, span)| {
+            let move_by = |n: usize, by: isize| (n as isize + by) as usize;
+            let mut token = token.clone();
+            token.span = Some(Span {
+                start: move_by(span.start, delta),
+                end: move_by(span.end, delta),
+                line: move_by(span.line, lines_moved),
+                column: if span.line == first.line {
+                    move_by(span.column, columns_moved)
+                } else {
+                    span.column
+                },
+            });
+            token
+        }));
+    }
+    tokens
+}
+
+/// The start of data written by [`encode`].
+const MAGIC: &[u8; 4] = b"CXTK";
+
+/// The version of the format [`encode`] writes. It is incremented whenever
+/// the format changes, so that data written by another version of cexpr is
+/// rejected instead of misread.
+const FORMAT_VERSION: u8 = 1;
+
+/// Why [`decode`] could not read tokens.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The data was not written by [`encode`].
+    NotTokens,
+    /// The data was written by a version of cexpr that uses a different
+    /// format, given here.
+    Version(u8),
+    /// The data was changed or truncated after it was written.
+    Corrupt,
+}
+
+/// Write tokens in a compact binary format, which [`decode`] reads.
+///
+/// This is meant for tools that keep the tokens of headers between runs,
+/// instead of obtaining them again, e.g. from libclang. Each distinct
+/// spelling is stored once, and numbers are stored in as few bytes as they
+/// need. The data starts with a version, and ends with a checksum so that
+/// corrupted data is detected.
+///
+/// # Example
+/// ```
+/// use cexpr::token::{decode, encode, tokenize};
+///
+/// let tokens = tokenize(b"#define A (B + B)");
+/// let data = encode(&tokens);
+/// let decoded = decode(&data).unwrap();
+/// assert_eq!(decoded, tokens);
+/// assert_eq!(decoded[5].span, tokens[5].span);
+/// ```
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let mut spellings: Vec<&[u8]> = vec![];
+    let mut indices = HashMap::new();
+    let mut body = vec![];
+    write_varint(&mut body, tokens.len() as u64);
+    for token in tokens {
+        let index = *indices.entry(&token.raw[..]).or_insert_with(|| {
+            spellings.push(&token.raw[..]);
+            spellings.len() - 1
+        });
+        body.push(kind_code(token.kind) | if token.span.is_some() { 0x80 } else { 0 });
+        write_varint(&mut body, index as u64);
+        if let Some(span) = token.span {
+            for &n in &[span.start, span.end - span.start, span.line, span.column] {
+                write_varint(&mut body, n as u64);
+            }
+        }
+    }
+
+    let mut data = MAGIC.to_vec();
+    data.push(FORMAT_VERSION);
+    write_varint(&mut data, spellings.len() as u64);
+    for spelling in spellings {
+        write_varint(&mut data, spelling.len() as u64);
+        data.extend_from_slice(spelling);
+    }
+    data.extend(body);
+    let checksum = fnv1a(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+/// Read tokens written by [`encode`].
+pub fn decode(data: &[u8]) -> Result<Vec<Token>, DecodeError> {
+    if data.len() < MAGIC.len() + 1 || data[..MAGIC.len()] != MAGIC[..] {
+        return Err(DecodeError::NotTokens);
+    }
+    let version = data[MAGIC.len()];
+    if version != FORMAT_VERSION {
+        return Err(DecodeError::Version(version));
+    }
+    if data.len() < MAGIC.len() + 1 + 8 {
+        return Err(DecodeError::Corrupt);
+    }
+    let (data, checksum) = data.split_at(data.len() - 8);
+    if fnv1a(data).to_le_bytes() != checksum {
+        return Err(DecodeError::Corrupt);
+    }
+
+    let mut reader = Reader(&data[MAGIC.len() + 1..]);
+    let count = reader.len()?;
+    let mut spellings = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len = reader.len()?;
+        spellings.push(reader.bytes(len)?);
+    }
+    let count = reader.len()?;
+    let mut tokens = Vec::with_capacity(count);
+    for _ in 0..count {
+        let tag = reader.bytes(1)?[0];
+        let kind = kind_from_code(tag & 0x7f).ok_or(DecodeError::Corrupt)?;
+        let raw = *spellings
+            .get(reader.number()?)
+            .ok_or(DecodeError::Corrupt)?;
+        let mut token: Token = (kind, raw).into();
+        if tag & 0x80 != 0 {
+            let start = reader.number()?;
+            let len = reader.number()?;
+            token.span = Some(Span {
+                start,
+                end: start.checked_add(len).ok_or(DecodeError::Corrupt)?,
+                line: reader.number()?,
+                column: reader.number()?,
+            });
+        }
+        tokens.push(token);
+    }
+    if !reader.is_empty() {
+        return Err(DecodeError::Corrupt);
+    }
+    Ok(tokens)
+}
+
+/// The number that stands for `kind` in encoded data.
+pub(crate) fn kind_code(kind: Kind) -> u8 {
+    match kind {
+        Kind::Punctuation => 0,
+        Kind::Keyword => 1,
+        Kind::Identifier => 2,
+        Kind::Literal => 3,
+        Kind::Comment => 4,
+        Kind::Whitespace => 5,
+    }
+}
+
+pub(crate) fn kind_from_code(code: u8) -> Option<Kind> {
+    match code {
+        0 => Some(Kind::Punctuation),
+        1 => Some(Kind::Keyword),
+        2 => Some(Kind::Identifier),
+        3 => Some(Kind::Literal),
+        4 => Some(Kind::Comment),
+        5 => Some(Kind::Whitespace),
+        _ => None,
+    }
+}
+
+/// The spelling of a punctuator or keyword that is `raw`.
+#[cfg(feature = "cache")]
+pub(crate) fn known_spelling(raw: &[u8]) -> Option<&'static [u8]> {
+    PUNCTUATION
+        .iter()
+        .chain(KEYWORDS)
+        .map(|spelling| spelling.as_bytes())
+        .find(|&spelling| spelling == raw)
+}
+
+/// Append `n` in LEB128 encoding, seven bits per byte.
+pub(crate) fn write_varint(data: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        data.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    data.push(n as u8);
+}
+
+/// The 64-bit FNV-1a hash of `data`.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &c| {
+        (hash ^ c as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Reads the parts of data written by [`encode`].
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.0.len() {
+            return Err(DecodeError::Corrupt);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn number(&mut self) -> Result<usize, DecodeError> {
+        usize::try_from(self.number64()?).map_err(|_| DecodeError::Corrupt)
+    }
+
+    pub(crate) fn number64(&mut self) -> Result<u64, DecodeError> {
+        let mut n: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            n |= u64::from(byte & 0x7f)
+                .checked_shl(shift)
+                .filter(|&bits| bits >> shift == u64::from(byte & 0x7f))
+                .ok_or(DecodeError::Corrupt)?;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::Corrupt)
+    }
+
+    /// A number of items that follow, each at least one byte long.
+    pub(crate) fn len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.number()?;
+        if len > self.0.len() {
+            return Err(DecodeError::Corrupt);
+        }
+        Ok(len)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Finds the lines and columns of increasing offsets in some input.
+struct Lines {
+    /// The line of `pos`, counting from 1.
+    line: usize,
+    /// The offset of the start of that line.
+    line_start: usize,
+    pos: usize,
+}
+
+impl Default for Lines {
+    fn default() -> Lines {
+        Lines {
+            line: 1,
+            line_start: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl Lines {
+    /// The span of `range` of `input`, which must not start before the
+    /// previous range.
+    fn span_in(&mut self, input: &[u8], range: Range<usize>) -> Span {
+        for (i, &c) in input[self.pos..range.start].iter().enumerate() {
+            if c == b'\n' {
+                self.line += 1;
+                self.line_start = self.pos + i + 1;
+            }
+        }
+        self.pos = range.start;
+        Span {
+            start: range.start,
+            end: range.end,
+            line: self.line,
+            column: range.start - self.line_start + 1,
+        }
+    }
+
+    fn token(&mut self, input: &[u8], kind: Kind, range: Range<usize>) -> Token {
+        let span = self.span_in(input, range.clone());
+        let mut token: Token = (kind, &input[range]).into();
+        token.span = Some(span);
+        token
+    }
+}
+
+/// The tokens of `input` at `spans`, with their locations.
+pub(crate) fn located(input: &[u8], spans: Vec<(Kind, Range<usize>)>) -> Vec<Token> {
+    let mut lines = Lines::default();
+    spans
+        .into_iter()
+        .map(|(kind, range)| lines.token(input, kind, range))
+        .collect()
+}
+
+/// Like [`tokenize`], but return the kinds and byte ranges of the tokens.
+pub(crate) fn spans(input: &[u8]) -> Vec<(Kind, Range<usize>)> {
+    scan(input, false, usize::MAX).spans
+}
+
+/// The kinds and byte ranges of the tokens of some input.
+struct Scan {
+    spans: Vec<(Kind, Range<usize>)>,
+    /// The indices in `spans` of the tokens that are not terminated.
+    incomplete: Vec<usize>,
+    /// The offset after the last token.
+    end: usize,
+}
+
+/// Split `input` into at most `fuel` tokens, not counting whitespace.
+fn scan(input: &[u8], whitespace: bool, mut fuel: usize) -> Scan {
+    let mut spans = vec![];
+    let mut incomplete = vec![];
+    let mut pos = 0;
+    loop {
+        let start = pos;
+        if fuel == 0 {
+            return Scan {
+                spans,
+                incomplete,
+                end: pos,
+            };
+        }
+        pos = input.len() - skip_whitespace(&input[pos..]).len();
+        if whitespace && pos > start {
+            spans.push((Kind::Whitespace, start..pos));
+        }
+        if pos == input.len() {
+            return Scan {
+                spans,
+                incomplete,
+                end: pos,
+            };
+        }
+        let (kind, len, terminated) = next_token(&input[pos..]);
+        if !terminated {
+            incomplete.push(spans.len());
+        }
+        spans.push((kind, pos..pos + len));
+        pos += len;
+        fuel -= 1;
+    }
+}
+
+fn skip_whitespace(mut input: &[u8]) -> &[u8] {
+    loop {
+        match input {
+            [b'\\', b'\n', rest @ ..] | [b'\\', b'\r', b'\n', rest @ ..] => input = rest,
+            [c, rest @ ..] if Class::WHITESPACE.contains(*c) => input = rest,
+            _ => return input,
+        }
+    }
+}
+
+/// The kind and length of the token at the start of `input`, and whether it
+/// is terminated if it is a comment or a quoted literal.
+fn next_token(input: &[u8]) -> (Kind, usize, bool) {
+    let len_while = |start: usize, f: &dyn Fn(u8) -> bool| {
+        start + input[start..].iter().take_while(|&&c| f(c)).count()
+    };
+    match input {
+        [b'/', b'*', ..] => match input[2..].windows(2).position(|w| w == b"*/") {
+            Some(pos) => (Kind::Comment, pos + 4, true),
+            None => (Kind::Comment, input.len(), false),
+        },
+        [b'/', b'/', ..] => (Kind::Comment, len_while(2, &|c| c != b'\n'), true),
+        [b'"', ..] | [b'\'', ..] => {
+            let (len, terminated) = quoted(input, 0);
+            (Kind::Literal, len, terminated)
+        }
+        [c, ..] if Class::DECIMAL.contains(*c) => (Kind::Literal, number(input), true),
+        [b'.', c, ..] if Class::DECIMAL.contains(*c) => (Kind::Literal, number(input), true),
+        [c, ..] if Class::IDENTIFIER_START.contains(*c) => {
+            let len = len_while(1, &|c| Class::IDENTIFIER.contains(c));
+            let word = &input[..len];
+            match input.get(len) {
+                Some(b'"') | Some(b'\'') if [&b"u8"[..], b"u", b"U", b"L"].contains(&word) => {
+                    let (len, terminated) = quoted(input, len);
+                    (Kind::Literal, len, terminated)
+                }
+                _ if KEYWORDS.iter().any(|k| k.as_bytes() == word) => (Kind::Keyword, len, true),
+                _ => (Kind::Identifier, len, true),
+            }
+        }
+        _ => {
+            let len = PUNCTUATION
+                .iter()
+                .find(|p| input.starts_with(p.as_bytes()))
+                .map_or(1, |p| p.len());
+            (Kind::Punctuation, len, true)
+        }
+    }
+}
+
+/// The length of the string or character literal whose opening quote is at
+/// `start`, and whether it has a closing quote.
+fn quoted(input: &[u8], start: usize) -> (usize, bool) {
+    let quote = input[start];
+    let mut i = start + 1;
+    while i < input.len() {
+        match input[i] {
+            b'\\' => i += 2,
+            b'\n' => return (i, false),
+            c if c == quote => return (i + 1, true),
+            _ => i += 1,
+        }
+    }
+    (input.len(), false)
+}
+
+/// The length of the preprocessing number at the start of `input`, which
+/// includes any suffixes and exponents.
+fn number(input: &[u8]) -> usize {
+    let mut i = 1;
+    while i < input.len() {
+        match input[i] {
+            b'+' | b'-' if matches!(input[i - 1], b'e' | b'E' | b'p' | b'P') => i += 1,
+            c if c == b'.' || Class::IDENTIFIER.contains(c) => i += 1,
+            _ => break,
+        }
+    }
+    i
+}
@@ -0,0 +1,441 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::assert_full_parse;
+use cexpr::ast::{parse, visit, Expr, Symbol, Symbols, Visitor};
+use cexpr::expr::{EvalResult, IdentifierParser};
+use cexpr::token::{tokenize, Token};
+
+thread_local! {
+    /// The symbols of the identifiers in the expressions of a test.
+    static SYMBOLS: RefCell<Symbols> = RefCell::new(Symbols::new());
+}
+
+fn symbol(name: &str) -> Symbol {
+    SYMBOLS.with(|symbols| symbols.borrow_mut().intern(name.as_bytes()))
+}
+
+fn ast(source: &str) -> Option<Expr> {
+    let tokens = tokenize(source.as_bytes());
+    SYMBOLS.with(|symbols| {
+        assert_full_parse(parse(&tokens, &mut symbols.borrow_mut()))
+            .ok()
+            .map(|(_, expr)| expr)
+    })
+}
+
+fn int(i: i64) -> Box<Expr> {
+    Box::new(Expr::Literal(EvalResult::Int(Wrapping(i))))
+}
+
+fn ident(name: &str) -> Box<Expr> {
+    Box::new(Expr::Identifier(symbol(name)))
+}
+
+fn binary(op: &str, lhs: Box<Expr>, rhs: Box<Expr>) -> Box<Expr> {
+    Box::new(Expr::Binary {
+        op: op.into(),
+        lhs,
+        rhs,
+    })
+}
+
+#[test]
+fn operators() {
+    assert_eq!(
+        ast("1 + A * 2").map(Box::new),
+        Some(binary("+", int(1), binary("*", ident("A"), int(2))))
+    );
+    assert_eq!(
+        ast("(1 + A) * 2").map(Box::new),
+        Some(binary("*", binary("+", int(1), ident("A")), int(2)))
+    );
+    assert_eq!(
+        ast("1 - 2 - 3").map(Box::new),
+        Some(binary("-", binary("-", int(1), int(2)), int(3)))
+    );
+    assert_eq!(
+        ast("A | B ^ C & D == E < F << G"),
+        Some(*binary(
+            "|",
+            ident("A"),
+            binary(
+                "^",
+                ident("B"),
+                binary(
+                    "&",
+                    ident("C"),
+                    binary(
+                        "==",
+                        ident("D"),
+                        binary("<", ident("E"), binary("<<", ident("F"), ident("G")))
+                    )
+                )
+            )
+        ))
+    );
+    assert_eq!(
+        ast("-~A"),
+        Some(Expr::Unary {
+            op: "-".into(),
+            operand: Box::new(Expr::Unary {
+                op: "~".into(),
+                operand: ident("A"),
+            }),
+        })
+    );
+    assert_eq!(
+        ast("A ? 1 : B ? 2 : 3"),
+        Some(Expr::Conditional {
+            cond: ident("A"),
+            then: int(1),
+            otherwise: Box::new(Expr::Conditional {
+                cond: ident("B"),
+                then: int(2),
+                otherwise: int(3),
+            }),
+        })
+    );
+    assert_eq!(
+        ast("(A, 1)").map(Box::new),
+        Some(binary(",", ident("A"), int(1)))
+    );
+    assert_eq!(
+        ast("!A || B && C | D").map(Box::new),
+        Some(binary(
+            "||",
+            Box::new(Expr::Unary {
+                op: "!".into(),
+                operand: ident("A"),
+            }),
+            binary("&&", ident("B"), binary("|", ident("C"), ident("D")))
+        ))
+    );
+}
+
+#[test]
+fn operands() {
+    assert_eq!(
+        ast("(unsigned long)A"),
+        Some(Expr::Cast {
+            ty: "unsigned long".into(),
+            operand: ident("A"),
+        })
+    );
+    assert_eq!(ast("(A)").map(Box::new), Some(ident("A")));
+    assert_eq!(
+        ast("static_cast<uint32_t>(-1)"),
+        Some(Expr::NamedCast {
+            cast: "static_cast".into(),
+            ty: "uint32_t".into(),
+            operand: Box::new(Expr::Unary {
+                op: "-".into(),
+                operand: int(1),
+            }),
+        })
+    );
+    assert_eq!(
+        ast("sizeof(struct foo *)"),
+        Some(Expr::Sizeof("struct foo *".into()))
+    );
+    assert_eq!(ast("_Alignof(int)"), Some(Expr::Alignof("int".into())));
+    assert_eq!(
+        ast("__builtin_offsetof(struct s, a.b[2])"),
+        Some(Expr::Offsetof {
+            ty: "struct s".into(),
+            member: "a.b[2]".into(),
+        })
+    );
+    assert_eq!(
+        ast("__builtin_choose_expr(A, 1, 2)"),
+        Some(Expr::ChooseExpr {
+            cond: ident("A"),
+            then: int(1),
+            otherwise: int(2),
+        })
+    );
+    assert_eq!(ast("defined(FOO)"), Some(Expr::Defined(symbol("FOO"))));
+    assert_eq!(ast("defined FOO"), Some(Expr::Defined(symbol("FOO"))));
+    assert_eq!(ast("::ns::A").map(Box::new), Some(ident("::ns::A")));
+    assert_eq!(
+        ast("'a'"),
+        Some(Expr::Literal(EvalResult::Char(
+            cexpr::literal::CChar::Char('a')
+        )))
+    );
+}
+
+#[test]
+fn strings() {
+    let s = |s: &str| Expr::Literal(EvalResult::Str(s.as_bytes().to_vec()));
+    assert_eq!(ast("\"a\""), Some(s("a")));
+    assert_eq!(
+        ast("\"a\" A \"b\""),
+        Some(Expr::Concat(vec![s("a"), *ident("A"), s("b")]))
+    );
+    assert_eq!(
+        ast("A \"b\""),
+        Some(Expr::Concat(vec![*ident("A"), s("b")]))
+    );
+    assert_eq!(
+        ast("#x \"b\""),
+        Some(Expr::Concat(vec![Expr::Stringify(symbol("x")), s("b")]))
+    );
+    assert_eq!(
+        ast("\"abc\"[1]"),
+        Some(Expr::Subscript {
+            base: Box::new(s("abc")),
+            index: int(1),
+        })
+    );
+    assert_eq!(ast("A B"), None);
+    assert_eq!(ast("\"a\" 1"), None);
+}
+
+#[test]
+fn errors() {
+    let error = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        match assert_full_parse(parse(&tokens, &mut Symbols::new())) {
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => {
+                Some((e.error.clone(), e.position(&tokens)))
+            }
+            _ => None,
+        }
+    };
+    assert_eq!(error("1 + )"), Some((cexpr::ErrorKind::UnexpectedToken, 2)));
+    assert_eq!(error("1 2"), Some((cexpr::ErrorKind::Partial, 1)));
+    assert_eq!(error("0x"), Some((cexpr::ErrorKind::InvalidLiteral, 0)));
+    assert_eq!(error("1 +"), None);
+    let deep = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+    assert_eq!(error(&deep).map(|e| e.0), Some(cexpr::ErrorKind::TooDeep));
+}
+
+/// Records the parts of an expression in the order they are reported.
+#[derive(Default)]
+struct Events(Vec<String>);
+
+impl Visitor for Events {
+    fn on_literal(&mut self, value: EvalResult) {
+        self.0.push(format!("{:?}", value));
+    }
+
+    fn on_identifier(&mut self, name: &[u8]) {
+        self.0.push(String::from_utf8_lossy(name).into_owned());
+    }
+
+    fn on_concat(&mut self, count: usize) {
+        self.0.push(format!("concat {}", count));
+    }
+
+    fn on_unary_op(&mut self, op: &[u8]) {
+        self.0
+            .push(format!("unary {}", String::from_utf8_lossy(op)));
+    }
+
+    fn on_binary_op(&mut self, op: &[u8]) {
+        self.0
+            .push(format!("binary {}", String::from_utf8_lossy(op)));
+    }
+
+    fn on_conditional(&mut self) {
+        self.0.push("?:".into());
+    }
+
+    fn on_cast(&mut self, ty: &[Token]) {
+        self.0.push(format!("cast {}", ty.len()));
+    }
+
+    fn on_subscript(&mut self) {
+        self.0.push("[]".into());
+    }
+}
+
+fn events(source: &str) -> Vec<String> {
+    let tokens = tokenize(source.as_bytes());
+    let mut events = Events::default();
+    assert_full_parse(visit(&tokens, &mut events)).unwrap();
+    events.0
+}
+
+#[test]
+fn visitor() {
+    assert_eq!(
+        events("1 + A * -2"),
+        ["Int(1)", "A", "Int(2)", "unary -", "binary *", "binary +"]
+    );
+    assert_eq!(
+        events("A ? (unsigned long)B : C[1], 2"),
+        ["A", "B", "cast 2", "C", "Int(1)", "[]", "?:", "Int(2)", "binary ,"]
+    );
+    assert_eq!(
+        events(r#""a" B "c""#),
+        [r#"Str([97])"#, "B", r#"Str([99])"#, "concat 3"]
+    );
+    // `(A)` is not a cast, so nothing is reported for that attempt.
+    assert_eq!(events("(A) + 1"), ["A", "Int(1)", "binary +"]);
+
+    let tokens = tokenize(b"1 + )");
+    let mut events = Events::default();
+    assert!(visit(&tokens, &mut events).is_err());
+    assert_eq!(events.0, ["Int(1)"]);
+}
+
+#[test]
+fn partial() {
+    let mut idents = HashMap::new();
+    idents.insert(b"ONE".to_vec(), EvalResult::Int(Wrapping(1)));
+    let parser = IdentifierParser::new(&idents);
+    let partial = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        SYMBOLS.with(|symbols| {
+            assert_full_parse(parser.partial_expr(&tokens, &mut symbols.borrow_mut()))
+                .ok()
+                .map(|(_, expr)| expr)
+        })
+    };
+
+    assert_eq!(partial("ONE + 1"), Some(*int(2)));
+    assert_eq!(
+        partial("FOO << 2"),
+        Some(*binary("<<", ident("FOO"), int(2)))
+    );
+    assert_eq!(
+        partial("(ONE + 2) * -FOO"),
+        Some(*binary(
+            "*",
+            int(3),
+            Box::new(Expr::Unary {
+                op: "-".into(),
+                operand: ident("FOO"),
+            })
+        ))
+    );
+    assert_eq!(
+        partial("(unsigned)-1 >> FOO"),
+        Some(*binary(">>", int(0xffff_ffff), ident("FOO")))
+    );
+    assert_eq!(partial("ONE ? 2 : FOO"), Some(*int(2)));
+    assert_eq!(
+        partial("FOO ? ONE : 2"),
+        Some(Expr::Conditional {
+            cond: ident("FOO"),
+            then: int(1),
+            otherwise: int(2),
+        })
+    );
+    assert_eq!(partial("1 / 0"), Some(*binary("/", int(1), int(0))));
+    assert_eq!(partial("!ONE && FOO / 0"), Some(*int(0)));
+    assert_eq!(partial("ONE || FOO"), Some(*int(1)));
+    assert_eq!(
+        partial("FOO && !ONE"),
+        Some(*binary("&&", ident("FOO"), int(0)))
+    );
+    assert_eq!(partial("FOO +"), None);
+}
+
+#[test]
+fn symbols() {
+    let mut symbols = Symbols::new();
+    let mut parse_expr = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        assert_full_parse(parse(&tokens, &mut symbols)).unwrap().1
+    };
+    let first = parse_expr("A + B");
+    let second = parse_expr("defined(B) ? #C : A");
+    let [a, b, c] = [b"A", b"B", b"C"].map(|name| symbols.get(name).unwrap());
+    assert_eq!(
+        first,
+        Expr::Binary {
+            op: "+".into(),
+            lhs: Box::new(Expr::Identifier(a)),
+            rhs: Box::new(Expr::Identifier(b)),
+        }
+    );
+    assert_eq!(
+        second,
+        Expr::Conditional {
+            cond: Box::new(Expr::Defined(b)),
+            then: Box::new(Expr::Stringify(c)),
+            otherwise: Box::new(Expr::Identifier(a)),
+        }
+    );
+    // Symbols are numbered in the order they were first seen.
+    assert_eq!([a, b, c].map(Symbol::index), [0, 1, 2]);
+    assert_eq!(symbols.name(c), b"C");
+    assert_eq!(symbols.len(), 3);
+    assert_eq!(symbols.get(b"D"), None);
+    assert_eq!(symbols.intern(b"A"), a);
+}
+
+#[test]
+fn symbolic() {
+    use cexpr::target::TargetInfo;
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let targets = [
+        TargetInfo {
+            pointer_width: 32,
+            long_width: None,
+            wchar_width: 16,
+            char_signed: true,
+        },
+        TargetInfo {
+            pointer_width: 64,
+            long_width: None,
+            wchar_width: 32,
+            char_signed: true,
+        },
+    ];
+    let symbolic = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        SYMBOLS.with(|symbols| {
+            assert_full_parse(parser.symbolic_expr(&targets, &tokens, &mut symbols.borrow_mut()))
+                .ok()
+                .map(|(_, expr)| expr)
+        })
+    };
+
+    assert_eq!(symbolic("sizeof(int) << 1"), Some(*int(8)));
+    assert_eq!(
+        symbolic("sizeof(long) << 1"),
+        Some(*binary("<<", Box::new(Expr::Sizeof("long".into())), int(1)))
+    );
+    assert_eq!(
+        symbolic("(size_t)-1 >> (2 - 1)"),
+        Some(*binary(
+            ">>",
+            Box::new(Expr::Cast {
+                ty: "size_t".into(),
+                operand: int(-1),
+            }),
+            int(1)
+        ))
+    );
+    assert_eq!(
+        symbolic("sizeof(L'a') + FOO"),
+        Some(*binary(
+            "+",
+            Box::new(Expr::Sizeof("L'a'".into())),
+            ident("FOO")
+        ))
+    );
+    assert_eq!(symbolic("(unsigned)-1"), Some(*int(0xffff_ffff)));
+    assert_eq!(symbolic("sizeof(long) +"), None);
+
+    let tokens = tokenize(b"sizeof(long)");
+    let (_, expr) =
+        assert_full_parse(parser.symbolic_expr(&[], &tokens, &mut Symbols::new())).unwrap();
+    assert_eq!(expr, *int(8));
+}
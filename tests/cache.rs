@@ -0,0 +1,116 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+#![cfg(feature = "cache")]
+extern crate cexpr;
+
+use std::fs;
+use std::path::PathBuf;
+
+use cexpr::cache::Cache;
+use cexpr::macros::MacroSet;
+use cexpr::target::TargetInfo;
+
+const HEADER: &[u8] = b"#define FLAGS (FLAG_A | FLAG_B)
+#define FLAG_A 1
+#define FLAG_B 2
+#define NAME \"na\" \"me\"
+#define CHAR '\\xff'
+#define WIDE L'\\u00e9'
+#define HALF 0.5
+#define MAX (size_t)-1
+#define LOOP (LOOP + 1)
+#define UNKNOWN (FOO + 1)
+#define UNFINISHED (1 +
+#define PASTE a ## b
+#define EMPTY
+#define F(x) x
+";
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cexpr-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn files(dir: &PathBuf) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn resolve() {
+    let dir = dir("resolve");
+    let cache = Cache::new(&dir);
+    let narrow = TargetInfo {
+        pointer_width: 32,
+        long_width: None,
+        wchar_width: 16,
+        char_signed: true,
+    };
+    let expected = MacroSet::from_header(HEADER).resolve();
+    assert_eq!(expected.outcomes.len(), 13);
+
+    assert_eq!(cache.resolve(HEADER, Default::default()).unwrap(), expected);
+    assert_eq!(files(&dir).len(), 1);
+    assert_eq!(cache.resolve(HEADER, Default::default()).unwrap(), expected);
+    assert_eq!(files(&dir).len(), 1);
+
+    let narrow_expected = MacroSet::from_header(HEADER).with_target(narrow).resolve();
+    assert_ne!(narrow_expected, expected);
+    assert_eq!(cache.resolve(HEADER, narrow).unwrap(), narrow_expected);
+    assert_eq!(cache.resolve(HEADER, narrow).unwrap(), narrow_expected);
+    assert_eq!(files(&dir).len(), 2);
+
+    let unsigned = TargetInfo {
+        char_signed: false,
+        ..Default::default()
+    };
+    assert_eq!(
+        cache.resolve(HEADER, unsigned).unwrap(),
+        MacroSet::from_header(HEADER)
+            .with_target(unsigned)
+            .resolve()
+    );
+    assert_eq!(files(&dir).len(), 3);
+
+    let other = b"#define FLAG_A 3\n";
+    assert_eq!(
+        cache.resolve(other, Default::default()).unwrap(),
+        MacroSet::from_header(other).resolve()
+    );
+    assert_eq!(files(&dir).len(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn corrupt() {
+    let dir = dir("corrupt");
+    let cache = Cache::new(&dir);
+    let expected = cache.resolve(HEADER, Default::default()).unwrap();
+    let file = files(&dir).remove(0);
+    let data = fs::read(&file).unwrap();
+    for len in [0, 5, data.len() / 2, data.len() - 1] {
+        fs::write(&file, &data[..len]).unwrap();
+        assert_eq!(cache.resolve(HEADER, Default::default()).unwrap(), expected);
+        assert_eq!(fs::read(&file).unwrap(), data);
+    }
+    let mut changed = data.clone();
+    changed[data.len() / 2] ^= 1;
+    fs::write(&file, &changed).unwrap();
+    assert_eq!(cache.resolve(HEADER, Default::default()).unwrap(), expected);
+    assert_eq!(fs::read(&file).unwrap(), data);
+
+    // A file that is in the way of the cache directory.
+    let blocked = Cache::new(file.join("sub"));
+    assert!(blocked.resolve(HEADER, Default::default()).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,206 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::assert_full_parse;
+use cexpr::expr::{expr, Designator, EvalResult, IdentifierParser, IntmaxWidth};
+use cexpr::macros::{MacroSet, Outcome};
+use cexpr::token::Kind::{self, *};
+use cexpr::token::{tokenize, Token};
+
+fn parses(list: &[(Kind, &str)]) -> bool {
+    let tokens: Vec<Token> = list
+        .iter()
+        .map(|&(kind, raw)| (kind, raw.as_bytes()).into())
+        .collect();
+    assert_full_parse(expr(&tokens)).is_ok()
+}
+
+#[test]
+fn operators() {
+    let caps = cexpr::capabilities();
+    for op in caps.unary_operators {
+        assert!(parses(&[(Punctuation, op), (Literal, "1")]), "{}", op);
+    }
+    for op in caps.binary_operators {
+        assert!(
+            parses(&[(Literal, "1"), (Punctuation, op), (Literal, "1")]),
+            "{}",
+            op
+        );
+    }
+    assert!(!parses(&[
+        (Literal, "1"),
+        (Punctuation, "="),
+        (Literal, "1")
+    ]));
+    assert_eq!(
+        caps.conditional_operator,
+        parses(&[
+            (Literal, "1"),
+            (Punctuation, "?"),
+            (Literal, "2"),
+            (Punctuation, ":"),
+            (Literal, "3"),
+        ])
+    );
+    assert_eq!(
+        caps.comma_operator,
+        parses(&[(Literal, "1"), (Punctuation, ","), (Literal, "2")])
+    );
+    assert_eq!(
+        caps.defined_operator,
+        parses(&[(Identifier, "defined"), (Identifier, "FOO")])
+    );
+    assert_eq!(
+        caps.digraphs,
+        parses(&[
+            (Literal, "\"a\""),
+            (Punctuation, "<:"),
+            (Literal, "0"),
+            (Punctuation, ":>"),
+        ])
+    );
+}
+
+#[test]
+fn builtins() {
+    let caps = cexpr::capabilities();
+    let int = [(Punctuation, "("), (Keyword, "int"), (Punctuation, ")")];
+    assert_eq!(
+        caps.sizeof_operator,
+        parses(&[&[(Keyword, "sizeof")][..], &int].concat())
+    );
+    assert_eq!(
+        caps.alignof_operator,
+        parses(&[&[(Keyword, "_Alignof")][..], &int].concat())
+    );
+    assert_eq!(
+        caps.choose_expr_builtin,
+        parses(&[
+            (Identifier, "__builtin_choose_expr"),
+            (Punctuation, "("),
+            (Literal, "1"),
+            (Punctuation, ","),
+            (Literal, "2"),
+            (Punctuation, ","),
+            (Literal, "3"),
+            (Punctuation, ")"),
+        ])
+    );
+
+    let idents = HashMap::new();
+    let layout = |_: &[Token], _: &[Designator<'_>]| Some(4);
+    let parser = IdentifierParser::new(&idents).with_offsetof(&layout);
+    let input = tokenize(b"__builtin_offsetof(struct s, a)");
+    assert_eq!(
+        caps.offsetof_builtin,
+        assert_full_parse(parser.expr(&input)).is_ok()
+    );
+    let parser = IdentifierParser::new(&idents).with_c23(true);
+    let input = tokenize(b"alignof(int)");
+    assert_eq!(
+        caps.alignof_operator,
+        assert_full_parse(parser.expr(&input)).is_ok()
+    );
+    let input = tokenize(b"sizeof(typeof(1)) + (typeof_unqual(const int))1");
+    assert_eq!(
+        caps.typeof_operator,
+        assert_full_parse(parser.expr(&input)).is_ok()
+    );
+    let parser = IdentifierParser::new(&idents).with_permissive(true);
+    let input = tokenize(b"(_Static_assert(1, \"\"), 2)");
+    assert_eq!(
+        caps.static_assertions,
+        assert_full_parse(parser.expr(&input)).is_ok()
+    );
+    let parser = IdentifierParser::new(&idents).with_intmax_width(Some(IntmaxWidth::Bits64));
+    let input = tokenize(b"1 << 40");
+    assert_eq!(
+        caps.intmax_arithmetic,
+        assert_full_parse(parser.expr(&input)).ok().map(|(_, v)| v)
+            == Some(EvalResult::Int(Wrapping(1 << 40)))
+    );
+}
+
+#[test]
+fn casts() {
+    for ty in cexpr::capabilities().cast_types {
+        // Typedef names end in `_t`, the others are spelled with keywords,
+        // except `bool` which is only a keyword in C++.
+        let kind = if ty.ends_with("_t") || *ty == "bool" {
+            Identifier
+        } else {
+            Keyword
+        };
+        let mut list = vec![(Punctuation, "(")];
+        list.extend(ty.split(' ').map(|word| (kind, word)));
+        list.extend([(Punctuation, ")"), (Literal, "1")]);
+        assert!(parses(&list), "{}", ty);
+    }
+    for op in ["static_cast", "const_cast"] {
+        assert_eq!(
+            cexpr::capabilities().named_casts,
+            parses(&[
+                (Identifier, op),
+                (Punctuation, "<"),
+                (Keyword, "int"),
+                (Punctuation, ">"),
+                (Punctuation, "("),
+                (Literal, "1"),
+                (Punctuation, ")"),
+            ]),
+            "{}",
+            op
+        );
+    }
+}
+
+#[test]
+fn token_pasting() {
+    let mut macros = MacroSet::new();
+    macros.insert(b"A".to_vec(), tokenize(b"1 ## 2"));
+    assert_eq!(
+        cexpr::capabilities().token_pasting,
+        macros.resolve().outcomes[&b"A"[..]] == Outcome::Evaluated(EvalResult::Int(Wrapping(12)))
+    );
+}
+
+#[test]
+fn features() {
+    let caps = cexpr::capabilities();
+    assert_eq!(caps.cache, cfg!(feature = "cache"));
+    assert_eq!(caps.compiler, cfg!(feature = "compiler"));
+    assert_eq!(caps.testing, cfg!(feature = "testing"));
+}
+
+#[test]
+fn literals() {
+    let caps = cexpr::capabilities();
+    assert_eq!(caps.binary_literals, parses(&[(Literal, "0b101")]));
+    assert_eq!(
+        caps.hexadecimal_float_literals,
+        parses(&[(Literal, "0x1p3")])
+    );
+    assert_eq!(
+        caps.string_concatenation,
+        parses(&[(Literal, "\"a\""), (Literal, "\"b\"")])
+    );
+    assert_eq!(
+        caps.string_subscripts,
+        parses(&[
+            (Literal, "\"a\""),
+            (Punctuation, "["),
+            (Literal, "0"),
+            (Punctuation, "]"),
+        ])
+    );
+}
@@ -0,0 +1,28 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use cexpr::chars::Class;
+
+fn members(class: Class) -> Vec<u8> {
+    (0..=255).filter(|&b| class.contains(b)).collect()
+}
+
+#[test]
+fn classes() {
+    assert_eq!(members(Class::BINARY), b"01");
+    assert_eq!(members(Class::OCTAL), b"01234567");
+    assert_eq!(members(Class::DECIMAL), b"0123456789");
+    assert_eq!(members(Class::HEXADECIMAL), &b"0123456789ABCDEFabcdef"[..]);
+    assert_eq!(members(Class::WHITESPACE), b"\t\n\x0b\x0c\r ");
+    assert!(Class::IDENTIFIER_START.contains(b'_'));
+    assert!(!Class::IDENTIFIER_START.contains(b'1'));
+    assert!(Class::IDENTIFIER.contains(b'1'));
+    assert!(!Class::IDENTIFIER.contains(b'$'));
+    assert!(members(Class::IDENTIFIER).iter().all(u8::is_ascii));
+}
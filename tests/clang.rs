@@ -8,161 +8,12 @@
 extern crate cexpr;
 extern crate clang_sys;
 
-use std::collections::HashMap;
-use std::io::Write;
-use std::str::{self, FromStr};
-use std::{char, ffi, mem, ptr, slice};
+use std::{ffi, mem, ptr, slice};
 
-use cexpr::assert_full_parse;
-use cexpr::expr::{fn_macro_declaration, EvalResult, IdentifierParser};
-use cexpr::literal::CChar;
+use cexpr::testing::Checker;
 use cexpr::token::Token;
 use clang_sys::*;
 
-// main testing routine
-fn test_definition(
-    ident: Vec<u8>,
-    tokens: &[Token],
-    idents: &mut HashMap<Vec<u8>, EvalResult>,
-) -> bool {
-    fn bytes_to_int(value: &[u8]) -> Option<EvalResult> {
-        str::from_utf8(value)
-            .ok()
-            .map(|s| s.replace("n", "-"))
-            .map(|s| s.replace("_", ""))
-            .and_then(|v| i64::from_str(&v).ok())
-            .map(::std::num::Wrapping)
-            .map(Int)
-    }
-
-    use cexpr::expr::EvalResult::*;
-
-    let display_name = String::from_utf8_lossy(&ident).into_owned();
-
-    let functional;
-    let test = {
-        // Split name such as Str_test_string into (Str,test_string)
-        let pos = ident
-            .iter()
-            .position(|c| *c == b'_')
-            .expect(&format!("Invalid definition in testcase: {}", display_name));
-        let mut expected = &ident[..pos];
-        let mut value = &ident[(pos + 1)..];
-
-        functional = expected == b"Fn";
-
-        if functional {
-            let ident = value;
-            let pos = ident
-                .iter()
-                .position(|c| *c == b'_')
-                .expect(&format!("Invalid definition in testcase: {}", display_name));
-            expected = &ident[..pos];
-            value = &ident[(pos + 1)..];
-        }
-
-        if expected == b"Str" {
-            let mut splits = value.split(|c| *c == b'U');
-            let mut s = Vec::with_capacity(value.len());
-            s.extend_from_slice(splits.next().unwrap());
-            for split in splits {
-                let (chr, rest) = split.split_at(6);
-                let chr = u32::from_str_radix(str::from_utf8(chr).unwrap(), 16).unwrap();
-                write!(s, "{}", char::from_u32(chr).unwrap()).unwrap();
-                s.extend_from_slice(rest);
-            }
-            Some(Str(s))
-        } else if expected == b"Int" {
-            bytes_to_int(value)
-        } else if expected == b"Float" {
-            str::from_utf8(value)
-                .ok()
-                .map(|s| s.replace("n", "-").replace("p", "."))
-                .and_then(|v| f64::from_str(&v).ok())
-                .map(Float)
-        } else if expected == b"CharRaw" {
-            str::from_utf8(value)
-                .ok()
-                .and_then(|v| u64::from_str(v).ok())
-                .map(CChar::Raw)
-                .map(Char)
-        } else if expected == b"CharChar" {
-            str::from_utf8(value)
-                .ok()
-                .and_then(|v| u32::from_str(v).ok())
-                .and_then(char::from_u32)
-                .map(CChar::Char)
-                .map(Char)
-        } else {
-            Some(Invalid)
-        }
-        .expect(&format!("Invalid definition in testcase: {}", display_name))
-    };
-
-    let result = if functional {
-        let mut fnidents;
-        let expr_tokens;
-        match fn_macro_declaration(&tokens) {
-            Ok((rest, (_, args))) => {
-                fnidents = idents.clone();
-                expr_tokens = rest;
-                for arg in args {
-                    let val = match test {
-                        Int(_) => bytes_to_int(&arg),
-                        Str(_) => Some(Str(arg.to_owned())),
-                        _ => unimplemented!(),
-                    }
-                    .expect(&format!(
-                        "Invalid argument in functional macro testcase: {}",
-                        display_name
-                    ));
-                    fnidents.insert(arg.to_owned(), val);
-                }
-            }
-            e => {
-                println!(
-                    "Failed test for {}, unable to parse functional macro declaration: {:?}",
-                    display_name, e
-                );
-                return false;
-            }
-        }
-        assert_full_parse(IdentifierParser::new(&fnidents).expr(&expr_tokens))
-    } else {
-        IdentifierParser::new(idents)
-            .macro_definition(&tokens)
-            .map(|(i, (_, val))| (i, val))
-    };
-
-    match result {
-        Ok((_, val)) => {
-            if val == test {
-                if let Some(_) = idents.insert(ident, val) {
-                    panic!("Duplicate definition for testcase: {}", display_name);
-                }
-                true
-            } else {
-                println!(
-                    "Failed test for {}, expected {:?}, got {:?}",
-                    display_name, test, val
-                );
-                false
-            }
-        }
-        e => {
-            if test == Invalid {
-                true
-            } else {
-                println!(
-                    "Failed test for {}, expected {:?}, got {:?}",
-                    display_name, test, e
-                );
-                false
-            }
-        }
-    }
-}
-
 // support code for the clang lexer
 unsafe fn clang_str_to_vec(s: CXString) -> Vec<u8> {
     let vec = ffi::CStr::from_ptr(clang_getCString(s))
@@ -183,7 +34,10 @@ unsafe fn token_clang_to_cexpr(tu: CXTranslationUnit, orig: &CXToken) -> Token {
             CXToken_Punctuation => cexpr::token::Kind::Punctuation,
             _ => panic!("invalid token kind: {:?}", *orig),
         },
-        raw: clang_str_to_vec(clang_getTokenSpelling(tu, *orig)).into_boxed_slice(),
+        raw: cexpr::token::splice_lines(&clang_str_to_vec(clang_getTokenSpelling(tu, *orig)))
+            .into_owned()
+            .into_boxed_slice(),
+        span: None,
     }
 }
 
@@ -221,7 +75,7 @@ unsafe fn location_in_scope(r: CXSourceRange) -> bool {
     );
     clang_Location_isFromMainFile(start) != 0
         && clang_Location_isInSystemHeader(start) == 0
-        && file != ptr::null_mut()
+        && !file.is_null()
 }
 
 /// tokenize_range_adjust can be used to work around LLVM bug 9069
@@ -262,7 +116,7 @@ fn file_visit_macros<F: FnMut(Vec<u8>, Vec<Token>)>(
                 let mut token_ptr = ptr::null_mut();
                 let mut num = 0;
                 clang_tokenize(tu, range, &mut token_ptr, &mut num);
-                if token_ptr != ptr::null_mut() {
+                if !token_ptr.is_null() {
                     let tokens = slice::from_raw_parts(token_ptr, num as usize);
                     let tokens: Vec<_> = tokens
                         .iter()
@@ -285,10 +139,13 @@ fn file_visit_macros<F: FnMut(Vec<u8>, Vec<Token>)>(
 }
 
 fn test_file(file: &str) -> bool {
-    let mut idents = HashMap::new();
+    let mut checker = Checker::from_names().with_header(file);
     let mut all_succeeded = true;
     file_visit_macros(file, fix_bug_9069(), |ident, tokens| {
-        all_succeeded &= test_definition(ident, &tokens, &mut idents)
+        if let Err(failure) = checker.check(&ident, &tokens) {
+            println!("Failed test for {}", failure);
+            all_succeeded = false;
+        }
     });
     all_succeeded
 }
@@ -337,3 +194,4 @@ test_file!(strings);
 test_file!(int_signed);
 test_file!(int_unsigned);
 test_file!(fail);
+test_file!(casts);
@@ -0,0 +1,74 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::compat;
+use cexpr::expr::{EvalResult, IdentifierParser};
+use cexpr::macros::{MacroSet, Outcome};
+use cexpr::token::tokenize;
+use cexpr::ErrorKind;
+
+#[test]
+fn outcomes() {
+    let value = EvalResult::Int(Wrapping(1));
+    assert_eq!(
+        compat::result(&Outcome::Evaluated(value.clone())),
+        Ok(value.clone())
+    );
+    assert_eq!(
+        compat::eval_result(&Outcome::Evaluated(value.clone())),
+        value
+    );
+    let invalid = Outcome::Invalid(ErrorKind::DivisionByZero);
+    assert_eq!(compat::result(&invalid), Err(ErrorKind::DivisionByZero));
+    assert_eq!(compat::eval_result(&invalid), EvalResult::Invalid);
+    assert_eq!(
+        compat::result(&Outcome::Cyclic),
+        Err(ErrorKind::UnknownIdentifier)
+    );
+}
+
+#[test]
+fn matches_macro_definition() {
+    let mut set = MacroSet::new();
+    let sources = [
+        ("ONE", "1"),
+        ("TWO", "ONE + ONE"),
+        ("NAME", "\"two\""),
+        ("ZERO", "1 / 0"),
+        ("SELF", "SELF"),
+    ];
+    for &(name, source) in &sources {
+        set.insert(name.as_bytes().to_vec(), tokenize(source.as_bytes()));
+    }
+    let evaluated = compat::evaluated(&set.resolve());
+    assert_eq!(
+        evaluated
+            .iter()
+            .map(|(name, _)| &name[..])
+            .collect::<Vec<_>>(),
+        [&b"NAME"[..], b"ONE", b"TWO"]
+    );
+
+    // Evaluating the macros one at a time in order gives the same values.
+    let mut known = HashMap::new();
+    for &(name, source) in &sources {
+        let mut tokens = tokenize(name.as_bytes());
+        tokens.extend(tokenize(source.as_bytes()));
+        let result = IdentifierParser::new(&known).macro_definition(&tokens);
+        if let Ok((_, (name, value))) = result {
+            known.insert(name.to_vec(), value);
+        }
+    }
+    let mut known: Vec<_> = known.into_iter().collect();
+    known.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(evaluated, known);
+}
@@ -0,0 +1,125 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+#![cfg(feature = "compiler")]
+extern crate cexpr;
+
+use std::num::Wrapping;
+
+use cexpr::compiler::{Compiler, Error};
+use cexpr::expr::EvalResult::*;
+use cexpr::literal::CChar;
+use cexpr::token::Kind::*;
+use cexpr::token::Token;
+
+fn compiler() -> Compiler {
+    let tokens: Vec<Token> = vec![
+        (Literal, &b"1"[..]).into(),
+        (Punctuation, &b"<<"[..]).into(),
+        (Literal, &b"4"[..]).into(),
+    ];
+    let string: Vec<Token> = vec![
+        (Literal, &br#""con""#[..]).into(),
+        (Literal, &br#""cat""#[..]).into(),
+    ];
+    let float: Vec<Token> = vec![(Literal, &b"1e-3"[..]).into()];
+    let chr: Vec<Token> = vec![(Literal, &b"'A'"[..]).into()];
+    Compiler::new("cc")
+        .arg("-std=c11")
+        .define(b"SHIFT", &tokens)
+        .define(b"STRING", &string)
+        .define(b"FLOAT", &float)
+        .define(b"CHAR", &chr)
+}
+
+#[test]
+fn check() {
+    let cc = compiler();
+    assert!(cc.check(b"SHIFT", &Int(Wrapping(16))).unwrap());
+    assert!(!cc.check(b"SHIFT", &Int(Wrapping(15))).unwrap());
+    assert!(cc.check(b"STRING", &Str(b"concat".to_vec())).unwrap());
+    assert!(!cc.check(b"STRING", &Str(b"con".to_vec())).unwrap());
+    assert!(cc.check(b"FLOAT", &Float(1e-3)).unwrap());
+    assert!(cc.check(b"CHAR", &Char(CChar::Char('A'))).unwrap());
+    match cc.check(b"UNDEFINED", &Int(Wrapping(0))) {
+        Err(Error::Compile(_)) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn check_type() {
+    use cexpr::target::{IntType, Type};
+
+    let cc = compiler();
+    assert!(cc.check_type(b"SHIFT", Type::Int(IntType::Int)).unwrap());
+    assert!(!cc
+        .check_type(b"SHIFT", Type::Int(IntType::UnsignedInt))
+        .unwrap());
+    assert!(cc.check_type(b"STRING", Type::Str).unwrap());
+    assert!(cc.check_type(b"FLOAT", Type::Double).unwrap());
+    assert!(!cc.check_type(b"FLOAT", Type::Float).unwrap());
+}
+
+/// Values and types of integer expressions that wrap around, computed like
+/// a compiler for the host, which is assumed to have 64-bit pointers.
+#[test]
+fn wraparound() {
+    use std::collections::HashMap;
+
+    use cexpr::assert_full_parse;
+    use cexpr::expr::IdentifierParser;
+    use cexpr::token::tokenize;
+
+    let sources = [
+        "0xFFFFFFFFFFFFFFFFULL",
+        "(-0x8000000000000000)",
+        "-0x8000000000000000LL / -1",
+        "-9223372036854775807LL - 1",
+        "-0xFFFFFFFF",
+        "-0x80000000",
+        "-2147483648",
+        "0x7FFFFFFFFFFFFFFF + 1u",
+        "0xFFFFFFFFFFFFFFFF / 3",
+        "0xFFFFFFFFFFFFFFFFULL * 0xFFFFFFFFFFFFFFFFULL",
+        "-1 / 2u",
+        "-1 < 0UL",
+        "-1L < 0u",
+        "(1ULL << 63) >> 62",
+        "(long long)0xFFFFFFFFFFFFFFFF",
+        "(unsigned char)-1 + 1",
+        "-(unsigned char)1",
+        "2147483647 + 1",
+        "1 << 31",
+        "(unsigned short)65535 * (unsigned short)65535",
+        "1 ? -1 : 0u",
+        "0xFFFFFFFFFFFFFFFF + 0.0",
+        "1 ? 0x8000000000000000 : 1.0",
+        "'a' + 1",
+        "-'a'",
+        "'a' - 98u",
+        "u'a' - 98",
+        "L'a' < 98u",
+        "1 ? 'a' : 0u",
+        "(unsigned char)'a' << 24",
+    ];
+    let identifiers = HashMap::new();
+    let parser = IdentifierParser::new(&identifiers);
+    let mut cc = Compiler::new("cc").arg("-std=c11").arg("-w");
+    let mut results = vec![];
+    for (i, source) in sources.iter().enumerate() {
+        let tokens = tokenize(source.as_bytes());
+        let (_, result) = assert_full_parse(parser.expr_with_type(&tokens)).unwrap();
+        let name = format!("WRAP_{}", i);
+        cc = cc.define(name.as_bytes(), &tokens);
+        results.push((name, result));
+    }
+    for (name, (value, ty)) in results {
+        assert!(cc.check(name.as_bytes(), &value).unwrap(), "{}", name);
+        assert!(cc.check_type(name.as_bytes(), ty).unwrap(), "{}", name);
+    }
+}
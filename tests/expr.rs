@@ -0,0 +1,2475 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::assert_full_parse;
+use cexpr::expr::EvalResult::{self, *};
+use cexpr::expr::IdentifierParser;
+use cexpr::token::Kind::{self, *};
+use cexpr::token::Token;
+
+fn tokens(list: &[(Kind, &str)]) -> Vec<Token> {
+    list.iter()
+        .map(|&(kind, raw)| (kind, raw.as_bytes()).into())
+        .collect()
+}
+
+fn idents(list: &[(&str, EvalResult)]) -> HashMap<Vec<u8>, EvalResult> {
+    list.iter()
+        .map(|(name, value)| (name.as_bytes().to_vec(), value.clone()))
+        .collect()
+}
+
+#[test]
+fn dependencies() {
+    let idents = idents(&[
+        ("A", Int(Wrapping(1))),
+        ("B", Int(Wrapping(2))),
+        ("UNUSED", Int(Wrapping(3))),
+        ("S", Str(b"s".to_vec())),
+    ]);
+    let parser = IdentifierParser::new(&idents);
+
+    let input = tokens(&[(Identifier, "A"), (Punctuation, "+"), (Identifier, "B")]);
+    let (_, (value, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(3)));
+    assert_eq!(
+        deps,
+        self::idents(&[("A", Int(Wrapping(1))), ("B", Int(Wrapping(2)))])
+    );
+
+    // `S` is first tried as a numeric operand, which fails
+    let input = tokens(&[(Identifier, "X"), (Identifier, "S"), (Literal, "\"t\"")]);
+    let (_, (name, value, deps)) = parser.macro_definition_with_dependencies(&input).unwrap();
+    assert_eq!(name, b"X");
+    assert_eq!(value, Str(b"st".to_vec()));
+    assert_eq!(deps, self::idents(&[("S", Str(b"s".to_vec()))]));
+
+    // `A` is not part of the parsed expression
+    let input = tokens(&[(Literal, "\"t\""), (Identifier, "A")]);
+    let (rest, (value, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(rest.len(), 1);
+    assert_eq!(value, Str(b"t".to_vec()));
+    assert!(deps.is_empty());
+}
+
+#[test]
+fn trace() {
+    let idents = idents(&[("A", Int(Wrapping(2)))]);
+    let parser = IdentifierParser::new(&idents);
+
+    // -(1+A)*3.5
+    let input = tokens(&[
+        (Punctuation, "-"),
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, "+"),
+        (Identifier, "A"),
+        (Punctuation, ")"),
+        (Punctuation, "*"),
+        (Literal, "3.5"),
+    ]);
+    let (_, (value, trace)) = parser.expr_with_trace(&input).unwrap();
+    assert_eq!(value, Float(-10.5));
+    assert_eq!(
+        trace.to_string(),
+        "1 + 2 = 3\n\
+         -(3) = -3\n\
+         (double)-3 = -3.0 (implicit)\n\
+         -3 * 3.5 = -10.5\n"
+    );
+}
+
+#[test]
+fn stringify() {
+    let idents = idents(&[
+        ("dir", Str(b"/usr".to_vec())),
+        ("n", Int(Wrapping(-5))),
+        ("q", Str(b"a\"b\\c\n\x01".to_vec())),
+    ]);
+    let parser = IdentifierParser::new(&idents);
+
+    let input = tokens(&[(Identifier, "dir"), (Literal, "\"/etc\"")]);
+    assert_eq!(parser.expr(&input).unwrap().1, Str(b"/usr/etc".to_vec()));
+
+    let input = tokens(&[(Punctuation, "#"), (Identifier, "n")]);
+    assert_eq!(parser.expr(&input).unwrap().1, Str(b"-5".to_vec()));
+
+    let input = tokens(&[(Literal, "\"q=\""), (Punctuation, "#"), (Identifier, "q")]);
+    assert_eq!(
+        parser.expr(&input).unwrap().1,
+        Str(b"q=\"a\\\"b\\\\c\\n\\001\"".to_vec())
+    );
+
+    let input = tokens(&[(Punctuation, "#"), (Identifier, "unknown")]);
+    assert!(parser.expr(&input).is_err());
+}
+
+#[test]
+fn ternary() {
+    let idents = idents(&[("A", Int(Wrapping(1))), ("F", Float(2.0))]);
+    let parser = IdentifierParser::new(&idents);
+    let strict = IdentifierParser::new(&idents).with_strict_ternary(true);
+
+    // A ? 1 : (1/0)
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, "?"),
+        (Literal, "1"),
+        (Punctuation, ":"),
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, "/"),
+        (Literal, "0"),
+        (Punctuation, ")"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    assert_eq!(strict.expr(&input).unwrap().1, Int(Wrapping(1)));
+
+    // A ? 1 : F
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, "?"),
+        (Literal, "1"),
+        (Punctuation, ":"),
+        (Identifier, "F"),
+    ]);
+    let (_, (value, trace)) = parser.expr_with_trace(&input).unwrap();
+    assert_eq!(value, Float(1.0));
+    assert_eq!(trace.to_string(), "(double)1 = 1.0 (implicit)\n");
+
+    // A ? 1 : UNKNOWN
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, "?"),
+        (Literal, "1"),
+        (Punctuation, ":"),
+        (Identifier, "UNKNOWN"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    assert!(assert_full_parse(strict.expr(&input)).is_err());
+
+    // A ? 1 : ~F
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, "?"),
+        (Literal, "1"),
+        (Punctuation, ":"),
+        (Punctuation, "~"),
+        (Identifier, "F"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    assert!(assert_full_parse(strict.expr(&input)).is_err());
+}
+
+#[test]
+fn validate() {
+    use cexpr::expr::{validate, Diagnostic};
+
+    // (FLAGS & 0x3) << SHIFT
+    let input = tokens(&[
+        (Punctuation, "("),
+        (Identifier, "FLAGS"),
+        (Punctuation, "&"),
+        (Literal, "0x3"),
+        (Punctuation, ")"),
+        (Punctuation, "<<"),
+        (Identifier, "SHIFT"),
+    ]);
+    assert!(validate(&input).is_ok());
+
+    let input = tokens(&[(Identifier, "A"), (Punctuation, "/"), (Literal, "0")]);
+    assert!(validate(&input).is_ok());
+
+    let input = tokens(&[(Literal, "\"a\""), (Literal, "\"b\"")]);
+    assert!(validate(&input).is_ok());
+
+    let input = tokens(&[(Identifier, "A"), (Punctuation, "+")]);
+    assert!(matches!(validate(&input), Err(Diagnostic::Incomplete)));
+
+    let input = tokens(&[(Identifier, "A"), (Identifier, "B")]);
+    assert!(matches!(
+        validate(&input),
+        Err(Diagnostic::Unexpected { position: 1, .. })
+    ));
+}
+
+#[test]
+fn limits() {
+    use cexpr::expr::{CResult, Limits};
+    use cexpr::nom::Err;
+    use cexpr::ErrorKind;
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents).with_limits(Limits {
+        max_tokens: Some(3),
+        max_literal_len: Some(4),
+    });
+    fn too_large<T>(result: CResult<'_, T>) -> bool {
+        matches!(
+            result,
+            Err(Err::Failure(cexpr::Error {
+                error: ErrorKind::TooLarge,
+                ..
+            }))
+        )
+    }
+
+    let input = tokens(&[(Literal, "1"), (Punctuation, "+"), (Literal, "2")]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(3)));
+
+    let input = tokens(&[
+        (Literal, "1"),
+        (Punctuation, "+"),
+        (Literal, "2"),
+        (Punctuation, "+"),
+        (Literal, "3"),
+    ]);
+    assert!(too_large(parser.expr(&input)));
+    assert!(too_large(
+        parser
+            .expr_with_trace(&input)
+            .map(|(rest, (v, _))| (rest, v))
+    ));
+
+    let input = tokens(&[(Literal, "\"str\"")]);
+    assert!(too_large(parser.expr(&input)));
+}
+
+#[test]
+fn typeof_operator() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(1)))]);
+    let sizes = |ty: &[Token]| match ty {
+        [s, t] if &s.raw[..] == b"struct" && &t.raw[..] == b"s" => Some(12),
+        _ => None,
+    };
+    let parser = IdentifierParser::new(&idents)
+        .with_sizeof(&sizes)
+        .with_c23(true);
+    let eval = |parser: &IdentifierParser<'_>, s: &str| {
+        assert_full_parse(parser.expr(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+
+    // The operand is a type,
+    assert_eq!(
+        eval(&parser, "sizeof(typeof(short))"),
+        Some(Int(Wrapping(2)))
+    );
+    assert_eq!(
+        eval(&parser, "sizeof(typeof(struct s))"),
+        Some(Int(Wrapping(12)))
+    );
+    assert_eq!(
+        eval(&parser, "alignof(typeof(size_t))"),
+        Some(Int(Wrapping(8)))
+    );
+    assert_eq!(
+        eval(&parser, "(typeof(unsigned char))-1"),
+        Some(Int(Wrapping(255)))
+    );
+    assert_eq!(
+        eval(&parser, "(typeof_unqual(const int))-1"),
+        Some(Int(Wrapping(-1)))
+    );
+    // or an expression, which is not evaluated.
+    assert_eq!(
+        eval(&parser, "sizeof(typeof(A + 1LL))"),
+        Some(Int(Wrapping(8)))
+    );
+    assert_eq!(
+        eval(&parser, "sizeof(typeof((char)A))"),
+        Some(Int(Wrapping(1)))
+    );
+    assert_eq!(
+        eval(&parser, "sizeof(typeof(1 / 0))"),
+        Some(Int(Wrapping(4)))
+    );
+    assert_eq!(eval(&parser, "sizeof(typeof(1.0))"), Some(Int(Wrapping(8))));
+    assert_eq!(
+        eval(&parser, "(typeof(1u))-1"),
+        Some(Int(Wrapping(0xffff_ffff)))
+    );
+    assert_eq!(eval(&parser, "(typeof(\"ab\"))1"), None);
+
+    let parser = IdentifierParser::new(&idents);
+    assert_eq!(eval(&parser, "(typeof(1u))-1"), None);
+    assert_eq!(eval(&parser, "sizeof(typeof(short))"), None);
+}
+
+#[test]
+fn permissive() {
+    let idents = idents(&[("A", Int(Wrapping(1)))]);
+    let parser = IdentifierParser::new(&idents).with_permissive(true);
+    let strict = IdentifierParser::new(&idents)
+        .with_permissive(true)
+        .with_strict_ternary(true);
+    let eval = |parser: &IdentifierParser<'_>, input: &[(Kind, &str)]| {
+        assert_full_parse(parser.expr(&tokens(input)))
+            .ok()
+            .map(|(_, value)| value)
+    };
+
+    // (_Static_assert_helper, A + 1)
+    let input = [
+        (Punctuation, "("),
+        (Identifier, "_Static_assert_helper"),
+        (Punctuation, ","),
+        (Identifier, "A"),
+        (Punctuation, "+"),
+        (Literal, "1"),
+        (Punctuation, ")"),
+    ];
+    assert_eq!(eval(&parser, &input), Some(Int(Wrapping(2))));
+    assert_eq!(eval(&strict, &input), None);
+    assert_eq!(eval(&IdentifierParser::new(&idents), &input), None);
+    // (A, 1 / 0, 3) * 2
+    let input = [
+        (Punctuation, "("),
+        (Identifier, "A"),
+        (Punctuation, ","),
+        (Literal, "1"),
+        (Punctuation, "/"),
+        (Literal, "0"),
+        (Punctuation, ","),
+        (Literal, "3"),
+        (Punctuation, ")"),
+        (Punctuation, "*"),
+        (Literal, "2"),
+    ];
+    assert_eq!(eval(&parser, &input), Some(Int(Wrapping(6))));
+    // (1, UNKNOWN)
+    let input = [
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, ","),
+        (Identifier, "UNKNOWN"),
+        (Punctuation, ")"),
+    ];
+    assert_eq!(eval(&parser, &input), None);
+
+    // (_Static_assert(f(1, 2), "size"), _Static_assert(0), A)
+    let input = [
+        (Punctuation, "("),
+        (Keyword, "_Static_assert"),
+        (Punctuation, "("),
+        (Identifier, "f"),
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, ","),
+        (Literal, "2"),
+        (Punctuation, ")"),
+        (Punctuation, ","),
+        (Literal, "\"size\""),
+        (Punctuation, ")"),
+        (Punctuation, ","),
+        (Keyword, "_Static_assert"),
+        (Punctuation, "("),
+        (Literal, "0"),
+        (Punctuation, ")"),
+        (Punctuation, ","),
+        (Identifier, "A"),
+        (Punctuation, ")"),
+    ];
+    assert_eq!(eval(&strict, &input), Some(Int(Wrapping(1))));
+    // _Static_assert(1)
+    let input = [
+        (Keyword, "_Static_assert"),
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, ")"),
+    ];
+    assert_eq!(eval(&parser, &input), None);
+    // (_Static_assert(1), 8)
+    let input = [
+        (Punctuation, "("),
+        (Keyword, "_Static_assert"),
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, ")"),
+        (Punctuation, ","),
+        (Literal, "8"),
+        (Punctuation, ")"),
+    ];
+    assert_eq!(eval(&parser, &input), Some(Int(Wrapping(8))));
+    assert_eq!(eval(&IdentifierParser::new(&idents), &input), None);
+}
+
+#[test]
+fn chained_comparison() {
+    use cexpr::expr::{Warning, WarningKind};
+
+    let idents = idents(&[("A", Int(Wrapping(3)))]);
+    let parser = IdentifierParser::new(&idents);
+
+    // A > 2 > 1
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, ">"),
+        (Literal, "2"),
+        (Punctuation, ">"),
+        (Literal, "1"),
+    ]);
+    let (_, (value, warnings)) = parser.expr_with_warnings(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(0)));
+    assert_eq!(
+        warnings,
+        [Warning {
+            position: 3,
+            kind: WarningKind::ChainedComparison
+        }]
+    );
+
+    // (A > 2) > 1
+    let input = tokens(&[
+        (Punctuation, "("),
+        (Identifier, "A"),
+        (Punctuation, ">"),
+        (Literal, "2"),
+        (Punctuation, ")"),
+        (Punctuation, ">"),
+        (Literal, "1"),
+    ]);
+    let (_, (value, warnings)) = parser.expr_with_warnings(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(0)));
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn normalization() {
+    let idents = idents(&[("FOO", Int(Wrapping(1))), ("foo", Int(Wrapping(2)))]);
+    let upper = |name: &[u8]| name.to_ascii_uppercase();
+    let parser = IdentifierParser::new(&idents).with_normalization(&upper);
+
+    let input = tokens(&[(Identifier, "foo")]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(2)));
+
+    let input = tokens(&[(Identifier, "Foo")]);
+    let (_, (value, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(1)));
+    assert_eq!(deps, self::idents(&[("FOO", Int(Wrapping(1)))]));
+
+    let input = tokens(&[(Identifier, "bar")]);
+    assert!(parser.expr(&input).is_err());
+}
+
+#[test]
+fn stringified() {
+    let idents = idents(&[
+        ("n", Int(Wrapping(-3))),
+        ("s", Str(b"a\"b\\".to_vec())),
+        ("i", Invalid),
+    ]);
+    let parser = IdentifierParser::new(&idents);
+    let spell = |input: &[Token]| -> Option<Vec<String>> {
+        let tokens = parser.stringified(input)?;
+        Some(
+            tokens
+                .iter()
+                .map(|t| String::from_utf8_lossy(&t.raw).into_owned())
+                .collect(),
+        )
+    };
+
+    let input = tokens(&[
+        (Punctuation, "#"),
+        (Identifier, "s"),
+        (Literal, "\"=\""),
+        (Punctuation, "#"),
+        (Identifier, "n"),
+    ]);
+    assert_eq!(
+        spell(&input).unwrap(),
+        [r#""\"a\\\"b\\\\\"""#, "\"=\"", "\"-3\""]
+    );
+    // The stringified tokens evaluate to the same string.
+    let stringified = parser.stringified(&input).unwrap();
+    assert_eq!(
+        parser.expr(&stringified).unwrap().1,
+        parser.expr(&input).unwrap().1
+    );
+
+    let input = tokens(&[(Identifier, "n"), (Punctuation, "#")]);
+    assert_eq!(spell(&input).unwrap(), ["n", "#"]);
+    let input = tokens(&[(Punctuation, "#"), (Identifier, "unknown")]);
+    assert_eq!(spell(&input), None);
+    let input = tokens(&[(Punctuation, "#"), (Identifier, "i")]);
+    assert_eq!(spell(&input), None);
+}
+
+#[test]
+fn resolvers() {
+    use std::collections::BTreeMap;
+
+    let input = tokens(&[
+        (Identifier, "A"),
+        (Punctuation, "+"),
+        (Identifier, "defined"),
+        (Identifier, "B"),
+    ]);
+
+    let mut map = BTreeMap::new();
+    map.insert(b"A".to_vec(), Int(Wrapping(2)));
+    let parser = IdentifierParser::from_resolver(&map);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(2)));
+
+    let resolve = |name: &[u8]| match name {
+        b"A" | b"B" => Some(Int(Wrapping(2))),
+        _ => None,
+    };
+    let parser = IdentifierParser::from_resolver(&resolve);
+    let (_, (value, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(3)));
+    assert_eq!(
+        deps,
+        idents(&[("A", Int(Wrapping(2))), ("B", Int(Wrapping(2)))])
+    );
+
+    let input = tokens(&[(Identifier, "C")]);
+    assert!(parser.expr(&input).is_err());
+}
+
+#[test]
+fn lazy_resolver() {
+    use cexpr::expr::LazyResolver;
+    use cexpr::token::tokenize;
+
+    let definition = |name: &[u8]| {
+        let name = std::str::from_utf8(name).ok()?;
+        let source = match name {
+            "LOOP_A" => "LOOP_B + 1".to_owned(),
+            "LOOP_B" => "LOOP_A".to_owned(),
+            "USES_LOOP" => "LOOP_A ? 1 : 2".to_owned(),
+            "BAD" => "1 +".to_owned(),
+            "UNSIGNED" => "M0 + 1u".to_owned(),
+            "M0" => "1".to_owned(),
+            _ => {
+                let n: usize = name.strip_prefix('M')?.parse().ok()?;
+                format!("M{} + 1", n - 1)
+            }
+        };
+        Some(tokenize(source.as_bytes()))
+    };
+    let resolver = LazyResolver::new(&definition);
+    let parser = IdentifierParser::from_resolver(&resolver);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+
+    assert_eq!(eval("M3 * M2"), Some(Int(Wrapping(12))));
+    assert_eq!(eval("M50"), Some(Int(Wrapping(51))));
+    assert_eq!(eval("M1000"), None);
+    assert_eq!(eval("LOOP_A"), None);
+    assert_eq!(eval("USES_LOOP"), None);
+    assert_eq!(eval("BAD"), None);
+    assert_eq!(eval("UNDEFINED"), None);
+    // The type of a macro follows from its definition.
+    assert_eq!(
+        eval("UNSIGNED - 3"),
+        Some(Int(Wrapping(i64::from(u32::MAX))))
+    );
+
+    let values = resolver.into_values();
+    assert_eq!(values.get(&b"M3"[..]), Some(&Int(Wrapping(4))));
+    assert!(!values.contains_key(&b"LOOP_A"[..]));
+}
+
+#[test]
+fn fn_macros() {
+    use cexpr::expr::{FnMacro, Warning, WarningKind};
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let definitions = [
+        "VERSION(a, b, c) ((a) << 16 | (b) << 8 | (c))",
+        "MAX(a, b) ((a) > (b) ? (a) : (b))",
+        "STR(x) #x",
+        "ZERO() 0",
+        "REC(x) REC(x)",
+        "D0(x) x",
+        "SHIFTED(x) (-8 >> 1) + 0 + 0 + 0 + 0 + 0 + 0 + 0 + 0 + x",
+    ];
+    let mut macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    for i in 1..40 {
+        let source = format!("D{i}(x) D{j}(x) + D{j}(x)", i = i, j = i - 1);
+        let (name, definition) = FnMacro::parse(&tokenize(source.as_bytes())).unwrap();
+        macros.insert(name, definition);
+    }
+    let idents = idents(&[("A", Int(Wrapping(7))), ("MAX", Int(Wrapping(9)))]);
+    let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .map(|(_, value)| value)
+            .map_err(|e| match e {
+                cexpr::nom::Err::Error(e) | cexpr::nom::Err::Failure(e) => e.error,
+                cexpr::nom::Err::Incomplete(_) => panic!("incomplete"),
+            })
+    };
+
+    assert_eq!(eval("VERSION(1, 2, 3)"), Ok(Int(Wrapping(0x010203))));
+    assert_eq!(eval("MAX(A, MAX((1, 8), 2)) + 1"), Ok(Int(Wrapping(9))));
+    assert_eq!(eval("MAX"), Ok(Int(Wrapping(9))));
+    assert_eq!(eval("ZERO()"), Ok(Int(Wrapping(0))));
+    assert_eq!(eval("D4(1)"), Ok(Int(Wrapping(16))));
+    assert_eq!(
+        eval(r#"STR(a+ "b\n"  'c')"#),
+        Ok(Str(br#"a+ "b\n" 'c'"#.to_vec()))
+    );
+    assert_eq!(eval("VERSION(1, 2)"), Err(ErrorKind::ArgumentCount));
+    assert_eq!(eval("ZERO(1)"), Err(ErrorKind::ArgumentCount));
+    assert_eq!(eval("VERSION(1, 2, B)"), Err(ErrorKind::UnknownIdentifier));
+    assert_eq!(eval("REC(1)"), Err(ErrorKind::UnknownIdentifier));
+    assert_eq!(eval("D39(1)"), Err(ErrorKind::TooLarge));
+    assert!(eval("VERSION(1, 2, 3").is_err());
+
+    // Warnings about the expansion are reported at the invocation.
+    let shifted = Warning {
+        position: 2,
+        kind: WarningKind::NegativeRightShift,
+    };
+    let input = tokenize(b"1 + SHIFTED(1)");
+    let (_, (value, warnings)) = assert_full_parse(parser.expr_with_warnings(&input)).unwrap();
+    assert_eq!(value, Int(Wrapping(-2)));
+    assert_eq!(warnings, [shifted]);
+    let (_, (value, warnings)) = assert_full_parse(parser.enumerator(None, &input)).unwrap();
+    assert_eq!(value, -2);
+    assert_eq!(warnings, [shifted]);
+}
+
+#[test]
+fn fn_macro_stringification() {
+    use cexpr::expr::FnMacro;
+    use cexpr::token::tokenize;
+
+    let definitions = [
+        "STR(x) #x",
+        "XSTR(x) STR(x)",
+        "SPACED(x) # /* comment */ x",
+        "PREFIXED(x) \"v\" #x \".\" XSTR(x)",
+        "ZERO() 0",
+    ];
+    let macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    let idents = idents(&[]);
+    let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    let s = |s: &str| Some(Str(s.as_bytes().to_vec()));
+
+    assert_eq!(eval("STR(  a  )"), s("a"));
+    assert_eq!(eval("STR()"), s(""));
+    assert_eq!(eval("STR(a /* comment */ b)"), s("a b"));
+    assert_eq!(eval("STR(a\n+b)"), s("a +b"));
+    assert_eq!(eval("STR((a, b))"), s("(a, b)"));
+    assert_eq!(eval(r#"STR("\n")"#), s(r#""\n""#));
+    assert_eq!(eval("SPACED(a)"), s("a"));
+    // Arguments are stringified before the macros in them are expanded.
+    assert_eq!(eval("STR(ZERO())"), s("ZERO()"));
+    assert_eq!(eval("XSTR(ZERO())"), s("0"));
+    assert_eq!(eval("PREFIXED(1)"), s("v1.1"));
+
+    // Tokens without spans, as libclang gives them, are separated by spaces.
+    let input = tokens(&[
+        (Identifier, "STR"),
+        (Punctuation, "("),
+        (Identifier, "a"),
+        (Punctuation, "+"),
+        (Literal, "1"),
+        (Punctuation, ")"),
+    ]);
+    let value = assert_full_parse(parser.expr(&input))
+        .ok()
+        .map(|(_, value)| value);
+    assert_eq!(value, s("a + 1"));
+}
+
+#[test]
+fn punctuation() {
+    use cexpr::expr::FnMacro;
+    use cexpr::token::tokenize;
+
+    let definitions = ["STR(x) %:x", "CAT(x, y) x %:%: y", "AT(s, i) s<:i:>"];
+    let macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    let idents = idents(&[]);
+    let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+
+    assert_eq!(eval("1<<4>>2<=4>=1==1!=0"), Some(Int(Wrapping(1))));
+    assert_eq!(eval("\"ab\"<:1:>"), eval("\"ab\"[1]"));
+    assert_eq!(eval("STR(a)"), Some(Str(b"a".to_vec())));
+    assert_eq!(eval("CAT(1, 2)"), Some(Int(Wrapping(12))));
+    assert_eq!(eval("AT(\"xy\", 1)"), eval("'y'"));
+}
+
+#[test]
+fn variadic_fn_macros() {
+    use cexpr::expr::{fn_macro_declaration, FnMacro};
+    use cexpr::token::tokenize;
+
+    let declaration = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        fn_macro_declaration(&tokens)
+            .ok()
+            .map(|(_, (_, params))| params.iter().map(|p| p.to_vec()).collect::<Vec<_>>())
+    };
+    assert_eq!(
+        declaration("F(a, ...) a"),
+        Some(vec![b"a".to_vec(), b"...".to_vec()])
+    );
+    assert_eq!(declaration("F(...) 1"), Some(vec![b"...".to_vec()]));
+    assert_eq!(declaration("F(..., a) a"), None);
+
+    let definitions = [
+        "ADD(a, ...) ((a) __VA_OPT__(+ (__VA_ARGS__)))",
+        "COUNT(...) (0 __VA_OPT__(+ 1))",
+        "STR(...) #__VA_ARGS__",
+    ];
+    let macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+
+    assert_eq!(eval("ADD(1)"), Some(Int(Wrapping(1))));
+    assert_eq!(eval("ADD(1, 2)"), Some(Int(Wrapping(3))));
+    assert_eq!(eval("ADD(1, 2, 3)"), Some(Int(Wrapping(4))));
+    assert_eq!(eval("COUNT()"), Some(Int(Wrapping(0))));
+    assert_eq!(eval("COUNT(x, (y, z))"), Some(Int(Wrapping(1))));
+    assert_eq!(eval("STR(a, b)"), Some(Str(b"a, b".to_vec())));
+    assert_eq!(eval("STR(a,b)"), Some(Str(b"a,b".to_vec())));
+
+    let add = &macros[&b"ADD"[..]];
+    let args = [tokenize(b"1"), tokenize(b"2"), tokenize(b"3")];
+    let args: Vec<&[Token]> = args.iter().map(|arg| &arg[..]).collect();
+    let expansion = add.expand(&args).unwrap();
+    assert_eq!(expansion, tokenize(b"((1) + (2, 3))"));
+}
+
+#[test]
+fn token_pasting() {
+    use cexpr::expr::FnMacro;
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let definitions = [
+        "CONCAT(a, b) a ## b",
+        "CONCAT3(a, b, c) a ## b ## c",
+        "HEX(digits) 0x ## digits",
+        "SUFFIX(x) x %:%: u",
+        "CALL(f, x) f ## 1(x)",
+        "F1(x) (x + 1)",
+        "ONE() 1",
+        "NAME(x) x ## _VALUE",
+        "START() ## 1",
+    ];
+    let macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    let idents = idents(&[("FOO_VALUE", Int(Wrapping(5))), ("ONE", Int(Wrapping(9)))]);
+    let parser = IdentifierParser::new(&idents).with_fn_macros(&macros);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .map(|(_, value)| value)
+            .map_err(|e| match e {
+                cexpr::nom::Err::Error(e) | cexpr::nom::Err::Failure(e) => e.error,
+                cexpr::nom::Err::Incomplete(_) => panic!("incomplete"),
+            })
+    };
+
+    assert_eq!(eval("CONCAT(0x1, 0)"), Ok(Int(Wrapping(0x10))));
+    assert_eq!(eval("CONCAT3(1, 2, 3)"), Ok(Int(Wrapping(123))));
+    assert_eq!(eval("HEX(ff)"), Ok(Int(Wrapping(0xff))));
+    assert_eq!(eval("SUFFIX(1) + 1"), Ok(Int(Wrapping(2))));
+    assert_eq!(eval("CALL(F, 2)"), Ok(Int(Wrapping(3))));
+    assert_eq!(eval("NAME(FOO)"), Ok(Int(Wrapping(5))));
+    // Empty arguments are left out, and arguments are pasted without
+    // expanding them.
+    assert_eq!(eval("CONCAT(, 4)"), Ok(Int(Wrapping(4))));
+    assert_eq!(eval("CONCAT(4, )"), Ok(Int(Wrapping(4))));
+    assert_eq!(eval("CONCAT3(, , 5)"), Ok(Int(Wrapping(5))));
+    assert_eq!(eval("CONCAT(ONE, )"), Ok(Int(Wrapping(9))));
+    assert_eq!(eval("CONCAT(ONE(), )"), Ok(Int(Wrapping(1))));
+
+    assert_eq!(eval("CONCAT(1, +)"), Err(ErrorKind::InvalidPaste));
+    assert_eq!(eval("CONCAT(/, /)"), Err(ErrorKind::InvalidPaste));
+    assert_eq!(eval("START()"), Err(ErrorKind::InvalidPaste));
+
+    let concat = &macros[&b"CONCAT"[..]];
+    let (a, b) = (tokenize(b"1 2"), tokenize(b"3 4"));
+    assert_eq!(concat.expand(&[&a, &b]), Ok(tokenize(b"1 23 4")));
+}
+
+#[test]
+fn expansion_spelling() {
+    use cexpr::expr::FnMacro;
+    use cexpr::token::tokenize;
+
+    let definitions = [
+        "ID(x) x",
+        "NEG(x) -x",
+        "CALL(f, x) f(x)",
+        "SPACED(x)  (  x  ) +1",
+        "PAIR(a, b) a b",
+        "CONCAT(a, b) a ## b",
+        "STR(x) = #x",
+        "ADD(x, ...) x +__VA_ARGS__",
+        "OPT(x, ...) x __VA_OPT__(, __VA_ARGS__)",
+    ];
+    let macros: HashMap<_, _> = definitions
+        .iter()
+        .map(|d| FnMacro::parse(&tokenize(d.as_bytes())).unwrap())
+        .collect();
+    let spell = |name: &str, args: &[&str]| {
+        let args: Vec<_> = args.iter().map(|arg| tokenize(arg.as_bytes())).collect();
+        let args: Vec<&[Token]> = args.iter().map(|arg| &arg[..]).collect();
+        macros[name.as_bytes()].expansion_spelling(&args).unwrap()
+    };
+
+    // Whitespace is kept from the replacement list and the arguments.
+    assert_eq!(spell("ID", &["a+ b  *c"]), "a+ b *c");
+    assert_eq!(spell("CALL", &["f", " 1,2 "]), "f(1,2)");
+    assert_eq!(spell("SPACED", &["1"]), "( 1 ) +1");
+    assert_eq!(spell("PAIR", &["", "1"]), "1");
+    assert_eq!(spell("PAIR", &["1", ""]), "1");
+    // Tokens that would run together are separated.
+    assert_eq!(spell("NEG", &["-1"]), "- -1");
+    assert_eq!(spell("ADD", &["1", "+2"]), "1 + +2");
+    assert_eq!(spell("ID", &["x/**/y"]), "x y");
+    // Pasted and stringified tokens.
+    assert_eq!(spell("CONCAT", &["x 1", "2 y"]), "x 12 y");
+    assert_eq!(spell("STR", &["  a  +b "]), "= \"a +b\"");
+    // Variadic arguments.
+    assert_eq!(spell("ADD", &["1", "2", "3"]), "1 +2, 3");
+    assert_eq!(spell("OPT", &["1"]), "1");
+    assert_eq!(spell("OPT", &["1", "2"]), "1 , 2");
+}
+
+#[test]
+fn arrays() {
+    use cexpr::expr::Array;
+    use cexpr::target::IntType;
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let values = idents(&[("_U", Int(Wrapping(1))), ("TABLE", Int(Wrapping(7)))]);
+    let mut arrays = HashMap::new();
+    let mut ctype = vec![0; 256];
+    ctype[b'A' as usize] = 1;
+    ctype[b'a' as usize] = 2;
+    arrays.insert(
+        b"_ctype_table".to_vec(),
+        Array {
+            elements: ctype,
+            ty: IntType::UnsignedShort,
+        },
+    );
+    arrays.insert(
+        b"TABLE".to_vec(),
+        Array {
+            elements: vec![300, 255, -1],
+            ty: IntType::SignedChar,
+        },
+    );
+    let resolver = (values, arrays);
+    let parser = IdentifierParser::from_resolver(&resolver);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr(&tokenize(source.as_bytes())))
+            .map(|(_, value)| value)
+            .map_err(|e| match e {
+                cexpr::nom::Err::Error(e) | cexpr::nom::Err::Failure(e) => e.error,
+                cexpr::nom::Err::Incomplete(_) => panic!("incomplete"),
+            })
+    };
+
+    assert_eq!(eval("_ctype_table[65]"), Ok(Int(Wrapping(1))));
+    assert_eq!(eval("_ctype_table['A']"), Ok(Int(Wrapping(1))));
+    assert_eq!(eval("(_ctype_table['A'] & _U)"), Ok(Int(Wrapping(1))));
+    assert_eq!(eval("(_ctype_table['a'] & _U)"), Ok(Int(Wrapping(0))));
+    assert_eq!(
+        eval("_ctype_table[0x61] | _ctype_table[65]"),
+        Ok(Int(Wrapping(3)))
+    );
+    assert_eq!(eval("_ctype_table[(1, 65)]"), Ok(Int(Wrapping(1))));
+    // Elements are converted to the type of the array.
+    assert_eq!(eval("TABLE[0]"), Ok(Int(Wrapping(44))));
+    assert_eq!(eval("TABLE[1] + TABLE[2]"), Ok(Int(Wrapping(-2))));
+    // The first resolver of the pair takes precedence.
+    assert_eq!(eval("TABLE"), Ok(Int(Wrapping(7))));
+
+    assert_eq!(eval("TABLE[3]"), Err(ErrorKind::NotConstant));
+    assert_eq!(eval("TABLE[-1]"), Err(ErrorKind::NotConstant));
+    assert_eq!(eval("TABLE[1.0]"), Err(ErrorKind::NotConstant));
+    assert_eq!(eval("_ctype_table"), Err(ErrorKind::NotConstant));
+    assert_eq!(
+        eval("_ctype_table[OTHER]"),
+        Err(ErrorKind::UnknownIdentifier)
+    );
+    assert_eq!(eval("1 ? 2 : TABLE[3]"), Ok(Int(Wrapping(2))));
+}
+
+#[test]
+fn escape_out_of_range() {
+    use cexpr::expr::{Warning, WarningKind};
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let warnings = |list: &[(Kind, &str)]| {
+        let (_, (_, warnings)) = parser.expr_with_warnings(&tokens(list)).unwrap();
+        warnings
+    };
+
+    assert_eq!(
+        warnings(&[(Literal, "\"a\""), (Literal, "\"\\x100\"")]),
+        [Warning {
+            position: 1,
+            kind: WarningKind::EscapeOutOfRange
+        }]
+    );
+    assert_eq!(
+        warnings(&[(Literal, "'\\x10000000000000000'")]),
+        [Warning {
+            position: 0,
+            kind: WarningKind::EscapeOutOfRange
+        }]
+    );
+    assert!(warnings(&[(Literal, "'\\xffffffffffffffff'")]).is_empty());
+    assert!(warnings(&[(Literal, "\"\\xff\\377\"")]).is_empty());
+}
+
+#[test]
+fn ambiguous_octal_escape() {
+    use cexpr::expr::{Warning, WarningKind};
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let warnings = |list: &[(Kind, &str)]| {
+        let (_, (_, warnings)) = parser.expr_with_warnings(&tokens(list)).unwrap();
+        warnings
+    };
+
+    assert_eq!(
+        warnings(&[(Literal, "\"a\""), (Literal, "\"\\0001\"")]),
+        [Warning {
+            position: 1,
+            kind: WarningKind::AmbiguousOctalEscape
+        }]
+    );
+    assert_eq!(
+        warnings(&[(Literal, "\"\\18\"")]),
+        [Warning {
+            position: 0,
+            kind: WarningKind::AmbiguousOctalEscape
+        }]
+    );
+    assert!(warnings(&[(Literal, "\"\\000\\0a\"")]).is_empty());
+    assert!(warnings(&[(Literal, "'\\0'")]).is_empty());
+}
+
+#[test]
+fn cast_comparison() {
+    let idents = idents(&[("X", Int(Wrapping(-1)))]);
+    let parser = IdentifierParser::new(&idents);
+    // ((unsigned)X > 0)
+    let input = tokens(&[
+        (Punctuation, "("),
+        (Punctuation, "("),
+        (Keyword, "unsigned"),
+        (Punctuation, ")"),
+        (Identifier, "X"),
+        (Punctuation, ">"),
+        (Literal, "0"),
+        (Punctuation, ")"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    // (int)X == (long)X
+    let input = tokens(&[
+        (Punctuation, "("),
+        (Keyword, "int"),
+        (Punctuation, ")"),
+        (Identifier, "X"),
+        (Punctuation, "=="),
+        (Punctuation, "("),
+        (Keyword, "long"),
+        (Punctuation, ")"),
+        (Identifier, "X"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    // (short long)X
+    let input = tokens(&[
+        (Punctuation, "("),
+        (Keyword, "short"),
+        (Keyword, "long"),
+        (Punctuation, ")"),
+        (Identifier, "X"),
+    ]);
+    assert!(parser.expr(&input).is_err());
+
+    // (const volatile unsigned long)X
+    let input = cexpr::token::tokenize(b"(const volatile unsigned long)X");
+    let (_, (value, trace)) = parser.expr_with_trace(&input).unwrap();
+    assert_eq!(value, Int(Wrapping(-1)));
+    assert_eq!(trace.to_string(), "(const volatile unsigned long)-1 = -1\n");
+    let input = cexpr::token::tokenize(b"(size_t const)X");
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(-1)));
+    let input = cexpr::token::tokenize(b"(const)X");
+    assert!(parser.expr(&input).is_err());
+}
+
+#[test]
+fn substitute() {
+    use cexpr::literal::CChar;
+
+    let idents = idents(&[
+        ("A", Int(Wrapping(-2))),
+        ("F", Float(0.5)),
+        ("S", Str(b"a\"b".to_vec())),
+        ("C", Char(CChar::Char('c'))),
+        ("I", Invalid),
+    ]);
+    let input = tokens(&[
+        (Literal, "3"),
+        (Punctuation, "-"),
+        (Identifier, "A"),
+        (Punctuation, "*"),
+        (Identifier, "F"),
+        (Punctuation, "+"),
+        (Identifier, "C"),
+        (Punctuation, "+"),
+        (Identifier, "I"),
+        (Punctuation, "+"),
+        (Identifier, "B"),
+        (Identifier, "S"),
+        (Punctuation, "#"),
+        (Identifier, "S"),
+    ]);
+    assert_eq!(
+        cexpr::expr::substitute(&input, &idents),
+        tokens(&[
+            (Literal, "3"),
+            (Punctuation, "-"),
+            (Punctuation, "("),
+            (Punctuation, "-"),
+            (Literal, "2"),
+            (Punctuation, ")"),
+            (Punctuation, "*"),
+            (Literal, "0.5"),
+            (Punctuation, "+"),
+            (Literal, "'c'"),
+            (Punctuation, "+"),
+            (Identifier, "I"),
+            (Punctuation, "+"),
+            (Identifier, "B"),
+            (Literal, "\"a\\\"b\""),
+            (Punctuation, "#"),
+            (Identifier, "S"),
+        ])
+    );
+
+    // The substituted tokens evaluate to the same value.
+    let input = &input[..5];
+    let value = IdentifierParser::new(&idents).expr(input).unwrap().1;
+    let substituted = cexpr::expr::substitute(input, &idents);
+    assert_eq!(cexpr::expr::expr(&substituted).unwrap().1, value);
+    assert_eq!(value, Float(4.0));
+
+    let textual = IdentifierParser::new(&idents).with_implicit_parentheses(false);
+    assert_eq!(
+        textual.substitute(&input[..3]),
+        tokens(&[
+            (Literal, "3"),
+            (Punctuation, "-"),
+            (Punctuation, "-"),
+            (Literal, "2"),
+        ])
+    );
+}
+
+#[test]
+fn string_arithmetic() {
+    use cexpr::ErrorKind;
+
+    let idents = idents(&[("S", Str(b"abc".to_vec()))]);
+    let parser = IdentifierParser::new(&idents);
+    let error = |list: &[(Kind, &str)]| match parser.expr(&tokens(list)) {
+        Err(cexpr::nom::Err::Failure(e)) => Some(e.error),
+        _ => None,
+    };
+    let is_string_arithmetic = |list| matches!(error(list), Some(ErrorKind::StringArithmetic));
+
+    assert!(is_string_arithmetic(&[
+        (Literal, "\"abc\""),
+        (Punctuation, "+"),
+        (Literal, "1")
+    ]));
+    assert!(is_string_arithmetic(&[
+        (Literal, "1"),
+        (Punctuation, "+"),
+        (Identifier, "S")
+    ]));
+    assert!(is_string_arithmetic(&[
+        (Punctuation, "("),
+        (Literal, "1"),
+        (Punctuation, "*"),
+        (Literal, "2"),
+        (Punctuation, ")"),
+        (Punctuation, "-"),
+        (Literal, "\"abc\"")
+    ]));
+    assert!(error(&[(Literal, "1"), (Punctuation, "+")]).is_none());
+
+    // S[1 + 1]
+    let input = tokens(&[
+        (Identifier, "S"),
+        (Punctuation, "["),
+        (Literal, "1"),
+        (Punctuation, "+"),
+        (Literal, "1"),
+        (Punctuation, "]"),
+    ]);
+    assert_eq!(
+        parser.expr(&input).unwrap().1,
+        Char(cexpr::literal::CChar::Char('c'))
+    );
+}
+
+#[test]
+fn file_and_line() {
+    use cexpr::expr::fn_macro_declaration;
+
+    // #define STR(x) #x
+    let str_macro = tokens(&[
+        (Identifier, "STR"),
+        (Punctuation, "("),
+        (Identifier, "x"),
+        (Punctuation, ")"),
+        (Punctuation, "#"),
+        (Identifier, "x"),
+    ]);
+    let (body, (_, args)) = fn_macro_declaration(&str_macro).unwrap();
+    assert_eq!(args, [b"x"]);
+    let line = idents(&[("x", Int(Wrapping(12)))]);
+    let (_, line) = assert_full_parse(IdentifierParser::new(&line).expr(body)).unwrap();
+    assert_eq!(line, Str(b"12".to_vec()));
+
+    // #define LOG_PREFIX __FILE__ ":" STR(__LINE__)
+    // with STR(__LINE__) replaced by an identifier defined as its value
+    let idents = idents(&[("__FILE__", Str(b"log.h".to_vec())), ("STR__LINE__", line)]);
+    let input = tokens(&[
+        (Identifier, "LOG_PREFIX"),
+        (Identifier, "__FILE__"),
+        (Literal, "\":\""),
+        (Identifier, "STR__LINE__"),
+    ]);
+    let (_, (name, value)) =
+        assert_full_parse(IdentifierParser::new(&idents).macro_definition(&input)).unwrap();
+    assert_eq!(name, b"LOG_PREFIX");
+    assert_eq!(value, Str(b"log.h:12".to_vec()));
+}
+
+#[test]
+fn offsetof() {
+    use cexpr::expr::Designator::{self, *};
+
+    let layout = |ty: &[Token], path: &[Designator<'_>]| {
+        let ty: Vec<&[u8]> = ty.iter().map(|t| &t.raw[..]).collect();
+        match (&ty[..], path) {
+            ([b"struct", b"hdr"], [Member(b"len")]) => Some(4),
+            ([b"struct", b"hdr"], [Member(b"opt"), Index(i), Member(b"kind")]) => {
+                Some(8 + 2 * *i as u64)
+            }
+            _ => None,
+        }
+    };
+    let idents = idents(&[("N", Int(Wrapping(3)))]);
+    let parser = IdentifierParser::new(&idents).with_offsetof(&layout);
+
+    // __builtin_offsetof(struct hdr, opt[N - 1].kind) + 1
+    let input = tokens(&[
+        (Identifier, "__builtin_offsetof"),
+        (Punctuation, "("),
+        (Keyword, "struct"),
+        (Identifier, "hdr"),
+        (Punctuation, ","),
+        (Identifier, "opt"),
+        (Punctuation, "["),
+        (Identifier, "N"),
+        (Punctuation, "-"),
+        (Literal, "1"),
+        (Punctuation, "]"),
+        (Punctuation, "."),
+        (Identifier, "kind"),
+        (Punctuation, ")"),
+        (Punctuation, "+"),
+        (Literal, "1"),
+    ]);
+    assert_eq!(
+        assert_full_parse(parser.expr(&input)).unwrap().1,
+        Int(Wrapping(13))
+    );
+    assert!(cexpr::expr::validate(&input).is_ok());
+
+    // __builtin_offsetof(struct hdr, len)
+    let input = tokens(&[
+        (Identifier, "__builtin_offsetof"),
+        (Punctuation, "("),
+        (Keyword, "struct"),
+        (Identifier, "hdr"),
+        (Punctuation, ","),
+        (Identifier, "len"),
+        (Punctuation, ")"),
+    ]);
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(4)));
+    match IdentifierParser::new(&idents).expr(&input) {
+        Err(cexpr::nom::Err::Failure(e)) => {
+            assert!(matches!(e.error, cexpr::ErrorKind::UnknownLayout))
+        }
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn sizeof() {
+    use cexpr::target::TargetInfo;
+    use cexpr::token::tokenize;
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let narrow = IdentifierParser::new(&idents).with_target(TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    });
+    let size = |parser: &IdentifierParser<'_>, s: &str| {
+        assert_full_parse(parser.expr(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    for &(ty, wide, narrow_size) in &[
+        ("char", 1, 1),
+        ("unsigned short int", 2, 2),
+        ("const int", 4, 4),
+        ("long", 8, 4),
+        ("long long", 8, 8),
+        ("size_t", 8, 4),
+        ("uint32_t", 4, 4),
+        ("_Bool", 1, 1),
+        ("double", 8, 8),
+        ("const char *", 8, 4),
+        ("struct s * const", 8, 4),
+    ] {
+        let expr = format!("sizeof({})", ty);
+        assert_eq!(size(&parser, &expr), Some(Int(Wrapping(wide))), "{}", ty);
+        assert_eq!(
+            size(&narrow, &expr),
+            Some(Int(Wrapping(narrow_size))),
+            "{}",
+            ty
+        );
+    }
+    assert_eq!(
+        size(&parser, "(sizeof(uint32_t)*2)"),
+        Some(Int(Wrapping(8)))
+    );
+    for input in &[
+        "sizeof(struct s)",
+        "sizeof(long double)",
+        "sizeof(1)",
+        "sizeof int",
+    ] {
+        assert_eq!(size(&parser, input), None, "{}", input);
+    }
+
+    let layout = |ty: &[Token]| {
+        let ty: Vec<&[u8]> = ty.iter().map(|t| &t.raw[..]).collect();
+        match &ty[..] {
+            [b"struct", b"s"] => Some(12),
+            [b"long"] => Some(4),
+            _ => None,
+        }
+    };
+    let parser = IdentifierParser::new(&idents).with_sizeof(&layout);
+    assert_eq!(
+        size(&parser, "sizeof(struct s) * 2"),
+        Some(Int(Wrapping(24)))
+    );
+    assert_eq!(size(&parser, "sizeof(long)"), Some(Int(Wrapping(4))));
+    assert_eq!(size(&parser, "sizeof(int)"), Some(Int(Wrapping(4))));
+    match IdentifierParser::new(&idents).expr(&tokenize(b"sizeof(struct s)")) {
+        Err(cexpr::nom::Err::Failure(e)) => {
+            assert!(matches!(e.error, cexpr::ErrorKind::UnknownLayout))
+        }
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(
+        size(&parser, "1 ? 2 : sizeof(struct t)"),
+        Some(Int(Wrapping(2)))
+    );
+}
+
+#[test]
+fn alignof() {
+    use cexpr::token::tokenize;
+
+    let idents = HashMap::new();
+    let align = |parser: &IdentifierParser<'_>, s: &str| {
+        assert_full_parse(parser.expr(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    let parser = IdentifierParser::new(&idents);
+    assert_eq!(align(&parser, "_Alignof(short)"), Some(Int(Wrapping(2))));
+    assert_eq!(align(&parser, "alignof(void *)"), None);
+    assert_eq!(align(&parser, "_Alignof(struct s)"), None);
+    let c23 = IdentifierParser::new(&idents).with_c23(true);
+    assert_eq!(align(&c23, "alignof(void *)"), Some(Int(Wrapping(8))));
+    let cplusplus = IdentifierParser::new(&idents).with_cplusplus(true);
+    assert_eq!(align(&cplusplus, "alignof(void *)"), Some(Int(Wrapping(8))));
+
+    let sizes = |ty: &[Token]| match ty {
+        [t] if &t.raw[..] == b"long" => Some(4),
+        _ => Some(12),
+    };
+    let alignments = |ty: &[Token]| match ty {
+        [t] if &t.raw[..] == b"double" => Some(4),
+        _ => None,
+    };
+    let parser = IdentifierParser::new(&idents)
+        .with_sizeof(&sizes)
+        .with_alignof(&alignments);
+    assert_eq!(align(&parser, "_Alignof(long)"), Some(Int(Wrapping(4))));
+    assert_eq!(align(&parser, "_Alignof(double)"), Some(Int(Wrapping(4))));
+    assert_eq!(align(&parser, "sizeof(double)"), Some(Int(Wrapping(12))));
+    assert_eq!(align(&parser, "_Alignof(struct s)"), None);
+}
+
+#[test]
+fn literal_parsers() {
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind::{InvalidLiteral, LiteralOverflow};
+
+    let idents = HashMap::new();
+    let eval = |parser: &IdentifierParser, source: &str| {
+        let input = tokenize(source.as_bytes());
+        match assert_full_parse(parser.expr(&input)) {
+            Ok((_, value)) => Ok(value),
+            Err(cexpr::nom::Err::Error(e)) => Err(e.error),
+            Err(e) => panic!("{:?}", e),
+        }
+    };
+    // Fixed-point literals such as `0y1.5`, with 8 fractional bits.
+    let fixed = |raw: &[u8]| {
+        let value: f64 = std::str::from_utf8(raw.strip_prefix(b"0y")?)
+            .ok()?
+            .parse()
+            .ok()?;
+        Some(Int(Wrapping((value * 256.0) as i64)))
+    };
+    let huge = |raw: &[u8]| match raw {
+        b"99999999999999999999" => Some(Float(1e20)),
+        _ => None,
+    };
+    let answer = |raw: &[u8]| match raw {
+        b"1" => Some(Int(Wrapping(42))),
+        _ => None,
+    };
+
+    let parser = IdentifierParser::new(&idents);
+    assert_eq!(eval(&parser, "0y1.5"), Err(InvalidLiteral));
+    assert_eq!(eval(&parser, "99999999999999999999"), Err(LiteralOverflow));
+
+    let parser = IdentifierParser::new(&idents).with_fallback_literal_parser(&fixed);
+    assert_eq!(eval(&parser, "0y1.5 + 1"), Ok(Int(Wrapping(385))));
+    assert_eq!(eval(&parser, "0x10 + 0y0.5"), Ok(Int(Wrapping(144))));
+    assert_eq!(eval(&parser, "0y"), Err(InvalidLiteral));
+    assert_eq!(eval(&parser, "99999999999999999999"), Err(LiteralOverflow));
+
+    let parser = IdentifierParser::new(&idents).with_fallback_literal_parser(&huge);
+    assert_eq!(eval(&parser, "99999999999999999999"), Ok(Float(1e20)));
+
+    let parser = IdentifierParser::new(&idents).with_literal_parser(&answer);
+    assert_eq!(eval(&parser, "1 + 2"), Ok(Int(Wrapping(44))));
+    assert_eq!(eval(&parser, "0x1"), Ok(Int(Wrapping(1))));
+    let input = tokenize(b"1");
+    let (_, (_, ty)) = assert_full_parse(parser.expr_with_type(&input)).unwrap();
+    assert_eq!(ty, cexpr::target::Type::Int(cexpr::target::IntType::Int));
+
+    let parser = IdentifierParser::new(&idents)
+        .with_literal_parser(&answer)
+        .with_fallback_literal_parser(&fixed);
+    assert_eq!(eval(&parser, "1 + 0y1"), Ok(Int(Wrapping(298))));
+}
+
+#[test]
+fn implementation_defined() {
+    use cexpr::expr::{Warning, WarningKind};
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let warnings = |list: &[(Kind, &str)]| {
+        let (_, (_, warnings)) =
+            assert_full_parse(parser.expr_with_warnings(&tokens(list))).unwrap();
+        warnings
+    };
+
+    // -8 >> 1 >> 1
+    let shift = warnings(&[
+        (Punctuation, "-"),
+        (Literal, "8"),
+        (Punctuation, ">>"),
+        (Literal, "1"),
+        (Punctuation, ">>"),
+        (Literal, "1"),
+    ]);
+    assert_eq!(
+        shift,
+        [
+            Warning {
+                position: 2,
+                kind: WarningKind::NegativeRightShift
+            },
+            Warning {
+                position: 4,
+                kind: WarningKind::NegativeRightShift
+            }
+        ]
+    );
+    assert!(shift[0].kind.is_implementation_defined());
+    assert!(warnings(&[(Literal, "8"), (Punctuation, ">>"), (Literal, "1")]).is_empty());
+
+    // 1 + (char)200 + (signed char)200 + (short)70000 + (unsigned char)-1
+    let casts = warnings(&[
+        (Literal, "1"),
+        (Punctuation, "+"),
+        (Punctuation, "("),
+        (Keyword, "char"),
+        (Punctuation, ")"),
+        (Literal, "200"),
+        (Punctuation, "+"),
+        (Punctuation, "("),
+        (Keyword, "signed"),
+        (Keyword, "char"),
+        (Punctuation, ")"),
+        (Literal, "200"),
+        (Punctuation, "+"),
+        (Punctuation, "("),
+        (Keyword, "short"),
+        (Punctuation, ")"),
+        (Literal, "70000"),
+        (Punctuation, "+"),
+        (Punctuation, "("),
+        (Keyword, "unsigned"),
+        (Keyword, "char"),
+        (Punctuation, ")"),
+        (Punctuation, "-"),
+        (Literal, "1"),
+    ]);
+    assert_eq!(
+        casts,
+        [
+            Warning {
+                position: 2,
+                kind: WarningKind::CharSignedness
+            },
+            Warning {
+                position: 7,
+                kind: WarningKind::SignedConversion
+            },
+            Warning {
+                position: 13,
+                kind: WarningKind::SignedConversion
+            },
+        ]
+    );
+    assert!(casts.iter().all(|w| w.kind.is_implementation_defined()));
+    assert!(!WarningKind::ChainedComparison.is_implementation_defined());
+
+    // 1 ? 1 : -8 >> 1
+    assert!(warnings(&[
+        (Literal, "1"),
+        (Punctuation, "?"),
+        (Literal, "1"),
+        (Punctuation, ":"),
+        (Punctuation, "-"),
+        (Literal, "8"),
+        (Punctuation, ">>"),
+        (Literal, "1"),
+    ])
+    .is_empty());
+}
+
+#[test]
+fn choose_expr() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(1))), ("F", Float(0.5))]);
+    let parser = IdentifierParser::new(&idents);
+    let strict = IdentifierParser::new(&idents).with_strict_ternary(true);
+    let eval = |parser: &IdentifierParser<'_>, source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        assert_full_parse(parser.expr(&tokens)).map(|(_, v)| v).ok()
+    };
+
+    assert_eq!(
+        eval(&parser, "__builtin_choose_expr(A, 2, 3) + 1").unwrap(),
+        Int(Wrapping(3))
+    );
+    assert_eq!(
+        eval(&parser, "__builtin_choose_expr(A - 1, 2, 3)").unwrap(),
+        Int(Wrapping(3))
+    );
+    // Unlike `A ? 2 : F`, the result is not converted to `double`.
+    assert_eq!(
+        eval(&parser, "__builtin_choose_expr(A, 2, F)").unwrap(),
+        Int(Wrapping(2))
+    );
+    assert_eq!(
+        eval(&parser, "__builtin_choose_expr(A, 2, 1 / 0)").unwrap(),
+        Int(Wrapping(2))
+    );
+    assert_eq!(
+        eval(&parser, "__builtin_choose_expr(A, 2, UNKNOWN)").unwrap(),
+        Int(Wrapping(2))
+    );
+    assert!(eval(&strict, "__builtin_choose_expr(A, 2, UNKNOWN)").is_none());
+    assert!(eval(&parser, "__builtin_choose_expr(F, 2, 3)").is_none());
+    assert!(eval(&parser, "__builtin_choose_expr(A, 2)").is_none());
+
+    let (_, (_, ty)) = parser
+        .expr_with_type(&tokenize(b"__builtin_choose_expr(0, 2, 3u)"))
+        .unwrap();
+    assert_eq!(
+        ty,
+        cexpr::target::Type::Int(cexpr::target::IntType::UnsignedInt)
+    );
+}
+
+#[test]
+fn intmax_width() {
+    use cexpr::expr::IntmaxWidth::*;
+    use cexpr::target::{IntType, Type};
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("BIG", Int(Wrapping(1 << 40)))]);
+    let parser = |width| IdentifierParser::new(&idents).with_intmax_width(width);
+    let value = |width, s: &str| {
+        assert_full_parse(parser(width).expr_with_type(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    let long_long = Type::Int(IntType::LongLong);
+    let unsigned_long_long = Type::Int(IntType::UnsignedLongLong);
+    assert_eq!(
+        value(Some(Bits64), "0x7fffffff + 1"),
+        Some((Int(Wrapping(0x8000_0000)), long_long))
+    );
+    assert_eq!(
+        value(Some(Bits64), "(1 < 2) << 40"),
+        Some((Int(Wrapping(1 << 40)), long_long))
+    );
+    assert_eq!(
+        value(Some(Bits64), "BIG + 0u"),
+        Some((Int(Wrapping(1 << 40)), unsigned_long_long))
+    );
+    assert_eq!(
+        value(Some(Bits64), "-1 < 0u"),
+        Some((Int(Wrapping(0)), long_long))
+    );
+
+    let int = Type::Int(IntType::Int);
+    let unsigned_int = Type::Int(IntType::UnsignedInt);
+    assert_eq!(
+        value(Some(Bits32), "0x7fffffff + 1"),
+        Some((Int(Wrapping(-0x8000_0000)), int))
+    );
+    assert_eq!(
+        value(Some(Bits32), "3000000000"),
+        Some((Int(Wrapping(3_000_000_000)), unsigned_int))
+    );
+    assert_eq!(value(Some(Bits32), "BIG"), Some((Int(Wrapping(0)), int)));
+    let input = tokenize(b"4294967296 > 0");
+    let error = parser(Some(Bits32))
+        .expr(&input)
+        .err()
+        .and_then(|e| match e {
+            cexpr::nom::Err::Error(e) | cexpr::nom::Err::Failure(e) => Some(e.error),
+            _ => None,
+        });
+    assert_eq!(error, Some(cexpr::ErrorKind::LiteralOverflow));
+    assert_eq!(
+        value(Some(Bits32), "1L << 31"),
+        Some((Int(Wrapping(-0x8000_0000)), int))
+    );
+    assert_eq!(
+        value(None, "1L << 31"),
+        Some((Int(Wrapping(0x8000_0000)), Type::Int(IntType::Long)))
+    );
+
+    // Overflowing intmax_t is still an error in checked arithmetic.
+    let checked = parser(Some(Bits32)).with_checked_arithmetic(true);
+    assert!(checked.expr(&tokenize(b"0x7fffffff + 1")).is_err());
+    assert!(parser(Some(Bits64))
+        .with_checked_arithmetic(true)
+        .expr(&tokenize(b"0x7fffffff + 1"))
+        .is_ok());
+}
+
+#[test]
+fn unsigned_arithmetic() {
+    use cexpr::expr::WarningKind;
+    use cexpr::target::TargetInfo;
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("MAX", Int(Wrapping(0xffff_ffff)))]);
+    let parser = IdentifierParser::new(&idents);
+    let narrow = IdentifierParser::new(&idents).with_target(TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    });
+    let eval = |parser: &IdentifierParser<'_>, source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        match assert_full_parse(parser.expr(&tokens)) {
+            Ok((_, Int(Wrapping(i)))) => Some(i),
+            _ => None,
+        }
+    };
+
+    assert_eq!(eval(&parser, "~0u >> 4"), Some(0x0fff_ffff));
+    assert_eq!(eval(&parser, "-1u"), Some(0xffff_ffff));
+    assert_eq!(eval(&parser, "0u - 1"), Some(0xffff_ffff));
+    assert_eq!(eval(&parser, "4294967295u + 1"), Some(0));
+    assert_eq!(eval(&parser, "1u << 31"), Some(0x8000_0000));
+    assert_eq!(eval(&parser, "-7 / 2u"), Some(0x7fff_fffc));
+    assert_eq!(eval(&parser, "-7 % 2u"), Some(1));
+    assert_eq!(eval(&parser, "-7 / 2"), Some(-3));
+    assert_eq!(eval(&parser, "-1 < 0u"), Some(0));
+    assert_eq!(eval(&parser, "-1 < 0"), Some(1));
+    assert_eq!(eval(&parser, "-1 < 0ul"), Some(0));
+    // `long` is wider than `unsigned int`, so the operands stay signed.
+    assert_eq!(eval(&parser, "-1l < 0u"), Some(1));
+    assert_eq!(eval(&narrow, "-1l < 0u"), Some(0));
+    assert_eq!(eval(&parser, "1 ? -1 : 0u"), Some(0xffff_ffff));
+    assert_eq!(eval(&parser, "MAX + 1"), Some(0x1_0000_0000));
+    assert_eq!(eval(&parser, "MAX + 1u"), Some(0x1_0000_0000));
+
+    // 64-bit unsigned values are represented by `i64` with the same bits.
+    assert_eq!(eval(&parser, "~0ull"), Some(-1));
+    assert_eq!(eval(&parser, "~0ull >> 60"), Some(15));
+    assert_eq!(eval(&parser, "18446744073709551615u / 2"), Some(i64::MAX));
+    assert_eq!(eval(&parser, "~0ull > 1"), Some(1));
+    assert_eq!(eval(&parser, "(size_t)-1 >> 63"), Some(1));
+    assert_eq!(eval(&narrow, "(size_t)-1 >> 31"), Some(1));
+    assert_eq!(eval(&parser, "1u / 0"), None);
+
+    let warnings = |source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        let (_, (_, warnings)) = parser.expr_with_warnings(&tokens).unwrap();
+        warnings.into_iter().map(|w| w.kind).collect::<Vec<_>>()
+    };
+    assert_eq!(warnings("-8 >> 1"), [WarningKind::NegativeRightShift]);
+    assert_eq!(warnings("~0ull >> 1"), []);
+}
+
+#[test]
+fn wraparound() {
+    use cexpr::target::TargetInfo;
+    use cexpr::token::tokenize;
+
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents);
+    let narrow = IdentifierParser::new(&idents).with_target(TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    });
+    let eval = |parser: &IdentifierParser<'_>, source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        assert_full_parse(parser.expr(&tokens)).map(|(_, v)| v).ok()
+    };
+    let int = |i| Some(Int(Wrapping(i)));
+
+    // Signed results wrap around to the width of their type, like clang
+    // computes them.
+    assert_eq!(eval(&parser, "2147483647 + 1"), int(-2_147_483_648));
+    assert_eq!(eval(&parser, "1 << 31"), int(-2_147_483_648));
+    assert_eq!(eval(&parser, "-(-2147483647 - 1)"), int(-2_147_483_648));
+    assert_eq!(eval(&parser, "65536 * 65536"), int(0));
+    assert_eq!(eval(&parser, "65536L * 65536"), int(1 << 32));
+    assert_eq!(eval(&narrow, "65536L * 65536"), int(0));
+    assert_eq!(eval(&parser, "0x7fffffffffffffff + 1"), int(i64::MIN));
+    assert_eq!(eval(&parser, "(-0x8000000000000000)"), int(i64::MIN));
+    assert_eq!(eval(&parser, "-0x8000000000000000 / -1"), int(0));
+
+    // 64-bit unsigned values convert to floating point as unsigned values.
+    assert_eq!(
+        eval(&parser, "0xffffffffffffffff + 0.0"),
+        Some(Float(18446744073709551615.0))
+    );
+    assert_eq!(eval(&parser, "0x8000000000000000 > 1.0"), int(1));
+    assert_eq!(
+        eval(&parser, "1 ? 0x8000000000000000 : 1.0"),
+        Some(Float(9223372036854775808.0))
+    );
+}
+
+#[test]
+fn gnu_conditional() {
+    use cexpr::target::{IntType, Type};
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(7))), ("F", Float(0.5))]);
+    let plain = IdentifierParser::new(&idents);
+    let parser = IdentifierParser::new(&idents).with_gnu_extensions(true);
+    let strict = IdentifierParser::new(&idents)
+        .with_gnu_extensions(true)
+        .with_strict_ternary(true);
+    let eval = |parser: &IdentifierParser<'_>, source: &str| {
+        let tokens = tokenize(source.as_bytes());
+        assert_full_parse(parser.expr(&tokens)).map(|(_, v)| v).ok()
+    };
+
+    assert_eq!(eval(&parser, "A ?: 2"), Some(Int(Wrapping(7))));
+    assert_eq!(eval(&parser, "(A - 7) ?: 2"), Some(Int(Wrapping(2))));
+    assert_eq!(eval(&parser, "0 ?: 0 ?: 3"), Some(Int(Wrapping(3))));
+    assert_eq!(eval(&parser, "0 ? 1 : 0 ?: 3"), Some(Int(Wrapping(3))));
+    assert_eq!(eval(&parser, "A ?: F"), Some(Float(7.0)));
+    assert_eq!(eval(&parser, "A ?: (1 / 0)"), Some(Int(Wrapping(7))));
+    assert_eq!(eval(&parser, "A ?: UNKNOWN"), Some(Int(Wrapping(7))));
+    assert_eq!(eval(&strict, "A ?: UNKNOWN"), None);
+    assert_eq!(eval(&parser, "A ? 1 : 2"), Some(Int(Wrapping(1))));
+    assert_eq!(eval(&plain, "A ?: 2"), None);
+
+    let (_, (_, ty)) = parser.expr_with_type(&tokenize(b"0 ?: 3u")).unwrap();
+    assert_eq!(ty, Type::Int(IntType::UnsignedInt));
+}
+
+#[test]
+fn enumerator() {
+    use cexpr::expr::{Warning, WarningKind};
+    use cexpr::token::tokenize;
+
+    let out_of_range = vec![Warning {
+        position: 0,
+        kind: WarningKind::EnumeratorOutOfRange,
+    }];
+    let mut idents = HashMap::new();
+    let value = |previous, source: &str, idents: &HashMap<_, _>| {
+        let parser = IdentifierParser::new(idents);
+        let tokens = tokenize(source.as_bytes());
+        parser.enumerator(previous, &tokens).map(|(_, v)| v).ok()
+    };
+
+    // enum { A, B, C = A + 10, D, E = 'e', F = 0x7fffffff, G };
+    assert_eq!(value(None, "", &idents), Some((0, vec![])));
+    idents.insert(b"A".to_vec(), Int(Wrapping(0)));
+    assert_eq!(value(Some(0), "", &idents), Some((1, vec![])));
+    assert_eq!(value(Some(1), "A + 10", &idents), Some((10, vec![])));
+    assert_eq!(value(Some(10), "", &idents), Some((11, vec![])));
+    assert_eq!(value(Some(11), "'e'", &idents), Some((101, vec![])));
+    assert_eq!(
+        value(Some(101), "0x7fffffff", &idents),
+        Some((0x7fff_ffff, vec![]))
+    );
+    assert_eq!(
+        value(Some(0x7fff_ffff), "", &idents),
+        Some((0x8000_0000, out_of_range.clone()))
+    );
+    assert_eq!(
+        value(None, "-2147483647L - 2", &idents),
+        Some((-2_147_483_649, out_of_range))
+    );
+
+    assert_eq!(value(None, "1.0", &idents), None);
+    assert_eq!(value(None, "\"a\"", &idents), None);
+    assert_eq!(value(None, "1 1", &idents), None);
+    assert_eq!(value(None, "B", &idents), None);
+}
+
+#[test]
+fn whitespace() {
+    use cexpr::expr::{Warning, WarningKind};
+    use cexpr::token::tokenize_with_whitespace;
+
+    let idents = idents(&[("x", Int(Wrapping(1)))]);
+    let parser = IdentifierParser::new(&idents);
+    let input = tokenize_with_whitespace(b" GREETING \"a\" # x ");
+    let (rest, (name, value)) = parser.macro_definition(&input).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(name, b"GREETING");
+    assert_eq!(value, Str(b"a1".to_vec()));
+
+    let input = tokenize_with_whitespace(b"1 < 2 < 3");
+    let (_, (_, warnings)) = assert_full_parse(parser.expr_with_warnings(&input)).unwrap();
+    assert_eq!(
+        warnings,
+        [Warning {
+            position: 6,
+            kind: WarningKind::ChainedComparison
+        }]
+    );
+
+    let input = tokenize_with_whitespace(b"\"a\" + 1");
+    match parser.expr(&input) {
+        Err(cexpr::nom::Err::Failure(e)) => {
+            assert!(matches!(e.error, cexpr::ErrorKind::StringArithmetic))
+        }
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn comma() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(1)))]);
+    let parser = IdentifierParser::new(&idents);
+    let error = |input: &[Token]| match parser.expr(input) {
+        Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => Some(e.error),
+        _ => None,
+    };
+
+    let input = tokenize(b"(A, A + 1)");
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(2)));
+    let input = tokenize(b"(init_flag, A + 1)");
+    assert_eq!(error(&input), Some(cexpr::ErrorKind::UnknownIdentifier));
+    let input = tokenize(b"(A, 1 / 0, 3)");
+    assert_eq!(error(&input), Some(cexpr::ErrorKind::DivisionByZero));
+    let input = tokenize(b"(1, UNKNOWN)");
+    assert!(parser.expr(&input).is_err());
+    let input = tokenize(b"A ? 1 : (UNKNOWN, 2)");
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(1)));
+    let input = tokenize(b"__builtin_choose_expr(1, 2, 3)");
+    assert_eq!(parser.expr(&input).unwrap().1, Int(Wrapping(2)));
+}
+
+#[test]
+fn defined() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(1))), ("BAD", Invalid)]);
+    let parser = IdentifierParser::new(&idents);
+    let value = |s: &str| {
+        assert_full_parse(parser.expr(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    assert_eq!(value("defined(A)"), Some(Int(Wrapping(1))));
+    assert_eq!(value("defined B"), Some(Int(Wrapping(0))));
+    assert_eq!(value("defined(BAD)"), Some(Int(Wrapping(1))));
+    assert_eq!(value("defined A ? A + 1 : B"), Some(Int(Wrapping(2))));
+    assert_eq!(value("defined(B) + defined(long)"), Some(Int(Wrapping(0))));
+    assert_eq!(value("defined"), None);
+    assert_eq!(value("defined(1)"), None);
+    assert_eq!(value("defined(A"), None);
+
+    assert_eq!(value("!defined(A)"), Some(Int(Wrapping(0))));
+    assert_eq!(value("defined(A) && !defined B"), Some(Int(Wrapping(1))));
+    assert_eq!(value("defined(B) && B > 2"), Some(Int(Wrapping(0))));
+    assert_eq!(value("!defined(B) || B > 2"), Some(Int(Wrapping(1))));
+
+    let input = tokenize(b"defined(A) + defined(B)");
+    let (_, (_, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[&b"A"[..]], Int(Wrapping(1)));
+}
+
+#[test]
+fn logical() {
+    use cexpr::target::{IntType, Type};
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let idents = idents(&[("A", Int(Wrapping(2))), ("F", Float(0.5))]);
+    let parser = IdentifierParser::new(&idents);
+    let eval = |parser: &IdentifierParser<'_>, s: &str| {
+        let input = tokenize(s.as_bytes());
+        match assert_full_parse(parser.expr_with_type(&input)) {
+            Ok((_, result)) => Ok(result),
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => Err(e.error),
+            Err(e) => panic!("{:?}", e),
+        }
+    };
+    let int = |i| Ok((Int(Wrapping(i)), Type::Int(IntType::Int)));
+    assert_eq!(eval(&parser, "!A"), int(0));
+    assert_eq!(eval(&parser, "!0 + !!A"), int(2));
+    assert_eq!(eval(&parser, "!F"), int(0));
+    assert_eq!(eval(&parser, "!'\\0'"), int(1));
+    assert_eq!(eval(&parser, "!0ULL"), int(1));
+    assert_eq!(eval(&parser, "A && F"), int(1));
+    assert_eq!(eval(&parser, "A && 0.0"), int(0));
+    assert_eq!(eval(&parser, "0 || 0 || 3"), int(1));
+    assert_eq!(eval(&parser, "1 || 0 && 0"), int(1));
+    assert_eq!(eval(&parser, "A | 1 && 0"), int(0));
+    assert_eq!(eval(&parser, "A && 0 ? 4 : 5"), int(5));
+    assert_eq!(eval(&parser, "0 && 1 ? 4 : 5"), int(5));
+
+    // The right operand is only evaluated if it decides the result.
+    assert_eq!(eval(&parser, "0 && 1 / 0"), int(0));
+    assert_eq!(eval(&parser, "A || B"), int(1));
+    assert_eq!(eval(&parser, "0 && (A << 100)"), int(0));
+    assert_eq!(
+        eval(&parser, "A && !B || 1"),
+        Err(ErrorKind::UnknownIdentifier)
+    );
+    assert_eq!(eval(&parser, "A && 1 / 0"), Err(ErrorKind::DivisionByZero));
+    assert_eq!(eval(&parser, "(A && )"), Err(ErrorKind::UnexpectedToken));
+    let strict = IdentifierParser::new(&idents).with_strict_ternary(true);
+    assert_eq!(eval(&strict, "0 && 1 / 0"), int(0));
+    assert_eq!(eval(&strict, "A || B"), Err(ErrorKind::UnknownIdentifier));
+
+    let cplusplus = IdentifierParser::new(&idents).with_cplusplus(true);
+    let bool = |i| Ok((Int(Wrapping(i)), Type::Int(IntType::Bool)));
+    assert_eq!(eval(&cplusplus, "!A"), bool(0));
+    assert_eq!(eval(&cplusplus, "A && 0 || 1"), bool(1));
+
+    let input = tokenize(b"A || B");
+    let (_, (_, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(deps.len(), 1);
+    let input = tokenize(b"!A || A && 3");
+    let (_, (_, trace)) = parser.expr_with_trace(&input).unwrap();
+    let steps: Vec<String> = trace.steps().iter().map(|step| step.to_string()).collect();
+    assert_eq!(steps, ["!(2) = 0", "2 && 3 = 1", "0 || 1 = 1"]);
+    let input = tokenize(b"A || 3");
+    let (_, (_, trace)) = parser.expr_with_trace(&input).unwrap();
+    assert!(trace.steps().is_empty());
+}
+
+#[test]
+fn qualified_names() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[
+        ("ns::inner::SIZE", Int(Wrapping(4))),
+        ("::GLOBAL", Int(Wrapping(2))),
+        ("ns", Int(Wrapping(100))),
+    ]);
+    let parser = IdentifierParser::new(&idents);
+    let value = |s: &str| {
+        assert_full_parse(parser.expr(&tokenize(s.as_bytes())))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    assert_eq!(value("ns::inner::SIZE * ::GLOBAL"), Some(Int(Wrapping(8))));
+    assert_eq!(value("ns :: inner :: SIZE"), Some(Int(Wrapping(4))));
+    assert_eq!(value("ns + 1"), Some(Int(Wrapping(101))));
+    assert_eq!(value("defined(ns::inner::SIZE)"), Some(Int(Wrapping(1))));
+    assert_eq!(value("ns::inner"), None);
+    assert_eq!(value("ns::"), None);
+    assert_eq!(value("1 ? ns : ::GLOBAL"), Some(Int(Wrapping(100))));
+
+    let input = tokenize(b"ns::inner::SIZE");
+    let (_, (_, deps)) = parser.expr_with_dependencies(&input).unwrap();
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[&b"ns::inner::SIZE"[..]], Int(Wrapping(4)));
+}
+
+#[test]
+fn named_casts() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("X", Int(Wrapping(-1)))]);
+    let parser = IdentifierParser::new(&idents);
+    let value = |s: &str| match assert_full_parse(parser.expr(&tokenize(s.as_bytes()))) {
+        Ok((_, value)) => Ok(value),
+        Err(cexpr::nom::Err::Failure(e)) => Err(Some(e.error)),
+        Err(_) => Err(None),
+    };
+    let int = |s: &str| value(s).ok();
+    assert_eq!(
+        int("static_cast<uint32_t>(X)"),
+        Some(Int(Wrapping(0xffff_ffff)))
+    );
+    assert_eq!(
+        int("static_cast<unsigned char>(X, 0x1ff) + 1"),
+        Some(Int(Wrapping(256)))
+    );
+    assert_eq!(int("const_cast<const int8_t>(X)"), Some(Int(Wrapping(-1))));
+    assert_eq!(int("static_cast<int>(1.5)"), Some(Int(Wrapping(1))));
+    assert_eq!(int("static_cast<int>(\"a\")"), None);
+    assert_eq!(int("static_cast<struct s>(X)"), None);
+    assert_eq!(int("static_cast<int>X"), None);
+    match value("reinterpret_cast<uintptr_t>(0x1000)") {
+        Err(Some(cexpr::ErrorKind::NotConstant)) => {}
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(
+        int("X ? 1 : reinterpret_cast<uintptr_t>(0x1000)"),
+        Some(Int(Wrapping(1)))
+    );
+
+    let input = tokenize(b"static_cast<int32_t>(0x80000000)");
+    let (_, (_, warnings)) = parser.expr_with_warnings(&input).unwrap();
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn error_spans() {
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Int(Wrapping(-1)))]);
+    let parser = IdentifierParser::new(&idents);
+    let input = tokenize(b"(A +\n   UNKNOWN)");
+    let e = match parser.expr(&input) {
+        Err(cexpr::nom::Err::Failure(e)) => e,
+        res => panic!("{:?}", res),
+    };
+    assert!(matches!(e.error, cexpr::ErrorKind::UnknownIdentifier));
+    let span = e.span().unwrap();
+    assert_eq!((span.line, span.column), (2, 4));
+    let input = tokenize(b"A 1");
+    let e = match assert_full_parse(parser.expr(&input)) {
+        Err(cexpr::nom::Err::Error(e)) => e,
+        res => panic!("{:?}", res),
+    };
+    assert_eq!(e.span().unwrap().start, 2);
+
+    let substituted = parser.substitute(&tokenize(b"1 + A"));
+    let spans: Vec<_> = substituted.iter().map(|t| t.span.unwrap().start).collect();
+    assert_eq!(spans, [0, 2, 4, 4, 4, 4]);
+}
+
+#[test]
+fn error_kinds() {
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind::{self, *};
+
+    let idents = idents(&[("A", Int(Wrapping(1)))]);
+    let parser = IdentifierParser::new(&idents);
+    let error = |source: &str| -> Option<(ErrorKind, usize)> {
+        let input = tokenize(source.as_bytes());
+        match assert_full_parse(parser.expr(&input)) {
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => {
+                Some((e.error.clone(), e.position(&input)))
+            }
+            _ => None,
+        }
+    };
+    assert_eq!(error("UNKNOWN"), Some((UnknownIdentifier, 0)));
+    assert_eq!(error("A + UNKNOWN"), Some((UnknownIdentifier, 2)));
+    assert_eq!(error("A * (2 - UNKNOWN)"), Some((UnknownIdentifier, 5)));
+    assert_eq!(error("-UNKNOWN"), Some((UnknownIdentifier, 1)));
+    assert_eq!(error("(A + )"), Some((UnexpectedToken, 3)));
+    assert_eq!(error("A 1"), Some((Partial, 1)));
+    assert_eq!(error("0x"), Some((InvalidLiteral, 0)));
+    assert_eq!(
+        error("1 + 99999999999999999999"),
+        Some((LiteralOverflow, 2))
+    );
+    assert_eq!(error("0x10000000000000000"), Some((LiteralOverflow, 0)));
+    assert_eq!(error("0 ? UNKNOWN : A"), None);
+    assert_eq!(error("(A + UNKNOWN, A)"), Some((UnknownIdentifier, 3)));
+    assert_eq!(error("A +"), None);
+    assert_eq!(error("A % (A - 1)"), Some((DivisionByZero, 1)));
+    assert_eq!(error("(2 * A / 0)"), Some((DivisionByZero, 4)));
+    assert_eq!(error("1u / 0"), Some((DivisionByZero, 1)));
+    assert_eq!(error("1 / '\\0'"), Some((DivisionByZero, 1)));
+    assert_eq!(error("'a' % 0"), Some((DivisionByZero, 1)));
+    assert_eq!(error("'a' / 1.0"), None);
+    assert_eq!(error("0 ? A / 0 : 1"), None);
+    assert_eq!(error("1.0 / 0"), None);
+    assert_eq!(
+        cexpr::literal::parse(b"1.0x").map_err(|e| match e {
+            cexpr::nom::Err::Error(e) => e.error,
+            _ => unreachable!(),
+        }),
+        Err(InvalidLiteral)
+    );
+
+    assert_eq!(UnknownIdentifier.to_string(), "unknown identifier");
+    assert_eq!(ErrorKind::from(cexpr::nom::ErrorKind::MapOpt), Unsupported);
+    assert_eq!(
+        ExactTokens(Punctuation, &["+", "-"]).to_string(),
+        "expected one of `+`, `-`"
+    );
+    assert_eq!(TypedToken(Literal).to_string(), "expected literal");
+}
+
+#[test]
+fn operators() {
+    use cexpr::expr::{Operators, Precedence};
+    use cexpr::target::{IntType, Type};
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind::{self, *};
+
+    let int = |i| Int(Wrapping(i));
+    let mut operators = Operators::new();
+    // Assembler-style operators.
+    operators.insert_infix(b"MOD", Precedence::Multiplicative, |a, b| match (a, b) {
+        (Int(a), Int(b)) if b.0 != 0 => Some(Int(Wrapping(a.0.rem_euclid(b.0)))),
+        _ => None,
+    });
+    operators.insert_infix(b"SHL", Precedence::Shift, |a, b| match (a, b) {
+        (Int(a), Int(b)) => Some(Int(*a << b.0 as usize)),
+        _ => None,
+    });
+    operators.insert_infix(b"@", Precedence::BitOr, |a, b| match (a, b) {
+        (Int(a), Int(b)) => Some(Int(Wrapping(((1 << (b.0 - a.0 + 1)) - 1) << a.0))),
+        _ => None,
+    });
+    operators.insert_prefix(b"NOT", |a| match a {
+        Int(a) => Some(Int(Wrapping((a.0 == 0) as i64))),
+        _ => None,
+    });
+    operators.insert_prefix(b"-", |_| None);
+    operators.insert_intrinsic(b"MAX", |args| {
+        args.iter()
+            .map(|arg| match arg {
+                Int(Wrapping(i)) => Some(*i),
+                _ => None,
+            })
+            .try_fold(None, |max: Option<i64>, i| Some(max.max(Some(i?))))?
+            .map(|max| Int(Wrapping(max)))
+    });
+    operators.insert_intrinsic(b"ZERO", |args| match args {
+        [] => Some(Int(Wrapping(0))),
+        _ => None,
+    });
+
+    let idents = idents(&[("A", int(3)), ("MAX", int(-5))]);
+    let parser = IdentifierParser::new(&idents).with_operators(&operators);
+    let eval = |source: &str| {
+        let input = tokenize(source.as_bytes());
+        assert_full_parse(parser.expr_with_type(&input))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let value = |source: &str| eval(source).map(|(value, _)| value);
+    let error = |source: &str| -> Option<(ErrorKind, usize)> {
+        let input = tokenize(source.as_bytes());
+        match assert_full_parse(parser.expr(&input)) {
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => {
+                Some((e.error.clone(), e.position(&input)))
+            }
+            _ => None,
+        }
+    };
+
+    assert_eq!(value("-7 MOD 4"), Some(int(1)));
+    assert_eq!(value("1 + 10 MOD 4 * 2"), Some(int(5)));
+    assert_eq!(value("1 SHL 2 + 1"), Some(int(8)));
+    assert_eq!(value("1 SHL 2 < 5"), Some(int(1)));
+    assert_eq!(value("4 @ 7 | 1"), Some(int(0xf1)));
+    assert_eq!(value("NOT A + NOT 0"), Some(int(1)));
+    assert_eq!(value("-A"), Some(int(-3)));
+    assert_eq!(value("MAX(1, A * 2, 4)"), Some(int(6)));
+    assert_eq!(value("MAX"), Some(int(-5)));
+    assert_eq!(value("ZERO() + 1"), Some(int(1)));
+    assert_eq!(value("0 ? A MOD 0 : NOT ZERO(1)"), None);
+    assert_eq!(value("0 ? A MOD 0 : 2"), Some(int(2)));
+    assert_eq!(
+        eval("1 SHL 40"),
+        Some((int(1 << 40), Type::Int(IntType::Long)))
+    );
+
+    assert_eq!(error("A MOD 0"), Some((Unsupported, 1)));
+    assert_eq!(error("A + NOT ZERO(1)"), Some((Unsupported, 3)));
+    assert_eq!(error("MAX(A, \"s\")"), Some((StringArithmetic, 4)));
+    assert_eq!(error("(A MOD )"), Some((UnexpectedToken, 3)));
+    // Without the operators, `MOD` is an unknown identifier.
+    let plain = IdentifierParser::new(&idents);
+    let input = tokenize(b"A MOD 2");
+    assert!(assert_full_parse(plain.expr(&input)).is_err());
+}
+
+#[test]
+fn full_range() {
+    use cexpr::target::{DataModel, IntType, TargetInfo, Type};
+    use cexpr::token::tokenize;
+
+    let idents = HashMap::new();
+    let exact = |target: TargetInfo, source: &str| {
+        let parser = IdentifierParser::new(&idents).with_target(target);
+        let input = tokenize(source.as_bytes());
+        let (_, (value, ty)) = assert_full_parse(parser.expr_with_type(&input)).unwrap();
+        value.int_value(ty, &target)
+    };
+    let lp64 = TargetInfo::default();
+    let max = u64::MAX as i128;
+    assert_eq!(exact(lp64, "0xFFFFFFFFFFFFFFFFULL"), Some(max));
+    assert_eq!(exact(lp64, "18446744073709551615ULL / 10"), Some(max / 10));
+    assert_eq!(exact(lp64, "0xFFFFFFFFFFFFFFFFULL / 3 * 3"), Some(max));
+    assert_eq!(exact(lp64, "-1 + 0ULL"), Some(max));
+    assert_eq!(exact(lp64, "9223372036854775808"), Some(1 << 63));
+    assert_eq!(exact(lp64, "-9223372036854775807LL - 1"), Some(-(1 << 63)));
+    assert_eq!(exact(lp64, "(size_t)-1"), Some(max));
+    assert_eq!(
+        exact(DataModel::ILP32.into(), "(size_t)-1"),
+        Some(0xffff_ffff)
+    );
+    assert_eq!(exact(lp64, "(unsigned char)-1"), Some(255));
+    assert_eq!(exact(lp64, "1.5"), None);
+    assert_eq!(
+        Int(Wrapping(-1)).int_value(Type::Int(IntType::UnsignedInt), &lp64),
+        Some(0xffff_ffff)
+    );
+    assert_eq!(
+        Int(Wrapping(2)).int_value(Type::Int(IntType::Bool), &lp64),
+        Some(1)
+    );
+    assert_eq!(Int(Wrapping(-1)).int_value(Type::Double, &lp64), None);
+
+    // Typed identifiers of unsigned types are substituted exactly.
+    let typed: HashMap<Vec<u8>, (EvalResult, Type)> = [
+        (
+            "MAX",
+            Int(Wrapping(-1)),
+            Type::Int(IntType::UnsignedLongLong),
+        ),
+        ("ALL", Int(Wrapping(-1)), Type::Int(IntType::UnsignedInt)),
+        ("SIZE", Int(Wrapping(8)), Type::Int(IntType::SizeT)),
+        ("BYTE", Int(Wrapping(255)), Type::Int(IntType::UnsignedChar)),
+        ("NEG", Int(Wrapping(-1)), Type::Int(IntType::Long)),
+    ]
+    .iter()
+    .map(|(name, value, ty)| (name.as_bytes().to_vec(), (value.clone(), *ty)))
+    .collect();
+    let substitute = |target, source: &str| {
+        let parser = IdentifierParser::from_resolver(&typed).with_target(target);
+        let input = tokenize(source.as_bytes());
+        let output = parser.substitute(&input);
+        let spelling: Vec<_> = output
+            .iter()
+            .map(|t| String::from_utf8_lossy(&t.raw).into_owned())
+            .collect();
+        (
+            spelling.join(" "),
+            parser.expr(&output).map(|(_, v)| v).ok(),
+        )
+    };
+    assert_eq!(
+        substitute(lp64, "MAX / 2"),
+        (
+            "18446744073709551615ull / 2".into(),
+            Some(Int(Wrapping(i64::MAX)))
+        )
+    );
+    assert_eq!(
+        substitute(lp64, "ALL >> 31"),
+        ("4294967295u >> 31".into(), Some(Int(Wrapping(1))))
+    );
+    assert_eq!(
+        substitute(lp64, "SIZE"),
+        ("8ul".into(), Some(Int(Wrapping(8))))
+    );
+    assert_eq!(
+        substitute(DataModel::LLP64.into(), "SIZE"),
+        ("8ull".into(), Some(Int(Wrapping(8))))
+    );
+    assert_eq!(
+        substitute(lp64, "BYTE"),
+        ("255".into(), Some(Int(Wrapping(255))))
+    );
+    assert_eq!(
+        substitute(lp64, "NEG"),
+        ("( - 1 )".into(), Some(Int(Wrapping(-1))))
+    );
+}
+
+#[test]
+fn checked_arithmetic() {
+    use cexpr::expr::{Warning, WarningKind};
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let idents = idents(&[("MAX", Int(Wrapping(i64::MAX)))]);
+    let wrapping = IdentifierParser::new(&idents);
+    let checked = IdentifierParser::new(&idents).with_checked_arithmetic(true);
+    let warnings = |source: &str| {
+        let input = tokenize(source.as_bytes());
+        let (_, (value, warnings)) =
+            assert_full_parse(wrapping.expr_with_warnings(&input)).unwrap();
+        (value, warnings)
+    };
+    let overflow = |position| {
+        vec![Warning {
+            position,
+            kind: WarningKind::SignedOverflow,
+        }]
+    };
+    let error = |source: &str| -> Option<(ErrorKind, usize)> {
+        let input = tokenize(source.as_bytes());
+        match assert_full_parse(checked.expr(&input)) {
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => {
+                Some((e.error.clone(), e.position(&input)))
+            }
+            _ => None,
+        }
+    };
+
+    assert_eq!(
+        warnings("2147483647 + 1"),
+        (Int(Wrapping(-2147483648)), overflow(1))
+    );
+    assert_eq!(
+        warnings("-(-2147483647 - 1)"),
+        (Int(Wrapping(-2147483648)), overflow(0))
+    );
+    assert_eq!(warnings("MAX * 2"), (Int(Wrapping(-2)), overflow(1)));
+    assert_eq!(
+        warnings("1 << 31"),
+        (Int(Wrapping(i32::MIN as i64)), overflow(1))
+    );
+    assert_eq!(
+        warnings("2147483647L + 1"),
+        (Int(Wrapping(1 << 31)), vec![])
+    );
+    assert_eq!(
+        warnings("0x7fffffffu + 1"),
+        (Int(Wrapping(1 << 31)), vec![])
+    );
+
+    let overflow = Some((ErrorKind::Overflow, 1));
+    assert_eq!(error("2147483647 + 1"), overflow);
+    assert_eq!(error("-2147483647 - 2"), Some((ErrorKind::Overflow, 2)));
+    assert_eq!(error("-(-2147483647 - 1)"), Some((ErrorKind::Overflow, 0)));
+    assert_eq!(error("65536 * 65536"), overflow);
+    assert_eq!(error("MAX + 1"), overflow);
+    assert_eq!(error("(-MAX - 1) / -1"), Some((ErrorKind::Overflow, 6)));
+    assert_eq!(error("(-MAX - 1) % -1"), Some((ErrorKind::Overflow, 6)));
+    assert_eq!(error("1 << 31"), overflow);
+    assert_eq!(
+        error("(1 + 1) * (2147483647 + 1)"),
+        Some((ErrorKind::Overflow, 8))
+    );
+    assert_eq!(error("1 << 30"), None);
+    assert_eq!(error("1 << 32"), Some((ErrorKind::InvalidShift, 1)));
+    assert_eq!(error("1u << 31"), None);
+    assert_eq!(error("~0u + 1"), None);
+    assert_eq!(error("-2147483648"), None);
+    assert_eq!(error("(short)32767 + 1"), None);
+    assert_eq!(error("1e308 * 10"), None);
+    assert_eq!(error("0 ? 2147483647 + 1 : 0"), None);
+    assert_eq!(error("1 ? 0 : -(-MAX - 1)"), None);
+    assert_eq!(ErrorKind::Overflow.to_string(), "signed integer overflow");
+}
+
+#[test]
+fn character_operands() {
+    use cexpr::literal::CChar;
+    use cexpr::target::{IntType, Type};
+    use cexpr::token::tokenize;
+
+    let idents = idents(&[("A", Char(CChar::Char('a')))]);
+    let parser = IdentifierParser::new(&idents);
+    let eval = |source: &str| {
+        assert_full_parse(parser.expr_with_type(&tokenize(source.as_bytes())))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let int = |i: i64| Some((Int(Wrapping(i)), Type::Int(IntType::Int)));
+    let unsigned = |i: i64| Some((Int(Wrapping(i)), Type::Int(IntType::UnsignedInt)));
+    let a = Some((Char(CChar::Char('a')), Type::Int(IntType::Int)));
+
+    assert_eq!(eval("'a' + 1"), int(98));
+    assert_eq!(eval("1 + 'a'"), int(98));
+    assert_eq!(eval("'a' == 97"), int(1));
+    assert_eq!(eval("-'a'"), int(-97));
+    assert_eq!(eval("~'a'"), int(-98));
+    assert_eq!(eval("+'a'"), int(97));
+    assert_eq!(eval("(int)'a'"), int(97));
+    assert_eq!(eval("'a' ? 1 : 2"), int(1));
+    assert_eq!(eval("'\\0' ? 1 : 2"), int(2));
+    assert_eq!(eval("A + 1"), int(98));
+    assert_eq!(eval("'a' - 98u"), unsigned(u32::MAX.into()));
+    assert_eq!(eval("u'a' - 98"), int(-1));
+    assert_eq!(eval("1 ? 'a' : 0u"), unsigned(97));
+    assert_eq!(eval("'a' * 1.5"), Some((Float(145.5), Type::Double)));
+    // Characters that are not operands of an operator stay characters.
+    assert_eq!(eval("'a'"), a);
+    assert_eq!(eval("('a')"), a);
+    assert_eq!(eval("A"), a);
+    assert_eq!(eval("0, 'a'"), a);
+    assert_eq!(eval("1 ? 'a' : 'b'"), a);
+}
+
+#[test]
+fn shift_counts() {
+    use cexpr::expr::{Warning, WarningKind};
+    use cexpr::token::tokenize;
+    use cexpr::ErrorKind;
+
+    let idents = idents(&[]);
+    let clamping = IdentifierParser::new(&idents);
+    let checked = IdentifierParser::new(&idents).with_checked_arithmetic(true);
+    let warnings = |source: &str| {
+        let input = tokenize(source.as_bytes());
+        let (_, (value, warnings)) =
+            assert_full_parse(clamping.expr_with_warnings(&input)).unwrap();
+        let kinds: Vec<WarningKind> = warnings.iter().map(|w: &Warning| w.kind).collect();
+        (value, kinds)
+    };
+    let error = |source: &str| -> Option<(ErrorKind, usize)> {
+        let input = tokenize(source.as_bytes());
+        match assert_full_parse(checked.expr(&input)) {
+            Err(cexpr::nom::Err::Error(e)) | Err(cexpr::nom::Err::Failure(e)) => {
+                Some((e.error.clone(), e.position(&input)))
+            }
+            _ => None,
+        }
+    };
+    let invalid = |i: i64| (Int(Wrapping(i)), vec![WarningKind::InvalidShift]);
+
+    assert_eq!(warnings("1 << 31").0, Int(Wrapping(i64::from(i32::MIN))));
+    assert_eq!(warnings("1 << 32"), invalid(0));
+    assert_eq!(warnings("1 << 64"), invalid(0));
+    assert_eq!(warnings("1L << 63").0, Int(Wrapping(i64::MIN)));
+    assert_eq!(warnings("1LL << 64"), invalid(0));
+    assert_eq!(warnings("1u << 32"), invalid(0));
+    assert_eq!(warnings("0xffffffffu >> 40"), invalid(0));
+    assert_eq!(warnings("7 << -1"), invalid(7));
+    assert_eq!(warnings("7u >> -1"), invalid(7));
+    // The count is converted to its own type, not to that of the shifted value.
+    assert_eq!(warnings("7 >> 0xffffffffu"), invalid(0));
+    assert_eq!(warnings("7 >> 0xffffffffffffffffu"), invalid(0));
+    // Shifted values are promoted, so a `char` can be shifted by 8.
+    assert_eq!(warnings("(char)1 << 8"), (Int(Wrapping(256)), vec![]));
+    assert_eq!(
+        warnings("-8 >> 40"),
+        (
+            Int(Wrapping(-1)),
+            vec![WarningKind::NegativeRightShift, WarningKind::InvalidShift]
+        )
+    );
+    assert_eq!(warnings("0 ? 1 << 40 : 2"), (Int(Wrapping(2)), vec![]));
+
+    assert_eq!(error("1 << 32"), Some((ErrorKind::InvalidShift, 1)));
+    assert_eq!(error("2 * (1u >> -1)"), Some((ErrorKind::InvalidShift, 4)));
+    assert_eq!(error("1 << 31 - 1"), None);
+    assert_eq!(error("1 ? 2 : 1 >> 99"), None);
+    assert_eq!(
+        ErrorKind::InvalidShift.to_string(),
+        "shift count is negative or too large"
+    );
+}
@@ -0,0 +1,172 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use cexpr::header::{is_assertion, statistics, SkipReason::*};
+use cexpr::target::TargetInfo;
+
+#[test]
+fn skip_reasons() {
+    let header = br#"
+#ifndef HEADER_H
+#define HEADER_H
+#define ONE 1
+  #  define TWO (ONE \
+                 + 1)
+#define CAT(a, b) a ## b
+#define PASTE ONE ## TWO
+#define HEX 0x1 ## 0
+#define BAD (1 ## +)
+#define STMT do { } while (0)
+#define SEMI ONE;
+#define UNKNOWN (TWO + THREE)
+#define CALL TWO(1)
+#define KB(x) ((x) * 1024 * ONE)
+#define BUF_SIZE KB(TWO)
+#define MISSING KB(THREE)
+#define ASSERT(e) ((void)sizeof(char[(e) ? 1 : -1]))
+#define CHECK_TWO typedef char check_two[TWO == 2 ? 1 : -1];
+#define STR "a" "b"
+#define DIV (TWO / (ONE - 1))
+#define NOTHING
+int x; /* #define COMMENTED 1 */
+#endif
+"#;
+    let stats = statistics(header, TargetInfo::default());
+    assert_eq!(stats.evaluated, 5);
+    assert_eq!(
+        stats.by_frequency(),
+        [
+            (UnknownIdentifier, 3),
+            (FunctionLike, 2),
+            (Assertion, 2),
+            (StatementLike, 2),
+            (Empty, 1),
+            (IncludeGuard, 1),
+            (TokenPasting, 1),
+            (DivisionByZero, 1),
+            (Unsupported, 1),
+        ]
+    );
+}
+
+#[test]
+fn include_guards() {
+    let guards = |header: &[u8]| {
+        let stats = statistics(header, TargetInfo::default());
+        stats.skipped.get(&IncludeGuard).copied().unwrap_or(0)
+    };
+    assert_eq!(guards(b"#ifndef A_H\n#define A_H\n#endif\n"), 1);
+    assert_eq!(guards(b"%:ifndef A_H /* guard */\n  #  define A_H\n"), 1);
+    assert_eq!(guards(b"#if !defined(A_H)\n#define A_H\n#endif\n"), 1);
+    assert_eq!(guards(b"#if !defined A_H\nint x;\n#define A_H\n"), 1);
+    assert_eq!(guards(b"#ifndef A_H\n#define B_H\n"), 0);
+    assert_eq!(guards(b"#ifndef A_H\n#include <b.h>\n#define A_H\n"), 0);
+    assert_eq!(guards(b"#ifdef A_H\n#define A_H\n"), 0);
+    assert_eq!(guards(b"#ifndef A_H\n#define A_H()\n"), 0);
+
+    let stats = statistics(
+        b"#ifndef DEBUG\n#define DEBUG 1\n#endif\n",
+        TargetInfo::default(),
+    );
+    assert_eq!(stats.evaluated, 1);
+    assert!(stats.skipped.is_empty());
+}
+
+#[test]
+fn redefinitions() {
+    use cexpr::token::Span;
+
+    let header = b"#define SAME (1 + 2)
+#define SAME (1  /* c */ +\t2)
+#define VALUE 1
+  #define VALUE 2
+#define SPACING (1 + 2)
+#define SPACING (1+2)
+#define F(x) x
+#define F(y) y
+#define G 1
+#define G(x) 1
+#ifdef WIN32
+#define BRANCH 1
+#elif defined(__APPLE__)
+#if X
+#define BRANCH 2
+#endif
+#else
+#define BRANCH 3
+#endif
+#ifdef WIN32
+#define OUTSIDE 1
+#endif
+#define OUTSIDE 2
+#define UNDEF 1
+#undef UNDEF
+#define UNDEF 2
+";
+    let stats = statistics(header, TargetInfo::default());
+    let names: Vec<&[u8]> = stats.redefinitions.iter().map(|r| &r.name[..]).collect();
+    assert_eq!(names, [&b"VALUE"[..], b"SPACING", b"F", b"G", b"OUTSIDE"]);
+    assert_eq!(
+        stats.redefinitions[0].previous,
+        Span {
+            start: 51,
+            end: 66,
+            line: 3,
+            column: 1,
+        }
+    );
+    assert_eq!(
+        stats.redefinitions[0].redefined,
+        Span {
+            start: 67,
+            end: 84,
+            line: 4,
+            column: 1,
+        }
+    );
+    assert_eq!(
+        cexpr::macros::MacroSet::from_header(header).redefinitions(),
+        &stats.redefinitions[..]
+    );
+    assert!(statistics(b"#define A 1\n", TargetInfo::default())
+        .redefinitions
+        .is_empty());
+}
+
+#[test]
+fn target() {
+    let header = b"#define MAX ((size_t)-1)\n#define BIG (MAX >> 32)";
+    assert_eq!(statistics(header, TargetInfo::default()).evaluated, 2);
+    let narrow = TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    };
+    assert_eq!(statistics(header, narrow).evaluated, 2);
+    assert!(statistics(b"", narrow).skipped.is_empty());
+}
+
+#[test]
+fn assertions() {
+    use cexpr::token::tokenize;
+
+    let assertion = |source: &str| is_assertion(&tokenize(source.as_bytes()));
+    assert!(assertion("((void)sizeof(char[(e) ? 1 : -1]))"));
+    assert!(assertion("((void)sizeof(char[(e) ? -1 : 1]))"));
+    assert!(assertion("((void)sizeof(char[1 - 2 * !!(e)]))"));
+    assert!(assertion("((void)sizeof(char[1 - 2*!(e)]))"));
+    assert!(assertion(
+        "extern int check[/* size */ sizeof(T) == 4 ? 1 : -1]"
+    ));
+    assert!(assertion("(sizeof(struct { int:-!!(e); }))"));
+    assert!(!assertion("(x ? 1 : -1)"));
+    assert!(!assertion("table[i - 1]"));
+    assert!(!assertion("table[i ? 1 : 2] - 1"));
+    assert!(!assertion("(char[]){ 1, -1 }"));
+    assert!(!assertion(""));
+}
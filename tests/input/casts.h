@@ -0,0 +1,17 @@
+#define Int_n1 (size_t)-1
+#define Int_0 (uintptr_t)~(size_t)-1
+#define Int_3 (ptrdiff_t)3.7
+#define Int_16 (size_t)1<<4
+#define Int_255 (unsigned char)-1
+#define Int_n_1 (signed char)255
+#define Int_n2 (short)65534
+#define Int_4294967295 (unsigned)-1
+#define Int_1 ((unsigned)-1 > 0)
+#define Int_0_ ((int)4294967295u == 4294967295)
+#define Int_65535 (unsigned short int)-1
+#define Int_n1_ (long long)-1
+#define Int_4294967295_ (const volatile unsigned)-1
+#define Int_255_ (unsigned const char)-1 + (const size_t)0
+#define Int_4294967295__ (uint32_t)-1
+#define Int_n1__ (int64_t)(uint64_t)-1
+#define Int_1_ (_Bool)0x100
@@ -1,3 +1,9 @@
 #define CharChar_65 'A'
 #define CharChar_127849 '\U0001f369' // 🍩
 #define CharRaw_255 U'\xff'
+#define CharChar_98 "abc"[1]
+#define CharChar_0 "abc"[3]
+#define CharChar_102 "0123456789abcdef"[(15 & 0xff)]
+#define CharChar_99 ("abcdef"[2])
+#define CharChar_100 ("ab" "cd")[3]
+#define CharChar_101 (("abcdef")[1 ? 4 : 0])
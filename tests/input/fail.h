@@ -7,3 +7,12 @@
 #define Str_str "str"
 #define FAIL_concat_integer "test" Str_str Int_0
 #define FAIL_too_large_int 18446744073709551616
+#define FAIL_cast_to_unknown_type (unknown_t)1
+#define FAIL_division_by_zero 1/0
+#define FAIL_ternary_division_by_zero 1 ? 1/0 : 1
+#define FAIL_ternary_missing_else 1 ? 2
+#define FAIL_string_arithmetic "abc" + 1
+#define FAIL_string_subscript_out_of_range "abc"[4]
+#define FAIL_comma_missing_operand (1, )
+#define FAIL_comma_unknown_identifier (UNKNOWN, 0x40)
+#define FAIL_comma_division_by_zero (1/0, 2)
@@ -6,3 +6,4 @@
 #define Float_2000 2e+3
 #define Float_p001 1e-3
 #define Float_80 10.0*(1<<3)
+#define Float_3 1 ? 3 : 4.0
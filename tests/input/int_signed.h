@@ -1,3 +1,9 @@
 #define Int_n3 -(-(-3))
 #define Int_n5 -3-2
 #define Int_n9223372036854775808 -9223372036854775808
+#define Int_1 3 < 2 < 1
+#define Int_0 3 > 2 > 1
+#define Int_2 (1 == 1) + (2 != 2) + (1.5 >= 1) + (0 <= -1)
+#define Int_n2 -(1 < 2 == 1 > 0) * 2
+#define Int_4 __builtin_choose_expr(1, 4, 5.0)
+#define Int_5 __builtin_choose_expr(0, 4.0, 5)
@@ -27,3 +27,25 @@
 #define Int_n9223372036854775808 9223372036854775808
 
 #define Fn_Int_9(_3) _3*3
+#define Fn_Int_8(_3) (1UL << (_3))
+#define Fn_Int_240(_7, _4) (((1UL << ((_7) - (_4) + 1)) - 1) << (_4))
+#define Fn_Int_4294967296(_32) (1ULL << (_32))
+
+#define Int_7 Int_1 ? 7 : 1/0
+#define Int_8 Int_0 ? UNKNOWN : 8
+#define Int_9 Int_0 ? 1 : Int_0 ? 2 : 9
+#define Int_10 Int_1 | 2 ? 10 : 11
+#define Int_11 (Int_10 > Int_9 ? Int_10 + 1 : Int_9)
+#define Int_12_ Int_0 ? 1 : 2 * 6
+#define Int_13_ (Int_1 ? 3 : 4) + 10
+#define Int_14 Int_1 ? Int_0 ? 1 : 14 : 2
+#define Int_64 (Int_1, 0x40)
+#define Int_3_ 1, 2, 3
+#define Int_5_ Int_1 ? 4, 5 : 6
+#define Int_15_ (1, 2) + (Int_0, 13)
+#define Int_8_ (sizeof(uint32_t)*2)
+#define Int_4_ sizeof(const unsigned int)
+#define Int_16_ (sizeof(char *) + sizeof(long))
+#define Int_8__ _Alignof(long long)
+#define Int_1_ defined(Int_0)
+#define Int_0_ defined UNKNOWN
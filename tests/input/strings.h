@@ -15,3 +15,11 @@
 #define Fn_Str_prepend_arg(arg) "prepend_" arg
 #define Fn_Str_two_args(two, args) two "_" args
 #define Fn_Str_three_args(three, _, args) three _ args
+#define Str_prefix "prefix"
+#define Str_suffix "suffix"
+#define Str_prefix_suffix Str_prefix "_suffix"
+#define Str_prefixsuffix Str_prefix Str_suffix
+#define Str_prefix_and_suffix Str_prefix "_and_" Str_suffix
+#define Str_the_suffix "the_" Str_suffix
+#define Fn_Str_U000022argU000022(arg) #arg
+#define Fn_Str_dir_U000022dirU000022(dir) dir "_" #dir
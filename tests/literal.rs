@@ -0,0 +1,155 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::num::Wrapping;
+
+use cexpr::expr::EvalResult::*;
+use cexpr::literal::{parse_tokens, CChar};
+use cexpr::token::Kind::*;
+use cexpr::token::Token;
+
+#[test]
+fn tokens() {
+    // x = 'a' + 0x10 /* 1 */ "s" 0b2
+    let tokens: Vec<Token> = vec![
+        (Identifier, &b"x"[..]).into(),
+        (Punctuation, &b"="[..]).into(),
+        (Literal, &b"'a'"[..]).into(),
+        (Punctuation, &b"+"[..]).into(),
+        (Literal, &b"0x10"[..]).into(),
+        (Comment, &b"/* 1 */"[..]).into(),
+        (Literal, &b"\"s\""[..]).into(),
+        (Literal, &b"0b2"[..]).into(),
+    ];
+    assert_eq!(
+        parse_tokens(&tokens),
+        [
+            (2, Char(CChar::Char('a'))),
+            (4, Int(Wrapping(16))),
+            (6, Str(b"s".to_vec())),
+            (7, Invalid),
+        ]
+    );
+}
+
+#[test]
+fn long_escapes() {
+    use cexpr::literal::parse;
+
+    let value = |s: &str| parse(s.as_bytes()).unwrap().1;
+    assert_eq!(value("'\\x00000000000000000041'"), Char(CChar::Char('A')));
+    assert_eq!(value("'\\x10000000000000041'"), Char(CChar::Char('A')));
+    assert_eq!(value("\"\\x141\""), Str(b"A".to_vec()));
+    assert_eq!(value("\"\\501\""), Str(b"A".to_vec()));
+}
+
+#[test]
+fn octal_escapes() {
+    use cexpr::literal::parse;
+
+    let value = |s: &str| parse(s.as_bytes()).unwrap().1;
+    assert_eq!(value("\"\\0001\""), Str(b"\x001".to_vec()));
+    assert_eq!(value("\"\\01234\""), Str(b"\x0a34".to_vec()));
+    assert_eq!(value("\"\\18\""), Str(b"\x018".to_vec()));
+    assert_eq!(value("\"\\0\\0\""), Str(b"\x00\x00".to_vec()));
+    assert_eq!(value("'\\0'"), Char(CChar::Char('\0')));
+    assert!(parse(b"'\\0001'").is_err());
+}
+
+#[test]
+fn overflow() {
+    use cexpr::literal::parse;
+    use cexpr::ErrorKind;
+
+    let error = |s: &str| match parse(s.as_bytes()) {
+        Err(cexpr::nom::Err::Error(e)) => Some(e.error),
+        _ => None,
+    };
+    assert_eq!(
+        error("18446744073709551616"),
+        Some(ErrorKind::LiteralOverflow)
+    );
+    assert_eq!(
+        error("0x1ffffffffffffffffULL"),
+        Some(ErrorKind::LiteralOverflow)
+    );
+    let spliced = format!("0b1\\\n{}", "0".repeat(64));
+    assert_eq!(error(&spliced), Some(ErrorKind::LiteralOverflow));
+    assert_ne!(error("0xg"), Some(ErrorKind::LiteralOverflow));
+    assert!(parse(b"18446744073709551615").is_ok());
+    assert!(parse(b"99999999999999999999.0").is_ok());
+}
+
+#[test]
+fn escaped_newlines() {
+    use cexpr::literal::parse;
+
+    let value = |s: &[u8]| parse(s).unwrap().1;
+    assert_eq!(value(b"0x1\\\n0"), Int(Wrapping(16)));
+    assert_eq!(value(b"1\\\r\n2u\\\nl"), Int(Wrapping(12)));
+    assert_eq!(value(b"\"a\\\nb\""), Str(b"ab".to_vec()));
+    assert_eq!(value(b"'\\\\\nn'"), Char(CChar::Char('\n')));
+    assert_eq!(value(b"\"\\\\\\\n\\\\\""), Str(b"\\\\".to_vec()));
+    assert!(parse(b"1\\\n\\\n").is_ok());
+    assert!(parse(b"1\\2").is_err());
+}
+
+#[test]
+fn code_units() {
+    use cexpr::literal::parse;
+
+    let value = |s: &str| parse(s.as_bytes()).ok().map(|r| r.1);
+    let c = |c| Some(Char(CChar::Char(c)));
+    assert_eq!(value("u'\\u20ac'"), c('\u{20ac}'));
+    assert_eq!(value("u'€'"), c('\u{20ac}'));
+    assert_eq!(value("u'\\xffff'"), Some(Char(CChar::Raw(0xffff))));
+    assert_eq!(value("U'\\U0001f600'"), c('\u{1f600}'));
+    assert_eq!(value("U'😀'"), c('\u{1f600}'));
+    assert_eq!(value("U'\\xffffffff'"), Some(Char(CChar::Raw(0xffff_ffff))));
+    assert_eq!(value("L'\\U0001f600'"), c('\u{1f600}'));
+    assert_eq!(value("u8'a'"), c('a'));
+    assert_eq!(value("u8'\\xff'"), Some(Char(CChar::Raw(0xff))));
+    for input in &[
+        "u'\\U0001f600'",
+        "u'😀'",
+        "u'\\x10000'",
+        "U'\\x100000000'",
+        "L'\\x100000000'",
+        "u8'\\u00e9'",
+        "u8'é'",
+        "u8'\\x100'",
+        "'€'",
+    ] {
+        assert_eq!(value(input), None, "{}", input);
+    }
+}
+
+#[test]
+fn user_defined() {
+    use cexpr::literal::{parse, parse_user_defined};
+
+    let value = |s: &str| {
+        let (_, (value, suffix)) = parse_user_defined(s.as_bytes()).ok()?;
+        Some((value, String::from_utf8(suffix.to_vec()).unwrap()))
+    };
+    let ud = |value, suffix: &str| Some((value, suffix.to_owned()));
+    assert_eq!(value("10_ms"), ud(Int(Wrapping(10)), "_ms"));
+    assert_eq!(value("10ms"), ud(Int(Wrapping(10)), "ms"));
+    assert_eq!(value("0x1f_ms"), ud(Int(Wrapping(31)), "_ms"));
+    assert_eq!(value("10ul_x"), ud(Int(Wrapping(10)), "_x"));
+    assert_eq!(value("1e3_km"), ud(Float(1000.0), "_km"));
+    assert_eq!(value("\"abc\"sv"), ud(Str(b"abc".to_vec()), "sv"));
+    assert_eq!(value("'a'_c"), ud(Char(CChar::Char('a')), "_c"));
+    assert_eq!(value("10ul"), ud(Int(Wrapping(10)), ""));
+    assert_eq!(value("0x1f"), ud(Int(Wrapping(31)), ""));
+    assert!(parse(b"10_ms").is_err());
+    for input in &["_ms", "1.m.s", "\"abc\"_s\"", ""] {
+        assert_eq!(value(input), None, "{}", input);
+    }
+}
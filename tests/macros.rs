@@ -0,0 +1,484 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::num::Wrapping;
+
+use cexpr::expr::EvalResult::{self, *};
+use cexpr::macros::{MacroSet, Outcome};
+use cexpr::target::TargetInfo;
+use cexpr::token::tokenize;
+use cexpr::ErrorKind;
+
+fn set(macros: &[(&str, &str)]) -> MacroSet {
+    let mut set = MacroSet::new();
+    for &(name, source) in macros {
+        set.insert(name.as_bytes().to_vec(), tokenize(source.as_bytes()));
+    }
+    set
+}
+
+fn int(i: i64) -> Outcome {
+    Outcome::Evaluated(Int(Wrapping(i)))
+}
+
+#[test]
+fn resolve() {
+    let macros = set(&[
+        ("C", "B * 2"),
+        ("B", "A + 1"),
+        ("A", "1"),
+        ("SELF", "SELF"),
+        ("LOOP_A", "LOOP_B + 1"),
+        ("LOOP_B", "2 * LOOP_A"),
+        ("USES_LOOP", "LOOP_A + A"),
+        ("SKIPS_LOOP", "A ? 3 : LOOP_A"),
+        ("BAD", "1 +"),
+        ("USES_BAD", "BAD"),
+        ("UNKNOWN", "A + OTHER"),
+        ("ZERO", "(1 / 0)"),
+        ("USES_ZERO", "ZERO + 1"),
+    ]);
+    let resolution = macros.resolve();
+    let outcome = |name: &str| resolution.outcomes[name.as_bytes()].clone();
+    assert_eq!(outcome("A"), int(1));
+    assert_eq!(outcome("B"), int(2));
+    assert_eq!(outcome("C"), int(4));
+    assert_eq!(outcome("SKIPS_LOOP"), int(3));
+    assert_eq!(outcome("SELF"), Outcome::Cyclic);
+    assert_eq!(
+        outcome("USES_LOOP"),
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+    assert_eq!(
+        outcome("USES_BAD"),
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+    assert_eq!(
+        outcome("UNKNOWN"),
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+    assert!(matches!(outcome("BAD"), Outcome::Invalid(_)));
+    assert_eq!(outcome("ZERO"), Outcome::Invalid(ErrorKind::DivisionByZero));
+    assert_eq!(
+        outcome("USES_ZERO"),
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+    assert_eq!(
+        resolution.cyclic(),
+        [&b"LOOP_A"[..], &b"LOOP_B"[..], &b"SELF"[..]]
+    );
+    assert_eq!(
+        resolution.invalid(),
+        [
+            &b"BAD"[..],
+            b"UNKNOWN",
+            b"USES_BAD",
+            b"USES_LOOP",
+            b"USES_ZERO",
+            b"ZERO"
+        ]
+    );
+    let values = resolution.values();
+    assert_eq!(values.len(), 4);
+    assert_eq!(values[&b"C"[..]], Int(Wrapping(4)));
+}
+
+#[test]
+fn token_pasting() {
+    let macros = set(&[
+        ("FULL", "PART ## IAL + 1"),
+        ("PARTIAL", "HEX"),
+        ("HEX", "0x ## ff"),
+        ("BAD", "1 ## +"),
+    ]);
+    let resolution = macros.resolve();
+    assert_eq!(resolution.outcomes[&b"FULL"[..]], int(0x100));
+    assert_eq!(
+        resolution.outcomes[&b"BAD"[..]],
+        Outcome::Invalid(ErrorKind::InvalidPaste)
+    );
+    // The report sees the pasted identifier too.
+    let report = macros.report();
+    let full = report.macros.iter().find(|m| m.name == b"FULL").unwrap();
+    assert_eq!(full.dependencies, [b"PARTIAL".to_vec()]);
+}
+
+#[test]
+fn insert() {
+    let mut macros = set(&[("A", "1"), ("B", "A")]);
+    assert_eq!(macros.len(), 2);
+    assert_eq!(macros.get(b"B"), Some(&tokenize(b"A")[..]));
+    assert_eq!(
+        macros.insert(b"A".to_vec(), tokenize(b"2")),
+        Some(tokenize(b"1"))
+    );
+    assert_eq!(macros.resolve().outcomes[&b"B"[..]], int(2));
+    assert!(MacroSet::new().is_empty());
+    assert_eq!(MacroSet::new().resolve().outcomes.len(), 0);
+}
+
+#[test]
+fn from_header() {
+    let header = b"#ifndef HEADER_H
+#define HEADER_H
+#ifndef A
+#define A 1
+#endif
+#define A 2
+  # define B A * \\
+ 3
+#define F(x) x
+int a;
+";
+    let macros = MacroSet::from_header(header);
+    assert_eq!(macros.len(), 2);
+    assert_eq!(macros.get(b"A"), Some(&tokenize(b"2")[..]));
+    assert_eq!(macros.resolve().outcomes[&b"B"[..]], int(6));
+    assert_eq!(macros.get(b"F"), None);
+    assert_eq!(macros.get_fn_macro(b"F").unwrap().params, [b"x"]);
+    let redefinitions = macros.redefinitions();
+    assert_eq!(redefinitions.len(), 1);
+    assert_eq!(redefinitions[0].name, b"A");
+    assert_eq!(redefinitions[0].previous.line, 4);
+    assert_eq!(redefinitions[0].redefined.line, 6);
+}
+
+#[test]
+fn fn_macros() {
+    use cexpr::expr::FnMacro;
+
+    let header = b"#define BUF_SIZE KB(PAGES)
+#define KB(x) ((x) * UNIT)
+#define UNIT 1024
+#define PAGES 4
+#define x KB(1)
+#define DOUBLE(y) (KB(y) * 2)
+#define BIG DOUBLE(x)
+#define REDEFINED 1
+#define REDEFINED(a) a
+";
+    let mut macros = MacroSet::from_header(header);
+    let resolution = macros.resolve();
+    assert_eq!(resolution.outcomes[&b"BUF_SIZE"[..]], int(4096));
+    // The parameter `x` of `KB` is not a use of the macro `x`.
+    assert_eq!(resolution.outcomes[&b"x"[..]], int(1024));
+    assert_eq!(resolution.outcomes[&b"BIG"[..]], int(2 << 20));
+    assert_eq!(macros.get(b"REDEFINED"), None);
+    assert_eq!(resolution.outcomes.len(), 5);
+
+    let (name, unit) = FnMacro::parse(&tokenize(b"KB(x) ((x) * UNIT * LOOP)")).unwrap();
+    macros.insert_fn_macro(name, unit);
+    macros.insert(b"LOOP".to_vec(), tokenize(b"BUF_SIZE"));
+    let resolution = macros.resolve();
+    assert_eq!(resolution.cyclic(), [&b"BUF_SIZE"[..], b"LOOP"]);
+    assert_eq!(
+        resolution.outcomes[&b"x"[..]],
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+}
+
+#[test]
+fn target() {
+    let macros = set(&[("MAX", "(size_t)-1")]);
+    let target = TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    };
+    let value = |set: MacroSet| match &set.resolve().outcomes[&b"MAX"[..]] {
+        Outcome::Evaluated(value) => value.clone(),
+        outcome => panic!("{:?}", outcome),
+    };
+    assert_eq!(value(macros.clone()), EvalResult::Int(Wrapping(-1)));
+    assert_eq!(
+        value(macros.with_target(target)),
+        EvalResult::Int(Wrapping(0xffff_ffff))
+    );
+}
+
+#[test]
+fn permissive() {
+    let macros = set(&[
+        ("N", "(_Static_assert_helper, 8)"),
+        ("M", "(_Static_assert(sizeof(struct s) == N, \"\"), N * 2)"),
+    ]);
+    let resolution = macros.clone().resolve();
+    assert_eq!(
+        resolution.outcomes[&b"N"[..]],
+        Outcome::Invalid(ErrorKind::UnknownIdentifier)
+    );
+    assert!(matches!(
+        resolution.outcomes[&b"M"[..]],
+        Outcome::Invalid(_)
+    ));
+    let resolution = macros.with_permissive(true).resolve();
+    assert_eq!(resolution.outcomes[&b"N"[..]], int(8));
+    assert_eq!(resolution.outcomes[&b"M"[..]], int(16));
+}
+
+#[test]
+fn c23() {
+    let macros = set(&[
+        ("WIDTH", "8u"),
+        ("MASK", "(typeof(WIDTH))-1"),
+        ("ALIGN", "alignof(long long)"),
+    ]);
+    let resolution = macros.clone().resolve();
+    assert!(matches!(
+        resolution.outcomes[&b"MASK"[..]],
+        Outcome::Invalid(_)
+    ));
+    assert!(matches!(
+        resolution.outcomes[&b"ALIGN"[..]],
+        Outcome::Invalid(_)
+    ));
+    let resolution = macros.with_c23(true).resolve();
+    assert_eq!(resolution.outcomes[&b"MASK"[..]], int(0xffff_ffff));
+    assert_eq!(resolution.outcomes[&b"ALIGN"[..]], int(8));
+}
+
+#[test]
+fn statistics() {
+    let macros = set(&[
+        ("SHIFT", "4"),
+        ("MASK", "(unsigned)~(0xf << SHIFT | 0xf << SHIFT * 2)"),
+        ("PICK", "SHIFT ? -1 : ~1 + 2"),
+        ("BAD", "MASK +"),
+        ("PARTIAL", "(1 + 2 ) )"),
+        ("LOOP", "LOOP"),
+    ]);
+    assert!(macros.clone().resolve().statistics.is_empty());
+
+    let resolution = macros.with_statistics(true).resolve();
+    let statistics = resolution.statistics;
+    let mut names: Vec<&[u8]> = statistics.keys().map(|name| &name[..]).collect();
+    names.sort_unstable();
+    assert_eq!(names, [&b"BAD"[..], b"MASK", b"PARTIAL", b"PICK", b"SHIFT"]);
+    let mask = &statistics[&b"MASK"[..]];
+    assert_eq!(mask.unary_operators.len(), 1);
+    assert_eq!(mask.unary_operators["~"], 1);
+    assert_eq!(mask.binary_operators.len(), 3);
+    assert_eq!(mask.binary_operators["<<"], 2);
+    assert_eq!(mask.binary_operators["*"], 1);
+    assert_eq!(mask.binary_operators["|"], 1);
+    assert_eq!(mask.casts, 1);
+    // Only the selected branch is counted.
+    let pick = &statistics[&b"PICK"[..]];
+    assert_eq!(pick.unary_operators["-"], 1);
+    assert_eq!(pick.unary_operators.len(), 1);
+    assert!(pick.binary_operators.is_empty());
+    assert!(statistics[&b"SHIFT"[..]].unary_operators.is_empty());
+    assert!(statistics[&b"BAD"[..]].binary_operators.is_empty());
+    // A macro that is only partially an expression could not be evaluated.
+    assert_eq!(
+        resolution.outcomes[&b"PARTIAL"[..]],
+        Outcome::Invalid(ErrorKind::Partial)
+    );
+    assert!(statistics[&b"PARTIAL"[..]].binary_operators.is_empty());
+}
+
+#[test]
+fn differences() {
+    let macros = set(&[
+        ("MAX", "(size_t)-1"),
+        ("HALF", "MAX / 2"),
+        ("WIDE", "L'\\xffff0'"),
+        ("BYTE", "(char)'\\xff'"),
+        ("NEGATIVE", "(char)'\\xff' < 0"),
+        ("SAME", "sizeof(int) + 1"),
+        ("NAN", "0.0 / 0.0"),
+        ("BAD", "UNKNOWN"),
+    ]);
+    let targets = [
+        TargetInfo::default(),
+        TargetInfo {
+            pointer_width: 32,
+            long_width: None,
+            wchar_width: 16,
+            char_signed: false,
+        },
+    ];
+    let differences = macros.differences(&targets);
+    let names: Vec<&[u8]> = differences.iter().map(|d| &d.name[..]).collect();
+    assert_eq!(names, [&b"BYTE"[..], b"HALF", b"MAX", b"NEGATIVE", b"WIDE"]);
+    assert_eq!(differences[0].outcomes, [int(-1), int(0xff)]);
+    assert_eq!(differences[2].outcomes, [int(-1), int(0xffff_ffff)]);
+    assert_eq!(differences[3].outcomes, [int(1), int(0)]);
+    assert!(matches!(
+        differences[4].outcomes[1],
+        Outcome::Invalid(ErrorKind::InvalidLiteral)
+    ));
+    assert!(macros.differences(&[TargetInfo::default()]).is_empty());
+    assert!(macros.differences(&[]).is_empty());
+}
+
+#[test]
+fn hover() {
+    use cexpr::target::{IntType, Type};
+
+    let macros = MacroSet::from_header(
+        b"#define KB(x) ((x) * 1024)
+#define A 2u
+#define B (A +  /* one */ 1)
+#define SIZE KB(B)
+#define BAD (A + UNKNOWN)
+#define LOOP (LOOP + 1)
+#define UNUSED (A + 100)
+",
+    );
+    let hover = macros.hover(b"SIZE").unwrap();
+    assert_eq!(hover.value, int(3072));
+    assert_eq!(hover.ty, Some(Type::Int(IntType::UnsignedInt)));
+    assert_eq!(hover.expansion, "KB(B)");
+    let dependencies: Vec<&[u8]> = hover.dependencies.keys().map(|k| &k[..]).collect();
+    assert_eq!(dependencies, [b"B"]);
+    assert_eq!(macros.hover(b"B").unwrap().expansion, "(A + 1)");
+
+    let hover = macros.hover(b"BAD").unwrap();
+    assert_eq!(hover.value, Outcome::Invalid(ErrorKind::UnknownIdentifier));
+    assert_eq!(hover.ty, None);
+    assert!(hover.dependencies.is_empty());
+    assert_eq!(macros.hover(b"LOOP").unwrap().value, Outcome::Cyclic);
+    assert_eq!(macros.hover(b"KB"), None);
+    assert_eq!(macros.hover(b"MISSING"), None);
+
+    let resolution = macros.resolve();
+    for (name, outcome) in &resolution.outcomes {
+        let hover = macros.hover(name).unwrap();
+        assert_eq!(&hover.value, outcome);
+        assert_eq!(hover.ty, resolution.types.get(name).copied());
+    }
+}
+
+#[test]
+fn long_chain() {
+    let mut macros = MacroSet::new();
+    macros.insert(b"M0".to_vec(), tokenize(b"0"));
+    for i in 1..2000 {
+        let source = format!("M{} + 1", i - 1);
+        macros.insert(format!("M{}", i).into_bytes(), tokenize(source.as_bytes()));
+    }
+    assert_eq!(macros.resolve().outcomes[&b"M1999"[..]], int(1999));
+}
+
+#[test]
+fn types() {
+    use cexpr::expr::IdentifierParser;
+    use cexpr::target::{IntType, Type};
+
+    let macros = set(&[
+        ("FLAG", "1u"),
+        ("FLAGS", "(FLAG | 2)"),
+        ("ALL", "FLAGS - 4"),
+        ("BIG", "4294967295"),
+        ("SIZE", "sizeof(int)"),
+        ("SHORT", "(short)1"),
+        ("PROMOTED", "SHORT + SHORT"),
+        ("HALF", "1.0f / 2"),
+        ("NAME", "\"name\""),
+        ("BAD", "1 +"),
+    ]);
+    let resolution = macros.resolve();
+    let ty = |name: &str| resolution.types.get(name.as_bytes()).copied();
+    assert_eq!(ty("FLAG"), Some(Type::Int(IntType::UnsignedInt)));
+    assert_eq!(ty("FLAGS"), Some(Type::Int(IntType::UnsignedInt)));
+    assert_eq!(ty("ALL"), Some(Type::Int(IntType::UnsignedInt)));
+    assert_eq!(ty("BIG"), Some(Type::Int(IntType::Long)));
+    assert_eq!(ty("SIZE"), Some(Type::Int(IntType::SizeT)));
+    assert_eq!(ty("SHORT"), Some(Type::Int(IntType::Short)));
+    assert_eq!(ty("PROMOTED"), Some(Type::Int(IntType::Int)));
+    assert_eq!(ty("HALF"), Some(Type::Float));
+    assert_eq!(ty("NAME"), Some(Type::Str));
+    assert_eq!(ty("BAD"), None);
+    // `FLAGS - 4` wraps around as an `unsigned int`.
+    assert_eq!(resolution.outcomes[&b"ALL"[..]], int(i64::from(u32::MAX)));
+
+    let values = resolution.typed_values();
+    assert_eq!(values.len(), 9);
+    let parser = IdentifierParser::from_resolver(&values);
+    let (_, (value, ty)) = parser.expr_with_type(&tokenize(b"FLAG - 2")).unwrap();
+    assert_eq!(value, Int(Wrapping(i64::from(u32::MAX))));
+    assert_eq!(ty, Type::Int(IntType::UnsignedInt));
+    let values = resolution.values();
+    let parser = IdentifierParser::new(&values);
+    let (_, (_, ty)) = parser.expr_with_type(&tokenize(b"FLAG")).unwrap();
+    assert_eq!(ty, Type::Int(IntType::Int));
+}
+
+#[test]
+fn stream() {
+    let mut macros = set(&[("BASE", "0x10u")]);
+    let (name, kb) = cexpr::expr::FnMacro::parse(&tokenize(b"KB(x) ((x) * 1024)")).unwrap();
+    macros.insert_fn_macro(name, kb);
+    let mut stream = macros.into_stream();
+    let header = b"
+#ifndef STREAM_H
+#define STREAM_H
+#define A (BASE + 1)
+#define B A
+#define A 2
+#define C (A + B)
+#define SELF (SELF + 1)
+#define LATER LAST
+#define LAST 3
+#define SIZE KB(A)
+#define KB(x) x
+#define SMALL KB(A)
+#define B(x) x
+#define USES_B B
+#define ZERO (1 / 0)
+";
+    let outcomes: Vec<(String, Outcome)> = stream
+        .header(header)
+        .map(|(name, outcome)| (String::from_utf8(name).unwrap(), outcome))
+        .collect();
+    let outcome = |name: &str| {
+        outcomes
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, outcome)| outcome.clone())
+    };
+    assert_eq!(outcomes.len(), 11);
+    assert_eq!(outcomes[0], ("A".to_string(), int(17)));
+    assert_eq!(outcome("STREAM_H"), None);
+    assert_eq!(outcome("B"), int(17).into());
+    assert_eq!(outcome("A"), int(2).into());
+    // `B` keeps the value it had when it was defined.
+    assert_eq!(outcome("C"), int(19).into());
+    assert_eq!(outcome("SELF"), Some(Outcome::Cyclic));
+    assert_eq!(
+        outcome("LATER"),
+        Some(Outcome::Invalid(ErrorKind::UnknownIdentifier))
+    );
+    assert_eq!(outcome("SIZE"), int(2048).into());
+    assert_eq!(outcome("SMALL"), int(2).into());
+    assert_eq!(
+        outcome("USES_B"),
+        Some(Outcome::Invalid(ErrorKind::UnknownIdentifier))
+    );
+    assert_eq!(
+        outcome("ZERO"),
+        Some(Outcome::Invalid(ErrorKind::DivisionByZero))
+    );
+    assert_eq!(stream.value(b"A"), Some(&Int(Wrapping(2))));
+    assert_eq!(stream.value(b"B"), None);
+    assert_eq!(stream.value(b"ZERO"), None);
+
+    // Macros that are not evaluated are not kept.
+    let retained = stream.retained_bytes();
+    let failing = "#define UNKNOWN (MISSING + 1)\n".repeat(1000);
+    assert_eq!(stream.header(failing.as_bytes()).count(), 1000);
+    assert_eq!(stream.retained_bytes(), retained);
+    assert_eq!(
+        stream.define(b"#define ONE 1"),
+        Some((b"ONE".to_vec(), int(1)))
+    );
+    assert!(stream.retained_bytes() > retained);
+    assert_eq!(stream.define(b"#undef ONE"), None);
+}
@@ -0,0 +1,186 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use cexpr::expr::WarningKind;
+use cexpr::macros::{MacroSet, Outcome};
+use cexpr::report::{ReadError, Report};
+use cexpr::target::{DataModel, IntType, TargetInfo, Type};
+use cexpr::token::{tokenize, Kind};
+use cexpr::ErrorKind;
+
+fn set(macros: &[(&str, &str)]) -> MacroSet {
+    let mut set = MacroSet::new();
+    for &(name, source) in macros {
+        set.insert(name.as_bytes().to_vec(), tokenize(source.as_bytes()));
+    }
+    set
+}
+
+#[test]
+fn report() {
+    let macros = set(&[
+        ("MAX", "(size_t)-1"),
+        ("SHIFTED", "-MAX_INT >> 1"),
+        ("MAX_INT", "2147483647"),
+        ("BAD", "MAX_INT +"),
+        ("LOOP", "LOOP + 1"),
+    ]);
+    let report = macros.report();
+    assert_eq!(report.version, cexpr::report::VERSION);
+    assert_eq!(report.target, TargetInfo::default());
+    let names: Vec<&[u8]> = report.macros.iter().map(|m| &m.name[..]).collect();
+    assert_eq!(
+        names,
+        [&b"BAD"[..], b"LOOP", b"MAX", b"MAX_INT", b"SHIFTED"]
+    );
+
+    let shifted = &report.macros[4];
+    assert_eq!(shifted.ty, Some(Type::Int(IntType::Int)));
+    assert_eq!(shifted.warnings, [WarningKind::NegativeRightShift]);
+    assert_eq!(shifted.dependencies, [b"MAX_INT"]);
+    assert_eq!(
+        report.macros[0].outcome,
+        Outcome::Invalid(ErrorKind::UnexpectedToken)
+    );
+    assert_eq!(report.macros[0].ty, None);
+
+    assert_eq!(
+        report.to_json(),
+        concat!(
+            r#"{"version":1,"target":{"pointer_width":64,"long_width":64,"wchar_width":32,"char_signed":true},"macros":["#,
+            "\n",
+            r#"{"name":"BAD","outcome":"invalid","error":"unexpected_token"},"#,
+            "\n",
+            r#"{"name":"LOOP","outcome":"cyclic"},"#,
+            "\n",
+            r#"{"name":"MAX","outcome":"evaluated","value":{"int":-1},"type":"size_t","warnings":[],"dependencies":[]},"#,
+            "\n",
+            r#"{"name":"MAX_INT","outcome":"evaluated","value":{"int":2147483647},"type":"int","warnings":[],"dependencies":[]},"#,
+            "\n",
+            r#"{"name":"SHIFTED","outcome":"evaluated","value":{"int":-1073741824},"type":"int","warnings":["negative_right_shift"],"dependencies":["MAX_INT"]}"#,
+            "\n]}\n",
+        )
+    );
+}
+
+#[test]
+fn json_values() {
+    let macros = set(&[
+        ("CHAR", "'a'"),
+        ("FLOAT", "1.5"),
+        ("HUGE", "1e999"),
+        ("STR", r#""a\"\\\n\xff""#),
+        ("WIDE", r"L'\xffffffff'"),
+    ]);
+    let json = macros.report().to_json();
+    let lines: Vec<&str> = json.lines().collect();
+    let value = |line: &str| {
+        let start = line.find(r#""value":"#).unwrap() + 8;
+        let end = line.find(r#","type""#).unwrap();
+        line[start..end].to_string()
+    };
+    assert_eq!(value(lines[1]), r#"{"char":97}"#);
+    assert_eq!(value(lines[2]), r#"{"float":1.5}"#);
+    assert_eq!(value(lines[3]), r#"{"float":"inf"}"#);
+    assert_eq!(value(lines[4]), r#"{"str":"a\"\\\u000a\u00ff"}"#);
+    assert_eq!(value(lines[5]), r#"{"raw_char":4294967295}"#);
+    assert!(lines[4].contains(r#""type":"char[]""#));
+
+    assert_eq!(
+        MacroSet::new().report().to_json().lines().last(),
+        Some("]}")
+    );
+}
+
+#[test]
+fn from_json() {
+    let target = TargetInfo {
+        pointer_width: 32,
+        long_width: None,
+        wchar_width: 16,
+        char_signed: false,
+    };
+    let macros = set(&[
+        ("CHAR", "'a'"),
+        ("DEPENDENT", "(WIDE < 0) + INT_MAX"),
+        ("FLOAT", "0.1"),
+        ("HUGE", "-1e999"),
+        ("INT_MAX", "2147483647"),
+        ("LOOP", "LOOP"),
+        ("MISSING", "UNKNOWN"),
+        ("SHIFTED", "-1 >> 1"),
+        ("STR", r#""a\"\\\n\xff""#),
+        ("TOKEN", "UNKNOWN"),
+        ("TOKENS", "UNKNOWN"),
+        ("TYPED", "UNKNOWN"),
+        ("WIDE", r"L'\xffff'"),
+    ]);
+    let mut report = macros.with_target(target).report();
+    report.macros[9].outcome = Outcome::Invalid(ErrorKind::ExactToken(Kind::Punctuation, b")"));
+    report.macros[10].outcome =
+        Outcome::Invalid(ErrorKind::ExactTokens(Kind::Punctuation, &["<<", ">>"]));
+    report.macros[11].outcome = Outcome::Invalid(ErrorKind::TypedToken(Kind::Identifier));
+    let json = report.to_json();
+    assert!(json.contains(
+        r#"{"name":"TOKENS","outcome":"invalid","error":"exact_tokens","kind":"punctuation","expected":["<<",">>"]}"#
+    ));
+    assert_eq!(Report::from_json(&json), Ok(report.clone()));
+    assert_eq!(Report::from_json(&json.replace('\n', "\r\n ")), Ok(report));
+
+    let llp64 = MacroSet::new()
+        .with_target(DataModel::LLP64.into())
+        .report();
+    assert_eq!(Report::from_json(&llp64.to_json()), Ok(llp64));
+    let lp64 = MacroSet::new()
+        .with_target(TargetInfo {
+            long_width: Some(64),
+            ..TargetInfo::default()
+        })
+        .report();
+    assert_eq!(
+        Report::from_json(&lp64.to_json()).unwrap().target,
+        TargetInfo::default()
+    );
+}
+
+#[test]
+fn from_json_errors() {
+    let json = MacroSet::new().report().to_json();
+    assert_eq!(
+        Report::from_json(&json.replace(r#""version":1"#, r#""version":2"#)),
+        Err(ReadError::Version(2))
+    );
+    assert_eq!(Report::from_json(&json[..20]), Err(ReadError::Invalid(20)));
+    assert_eq!(
+        Report::from_json(&format!("{} 1", json)),
+        Err(ReadError::Invalid(json.len() + 1))
+    );
+    // Each of these macros is invalid at the value after `at`.
+    let invalid = |line: &str, at: &str| {
+        let json = format!(
+            r#"{{"version":1,"target":{{"pointer_width":64,"long_width":64,"wchar_width":32,"char_signed":true}},"macros":[{}]}}"#,
+            line
+        );
+        let at = json.find("\"macros\"").unwrap() + 10 + line.find(at).unwrap() + at.len();
+        assert_eq!(Report::from_json(&json), Err(ReadError::Invalid(at)));
+    };
+    invalid(
+        r#"{"name":"A","outcome":"invalid","error":"unknown"}"#,
+        r#""error":"#,
+    );
+    invalid(r#"{"name":"Ā","outcome":"cyclic"}"#, r#"{"name":""#);
+    invalid(
+        r#"{"name":"A","outcome":"evaluated","value":{"int":1},"type":"int128_t","warnings":[],"dependencies":[]}"#,
+        r#""type":"#,
+    );
+    invalid(
+        r#"{"name":"A","outcome":"invalid","error":"exact_tokens","kind":"punctuation","expected":["+"]}"#,
+        r#""expected":"#,
+    );
+}
@@ -0,0 +1,350 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+//! Check that the public entry points don't panic on arbitrary input.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::ast::Symbols;
+use cexpr::expr::{self, EvalResult, IdentifierParser};
+use cexpr::literal;
+use cexpr::target::TargetInfo;
+use cexpr::token::Kind::{self, *};
+use cexpr::token::Token;
+
+const ITERATIONS: usize = 20000;
+
+/// A xorshift generator, so that failures are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+
+    fn bytes(&mut self, alphabet: &[u8]) -> Vec<u8> {
+        let len = self.below(12);
+        (0..len).map(|_| *self.pick(alphabet)).collect()
+    }
+}
+
+const LITERAL_BYTES: &[u8] = b"0123456789abcdefxXbBuUlLeEpP.+-'\"\\?n\x80\xff";
+
+fn literal(rng: &mut Rng) -> Vec<u8> {
+    let literals: &[&[u8]] = &[
+        b"0",
+        b"1",
+        b"-1",
+        b"0x7fffffffffffffff",
+        b"18446744073709551615",
+        b"1.5",
+        b"1e308",
+        b"'a'",
+        b"'\\xff'",
+        b"\"s\"",
+    ];
+    if rng.below(2) == 0 {
+        rng.pick(literals).to_vec()
+    } else {
+        rng.bytes(LITERAL_BYTES)
+    }
+}
+
+fn token(rng: &mut Rng) -> Token {
+    const PUNCTUATION: &[&str] = &[
+        "(", ")", "+", "-", "~", "!", "*", "/", "%", "<<", ">>", "<", ">", "<=", ">=", "==", "!=",
+        "&", "^", "|", "&&", "||", "?", ":", "#", "##", ",",
+    ];
+    const IDENTIFIERS: &[&str] = &["A", "F", "S", "C", "W", "size_t", "ssize_t", "UNKNOWN"];
+    let kinds = [
+        Punctuation,
+        Punctuation,
+        Identifier,
+        Literal,
+        Keyword,
+        Comment,
+        Whitespace,
+    ];
+    let kind: Kind = *rng.pick(&kinds);
+    let mut raw = match kind {
+        Punctuation => rng.pick(PUNCTUATION).as_bytes().to_vec(),
+        Identifier => rng.pick(IDENTIFIERS).as_bytes().to_vec(),
+        Literal => literal(rng),
+        _ => rng.bytes(b"abcz_"),
+    };
+    // Tokens that no tokenizer produces, with an empty spelling or a NUL.
+    match rng.below(40) {
+        0 => raw.clear(),
+        1 => {
+            let i = rng.below(raw.len() + 1);
+            raw.insert(i, 0);
+        }
+        _ => {}
+    }
+    (kind, &raw[..]).into()
+}
+
+fn identifiers() -> HashMap<Vec<u8>, EvalResult> {
+    let mut idents = HashMap::new();
+    idents.insert(b"A".to_vec(), EvalResult::Int(Wrapping(i64::MIN)));
+    idents.insert(b"F".to_vec(), EvalResult::Float(f64::NAN));
+    idents.insert(b"S".to_vec(), EvalResult::Str(b"\x00\xff".to_vec()));
+    idents.insert(b"C".to_vec(), EvalResult::Invalid);
+    idents
+}
+
+/// A visitor that ignores everything.
+struct Ignore;
+
+impl cexpr::ast::Visitor for Ignore {}
+
+#[test]
+fn literals() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..ITERATIONS {
+        let bytes = rng.bytes(LITERAL_BYTES);
+        let _ = literal::parse(&bytes);
+        let _ = literal::parse_user_defined(&bytes);
+    }
+}
+
+#[test]
+fn source() {
+    let mut rng = Rng(0x6a09_e667_f3bc_c908);
+    for _ in 0..ITERATIONS {
+        let mut line = b"#define ".to_vec();
+        line.extend(rng.bytes(b"#define F(x,...)/*\\\n\"'1.e+ "));
+        let _ = cexpr::token::normalize(&cexpr::token::tokenize(&line));
+        let _ = cexpr::token::tokenize_partial(&line, rng.below(8));
+        let _ = cexpr::parse_define(&line);
+        let _ = cexpr::header::statistics(&line, Default::default());
+        if let Some((name, None, tokens)) = cexpr::parse_define(&line) {
+            let mut macros = cexpr::macros::MacroSet::new();
+            macros.insert(name, tokens.clone());
+            macros.insert(b"F".to_vec(), tokens);
+            let _ = macros.resolve();
+        }
+    }
+}
+
+#[test]
+fn retokenize() {
+    use cexpr::token::{retokenize, tokenize, Edit};
+
+    let mut rng = Rng(0xbb67_ae85_84ca_a73b);
+    const SOURCE: &[u8] = b"#define F(x,...)/*\\\n\"'1.e+ u8L%:\r";
+    for _ in 0..ITERATIONS {
+        let old = rng.bytes(SOURCE);
+        let start = rng.below(old.len() + 1);
+        let end = start + rng.below(old.len() - start + 1);
+        let inserted = rng.bytes(SOURCE);
+        let mut new = old.clone();
+        new.splice(start..end, inserted.iter().cloned());
+        let edit = Edit {
+            replaced: start..end,
+            inserted: inserted.len(),
+        };
+        let actual = retokenize(&tokenize(&old), &edit, &new);
+        let encoded = cexpr::token::encode(&actual);
+        assert_eq!(cexpr::token::decode(&encoded).as_ref(), Ok(&actual));
+        let mut corrupt = encoded.clone();
+        let i = rng.below(corrupt.len());
+        corrupt[i] = rng.next() as u8;
+        let _ = cexpr::token::decode(&corrupt);
+        let _ = cexpr::token::decode(&old);
+        let expected = tokenize(&new);
+        assert_eq!(actual, expected);
+        let spans = |tokens: &[Token]| tokens.iter().map(|t| t.span).collect::<Vec<_>>();
+        assert_eq!(spans(&actual), spans(&expected));
+    }
+}
+
+#[test]
+fn expressions() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let idents = identifiers();
+    let parser = IdentifierParser::new(&idents);
+    let strict = IdentifierParser::new(&idents)
+        .with_strict_ternary(true)
+        .with_checked_arithmetic(true)
+        .with_gnu_extensions(true);
+    let fn_macros: HashMap<_, _> = [
+        &b"F(x, y) #x (y) F(x ## y, y ## 1)"[..],
+        b"W(x) (-8 >> 1) + (1 < 2 < 3) + 0 + 0 + 0 + 0 + 0 + 0 + 0 + 0 + x",
+        b"S(...) S __VA_OPT__((__VA_ARGS__) #__VA_ARGS__)",
+    ]
+    .iter()
+    .map(|d| expr::FnMacro::parse(&cexpr::token::tokenize(d)).unwrap())
+    .collect();
+    let invoking = IdentifierParser::new(&idents).with_fn_macros(&fn_macros);
+    let overflowing = |raw: &[u8]| Some(EvalResult::Int(Wrapping(i64::MAX - raw.len() as i64)));
+    let invalid = |_: &[u8]| Some(EvalResult::Invalid);
+    let hooked = IdentifierParser::new(&idents)
+        .with_literal_parser(&overflowing)
+        .with_fallback_literal_parser(&invalid);
+    let mut operators = expr::Operators::new();
+    operators.insert_infix(b"C", expr::Precedence::Additive, |a, _| Some(a.clone()));
+    operators.insert_prefix(b"#", |_| None);
+    operators.insert_intrinsic(b"S", |args| args.last().cloned());
+    let custom = IdentifierParser::new(&idents).with_operators(&operators);
+    let mut symbols = Symbols::new();
+    for _ in 0..ITERATIONS {
+        let len = rng.below(10);
+        let tokens: Vec<Token> = (0..len).map(|_| token(&mut rng)).collect();
+        let _ = parser.expr(&tokens);
+        let _ = parser.macro_definition(&tokens);
+        let _ = parser.expr_with_dependencies(&tokens);
+        let _ = parser.expr_with_trace(&tokens);
+        let _ = parser.expr_with_warnings(&tokens);
+        let _ = parser.expr_with_type(&tokens);
+        let _ = parser.partial_expr(&tokens, &mut symbols);
+        let _ = parser.symbolic_expr(&[Default::default()], &tokens, &mut symbols);
+        let _ = parser.enumerator(Some(i64::MAX), &tokens);
+        let _ = parser.substitute(&tokens);
+        let _ = cexpr::token::normalize(&tokens);
+        let _ = parser.expr(&cexpr::token::merge_punctuation(&tokens));
+        let _ = strict.expr(&tokens);
+        let _ = invoking.expr(&tokens);
+        let _ = invoking.expr_with_warnings(&tokens);
+        let _ = invoking.enumerator(None, &tokens);
+        let mut call = cexpr::token::tokenize(b"1 + W(");
+        call.extend(tokens.iter().cloned());
+        call.extend(cexpr::token::tokenize(b")"));
+        let _ = invoking.expr_with_warnings(&call);
+        let _ = invoking.enumerator(None, &call);
+        let _ = hooked.expr_with_type(&tokens);
+        let _ = custom.expr_with_type(&tokens);
+        let _ = expr::validate(&tokens);
+        let _ = cexpr::ast::parse(&tokens, &mut symbols);
+        let _ = cexpr::ast::visit(&tokens, &mut Ignore);
+        let _ = expr::fn_macro_declaration(&tokens);
+    }
+}
+
+#[test]
+fn targets() {
+    let mut rng = Rng(0x3c6e_f372_fe94_f82b);
+    let idents = identifiers();
+    const WIDTHS: &[u32] = &[0, 1, 8, 16, 32, 64, 65, 128, 200];
+    const CASTS: &[&str] = &[
+        "(size_t)",
+        "(ssize_t)",
+        "(long)",
+        "(unsigned long)",
+        "(char)",
+        "L'\\xff' + ",
+        "L'a' + ",
+    ];
+    const OPERATORS: &[&str] = &["<<", ">>", "+", "-", "*", "/", "%", "==", "<", "&&", "||"];
+    const OPERANDS: &[&str] = &["1", "-1", "150", "64", "0x7fffffffffffffff", "A", "'\\377'"];
+    for &width in WIDTHS {
+        let target = TargetInfo {
+            pointer_width: width,
+            long_width: Some(width),
+            wchar_width: width,
+            ..Default::default()
+        };
+        let checked = IdentifierParser::new(&idents)
+            .with_target(target)
+            .with_checked_arithmetic(true);
+        for source in [
+            &b"(ssize_t)1 << 150"[..],
+            b"(long)-1 >> 150",
+            b"L'\\xff' << 127",
+        ] {
+            let _ = checked.expr(&cexpr::token::tokenize(source));
+        }
+    }
+    for _ in 0..ITERATIONS / 10 {
+        let width = *rng.pick(WIDTHS);
+        let long_width = if rng.below(2) == 0 {
+            None
+        } else {
+            Some(*rng.pick(WIDTHS))
+        };
+        let target = TargetInfo {
+            pointer_width: width,
+            long_width,
+            wchar_width: *rng.pick(WIDTHS),
+            char_signed: rng.below(2) == 0,
+        };
+        let source = format!(
+            "{}{} {} {}{}",
+            rng.pick(CASTS),
+            rng.pick(OPERANDS),
+            rng.pick(OPERATORS),
+            rng.pick(CASTS),
+            rng.pick(OPERANDS),
+        );
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        let parser = IdentifierParser::new(&idents).with_target(target);
+        let _ = parser.expr_with_type(&tokens);
+        let _ = parser.expr_with_warnings(&tokens);
+        let _ = parser.substitute(&tokens);
+        let _ = parser.symbolic_expr(&[target, Default::default()], &tokens, &mut Symbols::new());
+        let checked = IdentifierParser::new(&idents)
+            .with_target(target)
+            .with_checked_arithmetic(true);
+        let _ = checked.expr(&tokens);
+        let _ = literal::parse_tokens(&tokens);
+    }
+}
+
+fn nested(depth: usize, open: &str, inner: &str, close: &str) -> Vec<Token> {
+    let open: Token = (Punctuation, open.as_bytes()).into();
+    let close: Token = (Punctuation, close.as_bytes()).into();
+    let mut tokens = vec![open; depth];
+    tokens.push((Literal, inner.as_bytes()).into());
+    if !close.raw.is_empty() {
+        tokens.extend(vec![close; depth]);
+    }
+    tokens
+}
+
+#[test]
+fn nesting() {
+    let one = EvalResult::Int(Wrapping(1));
+    let parens = nested(63, "(", "1", ")");
+    assert_eq!(expr::expr(&parens).unwrap().1, one);
+    let parens = nested(100_000, "(", "1", ")");
+    assert!(expr::expr(&parens).is_err());
+    assert!(cexpr::ast::parse(&parens, &mut Symbols::new()).is_err());
+    assert!(cexpr::ast::visit(&parens, &mut Ignore).is_err());
+    assert!(expr::validate(&parens).is_err());
+
+    let strings = nested(100_000, "(", "\"s\"", ")");
+    assert!(expr::expr(&strings).is_err());
+
+    let unary = nested(63, "+", "1", "");
+    assert_eq!(expr::expr(&unary).unwrap().1, one);
+    let unary = nested(100_000, "-", "1", "");
+    assert!(expr::expr(&unary).is_err());
+
+    // 1 ? 1 : 1 ? 1 : ...
+    let mut ternary = vec![];
+    for _ in 0..100_000 {
+        ternary.push((Literal, &b"1"[..]).into());
+        ternary.push((Punctuation, &b"?"[..]).into());
+        ternary.push((Literal, &b"1"[..]).into());
+        ternary.push((Punctuation, &b":"[..]).into());
+    }
+    ternary.push((Literal, &b"1"[..]).into());
+    assert!(expr::expr(&ternary).is_err());
+}
@@ -0,0 +1,385 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::assert_full_parse;
+use cexpr::expr::{EvalResult, IdentifierParser};
+use cexpr::target::TargetInfo;
+use cexpr::token::Kind::*;
+use cexpr::token::Token;
+
+fn eval(target: TargetInfo, tokens: &[Token]) -> EvalResult {
+    let idents = HashMap::new();
+    let parser = IdentifierParser::new(&idents).with_target(target);
+    assert_full_parse(parser.expr(tokens)).unwrap().1
+}
+
+fn ilp32() -> TargetInfo {
+    TargetInfo {
+        pointer_width: 32,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn size_t_minus_one() {
+    // (size_t)-1
+    let tokens = vec![
+        (Punctuation, &b"("[..]).into(),
+        (Identifier, &b"size_t"[..]).into(),
+        (Punctuation, &b")"[..]).into(),
+        (Punctuation, &b"-"[..]).into(),
+        (Literal, &b"1"[..]).into(),
+    ];
+    assert_eq!(
+        eval(ilp32(), &tokens),
+        EvalResult::Int(Wrapping(0xffff_ffff))
+    );
+    assert_eq!(
+        eval(TargetInfo::default(), &tokens),
+        EvalResult::Int(Wrapping(-1))
+    );
+}
+
+#[test]
+fn uintptr_t_not_zero() {
+    // (uintptr_t)~0
+    let tokens = vec![
+        (Punctuation, &b"("[..]).into(),
+        (Identifier, &b"uintptr_t"[..]).into(),
+        (Punctuation, &b")"[..]).into(),
+        (Punctuation, &b"~"[..]).into(),
+        (Literal, &b"0"[..]).into(),
+    ];
+    assert_eq!(
+        eval(ilp32(), &tokens),
+        EvalResult::Int(Wrapping(0xffff_ffff))
+    );
+}
+
+#[test]
+fn intptr_t_sign_extends() {
+    // (intptr_t)0xffffffff
+    let tokens = vec![
+        (Punctuation, &b"("[..]).into(),
+        (Identifier, &b"intptr_t"[..]).into(),
+        (Punctuation, &b")"[..]).into(),
+        (Literal, &b"0xffffffff"[..]).into(),
+    ];
+    assert_eq!(eval(ilp32(), &tokens), EvalResult::Int(Wrapping(-1)));
+    assert_eq!(
+        eval(TargetInfo::default(), &tokens),
+        EvalResult::Int(Wrapping(0xffff_ffff))
+    );
+}
+
+#[test]
+fn to_target_int() {
+    use cexpr::expr::ConversionError::*;
+    use cexpr::literal::CChar;
+    use cexpr::target::IntType;
+
+    let lp64 = TargetInfo::default();
+    let int = |i| EvalResult::Int(Wrapping(i));
+    assert_eq!(
+        int(255).to_target_int(IntType::UnsignedChar, &lp64),
+        Ok(255)
+    );
+    assert_eq!(
+        int(256).to_target_int(IntType::UnsignedChar, &lp64),
+        Err(Truncated { value: 0 })
+    );
+    assert_eq!(
+        int(128).to_target_int(IntType::Char, &lp64),
+        Err(Truncated { value: -128 })
+    );
+    assert_eq!(
+        int(-1).to_target_int(IntType::UnsignedLongLong, &lp64),
+        Err(Truncated {
+            value: u64::MAX as i128
+        })
+    );
+    assert_eq!(int(-70000).to_target_int(IntType::Long, &lp64), Ok(-70000));
+    assert_eq!(
+        int(0x1_0000_0000).to_target_int(IntType::UnsignedLong, &ilp32()),
+        Err(Truncated { value: 0 })
+    );
+    assert_eq!(
+        EvalResult::Float(2.0).to_target_int(IntType::Short, &lp64),
+        Ok(2)
+    );
+    assert_eq!(
+        EvalResult::Float(-2.5).to_target_int(IntType::Short, &lp64),
+        Err(Truncated { value: -2 })
+    );
+    assert_eq!(
+        EvalResult::Char(CChar::Raw(0xff)).to_target_int(IntType::UnsignedChar, &lp64),
+        Ok(255)
+    );
+    assert_eq!(
+        EvalResult::Float(f64::NAN).to_target_int(IntType::Int, &lp64),
+        Err(NotNumeric)
+    );
+    assert_eq!(
+        EvalResult::Str(vec![]).to_target_int(IntType::Int, &lp64),
+        Err(NotNumeric)
+    );
+}
+
+#[test]
+fn expr_with_type() {
+    use cexpr::target::IntType::*;
+    use cexpr::target::Type::{self, Double, Float, LongDouble, Str};
+
+    let int = Type::Int;
+
+    let mut idents = HashMap::new();
+    idents.insert(b"BIG".to_vec(), EvalResult::Int(Wrapping(1 << 40)));
+    idents.insert(b"S".to_vec(), EvalResult::Str(b"abc".to_vec()));
+    let ty = |target, source: &str| -> Type {
+        let parser = IdentifierParser::new(&idents).with_target(target);
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr_with_type(&tokens))
+            .unwrap()
+            .1
+             .1
+    };
+    let lp64 = TargetInfo::default();
+
+    assert_eq!(ty(lp64, "1"), int(Int));
+    assert_eq!(ty(lp64, "1u"), int(UnsignedInt));
+    assert_eq!(ty(lp64, "1UL"), int(UnsignedLong));
+    assert_eq!(ty(lp64, "1ll"), int(LongLong));
+    assert_eq!(ty(lp64, "0x7fffffff"), int(Int));
+    assert_eq!(ty(lp64, "0xffffffff"), int(UnsignedInt));
+    assert_eq!(ty(lp64, "4294967295"), int(Long));
+    assert_eq!(ty(ilp32(), "4294967295"), int(LongLong));
+    assert_eq!(ty(lp64, "0xffffffffffffffff"), int(UnsignedLong));
+    assert_eq!(ty(lp64, "18446744073709551615"), int(UnsignedLongLong));
+    assert_eq!(ty(lp64, "'a'"), int(Int));
+    assert_eq!(ty(lp64, "u8'a'"), int(UnsignedChar));
+    assert_eq!(ty(lp64, "u'a'"), int(UnsignedShort));
+    assert_eq!(ty(lp64, "U'a'"), int(UnsignedInt));
+    assert_eq!(ty(lp64, "L'a'"), int(Int));
+    assert_eq!(ty(lp64, "1.0f"), Float);
+    assert_eq!(ty(lp64, "1.0"), Double);
+    assert_eq!(ty(lp64, "1.0L"), LongDouble);
+    assert_eq!(ty(lp64, "\"a\" S"), Str);
+    assert_eq!(ty(lp64, "S[1]"), int(Char));
+    assert_eq!(ty(lp64, "BIG"), int(Long));
+
+    assert_eq!(ty(lp64, "1u + 1"), int(UnsignedInt));
+    assert_eq!(ty(lp64, "1u + 1l"), int(Long));
+    assert_eq!(ty(ilp32(), "1u + 1l"), int(UnsignedLong));
+    assert_eq!(ty(lp64, "1ul - 1ll"), int(UnsignedLongLong));
+    assert_eq!(ty(lp64, "1 + 1.0f"), Float);
+    assert_eq!(ty(lp64, "1.0f * 1.0"), Double);
+    assert_eq!(ty(lp64, "(char)1"), int(Char));
+    assert_eq!(ty(lp64, "-(char)1"), int(Int));
+    assert_eq!(ty(lp64, "(unsigned short)1 + (unsigned short)1"), int(Int));
+    assert_eq!(ty(lp64, "(size_t)1 + 1"), int(SizeT));
+    assert_eq!(ty(lp64, "(ssize_t)1 + 1ul"), int(UnsignedLong));
+    assert_eq!(ty(lp64, "1 << 40ull"), int(Int));
+    assert_eq!(ty(lp64, "1ull < 2"), int(Int));
+    assert_eq!(ty(lp64, "1 ? 1 : 2u"), int(UnsignedInt));
+    assert_eq!(ty(lp64, "(1ul ? 1 : 2) + 1"), int(Int));
+}
+
+#[test]
+fn wchar_width() {
+    use cexpr::target::IntType::*;
+    use cexpr::target::Type;
+
+    let idents = HashMap::new();
+    let windows = TargetInfo {
+        wchar_width: 16,
+        ..Default::default()
+    };
+    let eval = |target, source: &str| {
+        let parser = IdentifierParser::new(&idents).with_target(target);
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr_with_type(&tokens))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let raw = |i| EvalResult::Char(cexpr::literal::CChar::Raw(i));
+    assert_eq!(
+        eval(TargetInfo::default(), "L'\\x10000'"),
+        Some((raw(0x10000), Type::Int(Int)))
+    );
+    assert_eq!(eval(windows, "L'\\x10000'"), None);
+    assert_eq!(eval(windows, "L'\\U0001f600'"), None);
+    assert_eq!(
+        eval(windows, "L'\\xffff'"),
+        Some((raw(0xffff), Type::Int(UnsignedShort)))
+    );
+}
+
+#[test]
+fn char_signed() {
+    use cexpr::target::IntType::*;
+
+    let idents = HashMap::new();
+    let arm = TargetInfo {
+        char_signed: false,
+        ..Default::default()
+    };
+    let eval = |target, source: &str| {
+        let parser = IdentifierParser::new(&idents).with_target(target);
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr(&tokens))
+            .ok()
+            .map(|(_, value)| value)
+    };
+    let int = |i| Some(EvalResult::Int(Wrapping(i)));
+    assert_eq!(eval(TargetInfo::default(), "(char)200"), int(-56));
+    assert_eq!(eval(arm, "(char)200"), int(200));
+    assert_eq!(eval(arm, "(char)-1 > 0"), int(1));
+    assert_eq!(eval(arm, "(signed char)200"), int(-56));
+    assert_eq!(eval(arm, "(char)'\\x80' + 0"), int(128));
+    assert_eq!(
+        EvalResult::Int(Wrapping(255)).to_target_int(Char, &arm),
+        Ok(255)
+    );
+    assert!(Char.is_signed(&TargetInfo::default()));
+    assert!(!Char.is_signed(&arm));
+    assert!(SignedChar.is_signed(&arm));
+}
+
+#[test]
+fn cplusplus_bool() {
+    use cexpr::target::IntType::*;
+    use cexpr::target::Type;
+
+    let idents = HashMap::new();
+    let c = IdentifierParser::new(&idents);
+    let cxx = IdentifierParser::new(&idents).with_cplusplus(true);
+    let eval = |parser: &IdentifierParser<'_>, source: &str| {
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr_with_type(&tokens))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let one = EvalResult::Int(Wrapping(1));
+    let zero = EvalResult::Int(Wrapping(0));
+    assert_eq!(eval(&c, "1 < 2"), Some((one.clone(), Type::Int(Int))));
+    assert_eq!(eval(&cxx, "1 < 2"), Some((one.clone(), Type::Int(Bool))));
+    assert_eq!(eval(&cxx, "1 == 2"), Some((zero.clone(), Type::Int(Bool))));
+    assert_eq!(eval(&cxx, "true"), Some((one.clone(), Type::Int(Bool))));
+    assert_eq!(eval(&cxx, "false"), Some((zero, Type::Int(Bool))));
+    assert_eq!(
+        eval(&cxx, "true + 1"),
+        Some((EvalResult::Int(Wrapping(2)), Type::Int(Int)))
+    );
+    assert_eq!(
+        eval(&cxx, "1 ? true : 2 > 1"),
+        Some((one.clone(), Type::Int(Bool)))
+    );
+    assert_eq!(eval(&c, "true"), None);
+    assert_eq!(
+        eval(&c, "(_Bool)0x100"),
+        Some((one.clone(), Type::Int(Bool)))
+    );
+    assert_eq!(
+        eval(&cxx, "static_cast<bool>(-2)"),
+        Some((one, Type::Int(Bool)))
+    );
+
+    assert_eq!(
+        EvalResult::Int(Wrapping(2)).to_target_int(Bool, &TargetInfo::default()),
+        Err(cexpr::expr::ConversionError::Truncated { value: 1 })
+    );
+    assert_eq!(Bool.width(&TargetInfo::default()), 8);
+    assert!(!Bool.is_signed(&TargetInfo::default()));
+}
+
+#[test]
+fn data_models() {
+    use cexpr::target::IntType::*;
+    use cexpr::target::{DataModel, Type};
+
+    let idents = HashMap::new();
+    let eval = |model, source: &str| {
+        let parser = IdentifierParser::new(&idents).with_target(TargetInfo::from(model));
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr_with_type(&tokens))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let int = |i, ty| Some((EvalResult::Int(Wrapping(i)), Type::Int(ty)));
+    assert_eq!(TargetInfo::from(DataModel::LP64), TargetInfo::default());
+    assert_eq!(TargetInfo::from(DataModel::ILP32), ilp32());
+
+    assert_eq!(
+        eval(DataModel::LLP64, "(unsigned long)-1"),
+        int(0xffff_ffff, UnsignedLong)
+    );
+    assert_eq!(
+        eval(DataModel::LP64, "(unsigned long)-1"),
+        int(-1, UnsignedLong)
+    );
+    assert_eq!(eval(DataModel::LLP64, "(size_t)-1"), int(-1, SizeT));
+    assert_eq!(eval(DataModel::LLP64, "sizeof(long)"), int(4, SizeT));
+    assert_eq!(eval(DataModel::LLP64, "sizeof(void *)"), int(8, SizeT));
+    assert_eq!(eval(DataModel::ILP32, "sizeof(long)"), int(4, SizeT));
+    // A literal that doesn't fit in a 32-bit long is a long long.
+    assert_eq!(
+        eval(DataModel::LLP64, "4294967296L"),
+        int(0x1_0000_0000, LongLong)
+    );
+    // On LLP64, ptrdiff_t is a long long, which is wider than an unsigned
+    // long.
+    assert_eq!(
+        eval(DataModel::LLP64, "(ptrdiff_t)-1 + 1UL"),
+        int(0, PtrDiffT)
+    );
+    assert_eq!(
+        eval(DataModel::LP64, "(ptrdiff_t)-1 + 1UL"),
+        int(0, UnsignedLong)
+    );
+    assert_eq!(eval(DataModel::LLP64, "(size_t)1 + 1L"), int(2, SizeT));
+}
+
+#[test]
+fn narrowing_casts() {
+    use cexpr::target::DataModel;
+
+    let idents = HashMap::new();
+    let eval = |model, source: &str| {
+        let parser = IdentifierParser::new(&idents).with_target(TargetInfo::from(model));
+        let tokens = cexpr::token::tokenize(source.as_bytes());
+        assert_full_parse(parser.expr(&tokens))
+            .ok()
+            .map(|(_, result)| result)
+    };
+    let int = |i| Some(EvalResult::Int(Wrapping(i)));
+    for &model in &[DataModel::ILP32, DataModel::LP64, DataModel::LLP64] {
+        assert_eq!(eval(model, "(unsigned char)0x1FF"), int(0xff));
+        assert_eq!(eval(model, "(signed char)0x1FF"), int(-1));
+        assert_eq!(eval(model, "(short)-70000"), int(-4464));
+        assert_eq!(eval(model, "(unsigned short)-70000"), int(61072));
+        assert_eq!(eval(model, "(int)0x180000000LL"), int(-0x8000_0000));
+    }
+    assert_eq!(
+        eval(DataModel::LP64, "(long)0x1FFFFFFFFLL"),
+        int(0x1_ffff_ffff)
+    );
+    assert_eq!(eval(DataModel::LLP64, "(long)0x1FFFFFFFFLL"), int(-1));
+    assert_eq!(
+        eval(DataModel::ILP32, "(unsigned long)-2"),
+        int(0xffff_fffe)
+    );
+    assert_eq!(
+        eval(DataModel::LLP64, "(unsigned long)-2"),
+        int(0xffff_fffe)
+    );
+    assert_eq!(eval(DataModel::LP64, "(unsigned long)-2"), int(-2));
+}
@@ -0,0 +1,144 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use std::collections::HashMap;
+use std::num::Wrapping;
+
+use cexpr::expr::EvalResult::*;
+use cexpr::literal::CChar;
+use cexpr::testing::{expected_from_name, is_include_guard, Checker, Failure};
+use cexpr::token::Kind::*;
+use cexpr::token::Token;
+
+#[test]
+fn names() {
+    assert_eq!(
+        expected_from_name(b"Int_n1_000"),
+        Some(Int(Wrapping(-1000)))
+    );
+    assert_eq!(expected_from_name(b"Fn_Int_9"), Some(Int(Wrapping(9))));
+    assert_eq!(expected_from_name(b"Float_0p5"), Some(Float(0.5)));
+    assert_eq!(
+        expected_from_name(b"Str_rocket_U01F680"),
+        Some(Str("rocket_\u{1f680}".into()))
+    );
+    assert_eq!(
+        expected_from_name(b"CharChar_65"),
+        Some(Char(CChar::Char('A')))
+    );
+    assert_eq!(expected_from_name(b"FAIL_anything"), Some(Invalid));
+    assert_eq!(expected_from_name(b"Str_U01"), None);
+    assert_eq!(expected_from_name(b"NOPREFIX"), None);
+}
+
+#[test]
+fn table() {
+    let expected = vec![
+        (b"TWO".to_vec(), Int(Wrapping(2))),
+        (b"BAD".to_vec(), Invalid),
+    ]
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+    let mut checker = Checker::new(expected);
+
+    let one: Vec<Token> = vec![
+        (Identifier, &b"ONE"[..]).into(),
+        (Literal, &b"1"[..]).into(),
+    ];
+    let two: Vec<Token> = vec![
+        (Identifier, &b"TWO"[..]).into(),
+        (Identifier, &b"ONE"[..]).into(),
+        (Punctuation, &b"+"[..]).into(),
+        (Identifier, &b"ONE"[..]).into(),
+    ];
+    let bad: Vec<Token> = vec![
+        (Identifier, &b"BAD"[..]).into(),
+        (Identifier, &b"UNKNOWN"[..]).into(),
+    ];
+    assert_eq!(checker.check(b"ONE", &one), Ok(()));
+    assert_eq!(checker.check(b"TWO", &two), Ok(()));
+    assert_eq!(checker.check(b"BAD", &bad), Ok(()));
+    assert_eq!(
+        checker.check(b"ONE", &one),
+        Err(Failure::Redefined {
+            name: b"ONE".to_vec()
+        })
+    );
+    assert_eq!(checker.identifiers().len(), 2);
+}
+
+#[test]
+fn include_guards() {
+    let guard = |file: &str, name: &[u8]| {
+        let tokens: Vec<Token> = vec![(Identifier, name).into()];
+        is_include_guard(file, name, &tokens)
+    };
+    assert!(guard("foo.h", b"FOO_H"));
+    assert!(guard("include/foo.h", b"_FOO_H"));
+    assert!(guard("tests/input/int-signed.h", b"__INT_SIGNED_H__"));
+    assert!(!guard("foo.h", b"BAR_H"));
+    assert!(!guard("foo.h", b"FOO"));
+    assert!(!guard("", b"_"));
+
+    let defined: Vec<Token> = vec![
+        (Identifier, &b"FOO_H"[..]).into(),
+        (Literal, &b"1"[..]).into(),
+    ];
+    assert!(!is_include_guard("foo.h", b"FOO_H", &defined));
+
+    // A guard without an underscore doesn't encode an expected value.
+    let empty: Vec<Token> = vec![(Identifier, &b"CONFIG"[..]).into()];
+    let invalid = Err(Failure::InvalidName {
+        name: b"CONFIG".to_vec(),
+    });
+    assert_eq!(Checker::from_names().check(b"CONFIG", &empty), invalid);
+    let mut checker = Checker::from_names().with_header("include/config");
+    assert_eq!(checker.check(b"CONFIG", &empty), Ok(()));
+    let defined: Vec<Token> = vec![
+        (Identifier, &b"CONFIG"[..]).into(),
+        (Literal, &b"1"[..]).into(),
+    ];
+    assert_eq!(checker.check(b"CONFIG", &defined), invalid);
+    assert!(checker.identifiers().is_empty());
+}
+
+#[test]
+fn conflicts() {
+    let mut checker = Checker::new(HashMap::new());
+    let define = |list: &[(_, &str)]| -> Vec<Token> {
+        list.iter()
+            .map(|&(kind, raw)| (kind, raw.as_bytes()).into())
+            .collect()
+    };
+    let one = define(&[(Identifier, "ONE"), (Literal, "1")]);
+    let other = define(&[(Identifier, "ONE"), (Literal, "0x1")]);
+    let two = define(&[(Identifier, "TWO"), (Literal, "2")]);
+    assert_eq!(checker.check(b"ONE", &one), Ok(()));
+    assert_eq!(checker.check(b"TWO", &two), Ok(()));
+    let conflict = checker.check(b"ONE", &other);
+    assert_eq!(
+        conflict,
+        Err(Failure::Conflict {
+            name: b"ONE".to_vec(),
+            previous: 1,
+            redefined: 3,
+        })
+    );
+    assert_eq!(
+        conflict.unwrap_err().to_string(),
+        "ONE: definition 3 conflicts with definition 1"
+    );
+    assert_eq!(checker.identifiers()[&b"ONE"[..]], Int(Wrapping(1)));
+    assert_eq!(
+        checker.check(b"TWO", &two),
+        Err(Failure::Redefined {
+            name: b"TWO".to_vec()
+        })
+    );
+}
@@ -0,0 +1,471 @@
+// (C) Copyright 2016 Jethro G. Beekman
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+extern crate cexpr;
+
+use cexpr::parse_define;
+use cexpr::token::Kind::{self, *};
+use cexpr::token::{
+    merge_punctuation, normalize, retokenize, spelling, tokenize, tokenize_partial,
+    tokenize_with_whitespace, Edit, Span, Token,
+};
+
+fn tokens(list: &[(Kind, &str)]) -> Vec<Token> {
+    list.iter()
+        .map(|&(kind, raw)| (kind, raw.as_bytes()).into())
+        .collect()
+}
+
+#[test]
+fn tokenize_expression() {
+    assert_eq!(
+        tokenize(b"(unsigned)x>>=1.5e+3f+u8\"s\\\"\"L'\\''/* c */ //d\n.5 ->..."),
+        tokens(&[
+            (Punctuation, "("),
+            (Keyword, "unsigned"),
+            (Punctuation, ")"),
+            (Identifier, "x"),
+            (Punctuation, ">>="),
+            (Literal, "1.5e+3f"),
+            (Punctuation, "+"),
+            (Literal, "u8\"s\\\"\""),
+            (Literal, "L'\\''"),
+            (Comment, "/* c */"),
+            (Comment, "//d"),
+            (Literal, ".5"),
+            (Punctuation, "->"),
+            (Punctuation, "..."),
+        ])
+    );
+    assert_eq!(
+        tokenize(b"a\\\n+\t@ \"open"),
+        tokens(&[
+            (Identifier, "a"),
+            (Punctuation, "+"),
+            (Punctuation, "@"),
+            (Literal, "\"open"),
+        ])
+    );
+    assert_eq!(
+        tokenize(b"ns::N?a:::b"),
+        tokens(&[
+            (Identifier, "ns"),
+            (Punctuation, "::"),
+            (Identifier, "N"),
+            (Punctuation, "?"),
+            (Identifier, "a"),
+            (Punctuation, "::"),
+            (Punctuation, ":"),
+            (Identifier, "b"),
+        ])
+    );
+    assert!(tokenize(b" \n").is_empty());
+}
+
+#[test]
+fn maximal_munch() {
+    let spellings = |source: &str| {
+        tokenize(source.as_bytes())
+            .iter()
+            .map(|token| String::from_utf8_lossy(&token.raw).into_owned())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(spellings("a>>=b<<=c"), ["a", ">>=", "b", "<<=", "c"]);
+    assert_eq!(
+        spellings("a+++b---c"),
+        ["a", "++", "+", "b", "--", "-", "c"]
+    );
+    assert_eq!(
+        spellings("1<<<2>>>3"),
+        ["1", "<<", "<", "2", ">>", ">", "3"]
+    );
+    assert_eq!(spellings("p->x->*y"), ["p", "->", "x", "->", "*", "y"]);
+    assert_eq!(spellings("....."), ["...", ".", "."]);
+    assert_eq!(spellings("#x##y"), ["#", "x", "##", "y"]);
+    assert_eq!(spellings("%:x%:%:y"), ["%:", "x", "%:%:", "y"]);
+    assert_eq!(spellings("%:%:%:"), ["%:%:", "%:"]);
+    assert_eq!(
+        spellings("a<:0:><%%>&&=||"),
+        ["a", "<:", "0", ":>", "<%", "%>", "&&", "=", "||"]
+    );
+}
+
+#[test]
+fn merged_punctuation() {
+    let merged = |list: &[(Kind, &str)]| {
+        merge_punctuation(&tokens(list))
+            .iter()
+            .map(|token| String::from_utf8_lossy(&token.raw).into_owned())
+            .collect::<Vec<_>>()
+    };
+    let punctuation =
+        |list: &[&str]| merged(&list.iter().map(|&p| (Punctuation, p)).collect::<Vec<_>>());
+
+    assert_eq!(punctuation(&[">", ">", "="]), [">>="]);
+    assert_eq!(punctuation(&["<", "<=", "<"]), ["<<=", "<"]);
+    assert_eq!(punctuation(&["+", "+", "+"]), ["++", "+"]);
+    assert_eq!(punctuation(&["-", "->"]), ["-", "->"]);
+    assert_eq!(punctuation(&[".", "."]), [".", "."]);
+    assert_eq!(punctuation(&[".", ".", ".", "."]), ["...", "."]);
+    assert_eq!(punctuation(&["%:", "%:"]), ["%:%:"]);
+    assert_eq!(
+        merged(&[(Punctuation, ">"), (Whitespace, " "), (Punctuation, ">")]),
+        [">", " ", ">"]
+    );
+    assert_eq!(
+        merged(&[(Punctuation, "&"), (Identifier, "x"), (Punctuation, "&")]),
+        ["&", "x", "&"]
+    );
+
+    // Tokens with spans are only merged if they touch.
+    let spaced = merge_punctuation(&tokenize(b"- ->>="));
+    assert_eq!(spaced, tokenize(b"- ->>="));
+    let split: Vec<Token> = tokenize(b"1<<2")
+        .into_iter()
+        .flat_map(|token| match &token.raw[..] {
+            b"<<" => {
+                let span = token.span.unwrap();
+                let less = |start| Token {
+                    span: Some(Span {
+                        start,
+                        end: start + 1,
+                        column: start + 1,
+                        ..span
+                    }),
+                    ..(Punctuation, &b"<"[..]).into()
+                };
+                vec![less(span.start), less(span.start + 1)]
+            }
+            _ => vec![token],
+        })
+        .collect();
+    assert_eq!(split.len(), 4);
+    let merged = merge_punctuation(&split);
+    assert_eq!(merged, tokenize(b"1<<2"));
+    assert_eq!(merged[1].span, tokenize(b"1<<2")[1].span);
+    assert_eq!(
+        cexpr::expr::expr(&merged).unwrap().1,
+        cexpr::expr::EvalResult::Int(std::num::Wrapping(4))
+    );
+    assert!(cexpr::assert_full_parse(cexpr::expr::expr(&split)).is_err());
+}
+
+#[test]
+fn whitespace() {
+    let source = b" (1 +\\\n\t2)/* c */ ";
+    let mut spaced = tokenize_with_whitespace(source);
+    assert_eq!(
+        spaced,
+        tokens(&[
+            (Whitespace, " "),
+            (Punctuation, "("),
+            (Literal, "1"),
+            (Whitespace, " "),
+            (Punctuation, "+"),
+            (Whitespace, "\\\n\t"),
+            (Literal, "2"),
+            (Punctuation, ")"),
+            (Comment, "/* c */"),
+            (Whitespace, " "),
+        ])
+    );
+    let joined: Vec<u8> = spaced.iter().flat_map(|t| t.raw.iter().copied()).collect();
+    assert_eq!(joined, source);
+
+    cexpr::token::remove_comments(&mut spaced);
+    let (rest, value) = cexpr::expr::expr(&spaced).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(value, cexpr::expr::EvalResult::Int(std::num::Wrapping(3)));
+    assert_eq!(normalize(&spaced), tokenize(b"1 + 2"));
+}
+
+#[test]
+fn define() {
+    let (name, params, body) = parse_define(b"#define FOO(x, ...) ((x)+1) /* one */").unwrap();
+    assert_eq!(name, b"FOO");
+    assert_eq!(params, Some(vec![b"x".to_vec(), b"...".to_vec()]));
+    assert_eq!(
+        body,
+        tokens(&[
+            (Punctuation, "("),
+            (Punctuation, "("),
+            (Identifier, "x"),
+            (Punctuation, ")"),
+            (Punctuation, "+"),
+            (Literal, "1"),
+            (Punctuation, ")"),
+        ])
+    );
+
+    let (name, params, body) = parse_define(b"  # define BAR (1 << \\\n 2)\n#define BAZ").unwrap();
+    assert_eq!(name, b"BAR");
+    assert_eq!(params, None);
+    assert_eq!(body.len(), 5);
+
+    assert_eq!(
+        parse_define(b"#define EMPTY()"),
+        Some((b"EMPTY".to_vec(), Some(vec![]), vec![]))
+    );
+    let (name, _, body) = parse_define(b"%:define DIGRAPH <:1:>").unwrap();
+    assert_eq!(name, b"DIGRAPH");
+    assert_eq!(body.len(), 3);
+
+    assert_eq!(parse_define(b"#undef FOO"), None);
+    assert_eq!(parse_define(b"#define 1"), None);
+    assert_eq!(parse_define(b"#define F(x,) x"), None);
+    assert_eq!(parse_define(b"#define F(..., x) x"), None);
+    assert_eq!(parse_define(b"#define F(x"), None);
+}
+
+#[test]
+fn normalization() {
+    let normalized = |source: &str| {
+        let tokens = normalize(&tokenize(source.as_bytes()));
+        tokens
+            .iter()
+            .map(|token| String::from_utf8_lossy(&token.raw).into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    assert_eq!(normalized("((0X1F) + 010LU) /* c */"), "0x1f + 0x8ul");
+    assert_eq!(
+        normalized("(00) + 1Ul + 2LLU + 3.50F + 1e3"),
+        "0 + 1ul + 2ull + 3.5f + 1000.0"
+    );
+    assert_eq!(
+        normalized("'\\x41' L'a' \"\\x41\" u8\"a\""),
+        "'A' L'a' \"A\" u8\"a\""
+    );
+    assert_eq!(normalized("(1 + (2 * 3))"), "1 + ( 2 * 3 )");
+    assert_eq!(normalized("1 + ((2 * 3)) * ((4))"), "1 + ( 2 * 3 ) * 4");
+    // `(a)` could be a cast of `+(b)`.
+    assert_eq!(normalized("(a) + (b)"), "( a ) + b");
+    assert_eq!(normalized("(size_t)-1 + (x) / 2"), "( size_t ) - 1 + x / 2");
+    assert_eq!(
+        normalized("F((1)) + F((1, 2)) + a[0](1)"),
+        "F ( 1 ) + F ( ( 1 , 2 ) ) + a [ 0 ] ( 1 )"
+    );
+    assert_eq!(normalized("(1, 2)"), "( 1 , 2 )");
+    assert_eq!(normalized("()"), "( )");
+    assert_eq!(normalized("(1))"), "1 )");
+    assert_eq!(
+        normalized("a<:(1):> <% %:x %:%: y %>"),
+        "a [ 1 ] { # x ## y }"
+    );
+}
+
+#[test]
+fn splice_lines() {
+    use cexpr::token::splice_lines;
+    use std::borrow::Cow;
+
+    assert!(matches!(splice_lines(b"a\\b"), Cow::Borrowed(b"a\\b")));
+    assert_eq!(&splice_lines(b"FO\\\nO\\\r\n")[..], b"FOO");
+
+    let mut idents = std::collections::HashMap::new();
+    idents.insert(
+        b"FOO".to_vec(),
+        cexpr::expr::EvalResult::Int(std::num::Wrapping(1)),
+    );
+    let parser = cexpr::expr::IdentifierParser::new(&idents);
+    let (_, value) = parser.expr(&tokens(&[(Identifier, "FO\\\nO")])).unwrap();
+    assert_eq!(value, cexpr::expr::EvalResult::Int(std::num::Wrapping(1)));
+}
+
+#[test]
+fn spelling_of_tokens() {
+    assert_eq!(spelling(&tokenize(b"(a+b)  *\tc // d")), "(a+b) * c");
+    assert_eq!(spelling(&tokenize(b"a/**/b")), "a b");
+    assert_eq!(spelling(&tokenize_with_whitespace(b"x  \\\n  y")), "x y");
+    assert_eq!(
+        spelling(&tokens(&[(Punctuation, "-"), (Literal, "1\\\n2")])),
+        "- 12"
+    );
+    assert_eq!(spelling(&tokenize(b"/* only */")), "");
+    assert_eq!(spelling(&[]), "");
+}
+
+#[test]
+fn spans() {
+    use cexpr::token::Span;
+
+    let tokens = tokenize(b"/* a\n b */ x +\n\t\"s\\\n\" ?");
+    let spans: Vec<Span> = tokens.iter().map(|t| t.span.unwrap()).collect();
+    let span = |start, end, line, column| Span {
+        start,
+        end,
+        line,
+        column,
+    };
+    assert_eq!(
+        spans,
+        [
+            span(0, 10, 1, 1),
+            span(11, 12, 2, 7),
+            span(13, 14, 2, 9),
+            span(16, 21, 3, 2),
+            span(22, 23, 4, 3),
+        ]
+    );
+    assert_eq!(tokens[1], (Identifier, &b"x"[..]).into());
+
+    let (_, _, body) = cexpr::parse_define(b"#define A /* c */ (1 +\\\n 2)").unwrap();
+    assert_eq!(body[0].span, Some(span(18, 19, 1, 19)));
+    assert_eq!(body[3].span, Some(span(25, 26, 2, 2)));
+    assert_eq!(normalize(&body)[0].span, body[1].span);
+}
+
+#[test]
+fn partial() {
+    let partial = tokenize_partial(b"a = 'b' + \"c\\\" /* d", 100);
+    assert_eq!(
+        partial.tokens,
+        tokens(&[
+            (Identifier, "a"),
+            (Punctuation, "="),
+            (Literal, "'b'"),
+            (Punctuation, "+"),
+            (Literal, "\"c\\\" /* d"),
+        ])
+    );
+    assert_eq!(partial.incomplete, [4]);
+    assert_eq!(partial.end, 19);
+
+    let partial = tokenize_partial(b"'a\n\"b\" /* c", 100);
+    assert_eq!(
+        partial.tokens,
+        tokens(&[(Literal, "'a"), (Literal, "\"b\""), (Comment, "/* c")])
+    );
+    assert_eq!(partial.incomplete, [0, 2]);
+
+    let input = b"x + /* y */ 1";
+    let all = tokenize(input);
+    for fuel in 0..all.len() {
+        let partial = tokenize_partial(input, fuel);
+        assert_eq!(partial.tokens, all[..fuel]);
+        assert_eq!(partial.incomplete, []);
+        assert_eq!(tokenize(&input[..partial.end]), all[..fuel]);
+    }
+    assert_eq!(tokenize_partial(input, all.len()).end, input.len());
+    assert_eq!(tokenize_partial(b"", 0).end, 0);
+}
+
+#[test]
+fn incremental() {
+    let check = |old: &str, at: usize, removed: usize, inserted: &str| {
+        let mut new = old.as_bytes().to_vec();
+        new.splice(at..at + removed, inserted.bytes());
+        let edit = Edit {
+            replaced: at..at + removed,
+            inserted: inserted.len(),
+        };
+        let actual = retokenize(&tokenize(old.as_bytes()), &edit, &new);
+        let expected = tokenize(&new);
+        assert_eq!(actual, expected, "{:?}", String::from_utf8_lossy(&new));
+        let spans = |tokens: &[Token]| tokens.iter().map(|t| t.span).collect::<Vec<_>>();
+        assert_eq!(spans(&actual), spans(&expected));
+    };
+    let source = "#define A 1\n#define B (A << 2) /* b */\n#define C \"c\"\n";
+    // Inside a token, continuing a token and between tokens.
+    check(source, 10, 1, "0x10");
+    check(source, 9, 0, "B");
+    check(source, 11, 0, " + 1");
+    // Changing the lines of later tokens, and joining lines.
+    check(source, 11, 0, "\n\n");
+    check(source, 11, 1, "");
+    check(source, 11, 0, "\\");
+    // Starting or ending a comment or a string changes the tokens after it.
+    check(source, 24, 0, "/*");
+    check(source, 36, 2, "");
+    check(source, 48, 1, "");
+    // At the start and end.
+    check(source, 0, 0, " ");
+    check(source, 0, 8, "");
+    check(source, source.len(), 0, "#undef A");
+    check("", 0, 0, "1 + 2");
+
+    let mut old = tokenize(b"1 + 2");
+    old[0].span = None;
+    let edit = Edit {
+        replaced: 0..1,
+        inserted: 1,
+    };
+    assert_eq!(retokenize(&old, &edit, b"3 + 2"), tokenize(b"3 + 2"));
+}
+
+#[test]
+fn encoding() {
+    use cexpr::token::{decode, encode, DecodeError};
+
+    let source = b"#define ID(x) x /* c */\n#define TWICE ID(1) + ID(1)\n";
+    let mut tokens = tokenize_with_whitespace(source);
+    tokens.extend(self::tokens(&[(Literal, "\"no span\"")]));
+    let data = encode(&tokens);
+    let decoded = decode(&data).unwrap();
+    assert_eq!(decoded, tokens);
+    let spans = |tokens: &[Token]| tokens.iter().map(|t| t.span).collect::<Vec<_>>();
+    assert_eq!(spans(&decoded), spans(&tokens));
+    assert_eq!(decode(&encode(&[])), Ok(vec![]));
+
+    // Each spelling is stored once.
+    let repeated = tokenize(&b"ID(1) + ".repeat(100)[..]);
+    assert!(encode(&repeated).len() < 8 * repeated.len());
+
+    assert_eq!(decode(b""), Err(DecodeError::NotTokens));
+    assert_eq!(decode(b"#define A 1"), Err(DecodeError::NotTokens));
+    let mut other_version = data.clone();
+    other_version[4] += 1;
+    assert_eq!(decode(&other_version), Err(DecodeError::Version(2)));
+    for i in 5..data.len() {
+        let mut corrupt = data.clone();
+        corrupt[i] ^= 0x10;
+        assert_eq!(decode(&corrupt), Err(DecodeError::Corrupt));
+        assert_eq!(decode(&data[..i]), Err(DecodeError::Corrupt));
+    }
+}
+
+#[test]
+fn validation() {
+    use cexpr::token::InvalidToken;
+
+    for token in tokenize_with_whitespace(b"#define F(x) u8\"s\" x##1 /* c */ 1.e+3 \\\n - 'a'") {
+        assert_eq!(token.validate(), Ok(()));
+    }
+    let invalid = tokens(&[
+        (Punctuation, ""),
+        (Identifier, "a\0"),
+        (Punctuation, "+-"),
+        (Literal, "1 "),
+        (Comment, "/* a */ b"),
+        (Whitespace, "-"),
+    ]);
+    let errors: Vec<_> = invalid.iter().map(Token::validate).collect();
+    assert_eq!(
+        errors,
+        [
+            Err(InvalidToken::Empty),
+            Err(InvalidToken::Nul(1)),
+            Err(InvalidToken::Spelling),
+            Err(InvalidToken::Spelling),
+            Err(InvalidToken::Spelling),
+            Err(InvalidToken::Spelling),
+        ]
+    );
+    assert_eq!(tokens(&[(Keyword, "x")])[0].validate(), Ok(()));
+
+    let input = tokens(&[(Literal, "1"), (Punctuation, "+"), (Literal, "")]);
+    let error = match cexpr::expr::expr(&input) {
+        Err(cexpr::nom::Err::Failure(e)) => e,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(error.error, cexpr::ErrorKind::InvalidToken);
+    assert_eq!(error.position(&input), 2);
+    assert!(cexpr::ast::parse(&input, &mut cexpr::ast::Symbols::new()).is_err());
+    assert!(cexpr::expr::validate(&input).is_err());
+}